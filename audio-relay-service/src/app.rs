@@ -1,4 +1,17 @@
 use crate::common::app_config::AppConfig;
+use crate::common::services::events::LifecycleEvents;
+use crate::common::services::metrics::Metrics;
+use crate::common::services::reconnect_tokens::ReconnectTokenStore;
+use crate::common::services::users::UserRegistry;
+use crate::vc::codec_check;
+use crate::vc::decode_pool::DecodePool;
+use crate::vc::group_voice_session::{GroupVoiceSessions, RoomInfo};
+use crate::vc::injection;
+use crate::vc::jitter_buffer::JitterBufferDump;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use quinn::Endpoint;
 use tokio::signal::{self};
@@ -9,50 +22,157 @@ pub struct App {
     pub config: AppConfig,
     /// Token notifying of app shutdown
     pub cancellation_token: CancellationToken,
+    /// Per-connection stats, served on the metrics endpoint
+    pub metrics: Metrics,
+    /// Active rooms and their members
+    pub rooms: GroupVoiceSessions,
+    /// Connection lifecycle events, subscribe to get notified
+    pub events: LifecycleEvents,
+    /// Tokens letting dropped clients resume their membership
+    pub reconnect_tokens: ReconnectTokenStore,
+    /// Connections of authenticated users that sent a user id
+    pub users: UserRegistry,
+    /// Threads decoding and mixing run on, inline on the runtime if `decode_threads` is not set
+    pub decode_pool: Option<DecodePool>,
+    /// Token notifying that new connections are refused while existing ones keep running
+    pub draining_token: CancellationToken,
     /// Task tracker. Instead of using tokio::spawn use tracker.spawn
     task_tracker: TaskTracker,
+    /// Tracks connection tasks only, so draining can wait for exactly those
+    connection_tracker: TaskTracker,
+    /// Session id of the next accepted auth request
+    next_session_id: AtomicU32,
 }
 
 impl App {
-    pub fn new(config: AppConfig) -> &'static mut Self {
+    /// Every task the app spawns holds a handle, so it is freed once they all ended and the caller let go of it
+    pub fn new(config: AppConfig) -> Arc<Self> {
         let cancellation_token = CancellationToken::new();
         let task_tracker = TaskTracker::new();
-        let app = Box::new(Self {
+        Arc::new(Self {
+            rooms: GroupVoiceSessions::new(config.mixing_threshold)
+                .with_catch_up(config.get_catch_up())
+                .with_opus_settings(config.get_opus_settings())
+                .with_roster_push(
+                    config.roster_push_strategy,
+                    config.get_roster_push_interval(),
+                )
+                .with_task_tracker(task_tracker.clone()),
+            reconnect_tokens: ReconnectTokenStore::new(
+                config.get_reconnect_token_capacity(),
+                config.get_reconnect_token_ttl(),
+            ),
+            users: UserRegistry::new(config.get_duplicate_user_policy()),
+            decode_pool: config
+                .decode_threads
+                .and_then(|threads| match DecodePool::new(threads) {
+                    Ok(pool) => Some(pool),
+                    Err(e) => {
+                        tracing::error!("Decoding inline, failed to start the decode threads: {e}");
+                        None
+                    }
+                }),
             config,
             cancellation_token,
+            metrics: Metrics::default(),
+            events: LifecycleEvents::default(),
+            draining_token: CancellationToken::new(),
             task_tracker,
-        });
-        Box::leak(app)
+            connection_tracker: TaskTracker::new(),
+            next_session_id: AtomicU32::new(1),
+        })
     }
-    pub async fn run(&'static mut self) -> anyhow::Result<()> {
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        if self.config.is_codec_self_check_enabled() {
+            codec_check::check(&self.config)?;
+            tracing::info!("Opus self-check passed");
+        }
         let endpoint = self.create_endpoint()?;
         tracing::info!("listening on {}", endpoint.local_addr()?);
-        tokio::spawn(self.main_loop(endpoint));
+        if let Some(metrics_listen) = self.config.metrics_listen {
+            let app = self.clone();
+            self.task_tracker.spawn(async move {
+                if let Err(e) =
+                    crate::common::services::metrics::serve_metrics(app, metrics_listen).await
+                {
+                    tracing::error!("metrics endpoint failed: {e}");
+                }
+            });
+        }
+        tokio::spawn(self.clone().main_loop(endpoint));
         self.handle_signal().await;
         self.task_tracker.close();
+        self.connection_tracker.close();
         self.task_tracker.wait().await;
+        self.connection_tracker.wait().await;
         Ok(())
     }
-    async fn main_loop(&'static self, endpoint: Endpoint) {
+    /// Runs `task` on the app's task tracker, so shutdown waits for it
+    pub fn spawn_task(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.task_tracker.spawn(task);
+    }
+    /// State of every active room, for admin and debug tooling
+    pub fn describe_rooms(&self) -> Vec<RoomInfo> {
+        self.rooms.describe()
+    }
+    /// Plays the WAV file at `path` into `room_id` as a virtual member, returns its member id.
+    /// See [`crate::vc::injection`], admins reach it through `POST /admin/inject` on the metrics endpoint
+    pub async fn inject_wav(
+        self: &Arc<Self>,
+        room_id: u32,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<u64> {
+        Ok(injection::inject_wav(self, room_id, path.as_ref()).await? as u64)
+    }
+    /// Jitter buffer state of every connection, for diagnosing audio glitches
+    pub fn dump_jitter_buffers(&self) -> Vec<JitterBufferDump> {
+        self.metrics.jitter_dump()
+    }
+    /// Session ids count up from 1 for every accepted auth request, 0 is left to clients of older servers
+    pub fn next_session_id(&self) -> u32 {
+        self.next_session_id.fetch_add(1, Ordering::Relaxed)
+    }
+    pub fn is_draining(&self) -> bool {
+        self.draining_token.is_cancelled()
+    }
+    /// Stops accepting connections and returns once every existing one has ended.
+    pub async fn drain(&self) {
+        self.draining_token.cancel();
+        self.connection_tracker.close();
+        self.connection_tracker.wait().await;
+    }
+    /// Accepts connections on `endpoint` and serves each in its own task until the app shuts down.
+    pub async fn main_loop(self: Arc<Self>, endpoint: Endpoint) {
         let connection_limit = self.config.connection_limit;
+        let stateless_retry = self.config.is_stateless_retry_enabled();
 
         loop {
             tokio::select! {
                             Some(conn) = endpoint.accept() => {
-                                if endpoint.open_connections() >= connection_limit {
-                                    tracing::debug!("refusing due to open connection limit");
-                                    conn.refuse();
-                                } else if !conn.remote_address_validated() {
-                                    tracing::debug!("requiring connection to validate its address");
-                                    conn.retry().unwrap();
-                                } else {
-                                    tracing::info!("Accepted connection");
-                                    let fut = crate::vc::handle_connection(self, conn);
-                                    self.task_tracker.spawn(async move {
-                                        if let Err(e) = fut.await {
-                                            tracing::error!("connection failed: {reason}", reason = e.to_string())
+                                let incoming = IncomingState {
+                                    draining: self.is_draining(),
+                                    at_connection_limit: endpoint.open_connections() >= connection_limit,
+                                    address_validated: conn.remote_address_validated(),
+                                    may_retry: conn.may_retry(),
+                                };
+                                match admission(incoming, stateless_retry) {
+                                    Admission::Refuse => {
+                                        tracing::debug!("refusing {incoming:?}");
+                                        conn.refuse();
+                                    }
+                                    Admission::Retry => {
+                                        tracing::debug!("requiring connection to validate its address");
+                                        match conn.retry() {
+                                            Ok(()) => self.metrics.add_stateless_retry(),
+                                            // Already came back from a retry, another one would stall the handshake
+                                            Err(e) => {
+                                                let conn = e.into_incoming();
+                                                tracing::warn!("Unable to retry {}, accepting as is", conn.remote_address());
+                                                self.accept(conn);
+                                            }
                                         }
-                                    });
+                                    }
+                                    Admission::Accept => self.accept(conn),
                                 }
                             },
                             _ = self.cancellation_token.cancelled()
@@ -65,7 +185,16 @@ impl App {
                         }
         }
     }
-    fn create_endpoint(&'static self) -> anyhow::Result<Endpoint> {
+    fn accept(self: &Arc<Self>, conn: quinn::Incoming) {
+        tracing::info!("Accepted connection");
+        let fut = crate::vc::handle_connection(self.clone(), conn);
+        self.connection_tracker.spawn(async move {
+            if let Err(e) = fut.await {
+                tracing::error!("connection failed: {reason}", reason = e.to_string())
+            }
+        });
+    }
+    fn create_endpoint(&self) -> anyhow::Result<Endpoint> {
         let options = self.config.clone();
         let (certs, key) = crate::common::security::certs::load_certs(&self.config)?;
         let server_config = crate::common::security::endpoint_config::create_server_config(
@@ -77,16 +206,76 @@ impl App {
         Ok(quinn::Endpoint::server(server_config, options.listen)?)
     }
 
-    async fn handle_signal(&'static self) {
-        match signal::ctrl_c().await {
-            Ok(_) => {
-                tracing::info!("Interrupt detected!");
+    async fn handle_signal(&self) {
+        tokio::select! {
+            result = signal::ctrl_c() => match result {
+                Ok(_) => {
+                    tracing::info!("Interrupt detected!");
+                    self.cancellation_token.cancel();
+                    tracing::info!("Sent exit signal. Waiting for jobs to finish...");
+                }
+                Err(e) => {
+                    tracing::error!("Cannot listen for interrupt, app closing: {e}");
+                }
+            },
+            _ = drain_signal() => {
+                tracing::info!("Drain signal detected! Refusing new connections until existing ones end...");
+                tokio::select! {
+                    _ = self.drain() => tracing::info!("All connections ended."),
+                    _ = signal::ctrl_c() => tracing::info!("Interrupt detected while draining!"),
+                }
                 self.cancellation_token.cancel();
-                tracing::info!("Sent exit signal. Waiting for jobs to finish...");
-            }
-            Err(e) => {
-                tracing::error!("Cannot listen for interrupt, app closing: {e}");
             }
         }
     }
 }
+
+/// SIGUSR1 puts the app into draining mode
+#[cfg(unix)]
+async fn drain_signal() {
+    match signal::unix::signal(signal::unix::SignalKind::user_defined1()) {
+        Ok(mut signal) => {
+            signal.recv().await;
+        }
+        Err(e) => {
+            tracing::error!("Cannot listen for drain signal: {e}");
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn drain_signal() {
+    std::future::pending::<()>().await
+}
+
+/// What the accept loop knows about an incoming connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncomingState {
+    pub draining: bool,
+    pub at_connection_limit: bool,
+    /// Came back with a valid retry token
+    pub address_validated: bool,
+    /// False once the incoming carries a retry token, valid or not
+    pub may_retry: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Refuse,
+    /// Send a stateless retry, the client comes back with a token proving its address
+    Retry,
+    Accept,
+}
+
+/// Decides an incoming connection's fate. An incoming is retried at most once,
+/// so a client answering the retry is accepted instead of being sent around again.
+pub fn admission(incoming: IncomingState, stateless_retry: bool) -> Admission {
+    if incoming.draining || incoming.at_connection_limit {
+        Admission::Refuse
+    } else if stateless_retry && !incoming.address_validated && incoming.may_retry {
+        Admission::Retry
+    } else {
+        Admission::Accept
+    }
+}
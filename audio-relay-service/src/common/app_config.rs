@@ -1,29 +1,31 @@
+use std::collections::HashMap;
 use std::io::BufReader;
 use std::net::SocketAddrV6;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use std::{fs::File, net::SocketAddr};
 
+use aws_lc_rs::{constant_time, digest};
 use clap_serde_derive::{
     ClapSerde,
     clap::{self, Parser},
 };
+use lib_common_voxoxide::types::{ArsAudioFormat, ArsCodecPolicy, VoxoxideError};
 use serde::{Deserialize, Serialize};
 use tracing::Level;
 
 #[cfg(test)]
-const CONFIG_PATH_ENV: &'static str = "TEST_CONFIG_PATH";
+const CONFIG_PATH_ENV: &str = "TEST_CONFIG_PATH";
 
 #[cfg(not(test))]
-pub const CONFIG_PATH_ENV: &'static str = "ARS_CONFIG_PATH";
+pub const CONFIG_PATH_ENV: &str = "ARS_CONFIG_PATH";
 
 /// Configuration for the app.
+/// Takes no positional arguments, so a stray word on the command line is an error instead of being ignored.
 #[derive(Parser, Deserialize, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct AppConfigArgs {
-    /// stding input (unused)
-    pub input: Option<Vec<String>>,
-
     /// Path pointing to config.yaml
     #[clap(long = "config", default_value = "config.yaml")]
     pub config_path: std::path::PathBuf,
@@ -41,32 +43,404 @@ pub enum Environment {
     Development,
 }
 
+/// Opus application the server's encoders are tuned for, see [`OpusSettings`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, derive_more::FromStr, PartialEq, Eq)]
+#[from_str(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OpusApplication {
+    /// Best for speech intelligibility
+    Voip,
+    /// Best for music and mixed content
+    Audio,
+    /// Lowest latency and CPU, drops the speech-optimized modes
+    LowDelay,
+}
+
+impl From<OpusApplication> for opus::Application {
+    fn from(application: OpusApplication) -> Self {
+        match application {
+            OpusApplication::Voip => opus::Application::Voip,
+            OpusApplication::Audio => opus::Application::Audio,
+            OpusApplication::LowDelay => opus::Application::LowDelay,
+        }
+    }
+}
+
+/// What happens when a user authenticates while another connection of theirs is still open
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, derive_more::FromStr, PartialEq)]
+#[from_str(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateUserPolicy {
+    /// The new connection fails to authenticate
+    #[default]
+    Reject,
+    /// The old connection is closed and the new one takes over
+    Replace,
+}
+
+/// What happens to RTP packets whose SSRC differs from the one a connection first sent
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, derive_more::FromStr, PartialEq)]
+#[from_str(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownSsrcPolicy {
+    /// The packet is dropped and counted
+    #[default]
+    Drop,
+    /// The new SSRC is decoded as another stream of the connection
+    Register,
+}
+
+/// What happens when a member declares an SSRC another member of the room already streams with
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, derive_more::FromStr, PartialEq)]
+#[from_str(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SsrcCollisionPolicy {
+    /// The member is told a free SSRC to stream with in its auth response
+    #[default]
+    Reassign,
+    /// The member fails to authenticate
+    Reject,
+}
+
+/// When roster and level updates are pushed to members, see `vc::push_schedule`
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, derive_more::FromStr, PartialEq)]
+#[from_str(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PushStrategy {
+    /// Every change is pushed right away
+    #[default]
+    OnChange,
+    /// The current state is pushed every `roster_push_interval_ms`, changed or not
+    Periodic,
+    /// The first change opens a window of `roster_push_interval_ms`, everything changing within it goes out as one push
+    Debounced,
+}
+
+/// What happens to control messages beyond `max_control_messages_per_sec`
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, derive_more::FromStr, PartialEq)]
+#[from_str(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ControlRateEnforcement {
+    /// The message is discarded unread, the connection keeps streaming
+    #[default]
+    Drop,
+    /// The connection is closed with a protocol error
+    Close,
+}
+
+/// What happens to audio datagrams arriving before the auth handshake completes
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, derive_more::FromStr, PartialEq)]
+#[from_str(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PreAuthDatagrams {
+    /// They are discarded, audio starts with the first datagram after auth
+    #[default]
+    Drop,
+    /// Up to [`MAX_PRE_AUTH_DATAGRAMS`] are kept and played once the member is admitted,
+    /// so a client streaming right after connecting loses none of its first words
+    Buffer,
+}
+
+/// Secret auth tokens are signed with, see `common::services::auth_tokens`. Never printed
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct AuthSecret(pub String);
+
+impl FromStr for AuthSecret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl std::fmt::Debug for AuthSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthSecret(..)")
+    }
+}
+
 #[derive(ClapSerde, Debug, Clone, Deserialize)]
 pub struct AppConfig {
     #[clap(short = 'e', long = "environment")]
     pub environment: Environment,
 
-    /// TLS private key in PEM format
+    /// TLS private key in PEM format, a path, `env:NAME` for an environment variable or `-` for stdin
     #[clap(short = 'k', long = "key", requires = "cert")]
     pub key: PathBuf,
-    /// TLS certificate in PEM format
+    /// TLS certificate in PEM format, like `key` or given inline
     #[clap(short = 'c', long = "cert", requires = "key")]
     pub cert: PathBuf,
 
-    /// Address to listen on
+    /// Address to listen on, `[::1]:4433` if neither the YAML nor `--listen` set it
     #[clap(long = "listen")]
     #[default(SocketAddr::V6(SocketAddrV6::from_str("[::1]:4433").unwrap()))]
     pub listen: SocketAddr,
 
-    /// Maximum number of concurrent connections to allow
+    /// Maximum number of concurrent connections to allow, [`DEFAULT_CONNECTION_LIMIT`] if not set
     #[clap(long = "connection-limit")]
+    #[default(DEFAULT_CONNECTION_LIMIT)]
     pub connection_limit: usize,
-    /// Log level as per tracing convention trace < debug < info < warn < error
+    /// Make new clients prove their address with a stateless retry before any connection state is kept.
+    /// Costs every handshake one extra round trip, enabled if not set
+    #[clap(long = "stateless-retry")]
+    pub stateless_retry: Option<bool>,
+    /// Round trip Opus at the default format and every room's configured one before accepting connections,
+    /// so startup fails on a codec that can't serve them. Enabled if not set
+    #[clap(long = "codec-self-check")]
+    pub codec_self_check: Option<bool>,
+    /// Log level as per tracing convention trace < debug < info < warn < error, `info` if not set
     #[clap(short, long)]
+    #[default(DEFAULT_LOG_LEVEL.to_string())]
     pub log_level: String,
 
+    /// File logs are written to besides stdout, stdout only if not set
     #[clap(long)]
     pub log_file: Option<PathBuf>,
+
+    /// TLS 1.3 cipher suites to allow, eg. `TLS13_AES_256_GCM_SHA384`, every suite of the crypto provider if empty
+    #[clap(long = "cipher-suites", value_delimiter = ',')]
+    #[serde(default)]
+    pub cipher_suites: Vec<String>,
+
+    /// Address to serve Prometheus metrics on, disabled if not set.
+    /// Also serves the jitter buffer dump at `/debug/jitter`
+    #[clap(long = "metrics-listen")]
+    pub metrics_listen: Option<SocketAddr>,
+    /// Bearer token for the admin routes of the metrics endpoint, which are disabled if not set.
+    /// Better set in the YAML, the command line shows in the process list
+    #[clap(long = "admin-token")]
+    pub admin_token: Option<AuthSecret>,
+
+    /// Playout delay target, the settings below are derived from it unless set explicitly
+    #[clap(long = "target-latency-ms")]
+    pub target_latency_ms: Option<u64>,
+    /// Jitter buffer depth in frames, packets are held that long to put them back in order
+    #[clap(long = "jitter-buffer-depth")]
+    pub jitter_buffer_depth: Option<usize>,
+    /// Packets a connection's jitter buffer holds at most across its streams, the oldest is dropped beyond that
+    #[clap(long = "max-buffered-packets")]
+    pub max_buffered_packets: Option<usize>,
+    /// QUIC keepalive interval
+    #[clap(long = "keepalive-interval-ms")]
+    pub keepalive_interval_ms: Option<u64>,
+    /// Time without any traffic after which a connection is dropped
+    #[clap(long = "inactivity-timeout-ms")]
+    pub inactivity_timeout_ms: Option<u64>,
+
+    /// Directory connection recordings are written to, the working directory if not set
+    #[clap(long = "recording-dir")]
+    pub recording_dir: Option<PathBuf>,
+
+    /// Flush recordings to disk this often, so a crash loses at most this much audio. Only on finalize if not set
+    #[clap(long = "wav-flush-interval-ms")]
+    pub wav_flush_interval_ms: Option<u64>,
+
+    /// Fill recording gaps with noise this many dB below the last active frame, eg. `-30`.
+    /// Gaps are recorded as digital silence if not set
+    #[clap(long = "comfort-noise-level-db", allow_negative_numbers = true)]
+    pub comfort_noise_level_db: Option<f32>,
+
+    /// Rewrite the WAV header sample rate on finalize to the rate measured against wall-clock time
+    #[clap(long = "wav-sample-rate-correction")]
+    #[serde(default)]
+    pub wav_sample_rate_correction: bool,
+
+    /// Decode errors tolerated within `decode_error_window_ms` before the connection is closed
+    #[clap(long = "max-decode-errors")]
+    pub max_decode_errors: Option<usize>,
+    /// Sliding window over which decode errors are counted
+    #[clap(long = "decode-error-window-ms")]
+    pub decode_error_window_ms: Option<u64>,
+
+    /// Connections receiving more than this over a one second window are closed, uncapped if not set
+    #[clap(long = "max-ingress-bytes-per-sec")]
+    pub max_ingress_bytes_per_sec: Option<u64>,
+    /// Connections sending no datagram for this long, keepalives included, are closed as inactive.
+    /// Catches dead peers well before the QUIC idle timeout, which alone applies if not set
+    #[clap(long = "datagram-timeout-ms")]
+    pub datagram_timeout_ms: Option<u64>,
+    /// Connections that haven't completed the auth handshake this long after connecting are closed,
+    /// [`DEFAULT_AUTH_TIMEOUT_MS`] if not set
+    #[clap(long = "auth-timeout-ms")]
+    pub auth_timeout_ms: Option<u64>,
+    /// Control messages a connection may send over a one second window, uncapped if not set
+    #[clap(long = "max-control-messages-per-sec")]
+    pub max_control_messages_per_sec: Option<usize>,
+    /// `drop` or `close` on control messages beyond `max_control_messages_per_sec`
+    #[clap(long = "control-rate-enforcement")]
+    #[serde(default)]
+    pub control_rate_enforcement: ControlRateEnforcement,
+    /// `drop` or `buffer` audio datagrams arriving before the auth handshake completes
+    #[clap(long = "pre-auth-datagrams")]
+    #[serde(default)]
+    pub pre_auth_datagrams: PreAuthDatagrams,
+
+    /// Rooms with more members than this are mixed on the server instead of forwarding every stream,
+    /// rooms are always forwarded if not set. See the `vc::mixer` docs for choosing a value
+    #[clap(long = "mixing-threshold")]
+    pub mixing_threshold: Option<usize>,
+    /// Threads Opus decoding and room mixing run on instead of the async runtime, inline if not set.
+    /// Pays off once decoding takes 10% of a runtime worker, at 2ms / (decode time of one frame) connections
+    /// per worker, eg. 100 at 20µs a frame. `measure_decode_cost` in `tests/test_decode_pool.rs` measures it
+    #[clap(long = "decode-threads")]
+    pub decode_threads: Option<usize>,
+    /// Recent audio every mixed room keeps to send to members joining late, eg. `500`. Nothing is kept if not set
+    #[clap(long = "catch-up-ms")]
+    pub catch_up_ms: Option<u64>,
+
+    /// Reconnect tokens kept at most, the least recently used one is evicted beyond that
+    #[clap(long = "reconnect-token-capacity")]
+    pub reconnect_token_capacity: Option<usize>,
+    /// Time after which a reconnect token expires
+    #[clap(long = "reconnect-token-ttl-secs")]
+    pub reconnect_token_ttl_secs: Option<u64>,
+
+    /// Require every auth request to carry a user id and a token signed with this secret,
+    /// anyone may join as anyone if not set. Better set in the YAML, the command line shows in the process list
+    #[clap(long = "auth-secret")]
+    pub auth_secret: Option<AuthSecret>,
+
+    /// `reject` or `replace` a second connection authenticating with the same user id.
+    /// `replace` needs `auth_secret`, anyone could take over a user's session otherwise
+    #[clap(long = "duplicate-user-policy")]
+    #[serde(default)]
+    pub duplicate_user_policy: DuplicateUserPolicy,
+
+    /// `drop` or `register` RTP packets with another SSRC than the connection's first packet
+    #[clap(long = "unknown-ssrc-policy")]
+    #[serde(default)]
+    pub unknown_ssrc_policy: UnknownSsrcPolicy,
+    /// `reassign` or `reject` a member declaring an SSRC already used in its room
+    #[clap(long = "ssrc-collision-policy")]
+    #[serde(default)]
+    pub ssrc_collision_policy: SsrcCollisionPolicy,
+
+    /// `onchange`, `periodic` or `debounced` pushing of roster and level updates
+    #[clap(long = "roster-push-strategy")]
+    #[serde(default)]
+    pub roster_push_strategy: PushStrategy,
+    /// Push period for `periodic`, coalescing window for `debounced`
+    #[clap(long = "roster-push-interval-ms")]
+    pub roster_push_interval_ms: Option<u64>,
+
+    /// `voip`, `audio` or `lowdelay` for the server's encoders, picked by `environment` if not set
+    #[clap(long = "opus-application")]
+    pub opus_application: Option<OpusApplication>,
+    /// Opus encoder complexity from 0 (cheapest) to 10 (best quality), picked by `environment` if not set
+    #[clap(long = "opus-complexity")]
+    pub opus_complexity: Option<u8>,
+
+    /// Connections are closed once their session ran this long, unlimited if not set
+    #[clap(long = "max-session-secs")]
+    pub max_session_secs: Option<u64>,
+    /// How long before `max_session_secs` the member is warned
+    #[clap(long = "session-warning-secs")]
+    pub session_warning_secs: Option<u64>,
+
+    /// Per-room settings keyed by room id, only configurable in YAML
+    #[clap(skip)]
+    #[serde(default)]
+    pub rooms: HashMap<u32, RoomConfig>,
+}
+
+/// What a room requiring consent to recording does about members that didn't give it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingConsent {
+    /// They are admitted, and nobody in the room is recorded while they are in it
+    Disable,
+    /// They are refused on auth
+    Reject,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RoomConfig {
+    /// Codec settings advertised to clients joining the room
+    #[serde(default)]
+    pub codec_policy: Option<ArsCodecPolicy>,
+    /// Clients presenting this token on auth become moderators of the room
+    #[serde(default)]
+    pub moderator_token: Option<String>,
+    /// Audio format every member has to send, the first member's if not set
+    #[serde(default)]
+    pub format: Option<ArsAudioFormat>,
+    /// Only record the room while every member consents, members are recorded regardless if not set
+    #[serde(default)]
+    pub recording_consent: Option<RecordingConsent>,
+}
+
+/// Duration of one audio frame, all latency derivations are in multiples of it
+pub const FRAME_DURATION_MS: u64 = 20;
+pub const DEFAULT_TARGET_LATENCY_MS: u64 = 60;
+pub const DEFAULT_CONNECTION_LIMIT: usize = 100;
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+/// Don't drop connections faster than this no matter how low the latency target is
+const MIN_INACTIVITY_TIMEOUT_MS: u64 = 5_000;
+pub const DEFAULT_MAX_DECODE_ERRORS: usize = 20;
+/// One second of 20ms frames
+pub const DEFAULT_MAX_BUFFERED_PACKETS: usize = 50;
+pub const DEFAULT_DECODE_ERROR_WINDOW_MS: u64 = 1_000;
+pub const DEFAULT_RECONNECT_TOKEN_CAPACITY: usize = 10_000;
+pub const DEFAULT_RECONNECT_TOKEN_TTL_SECS: u64 = 300;
+pub const DEFAULT_ROSTER_PUSH_INTERVAL_MS: u64 = 250;
+pub const MAX_OPUS_COMPLEXITY: u8 = 10;
+pub const DEFAULT_SESSION_WARNING_SECS: u64 = 60;
+pub const DEFAULT_AUTH_TIMEOUT_MS: u64 = 10_000;
+/// Datagrams kept with [`PreAuthDatagrams::Buffer`], one second of 20ms frames
+pub const MAX_PRE_AUTH_DATAGRAMS: usize = 50;
+
+/// Latency related settings derived from a single playout delay target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySettings {
+    pub jitter_buffer_depth: usize,
+    pub keepalive_interval: Duration,
+    pub inactivity_timeout: Duration,
+}
+
+impl LatencySettings {
+    /// The jitter buffer holds as many frames as fit in the target,
+    /// the inactivity timeout is 100 targets (at least 5s) and keepalives are sent 3 times per timeout.
+    pub fn from_target(target_latency_ms: u64) -> Self {
+        let jitter_buffer_depth = target_latency_ms.div_ceil(FRAME_DURATION_MS).max(1) as usize;
+        let inactivity_timeout_ms = target_latency_ms
+            .saturating_mul(100)
+            .max(MIN_INACTIVITY_TIMEOUT_MS);
+        Self {
+            jitter_buffer_depth,
+            keepalive_interval: Duration::from_millis(inactivity_timeout_ms / 3),
+            inactivity_timeout: Duration::from_millis(inactivity_timeout_ms),
+        }
+    }
+}
+
+/// Settings of the server's Opus encoders, eg. the mixed return streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusSettings {
+    pub application: OpusApplication,
+    pub complexity: u8,
+}
+
+impl OpusSettings {
+    /// Production spends CPU on voice clarity, development keeps encoding cheap
+    pub fn for_environment(environment: Environment) -> Self {
+        match environment {
+            Environment::Production => Self {
+                application: OpusApplication::Voip,
+                complexity: MAX_OPUS_COMPLEXITY,
+            },
+            Environment::Development => Self {
+                application: OpusApplication::LowDelay,
+                complexity: 5,
+            },
+        }
+    }
+}
+
+impl Default for OpusSettings {
+    fn default() -> Self {
+        Self::for_environment(Environment::default())
+    }
 }
 
 impl std::fmt::Debug for ClapSerdeOptionalAppConfig {
@@ -77,7 +451,51 @@ impl std::fmt::Debug for ClapSerdeOptionalAppConfig {
             .field("cert", &self.cert)
             .field("listen", &self.listen)
             .field("connection_limit", &self.connection_limit)
+            .field("stateless_retry", &self.stateless_retry)
+            .field("codec_self_check", &self.codec_self_check)
             .field("log_level", &self.log_level)
+            .field("cipher_suites", &self.cipher_suites)
+            .field("metrics_listen", &self.metrics_listen)
+            .field("admin_token", &self.admin_token)
+            .field("target_latency_ms", &self.target_latency_ms)
+            .field("jitter_buffer_depth", &self.jitter_buffer_depth)
+            .field("max_buffered_packets", &self.max_buffered_packets)
+            .field("keepalive_interval_ms", &self.keepalive_interval_ms)
+            .field("inactivity_timeout_ms", &self.inactivity_timeout_ms)
+            .field("recording_dir", &self.recording_dir)
+            .field("wav_flush_interval_ms", &self.wav_flush_interval_ms)
+            .field("comfort_noise_level_db", &self.comfort_noise_level_db)
+            .field(
+                "wav_sample_rate_correction",
+                &self.wav_sample_rate_correction,
+            )
+            .field("max_decode_errors", &self.max_decode_errors)
+            .field("decode_error_window_ms", &self.decode_error_window_ms)
+            .field("max_ingress_bytes_per_sec", &self.max_ingress_bytes_per_sec)
+            .field("datagram_timeout_ms", &self.datagram_timeout_ms)
+            .field("auth_timeout_ms", &self.auth_timeout_ms)
+            .field(
+                "max_control_messages_per_sec",
+                &self.max_control_messages_per_sec,
+            )
+            .field("control_rate_enforcement", &self.control_rate_enforcement)
+            .field("pre_auth_datagrams", &self.pre_auth_datagrams)
+            .field("mixing_threshold", &self.mixing_threshold)
+            .field("decode_threads", &self.decode_threads)
+            .field("catch_up_ms", &self.catch_up_ms)
+            .field("reconnect_token_capacity", &self.reconnect_token_capacity)
+            .field("reconnect_token_ttl_secs", &self.reconnect_token_ttl_secs)
+            .field("auth_secret", &self.auth_secret)
+            .field("duplicate_user_policy", &self.duplicate_user_policy)
+            .field("unknown_ssrc_policy", &self.unknown_ssrc_policy)
+            .field("ssrc_collision_policy", &self.ssrc_collision_policy)
+            .field("roster_push_strategy", &self.roster_push_strategy)
+            .field("roster_push_interval_ms", &self.roster_push_interval_ms)
+            .field("opus_application", &self.opus_application)
+            .field("opus_complexity", &self.opus_complexity)
+            .field("max_session_secs", &self.max_session_secs)
+            .field("session_warning_secs", &self.session_warning_secs)
+            .field("rooms", &self.rooms)
             .finish()
     }
 }
@@ -85,13 +503,51 @@ impl std::fmt::Debug for ClapSerdeOptionalAppConfig {
 impl Clone for ClapSerdeOptionalAppConfig {
     fn clone(&self) -> Self {
         Self {
-            environment: self.environment.clone(),
+            environment: self.environment,
             key: self.key.clone(),
             cert: self.cert.clone(),
-            listen: self.listen.clone(),
-            connection_limit: self.connection_limit.clone(),
+            listen: self.listen,
+            connection_limit: self.connection_limit,
+            stateless_retry: self.stateless_retry,
+            codec_self_check: self.codec_self_check,
             log_level: self.log_level.clone(),
             log_file: self.log_file.clone(),
+            cipher_suites: self.cipher_suites.clone(),
+            metrics_listen: self.metrics_listen,
+            admin_token: self.admin_token.clone(),
+            target_latency_ms: self.target_latency_ms,
+            jitter_buffer_depth: self.jitter_buffer_depth,
+            max_buffered_packets: self.max_buffered_packets,
+            keepalive_interval_ms: self.keepalive_interval_ms,
+            inactivity_timeout_ms: self.inactivity_timeout_ms,
+            recording_dir: self.recording_dir.clone(),
+            wav_flush_interval_ms: self.wav_flush_interval_ms,
+            comfort_noise_level_db: self.comfort_noise_level_db,
+            wav_sample_rate_correction: self.wav_sample_rate_correction,
+            max_decode_errors: self.max_decode_errors,
+            decode_error_window_ms: self.decode_error_window_ms,
+            max_ingress_bytes_per_sec: self.max_ingress_bytes_per_sec,
+            datagram_timeout_ms: self.datagram_timeout_ms,
+            auth_timeout_ms: self.auth_timeout_ms,
+            max_control_messages_per_sec: self.max_control_messages_per_sec,
+            control_rate_enforcement: self.control_rate_enforcement,
+            pre_auth_datagrams: self.pre_auth_datagrams,
+            mixing_threshold: self.mixing_threshold,
+            decode_threads: self.decode_threads,
+            catch_up_ms: self.catch_up_ms,
+            reconnect_token_capacity: self.reconnect_token_capacity,
+            reconnect_token_ttl_secs: self.reconnect_token_ttl_secs,
+            auth_secret: self.auth_secret.clone(),
+            duplicate_user_policy: self.duplicate_user_policy,
+            unknown_ssrc_policy: self.unknown_ssrc_policy,
+            ssrc_collision_policy: self.ssrc_collision_policy,
+            roster_push_strategy: self.roster_push_strategy,
+            roster_push_interval_ms: self.roster_push_interval_ms,
+            opus_application: self.opus_application,
+            opus_complexity: self.opus_complexity,
+            max_session_secs: self.max_session_secs,
+            session_warning_secs: self.session_warning_secs,
+            rooms: self.rooms.clone(),
         }
     }
 }
@@ -115,16 +571,47 @@ impl AppConfig {
             println!("{:?}", &path);
             args.config_path = path.into();
         }
-        match File::open(&args.config_path) {
-            Ok(f) => match serde_yaml::from_reader::<_, AppConfig>(BufReader::new(f)) {
-                Ok(file_config) => {
-                    let cfg = AppConfig::try_from(file_config)?;
-                    Ok(cfg.merge(&mut args.config))
-                }
-                Err(err) => Err(err.into()),
-            },
-            Err(open_error) => Err(open_error.into()),
+        // Every field may be left out of the YAML, those set nowhere take their `#[default]`
+        let file = File::open(&args.config_path)?;
+        let file_config =
+            serde_yaml::from_reader::<_, <AppConfig as ClapSerde>::Opt>(BufReader::new(file))?;
+        let config = AppConfig::from(file_config).merge(&mut args.config);
+        config.validate()?;
+        Ok(config)
+    }
+    /// The TLS key and certificate have no sensible default, one of the sources has to set them.
+    /// Settings with a bounded range are rejected outside of it
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.key.as_os_str().is_empty() {
+            return Err(VoxoxideError::Config(
+                "no TLS key configured, set `key` in the YAML or pass --key".into(),
+            )
+            .into());
+        }
+        if self.cert.as_os_str().is_empty() {
+            return Err(VoxoxideError::Config(
+                "no TLS certificate configured, set `cert` in the YAML or pass --cert".into(),
+            )
+            .into());
         }
+        if let Some(level) = self.comfort_noise_level_db.filter(|level| *level > 0.0) {
+            return Err(VoxoxideError::Config(format!(
+                "comfort_noise_level_db is {level}, it is relative to the last active frame and must not be above 0"
+            ))
+            .into());
+        }
+        if self.duplicate_user_policy != self.get_duplicate_user_policy() {
+            tracing::warn!(
+                "duplicate_user_policy `replace` needs auth_secret, rejecting duplicates instead"
+            );
+        }
+        if let Some(complexity) = self.opus_complexity.filter(|c| *c > MAX_OPUS_COMPLEXITY) {
+            return Err(VoxoxideError::Config(format!(
+                "opus_complexity is {complexity}, it ranges from 0 to {MAX_OPUS_COMPLEXITY}"
+            ))
+            .into());
+        }
+        Ok(())
     }
     pub fn get_log_level(&self) -> Level {
         match self.log_level.as_str() {
@@ -136,4 +623,133 @@ impl AppConfig {
             _ => Level::INFO,
         }
     }
+    /// Settings derived from `target_latency_ms`, with explicitly configured values taking priority
+    pub fn get_latency_settings(&self) -> LatencySettings {
+        let mut settings = LatencySettings::from_target(
+            self.target_latency_ms.unwrap_or(DEFAULT_TARGET_LATENCY_MS),
+        );
+        if let Some(depth) = self.jitter_buffer_depth {
+            settings.jitter_buffer_depth = depth.max(1);
+        }
+        if let Some(ms) = self.keepalive_interval_ms {
+            settings.keepalive_interval = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.inactivity_timeout_ms {
+            settings.inactivity_timeout = Duration::from_millis(ms);
+        }
+        settings
+    }
+    pub fn get_codec_policy(&self, room_id: u32) -> Option<ArsCodecPolicy> {
+        self.rooms.get(&room_id)?.codec_policy
+    }
+    pub fn get_room_format(&self, room_id: u32) -> Option<ArsAudioFormat> {
+        self.rooms.get(&room_id)?.format
+    }
+    pub fn get_recording_consent(&self, room_id: u32) -> Option<RecordingConsent> {
+        self.rooms.get(&room_id)?.recording_consent
+    }
+    /// Rooms without a configured token have no moderators.
+    /// Compares digests in constant time, so neither the token's contents nor its length leak through timing
+    pub fn is_moderator_token(&self, room_id: u32, token: &str) -> bool {
+        let Some(expected) = self
+            .rooms
+            .get(&room_id)
+            .and_then(|room| room.moderator_token.as_deref())
+        else {
+            return false;
+        };
+        let expected = digest::digest(&digest::SHA256, expected.as_bytes());
+        let token = digest::digest(&digest::SHA256, token.as_bytes());
+        constant_time::verify_slices_are_equal(expected.as_ref(), token.as_ref()).is_ok()
+    }
+    /// Whether `token` is the configured admin token, always false without one.
+    /// Compared like [`Self::is_moderator_token`]
+    pub fn is_admin_token(&self, token: &str) -> bool {
+        let Some(expected) = &self.admin_token else {
+            return false;
+        };
+        let expected = digest::digest(&digest::SHA256, expected.0.as_bytes());
+        let token = digest::digest(&digest::SHA256, token.as_bytes());
+        constant_time::verify_slices_are_equal(expected.as_ref(), token.as_ref()).is_ok()
+    }
+    /// Only authenticated user ids may replace a session, duplicates are rejected without `auth_secret`
+    pub fn get_duplicate_user_policy(&self) -> DuplicateUserPolicy {
+        match self.auth_secret {
+            Some(_) => self.duplicate_user_policy,
+            None => DuplicateUserPolicy::Reject,
+        }
+    }
+    pub fn get_recording_dir(&self) -> PathBuf {
+        self.recording_dir.clone().unwrap_or_default()
+    }
+    pub fn get_wav_flush_interval(&self) -> Option<Duration> {
+        self.wav_flush_interval_ms.map(Duration::from_millis)
+    }
+    pub fn is_stateless_retry_enabled(&self) -> bool {
+        self.stateless_retry.unwrap_or(true)
+    }
+    pub fn is_codec_self_check_enabled(&self) -> bool {
+        self.codec_self_check.unwrap_or(true)
+    }
+    pub fn get_datagram_timeout(&self) -> Option<Duration> {
+        self.datagram_timeout_ms.map(Duration::from_millis)
+    }
+    pub fn get_auth_timeout(&self) -> Duration {
+        Duration::from_millis(self.auth_timeout_ms.unwrap_or(DEFAULT_AUTH_TIMEOUT_MS))
+    }
+    pub fn get_catch_up(&self) -> Option<Duration> {
+        self.catch_up_ms.map(Duration::from_millis)
+    }
+    pub fn get_max_buffered_packets(&self) -> usize {
+        self.max_buffered_packets
+            .unwrap_or(DEFAULT_MAX_BUFFERED_PACKETS)
+    }
+    pub fn get_max_decode_errors(&self) -> usize {
+        self.max_decode_errors.unwrap_or(DEFAULT_MAX_DECODE_ERRORS)
+    }
+    pub fn get_decode_error_window(&self) -> Duration {
+        Duration::from_millis(
+            self.decode_error_window_ms
+                .unwrap_or(DEFAULT_DECODE_ERROR_WINDOW_MS),
+        )
+    }
+    pub fn get_reconnect_token_capacity(&self) -> usize {
+        self.reconnect_token_capacity
+            .unwrap_or(DEFAULT_RECONNECT_TOKEN_CAPACITY)
+    }
+    pub fn get_reconnect_token_ttl(&self) -> Duration {
+        Duration::from_secs(
+            self.reconnect_token_ttl_secs
+                .unwrap_or(DEFAULT_RECONNECT_TOKEN_TTL_SECS),
+        )
+    }
+    pub fn get_roster_push_interval(&self) -> Duration {
+        Duration::from_millis(
+            self.roster_push_interval_ms
+                .unwrap_or(DEFAULT_ROSTER_PUSH_INTERVAL_MS),
+        )
+    }
+    pub fn get_max_session(&self) -> Option<Duration> {
+        self.max_session_secs.map(Duration::from_secs)
+    }
+    /// Never longer than the session itself
+    pub fn get_session_warning(&self) -> Duration {
+        let warning = Duration::from_secs(
+            self.session_warning_secs
+                .unwrap_or(DEFAULT_SESSION_WARNING_SECS),
+        );
+        self.get_max_session()
+            .map_or(warning, |limit| warning.min(limit))
+    }
+    /// The environment's Opus defaults, with explicitly configured values taking priority
+    pub fn get_opus_settings(&self) -> OpusSettings {
+        let mut settings = OpusSettings::for_environment(self.environment);
+        if let Some(application) = self.opus_application {
+            settings.application = application;
+        }
+        if let Some(complexity) = self.opus_complexity {
+            settings.complexity = complexity;
+        }
+        settings
+    }
 }
@@ -1,10 +1,13 @@
-use std::{fs::OpenOptions, path::PathBuf};
+use std::fs::{File, OpenOptions};
+use std::path::Path;
 
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{Layer, layer::SubscriberExt};
 
 use crate::common::app_config::AppConfig;
 
+/// Sets up stdout, console and, if configured, file logging.
+/// A log file that cannot be opened only disables file logging, startup carries on with a warning.
 pub fn setup_tracing_subscriber(config: &AppConfig) {
     let stdout_layer = tracing_subscriber::fmt::layer()
         .with_ansi(true)
@@ -14,21 +17,17 @@ pub fn setup_tracing_subscriber(config: &AppConfig) {
         .with_default_env()
         .spawn();
 
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(if let Some(f) = &config.log_file {
-            f.clone()
-        } else {
-            PathBuf::from("/dev/null")
-        })
-        .expect("Cannot open log file");
-    let file_layer = tracing_subscriber::fmt::layer()
-        .compact()
-        .with_ansi(config.log_file.is_none())
-        .with_writer(file)
-        .with_filter(LevelFilter::from_level(config.get_log_level()));
+    let (file, file_error) = match config.log_file.as_deref().map(open_log_file).transpose() {
+        Ok(file) => (file, None),
+        Err(e) => (None, Some(e)),
+    };
+    let file_layer = file.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .compact()
+            .with_ansi(false)
+            .with_writer(file)
+            .with_filter(LevelFilter::from_level(config.get_log_level()))
+    });
 
     let registry = tracing_subscriber::registry()
         .with(console_layer)
@@ -37,5 +36,19 @@ pub fn setup_tracing_subscriber(config: &AppConfig) {
 
     tracing::subscriber::set_global_default(registry).unwrap();
 
+    if let Some(e) = file_error {
+        tracing::warn!(
+            "Cannot open log file {:?}, logging to stdout only: {e}",
+            config.log_file
+        );
+    }
     tracing::debug!("Set up tracing subscriber");
 }
+
+/// Opens the log file for appending, creating it and any missing parent directories.
+pub fn open_log_file(path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
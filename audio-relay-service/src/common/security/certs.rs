@@ -1,42 +1,57 @@
 //! This module handles loading certificates for use in TLS.
 
-use std::fs;
-
 use anyhow::Context;
+use lib_common_voxoxide::types::PemSource;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, pem::PemObject};
 
 use crate::common::app_config::AppConfig;
+
+/// Loads the key and certificate chain from their configured [`PemSource`]s.
+/// Files ending in `.der` are read as DER, everything else as PEM. Only the certificate may be inline PEM.
 pub fn load_certs<'a>(
     config: &AppConfig,
 ) -> anyhow::Result<(Vec<CertificateDer<'a>>, PrivateKeyDer<'a>)> {
-    let options = config.clone();
-    tracing::debug!(
-        "Loading certificates from {:?} and {:?}",
-        &options.cert.to_str(),
-        &options.key.to_str()
-    );
-    let key = if options.key.extension().is_some_and(|x| x == "der") {
-        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
-            fs::read(options.key).context("failed to read private key file")?,
-        ))
+    let key_source = PemSource::new(&config.key);
+    let cert_source = PemSource::new(&config.cert);
+    tracing::debug!("Loading certificates from {cert_source} and {key_source}");
+    if let PemSource::Literal(_) = key_source {
+        // The config is logged on startup, the key would end up in the logs
+        anyhow::bail!(
+            "the private key can't be given inline, pass it as `env:NAME` or `-` instead"
+        );
+    }
+    let key = read(&key_source).context("failed to read private key")?;
+    let key = if is_der(&key_source) {
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key))
     } else {
-        PrivateKeyDer::from_pem_file(options.key)
-            .context("failed to read PEM from private key file")?
+        PrivateKeyDer::from_pem_slice(&key)
+            .with_context(|| format!("failed to read PEM private key from {key_source}"))?
     };
 
-    let cert_chain = if options.cert.extension().is_some_and(|x| x == "der") {
-        vec![CertificateDer::from(
-            fs::read(options.cert).context("failed to read certificate chain file")?,
-        )]
+    let cert = read(&cert_source).context("failed to read certificate chain")?;
+    let cert_chain = if is_der(&cert_source) {
+        vec![CertificateDer::from(cert)]
     } else {
-        CertificateDer::pem_file_iter(options.cert)
-            .context("failed to read PEM from certificate chain file")?
-            .collect::<Result<_, _>>()
-            .context("invalid PEM-encoded certificate")?
+        CertificateDer::pem_slice_iter(&cert)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid PEM-encoded certificate in {cert_source}"))?
     };
+    if cert_chain.is_empty() {
+        anyhow::bail!("no certificate in {cert_source}");
+    }
     tracing::info!(
         "Created certificate chain with {} certificate",
         cert_chain.len()
     );
     Ok((cert_chain, key))
 }
+
+fn read(source: &PemSource) -> anyhow::Result<Vec<u8>> {
+    source.read().with_context(|| format!("reading {source}"))
+}
+
+fn is_der(source: &PemSource) -> bool {
+    source
+        .file()
+        .is_some_and(|path| path.extension().is_some_and(|x| x == "der"))
+}
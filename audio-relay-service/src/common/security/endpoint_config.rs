@@ -1,32 +1,85 @@
 use std::sync::Arc;
 
-use quinn::{ServerConfig, crypto::rustls::QuicServerConfig};
+use lib_common_voxoxide::types::ARS_ALPN;
+use quinn::{ServerConfig, TransportConfig, crypto::rustls::QuicServerConfig};
+use rustls::crypto::CryptoProvider;
 use rustls::pki_types::PrivateKeyDer;
 
 use crate::common::app_config::AppConfig;
 
 pub fn create_server_config(
-    // AppConfig will probably be used when new options are added
-    _app_config: &AppConfig,
+    app_config: &AppConfig,
     certs: Vec<rustls::pki_types::CertificateDer<'static>>,
     key: PrivateKeyDer<'static>,
 ) -> anyhow::Result<ServerConfig> {
-    let mut server_crypto = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)?;
-    server_crypto.alpn_protocols = vec![b"hq-29".to_vec()];
+    // QUIC requires TLS 1.3 anyway, pinned so the policy doesn't depend on rustls defaults
+    let mut server_crypto =
+        rustls::ServerConfig::builder_with_provider(Arc::new(create_crypto_provider(app_config)?))
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+    server_crypto.alpn_protocols = vec![ARS_ALPN.to_vec()];
 
     let mut server_config =
         quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(server_crypto)?));
-    let transport_config = Arc::get_mut(&mut server_config.transport).unwrap();
-    // No unidirectional streams are needed.
-    transport_config.max_concurrent_uni_streams(0_u8.into());
+    server_config.transport_config(Arc::new(create_transport_config(app_config)?));
+    tracing::debug!("Created server config: {:?}", server_config);
+    Ok(server_config)
+}
+
+const INITIAL_CIPHER_SUITE: &str = "TLS13_AES_128_GCM_SHA256";
+
+/// The installed provider, restricted to the configured `cipher_suites` if any.
+/// Fails if a configured suite isn't a TLS 1.3 suite the provider offers,
+/// or if `TLS13_AES_128_GCM_SHA256` is missing since QUIC protects its initial packets with it.
+pub fn create_crypto_provider(app_config: &AppConfig) -> anyhow::Result<CryptoProvider> {
+    let Some(installed) = CryptoProvider::get_default() else {
+        anyhow::bail!("no rustls crypto provider installed");
+    };
+    let mut provider = installed.as_ref().clone();
+    if app_config.cipher_suites.is_empty() {
+        return Ok(provider);
+    }
+    let mut suites = Vec::with_capacity(app_config.cipher_suites.len());
+    for name in &app_config.cipher_suites {
+        let suite = provider
+            .cipher_suites
+            .iter()
+            .find(|suite| suite.tls13().is_some() && suite.suite().as_str() == Some(name.as_str()))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "cipher suite {name} is not a TLS 1.3 suite of the installed crypto provider"
+                )
+            })?;
+        suites.push(*suite);
+    }
+    if !app_config
+        .cipher_suites
+        .iter()
+        .any(|name| name == INITIAL_CIPHER_SUITE)
+    {
+        anyhow::bail!("cipher_suites must include {INITIAL_CIPHER_SUITE}, QUIC requires it");
+    }
+    provider.cipher_suites = suites;
+    Ok(provider)
+}
+
+/// Built on its own and only then shared, so there is no `Arc::get_mut` that could fail.
+pub fn create_transport_config(app_config: &AppConfig) -> anyhow::Result<TransportConfig> {
+    let mut transport_config = TransportConfig::default();
+    // Control messages after auth come one per unidirectional stream, or framed on the auth stream.
+    transport_config.max_concurrent_uni_streams(4_u8.into());
     // Big buffer just in case... there shouldn't be many simultaneous conenctions on one ars anyway
     transport_config.datagram_receive_buffer_size(Some(1024 * 5));
 
-    // streams for auth... receive_window needs to be at least auth request struct long
-    transport_config.max_concurrent_bidi_streams(5_u8.into());
+    // The auth stream, which may stay open, plus credit for one more. A second stream has to reach
+    // the server to be rejected with a protocol error, instead of waiting for credit forever.
+    // receive_window needs to be at least auth request struct long
+    transport_config.max_concurrent_bidi_streams(2_u8.into());
     transport_config.stream_receive_window(1024_u32.into());
-    tracing::debug!("Created server config: {:?}", server_config);
-    Ok(server_config)
+
+    let latency = app_config.get_latency_settings();
+    transport_config.keep_alive_interval(Some(latency.keepalive_interval));
+    transport_config.max_idle_timeout(Some(latency.inactivity_timeout.try_into()?));
+    Ok(transport_config)
 }
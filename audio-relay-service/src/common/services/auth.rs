@@ -1,33 +1,229 @@
-use lib_common_voxoxide::types::{ArsAuthError, ArsAuthRequest};
+use std::future::Future;
+
+use lib_common_voxoxide::types::{
+    ArsAudioFormat, ArsAuthError, ArsAuthRequest, ArsAuthResponse, Features, sanitize_display_name,
+};
 
 use crate::app::App;
+use crate::common::app_config::RecordingConsent;
+use crate::common::services::auth_tokens;
+use crate::common::services::users::UserConnection;
+use crate::vc::control_stream::ControlRecvStream;
+
+/// Optional features the relay implements
+pub const SERVER_FEATURES: Features = Features::FEC.union(Features::MIXING);
+/// Longest auth request accepted, in bytes
+pub const MAX_AUTH_REQUEST_LEN: usize = 1024;
+
+/// The parts of a connection the auth handshake talks through, so it can run against an in-memory double.
+pub trait AuthConnection: UserConnection + Clone + 'static {
+    type SendStream: AuthSendStream;
+    type RecvStream: AuthRecvStream;
+    /// Accepts the control stream, None if the peer never opens one
+    fn accept_control_stream(
+        &self,
+    ) -> impl Future<Output = Option<(Self::SendStream, Self::RecvStream)>> + Send;
+}
+
+pub trait AuthRecvStream: Send {
+    /// Reads the first length-prefixed message,
+    /// None if it fails, the stream ends early or the message is longer than `limit` bytes
+    fn read_request(&mut self, limit: usize) -> impl Future<Output = Option<Vec<u8>>> + Send;
+}
+
+pub trait AuthSendStream: Send {
+    /// Writes the whole response and finishes the stream
+    fn write_response(
+        &mut self,
+        response: &[u8],
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+impl AuthConnection for quinn::Connection {
+    type SendStream = quinn::SendStream;
+    type RecvStream = ControlRecvStream;
+
+    async fn accept_control_stream(&self) -> Option<(quinn::SendStream, ControlRecvStream)> {
+        let (send, recv) = self.accept_bi().await.ok()?;
+        Some((send, ControlRecvStream::new(recv)))
+    }
+}
+
+impl AuthRecvStream for ControlRecvStream {
+    async fn read_request(&mut self, limit: usize) -> Option<Vec<u8>> {
+        self.next_message(limit).await.ok().flatten()
+    }
+}
+
+impl AuthSendStream for quinn::SendStream {
+    async fn write_response(&mut self, response: &[u8]) -> anyhow::Result<()> {
+        self.write_all(response).await?;
+        self.finish()?;
+        Ok(())
+    }
+}
 
-pub async fn auth_user_for_session(
-    _app: &'static App,
-    connection: &mut quinn::Connection,
-) -> Result<(), ArsAuthError> {
-    // Accept first bidirectional stream (control)
-    let (mut send, mut recv) = connection
-        .accept_bi()
+/// What the auth handshake established about a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedMember {
+    pub room_id: u32,
+    pub moderator: bool,
+    /// Claimed in [`App::users`] until the connection ends
+    pub user_id: Option<u64>,
+    /// Admitted to the room, see [`crate::vc::group_voice_session::GroupVoiceSessions::admit_format`]
+    pub format: ArsAudioFormat,
+    /// Supported by both the member and the relay
+    pub features: Features,
+    /// Sanitized, shown to the room instead of the member id if set
+    pub display_name: Option<String>,
+    /// Whether the member agreed to being recorded
+    pub recording_consent: bool,
+    /// Declared on auth, reassigned if another member of the room used it
+    pub ssrc: Option<u32>,
+    /// Assigned on auth, see [`App::next_session_id`]
+    pub session_id: u32,
+    /// Handed to the client along with the session id, nobody else learns it
+    pub session_key: u32,
+}
+
+/// Receives the auth request on the first bidirectional stream (control) and checks what it can on its own.
+/// The display name comes back sanitized, the send half is where the response goes
+/// and the receive half carries any control messages following the request.
+pub async fn receive_auth_request<C: AuthConnection>(
+    connection: &C,
+) -> Result<(C::SendStream, C::RecvStream, ArsAuthRequest), ArsAuthError> {
+    let (send, mut recv) = connection
+        .accept_control_stream()
         .await
-        .map_err(|_| ArsAuthError::NoAuthRequestReceived)?;
+        .ok_or(ArsAuthError::NoAuthRequestReceived)?;
 
     let auth_request = recv
-        .read_to_end(1024)
+        .read_request(MAX_AUTH_REQUEST_LEN)
         .await
-        .map_err(|_| ArsAuthError::InvalidAuthRequestReceived)?; // too long - invalid request
+        .ok_or(ArsAuthError::InvalidAuthRequestReceived)?; // too long - invalid request
 
     tracing::debug!(
         "Auth payload from {}: {:?}",
         connection.remote_address(),
         String::from_utf8_lossy(&auth_request)
     );
-    let auth_request = serde_json::from_slice::<ArsAuthRequest>(&auth_request.as_slice())
+    let mut auth_request = serde_json::from_slice::<ArsAuthRequest>(auth_request.as_slice())
         .map_err(|_| ArsAuthError::InvalidAuthRequestReceived)?;
 
     tracing::info!("Auth request: {:?}", auth_request);
 
-    send.write_all(b"OK").await.unwrap();
-    send.finish().unwrap();
-    Ok(())
+    auth_request.display_name = match auth_request.display_name.as_deref() {
+        Some(name) => Some(sanitize_display_name(name).ok_or(ArsAuthError::InvalidDisplayName)?),
+        None => None,
+    };
+    Ok((send, recv, auth_request))
+}
+
+/// Sends the accepted handshake's response, failing only if the connection is gone
+pub async fn send_auth_response<S: AuthSendStream>(
+    send: &mut S,
+    response: &ArsAuthResponse,
+) -> anyhow::Result<()> {
+    send.write_response(&serde_json::to_vec(response)?).await
+}
+
+/// Random key handed to the client along with its session id
+fn session_key() -> u32 {
+    let mut key = [0u8; 4];
+    // The TLS handshake before this drew from the same source
+    aws_lc_rs::rand::fill(&mut key).expect("system randomness is unavailable");
+    u32::from_ne_bytes(key)
+}
+
+/// Returns the accepted member along with the rest of the auth stream, see [`ControlRecvStream`]
+pub async fn auth_user_for_session<C: AuthConnection>(
+    app: &App,
+    connection: &C,
+) -> Result<(AuthenticatedMember, C::RecvStream), ArsAuthError> {
+    let (mut send, recv, auth_request) = receive_auth_request(connection).await?;
+
+    let mut member = AuthenticatedMember {
+        room_id: auth_request.room_id,
+        moderator: auth_request
+            .moderator_token
+            .as_deref()
+            .is_some_and(|token| app.config.is_moderator_token(auth_request.room_id, token)),
+        user_id: auth_request.user_id,
+        format: auth_request.format.unwrap_or_default(),
+        features: auth_request.features.unwrap_or(SERVER_FEATURES) & SERVER_FEATURES,
+        display_name: auth_request.display_name.clone(),
+        recording_consent: auth_request.recording_consent,
+        ssrc: None,
+        session_id: 0,
+        session_key: 0,
+    };
+    if let Some(secret) = &app.config.auth_secret {
+        let authorized = match (auth_request.user_id, auth_request.token.as_deref()) {
+            (Some(user_id), Some(token)) => {
+                auth_tokens::verify(secret, user_id, auth_request.room_id, token)
+            }
+            _ => false,
+        };
+        if !authorized {
+            return Err(ArsAuthError::Unauthorized);
+        }
+    }
+    if !member.recording_consent
+        && app.config.get_recording_consent(member.room_id) == Some(RecordingConsent::Reject)
+    {
+        return Err(ArsAuthError::RecordingConsentRequired);
+    }
+    app.rooms.admit_member(member.room_id, member.moderator)?;
+    if let Some(user_id) = member.user_id {
+        app.users.check(user_id)?;
+    }
+    app.rooms.admit_format(
+        member.room_id,
+        member.format,
+        app.config.get_room_format(member.room_id),
+    )?;
+    // Reserved until the member joins, so nobody authenticating alongside gets the same one
+    if let Some(requested) = auth_request.ssrc {
+        member.ssrc = Some(app.rooms.resolve_ssrc(
+            member.room_id,
+            requested,
+            app.config.ssrc_collision_policy,
+        )?);
+    }
+    // Last, a replaced connection can't be reopened if admission failed after it
+    if let Some(user_id) = member.user_id
+        && let Err(e) = app.users.claim(user_id, connection)
+    {
+        if let Some(ssrc) = member.ssrc {
+            app.rooms.release_ssrc(member.room_id, ssrc);
+        }
+        return Err(e);
+    }
+    member.session_id = app.next_session_id();
+    member.session_key = session_key();
+    let response = ArsAuthResponse {
+        member_id: connection.stable_id() as u64,
+        session_id: member.session_id,
+        session_key: member.session_key,
+        moderator: member.moderator,
+        codec_policy: app
+            .config
+            .get_codec_policy(auth_request.room_id)
+            .map(|mut policy| {
+                // The room's FEC demand degrades to none for members that can't do it
+                if !member.features.contains(Features::FEC) && policy.fec.is_some() {
+                    policy.fec = Some(false);
+                }
+                policy
+            }),
+        features: member.features,
+        reassigned_ssrc: member.ssrc.filter(|ssrc| auth_request.ssrc != Some(*ssrc)),
+    };
+    if let Err(e) = send_auth_response(&mut send, &response).await {
+        tracing::debug!(
+            "Failed to send the auth response to {}: {e}",
+            connection.remote_address()
+        );
+    }
+    Ok((member, recv))
 }
@@ -0,0 +1,55 @@
+//! Connection lifecycle events for code embedding the relay, independent of logging.
+
+use std::net::SocketAddr;
+
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber, one that falls behind gets `RecvError::Lagged` instead of slowing connections down
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    ConnectionAccepted {
+        connection_id: usize,
+        remote: SocketAddr,
+    },
+    Authenticated {
+        connection_id: usize,
+    },
+    JoinedRoom {
+        connection_id: usize,
+        room_id: u32,
+    },
+    LeftRoom {
+        connection_id: usize,
+        room_id: u32,
+    },
+    ConnectionClosed {
+        connection_id: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct LifecycleEvents {
+    sender: broadcast::Sender<LifecycleEvent>,
+}
+
+impl Default for LifecycleEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::Sender::new(EVENT_CHANNEL_CAPACITY),
+        }
+    }
+}
+
+impl LifecycleEvents {
+    /// Receives every event emitted after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: LifecycleEvent) {
+        // Having no subscribers is fine
+        let _ = self.sender.send(event);
+    }
+}
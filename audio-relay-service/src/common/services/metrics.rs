@@ -0,0 +1,520 @@
+//! Metrics registry and a minimal HTTP endpoint serving it in the Prometheus text format.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::app::App;
+use crate::vc::jitter_buffer::{JitterBufferDump, JitterBufferProbe};
+use crate::vc::stats::{ConnectionStats, ConnectionStatsSnapshot};
+
+/// Upper bounds of the members-per-room histogram buckets, doubling from a lone member
+/// (nobody to talk to) and a one-on-one call up to rooms of 32. Larger rooms only show in `+Inf`
+pub const ROOM_SIZE_BUCKETS: [usize; 6] = [1, 2, 4, 8, 16, 32];
+
+/// Members per active room as a cumulative histogram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomSizeHistogram {
+    /// Rooms with at most `le` members for every bound of [`ROOM_SIZE_BUCKETS`]
+    pub buckets: Vec<(usize, u64)>,
+    /// Active rooms
+    pub count: u64,
+    /// Members over all rooms
+    pub sum: u64,
+}
+
+/// Live stats of every streaming connection, keyed by quinn's stable id.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    connections: Mutex<HashMap<usize, Arc<ConnectionStats>>>,
+    /// Jitter buffers of streaming connections, for the debug dump
+    jitter_buffers: Mutex<HashMap<usize, Arc<dyn JitterBufferProbe>>>,
+    /// Incoming connections asked to validate their address, before any of them has an id
+    stateless_retries: AtomicU64,
+    /// Members of every active room, updated on join and leave
+    room_sizes: Mutex<HashMap<u32, usize>>,
+}
+
+impl Metrics {
+    pub fn register_connection(&self, connection_id: usize) -> Arc<ConnectionStats> {
+        let stats = Arc::new(ConnectionStats::default());
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(connection_id, stats.clone());
+        stats
+    }
+
+    pub fn unregister_connection(&self, connection_id: usize) {
+        self.connections.lock().unwrap().remove(&connection_id);
+        self.jitter_buffers.lock().unwrap().remove(&connection_id);
+    }
+
+    pub fn add_stateless_retry(&self) {
+        self.stateless_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stateless_retries(&self) -> u64 {
+        self.stateless_retries.load(Ordering::Relaxed)
+    }
+
+    pub fn room_joined(&self, room_id: u32) {
+        *self.room_sizes.lock().unwrap().entry(room_id).or_default() += 1;
+    }
+
+    pub fn room_left(&self, room_id: u32) {
+        let mut sizes = self.room_sizes.lock().unwrap();
+        if let Some(size) = sizes.get_mut(&room_id) {
+            *size -= 1;
+            if *size == 0 {
+                sizes.remove(&room_id);
+            }
+        }
+    }
+
+    pub fn active_rooms(&self) -> usize {
+        self.room_sizes.lock().unwrap().len()
+    }
+
+    pub fn room_size_histogram(&self) -> RoomSizeHistogram {
+        let sizes = self.room_sizes.lock().unwrap();
+        RoomSizeHistogram {
+            buckets: ROOM_SIZE_BUCKETS
+                .iter()
+                .map(|le| {
+                    (
+                        *le,
+                        sizes.values().filter(|size| *size <= le).count() as u64,
+                    )
+                })
+                .collect(),
+            count: sizes.len() as u64,
+            sum: sizes.values().sum::<usize>() as u64,
+        }
+    }
+
+    /// Dropped again with [`Self::unregister_connection`]
+    pub fn register_jitter_buffer(&self, connection_id: usize, probe: Arc<dyn JitterBufferProbe>) {
+        self.jitter_buffers
+            .lock()
+            .unwrap()
+            .insert(connection_id, probe);
+    }
+
+    /// State of every registered jitter buffer sorted by connection id
+    pub fn jitter_dump(&self) -> Vec<JitterBufferDump> {
+        let mut dump: Vec<_> = self
+            .jitter_buffers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, probe)| JitterBufferDump {
+                connection_id: *id as u64,
+                state: probe.state(),
+            })
+            .collect();
+        dump.sort_by_key(|entry| entry.connection_id);
+        dump
+    }
+
+    /// Snapshots sorted by connection id
+    pub fn connection_snapshots(&self) -> Vec<(usize, ConnectionStatsSnapshot)> {
+        let mut snapshots: Vec<_> = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, stats)| (*id, stats.snapshot()))
+            .collect();
+        snapshots.sort_by_key(|(id, _)| *id);
+        snapshots
+    }
+
+    pub fn render(&self) -> String {
+        let snapshots = self.connection_snapshots();
+        let mut out = String::new();
+        write_header(
+            &mut out,
+            "ars_stateless_retries_total",
+            "counter",
+            "Incoming connections sent a stateless retry",
+        );
+        let _ = writeln!(
+            out,
+            "ars_stateless_retries_total {}",
+            self.stateless_retries()
+        );
+        write_header(
+            &mut out,
+            "ars_rooms_active",
+            "gauge",
+            "Rooms with at least one member",
+        );
+        let _ = writeln!(out, "ars_rooms_active {}", self.active_rooms());
+        let histogram = self.room_size_histogram();
+        write_header(
+            &mut out,
+            "ars_room_members",
+            "histogram",
+            "Members per active room",
+        );
+        for (le, rooms) in &histogram.buckets {
+            let _ = writeln!(out, "ars_room_members_bucket{{le=\"{le}\"}} {rooms}");
+        }
+        let _ = writeln!(
+            out,
+            "ars_room_members_bucket{{le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(out, "ars_room_members_sum {}", histogram.sum);
+        let _ = writeln!(out, "ars_room_members_count {}", histogram.count);
+        write_counter(
+            &mut out,
+            "ars_packets_received_total",
+            "Packets received and decoded normally",
+            &snapshots,
+            |s| s.packets_received,
+        );
+        write_counter(
+            &mut out,
+            "ars_packets_reordered_total",
+            "Duplicate or late packets dropped",
+            &snapshots,
+            |s| s.packets_reordered,
+        );
+        write_counter(
+            &mut out,
+            "ars_frames_recovered_fec_total",
+            "Lost frames recovered from in-band FEC",
+            &snapshots,
+            |s| s.frames_recovered_fec,
+        );
+        write_counter(
+            &mut out,
+            "ars_frames_concealed_plc_total",
+            "Lost frames concealed by PLC",
+            &snapshots,
+            |s| s.frames_concealed_plc,
+        );
+        write_counter(
+            &mut out,
+            "ars_datagrams_dropped_unauthenticated_total",
+            "Datagrams dropped because they arrived before auth completed",
+            &snapshots,
+            |s| s.datagrams_dropped_unauthenticated,
+        );
+        write_counter(
+            &mut out,
+            "ars_datagrams_dropped_unknown_ssrc_total",
+            "Datagrams dropped because their SSRC is not the connection's",
+            &snapshots,
+            |s| s.datagrams_dropped_unknown_ssrc,
+        );
+        write_counter(
+            &mut out,
+            "ars_datagrams_dropped_short_total",
+            "Datagrams dropped because they are too short to hold an RTP header",
+            &snapshots,
+            |s| s.datagrams_dropped_short,
+        );
+        write_counter(
+            &mut out,
+            "ars_keepalives_received_total",
+            "Keepalive datagrams of clients with no audio to send",
+            &snapshots,
+            |s| s.keepalives_received,
+        );
+        write_counter(
+            &mut out,
+            "ars_decode_errors_total",
+            "Datagrams that failed to parse or decode",
+            &snapshots,
+            |s| s.decode_errors,
+        );
+        write_counter(
+            &mut out,
+            "ars_bytes_received_total",
+            "Datagram bytes received",
+            &snapshots,
+            |s| s.bytes_received,
+        );
+        write_gauge(
+            &mut out,
+            "ars_ingress_bytes_per_second",
+            "Ingress rate over the last bandwidth window",
+            &snapshots,
+            |s| s.ingress_bytes_per_second,
+        );
+        write_gauge(
+            &mut out,
+            "ars_packets_buffered",
+            "Packets waiting in the jitter buffer",
+            &snapshots,
+            |s| s.packets_buffered,
+        );
+        write_counter(
+            &mut out,
+            "ars_packets_dropped_overflow_total",
+            "Oldest packets dropped because the jitter buffer was full",
+            &snapshots,
+            |s| s.packets_dropped_overflow,
+        );
+        out
+    }
+}
+
+fn write_counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    snapshots: &[(usize, ConnectionStatsSnapshot)],
+    value: impl Fn(&ConnectionStatsSnapshot) -> u64,
+) {
+    write_metric(out, name, "counter", help, snapshots, value);
+}
+
+fn write_gauge(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    snapshots: &[(usize, ConnectionStatsSnapshot)],
+    value: impl Fn(&ConnectionStatsSnapshot) -> u64,
+) {
+    write_metric(out, name, "gauge", help, snapshots, value);
+}
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    snapshots: &[(usize, ConnectionStatsSnapshot)],
+    value: impl Fn(&ConnectionStatsSnapshot) -> u64,
+) {
+    write_header(out, name, kind, help);
+    for (id, snapshot) in snapshots {
+        let _ = writeln!(out, "{name}{{connection=\"{id}\"}} {}", value(snapshot));
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Serves the metrics endpoint on `listen` until the app shuts down, see [`serve`].
+pub async fn serve_metrics(app: Arc<App>, listen: SocketAddr) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    tracing::info!("serving metrics on {}", listener.local_addr()?);
+    serve(app, listener).await
+}
+
+/// Answers requests on `listener` until the app shuts down:
+/// `GET /metrics` (or `/`) with the rendered metrics,
+/// `GET /debug/jitter` with every connection's jitter buffer state as JSON,
+/// and once `admin_token` is set `POST /admin/inject?room=<id>` playing the WAV file named by the body into the room.
+pub async fn serve(app: Arc<App>, listener: TcpListener) -> anyhow::Result<()> {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("Failed to accept a metrics connection: {e}");
+                        continue;
+                    }
+                };
+                app.spawn_task(serve_request(app.clone(), stream, peer));
+            }
+            _ = app.cancellation_token.cancelled() => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Answers one request, on the app's task tracker so shutdown waits for it
+async fn serve_request(app: Arc<App>, mut stream: TcpStream, peer: SocketAddr) {
+    let response = match read_request(&mut stream).await {
+        Some(request) => respond(&app, &request).await,
+        None => Response::text("400 Bad Request", "Malformed request\n".to_string()),
+    };
+    if let Err(e) = stream.write_all(response.to_http().as_bytes()).await {
+        tracing::debug!("Failed to serve metrics to {peer}: {e}");
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Requests larger than this, headers and body together, are refused
+const MAX_REQUEST_BYTES: usize = 8192;
+/// Time a client gets to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    /// Token of an `Authorization: Bearer` header
+    bearer: Option<String>,
+    body: String,
+}
+
+impl Request {
+    /// Value of `key` in the query string, undecoded
+    fn query_param(&self, key: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Reads one request up to the end of its body, None if it's malformed, too large or too slow
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let read = async {
+        loop {
+            if let Some(header_end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+                let content_length = head
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                    .map_or(Some(0), |(_, value)| value.trim().parse::<usize>().ok())?;
+                let body_start = header_end + 4;
+                let body_end = body_start
+                    .checked_add(content_length)
+                    .filter(|end| *end <= MAX_REQUEST_BYTES)?;
+                if buffer.len() >= body_end {
+                    let body = &buffer[body_start..body_end];
+                    return Some((head, String::from_utf8_lossy(body).into_owned()));
+                }
+            } else if buffer.len() > MAX_REQUEST_BYTES {
+                return None;
+            }
+            let read = stream.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+    };
+    let (head, body) = tokio::time::timeout(REQUEST_TIMEOUT, read).await.ok()??;
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+    let bearer = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.trim().strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    Some(Request {
+        method,
+        path,
+        query,
+        bearer,
+        body,
+    })
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body,
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Self::text(status, format!("{status}\n"))
+    }
+
+    fn to_http(&self) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// Routes a request by its method and path
+async fn respond(app: &Arc<App>, request: &Request) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/" | "/metrics") => {
+            Response::ok("text/plain; version=0.0.4", app.metrics.render())
+        }
+        ("GET", "/debug/jitter") => match serde_json::to_string(&app.dump_jitter_buffers()) {
+            Ok(dump) => Response::ok("application/json", dump),
+            Err(e) => {
+                tracing::error!("Failed to serialize the jitter buffer dump: {e}");
+                Response::error("500 Internal Server Error")
+            }
+        },
+        // Hidden unless enabled, so nothing tells it apart from an unknown path
+        (_, "/admin/inject") if app.config.admin_token.is_none() => {
+            Response::error("404 Not Found")
+        }
+        ("POST", "/admin/inject") => admin_inject(app, request).await,
+        (_, "/" | "/metrics" | "/debug/jitter" | "/admin/inject") => {
+            Response::error("405 Method Not Allowed")
+        }
+        _ => Response::error("404 Not Found"),
+    }
+}
+
+/// Plays the WAV file named by the body into the room of the `room` parameter, answers with the virtual member's id
+async fn admin_inject(app: &Arc<App>, request: &Request) -> Response {
+    if !request
+        .bearer
+        .as_deref()
+        .is_some_and(|token| app.config.is_admin_token(token))
+    {
+        return Response::error("401 Unauthorized");
+    }
+    let Some(room_id) = request
+        .query_param("room")
+        .and_then(|room| room.parse::<u32>().ok())
+    else {
+        return Response::text("400 Bad Request", "Missing room parameter\n".to_string());
+    };
+    let path = request.body.trim();
+    match app.inject_wav(room_id, path).await {
+        Ok(member_id) => {
+            tracing::info!("Admin injected {path:?} into room {room_id} as member {member_id}");
+            Response::ok(
+                "application/json",
+                serde_json::json!({ "member_id": member_id }).to_string(),
+            )
+        }
+        Err(e) => Response::text("400 Bad Request", format!("{e:#}\n")),
+    }
+}
@@ -1 +1,6 @@
 pub mod auth;
+pub mod auth_tokens;
+pub mod events;
+pub mod metrics;
+pub mod reconnect_tokens;
+pub mod users;
@@ -0,0 +1,146 @@
+//! Server-side storage of reconnect tokens, so a dropped client can resume its membership.
+//! Memory is bounded: tokens expire after a TTL and the least recently used one
+//! is evicted once the store is full, so a flood of connections can't grow it without limit.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::common::services::auth::AuthenticatedMember;
+
+/// Orders tokens in [`Tokens`]' indexes, the sequence number breaks ties between equal instants
+type Stamp = (Instant, u64);
+
+#[derive(Debug, Clone)]
+struct TokenEntry {
+    member: AuthenticatedMember,
+    issued: Stamp,
+    last_used: Stamp,
+}
+
+/// Tokens along with indexes by age and by use, so expiry and eviction don't scan every token
+#[derive(Debug, Default)]
+struct Tokens {
+    entries: HashMap<String, TokenEntry>,
+    /// Oldest issued first
+    by_issue: BTreeMap<Stamp, String>,
+    /// Least recently used first
+    by_use: BTreeMap<Stamp, String>,
+    next_stamp: u64,
+}
+
+impl Tokens {
+    fn stamp(&mut self, now: Instant) -> Stamp {
+        self.next_stamp += 1;
+        (now, self.next_stamp)
+    }
+
+    fn remove(&mut self, token: &str) -> Option<TokenEntry> {
+        let entry = self.entries.remove(token)?;
+        self.by_issue.remove(&entry.issued);
+        self.by_use.remove(&entry.last_used);
+        Some(entry)
+    }
+
+    /// Removes the token at the front of `index`, if any
+    fn remove_first(&mut self, index: fn(&Self) -> &BTreeMap<Stamp, String>) {
+        if let Some(token) = index(self).values().next().cloned() {
+            self.remove(&token);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReconnectTokenStore {
+    capacity: usize,
+    ttl: Duration,
+    tokens: Mutex<Tokens>,
+}
+
+impl ReconnectTokenStore {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            tokens: Mutex::default(),
+        }
+    }
+
+    /// Stores `token` for `member`, evicting expired tokens and then the least recently used one if full.
+    pub fn insert(&self, token: String, member: AuthenticatedMember) {
+        self.insert_at(token, member, Instant::now());
+    }
+
+    pub fn insert_at(&self, token: String, member: AuthenticatedMember, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.remove(&token);
+        if tokens.entries.len() >= self.capacity {
+            while tokens
+                .by_issue
+                .keys()
+                .next()
+                .is_some_and(|(issued, _)| now.duration_since(*issued) >= self.ttl)
+            {
+                tokens.remove_first(|tokens| &tokens.by_issue);
+            }
+        }
+        if tokens.entries.len() >= self.capacity {
+            tokens.remove_first(|tokens| &tokens.by_use);
+        }
+        let stamp = tokens.stamp(now);
+        tokens.by_issue.insert(stamp, token.clone());
+        tokens.by_use.insert(stamp, token.clone());
+        tokens.entries.insert(
+            token,
+            TokenEntry {
+                member,
+                issued: stamp,
+                last_used: stamp,
+            },
+        );
+    }
+
+    /// Returns the member a valid token was issued for, expired tokens are dropped on lookup.
+    pub fn lookup(&self, token: &str) -> Option<AuthenticatedMember> {
+        self.lookup_at(token, Instant::now())
+    }
+
+    pub fn lookup_at(&self, token: &str, now: Instant) -> Option<AuthenticatedMember> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let entry = tokens.entries.get(token)?;
+        if now.duration_since(entry.issued.0) >= self.ttl {
+            tokens.remove(token);
+            return None;
+        }
+        let (previous, member) = (entry.last_used, entry.member.clone());
+        let stamp = tokens.stamp(now);
+        tokens.by_use.remove(&previous);
+        tokens.by_use.insert(stamp, token.to_string());
+        if let Some(entry) = tokens.entries.get_mut(token) {
+            entry.last_used = stamp;
+        }
+        Some(member)
+    }
+
+    pub fn remove(&self, token: &str) -> Option<AuthenticatedMember> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .remove(token)
+            .map(|entry| entry.member)
+    }
+
+    /// Stored tokens, including expired ones not evicted yet
+    pub fn len(&self) -> usize {
+        self.tokens.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
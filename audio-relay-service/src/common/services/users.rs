@@ -0,0 +1,103 @@
+//! Tracks which connection each user id is authenticated on, so a user is never in the call twice.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use lib_common_voxoxide::types::{ArsAuthError, CloseCode};
+
+use crate::common::app_config::DuplicateUserPolicy;
+
+/// What the registry needs of a user's connection, so it can hold the in-memory doubles of the auth tests too
+pub trait UserConnection: Send + Sync {
+    /// Unique among open connections
+    fn stable_id(&self) -> usize;
+    fn remote_address(&self) -> SocketAddr;
+    fn is_open(&self) -> bool;
+    fn close(&self, code: CloseCode, reason: &[u8]);
+}
+
+impl UserConnection for quinn::Connection {
+    fn stable_id(&self) -> usize {
+        quinn::Connection::stable_id(self)
+    }
+    fn remote_address(&self) -> SocketAddr {
+        quinn::Connection::remote_address(self)
+    }
+    fn is_open(&self) -> bool {
+        self.close_reason().is_none()
+    }
+    fn close(&self, code: CloseCode, reason: &[u8]) {
+        quinn::Connection::close(self, code.code().into(), reason);
+    }
+}
+
+pub struct UserRegistry {
+    policy: DuplicateUserPolicy,
+    /// Connection of every user, keyed by user id
+    connections: Mutex<HashMap<u64, Box<dyn UserConnection>>>,
+}
+
+impl UserRegistry {
+    pub fn new(policy: DuplicateUserPolicy) -> Self {
+        Self {
+            policy,
+            connections: Mutex::default(),
+        }
+    }
+
+    /// Whether the user could claim a connection now, without changing anything.
+    /// Lets auth refuse a duplicate early, before [`Self::claim`] closes anything.
+    pub fn check(&self, user_id: u64) -> Result<(), ArsAuthError> {
+        let connections = self.connections.lock().unwrap();
+        let taken = connections
+            .get(&user_id)
+            .is_some_and(|existing| existing.is_open());
+        if taken && self.policy == DuplicateUserPolicy::Reject {
+            return Err(ArsAuthError::DuplicateUser);
+        }
+        Ok(())
+    }
+
+    /// Registers `connection` as the user's, resolving a conflict with an open connection per the policy.
+    /// Replacing closes the older connection, so only claim once every other admission step has passed.
+    pub fn claim<C: UserConnection + Clone + 'static>(
+        &self,
+        user_id: u64,
+        connection: &C,
+    ) -> Result<(), ArsAuthError> {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(existing) = connections.get(&user_id)
+            && existing.is_open()
+        {
+            match self.policy {
+                DuplicateUserPolicy::Reject => return Err(ArsAuthError::DuplicateUser),
+                DuplicateUserPolicy::Replace => {
+                    tracing::info!(
+                        "User {user_id} reconnected from {}, closing {}",
+                        connection.remote_address(),
+                        existing.remote_address()
+                    );
+                    existing.close(CloseCode::Replaced, b"replaced by a newer connection");
+                }
+            }
+        }
+        connections.insert(user_id, Box::new(connection.clone()));
+        Ok(())
+    }
+
+    /// Forgets the user, unless a newer connection replaced `connection_id` in the meantime.
+    pub fn release(&self, user_id: u64, connection_id: usize) {
+        let mut connections = self.connections.lock().unwrap();
+        if connections
+            .get(&user_id)
+            .is_some_and(|connection| connection.stable_id() == connection_id)
+        {
+            connections.remove(&user_id);
+        }
+    }
+
+    pub fn is_claimed(&self, user_id: u64) -> bool {
+        self.connections.lock().unwrap().contains_key(&user_id)
+    }
+}
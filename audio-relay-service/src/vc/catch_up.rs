@@ -0,0 +1,57 @@
+//! Recent mixed audio of a room, sent to members joining mid-conversation so they hear
+//! the last words before they came in instead of starting mid-sentence.
+//! The buffer holds a fixed number of frames and recycles the oldest one for every new frame,
+//! so a room never keeps more than its configured duration however long it runs.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// The last few mixed frames of a room, oldest first.
+pub struct CatchUpBuffer {
+    frames: VecDeque<Vec<i16>>,
+    capacity: usize,
+}
+
+impl CatchUpBuffer {
+    /// Keeps as many whole frames of `frame_duration` as fit into `duration`
+    pub fn new(duration: Duration, frame_duration: Duration) -> Self {
+        let capacity = (duration.as_micros() / frame_duration.as_micros().max(1)) as usize;
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Appends a frame, evicting the oldest one once the buffer is full
+    pub fn push(&mut self, samples: impl IntoIterator<Item = i16>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut frame = if self.frames.len() == self.capacity {
+            self.frames.pop_front().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        frame.clear();
+        frame.extend(samples);
+        self.frames.push_back(frame);
+    }
+
+    /// Buffered frames, oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &[i16]> {
+        self.frames.iter().map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Frames kept at most
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
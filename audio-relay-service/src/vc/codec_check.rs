@@ -0,0 +1,70 @@
+//! Startup self-check of the Opus codec.
+//! Before the endpoint accepts anyone, a tone is encoded at every format members may join with,
+//! the default one and each room's configured one, and decoded again through [`StreamDecoder`] like a member's stream.
+//! A codec library that fails to initialize or a room configured with a rate Opus can't take
+//! then stops startup with the room and format named, instead of failing every member on auth.
+
+use std::sync::Arc;
+
+use anyhow::{Context, bail};
+use lib_common_voxoxide::types::ArsAudioFormat;
+use rvoip_rtp_core::RtpPacket;
+
+use crate::common::app_config::{AppConfig, OpusSettings};
+use crate::vc::stats::ConnectionStats;
+use crate::vc::stream_decoder::{FRAME_SAMPLES, SAMPLE_RATE, StreamDecoder, channels_of};
+
+const TONE_HZ: f32 = 440.0;
+
+/// Round trips every format of `config`, the error names the first one that failed
+pub fn check(config: &AppConfig) -> anyhow::Result<()> {
+    let settings = config.get_opus_settings();
+    round_trip(ArsAudioFormat::default(), settings)
+        .context("Opus self-check failed for the default format")?;
+    for (room_id, room) in &config.rooms {
+        if let Some(format) = room.format {
+            round_trip(format, settings).with_context(|| {
+                format!(
+                    "Opus self-check failed for room {room_id} at {}Hz, {} channel(s)",
+                    format.sample_rate, format.channels
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes one 20ms frame of a tone in `format` and decodes it at the relay's rate
+pub fn round_trip(format: ArsAudioFormat, settings: OpusSettings) -> anyhow::Result<()> {
+    let channels = match format.channels {
+        1 => opus::Channels::Mono,
+        2 => opus::Channels::Stereo,
+        other => bail!("{other} channels are unsupported, Opus takes 1 or 2"),
+    };
+    let mut encoder =
+        opus::Encoder::new(format.sample_rate, channels, settings.application.into())?;
+    encoder.set_complexity(settings.complexity.into())?;
+
+    let frame_len = format.sample_rate as usize / 50;
+    let tone: Vec<i16> = (0..frame_len)
+        .flat_map(|i| {
+            let phase = i as f32 * TONE_HZ * std::f32::consts::TAU / format.sample_rate as f32;
+            std::iter::repeat_n((phase.sin() * 8000.0) as i16, format.channels.into())
+        })
+        .collect();
+    let mut output = vec![0u8; 4000];
+    let len = encoder.encode(&tone, &mut output)?;
+    let packet = RtpPacket::new_with_payload(111, 0, 0, 0, output[..len].to_vec().into());
+
+    let mut decoder: StreamDecoder =
+        StreamDecoder::with_channels(Arc::new(ConnectionStats::default()), channels_of(format))?;
+    let decoded = decoder.decode(&packet)?;
+    let expected = FRAME_SAMPLES * format.channels as usize;
+    if decoded.len() != expected {
+        bail!(
+            "decoded {} samples at {SAMPLE_RATE}Hz, expected {expected}",
+            decoded.len()
+        );
+    }
+    Ok(())
+}
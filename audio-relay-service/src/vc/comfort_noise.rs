@@ -0,0 +1,52 @@
+//! Comfort noise for gaps in a stream, eg. while a DTX sender transmits nothing.
+//! Digital silence between words sounds like a dropped call, so the gap is filled with white noise
+//! at a level derived from the energy of the last active frame, keeping the speaker's ambient level.
+
+use crate::vc::stream_decoder::Sample;
+
+pub struct ComfortNoise {
+    /// Noise RMS relative to the active frame RMS, linear
+    gain: f32,
+    /// RMS the noise is generated at, silent until a frame was observed
+    level: f32,
+    /// xorshift32 state, noise quality does not matter here
+    state: u32,
+}
+
+impl ComfortNoise {
+    /// `level_db` is the noise level relative to the last active frame, eg. `-30.0`
+    pub fn new(level_db: f32) -> Self {
+        Self {
+            gain: 10f32.powf(level_db / 20.0),
+            level: 0.0,
+            state: 0x9E37_79B9,
+        }
+    }
+
+    /// Measures an active frame, the following noise is generated relative to it
+    pub fn observe<S: Sample>(&mut self, frame: &[S]) {
+        if frame.is_empty() {
+            return;
+        }
+        let energy: f64 = frame
+            .iter()
+            .map(|sample| (sample.to_i16() as f64).powi(2))
+            .sum::<f64>()
+            / frame.len() as f64;
+        self.level = energy.sqrt() as f32 * self.gain;
+    }
+
+    /// Target RMS of the generated noise
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn next_sample(&mut self) -> i16 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        // Uniform in [-1, 1] has an RMS of 1/sqrt(3)
+        let uniform = self.state as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        (uniform * self.level * 3f32.sqrt()).round() as i16
+    }
+}
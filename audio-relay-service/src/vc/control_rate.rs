@@ -0,0 +1,45 @@
+//! Sliding window over a connection's control messages, so a member can't flood the room with them.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Window control messages are counted over
+pub const CONTROL_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct ControlRate {
+    max_messages: usize,
+    messages: VecDeque<Instant>,
+}
+
+impl ControlRate {
+    pub fn new(max_messages: usize) -> Self {
+        Self {
+            max_messages,
+            messages: VecDeque::with_capacity(max_messages),
+        }
+    }
+
+    /// Records a message now, returns false if it is over the limit.
+    /// Rejected messages don't count, so a flood still lets the allowed rate through.
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    pub fn allow_at(&mut self, now: Instant) -> bool {
+        while self
+            .messages
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= CONTROL_RATE_WINDOW)
+        {
+            self.messages.pop_front();
+        }
+        if self.messages.len() >= self.max_messages {
+            return false;
+        }
+        self.messages.push_back(now);
+        true
+    }
+}
@@ -0,0 +1,54 @@
+//! Receive half of the auth stream, split into length-prefixed messages,
+//! see [`frame_message`](lib_common_voxoxide::types::frame_message).
+//! The first message is the auth request, any further ones are control messages,
+//! handled like those sent on their own unidirectional stream.
+
+use anyhow::Result;
+use lib_common_voxoxide::types::{FRAME_HEADER_LEN, VoxoxideError, framed_len, split_frame};
+
+/// Longest control message accepted, in bytes
+pub const MAX_CONTROL_MESSAGE_LEN: usize = 1024;
+
+#[derive(Debug)]
+pub struct ControlRecvStream {
+    recv: quinn::RecvStream,
+    /// Received past the end of the last message
+    buffered: Vec<u8>,
+}
+
+impl ControlRecvStream {
+    pub fn new(recv: quinn::RecvStream) -> Self {
+        Self {
+            recv,
+            buffered: Vec::new(),
+        }
+    }
+
+    /// Reads the next whole message. None once the stream is finished and fully read.
+    /// Cancel safe, a partial message stays buffered.
+    pub async fn next_message(&mut self, limit: usize) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some((message, rest)) = split_frame(&self.buffered) {
+                let (message, consumed) = (message.to_vec(), self.buffered.len() - rest.len());
+                self.buffered.drain(..consumed);
+                return Ok(Some(message));
+            }
+            if let Some(len) = framed_len(&self.buffered).filter(|len| *len > limit) {
+                return Err(VoxoxideError::Protocol(format!(
+                    "control message of {len} bytes, longer than {limit}"
+                ))
+                .into());
+            }
+            match self.recv.read_chunk(FRAME_HEADER_LEN + limit, true).await? {
+                Some(chunk) => self.buffered.extend_from_slice(&chunk.bytes),
+                None if self.buffered.is_empty() => return Ok(None),
+                None => {
+                    return Err(VoxoxideError::Protocol(
+                        "auth stream finished in the middle of a message".into(),
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+}
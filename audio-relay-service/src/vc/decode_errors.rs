@@ -0,0 +1,41 @@
+//! Sliding window over a connection's decode errors.
+//! A single bad packet is tolerated, a sustained stream of them means the peer is broken or malicious.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+#[derive(Debug)]
+pub struct DecodeErrorWindow {
+    max_errors: usize,
+    window: Duration,
+    errors: VecDeque<Instant>,
+}
+
+impl DecodeErrorWindow {
+    pub fn new(max_errors: usize, window: Duration) -> Self {
+        Self {
+            max_errors,
+            window,
+            errors: VecDeque::with_capacity(max_errors + 1),
+        }
+    }
+
+    /// Records an error now, returns true once more than `max_errors` happened within the window.
+    pub fn record(&mut self) -> bool {
+        self.record_at(Instant::now())
+    }
+
+    pub fn record_at(&mut self, now: Instant) -> bool {
+        while self
+            .errors
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= self.window)
+        {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(now);
+        self.errors.len() > self.max_errors
+    }
+}
@@ -0,0 +1,68 @@
+//! A fixed set of threads that Opus decoding and room mixing can run on instead of the async runtime.
+//! Every connection decodes 50 frames a second inline on its task, and every mixed room encodes one frame
+//! per member per tick. With many connections that codec work takes the runtime workers away from
+//! the datagram and stream I/O they share, and the I/O latency grows with it.
+//! When `decode_threads` is set, each job is handed to the pool and the task awaits the result,
+//! so the runtime only waits on a channel.
+//!
+//! Handing off costs a channel round trip plus moving the decoder state and a copy of the PCM,
+//! which is only worth it once the codec work is a sizeable share of a worker's time.
+//! The crossover is where decoding alone takes 10% of one runtime worker: a connection decodes 50 frames
+//! a second, so at 2ms / (decode time of one frame) connections. The decode time depends on the CPU,
+//! `measure_decode_cost` in `tests/test_decode_pool.rs` (run it with `cargo test --release -- --ignored`)
+//! fails with the decode time and the crossover should a frame take more than a tenth of its duration.
+
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct DecodePool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl DecodePool {
+    /// Starts `threads` worker threads, at least one. They run until the pool is dropped
+    pub fn new(threads: usize) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("opus-decode-{index}"))
+                .spawn(move || {
+                    loop {
+                        // The lock is released before the job runs, so the other workers keep taking jobs
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            // A panicking job fails its caller, not the thread
+                            Ok(job) => {
+                                let _ = std::panic::catch_unwind(AssertUnwindSafe(job));
+                            }
+                            Err(_) => return,
+                        }
+                    }
+                })?;
+        }
+        Ok(Self { jobs: sender })
+    }
+
+    /// Runs `job` on one of the pool's threads and returns its result.
+    /// Jobs queue up while every thread is busy, fails only if the job panicked
+    pub async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = sender.send(job());
+        });
+        self.jobs
+            .send(job)
+            .map_err(|_| anyhow!("decode pool is shut down"))?;
+        receiver.await.map_err(|_| anyhow!("decode job panicked"))
+    }
+}
@@ -1,15 +1,803 @@
 //! This module contains the GroupVoiceSession struct.
 //! A Group Voice Session is created, when at least one user joins a room and creates a session.
 //! Other users joining the room will be assigned to this GroupVoiceSession, bringing their own session with them.
+//! The session is dropped again once its last member leaves.
+//! Audio is forwarded as is, unless the session grows past the mixing threshold (see [`crate::vc::mixer`]).
+//! Mixing only works if every member sends the same audio format, so a room is fixed to the format
+//! of its configuration or first member, and members declaring another one are refused on auth.
+//! Mixed sessions may keep their most recent mix (see [`crate::vc::catch_up`]) and send it to members
+//! joining late, forwarded sessions keep nothing since every stream goes out as is.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use rvoip_rtp_core::RtpPacket;
+use anyhow::bail;
+use bytes::Bytes;
+use lib_common_voxoxide::types::{
+    ArsAudioFormat, ArsAuthError, ArsControlMessage, CloseCode, Features,
+};
+use serde::Serialize;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::common::app_config::{
+    FRAME_DURATION_MS, OpusSettings, PushStrategy, SsrcCollisionPolicy,
+};
+use crate::common::services::auth::AuthenticatedMember;
+use crate::vc::catch_up::CatchUpBuffer;
+use crate::vc::mixer::{MIXER_SSRC, MixChannel, MixedStreamEncoder, Mixer, frame_samples};
+use crate::vc::push_schedule::PushSchedule;
+use crate::vc::room_events::{RoomEvent, RoomEventKind, RoomEventLog};
+use crate::vc::send_control_message;
 
 pub struct GroupVoiceSessionMember {
-    pub packet_buffer: Vec<RtpPacket>,
+    /// None for a virtual member whose audio the server streams itself, see [`crate::vc::injection`]
+    pub connection: Option<quinn::Connection>,
+    pub moderator: bool,
+    /// Muted by a moderator, the member's audio is not forwarded no matter what its client does
+    pub muted: bool,
+    /// Muted by the member itself, only shown to the others in the roster
+    pub self_muted: bool,
+    /// SSRC of the member's most recently registered stream, None until it sent any
+    pub ssrc: Option<u32>,
+    /// Whether the member's audio is currently being recorded
+    pub recording: bool,
+    /// Whether the member agreed to being recorded on auth
+    pub recording_consent: bool,
+    /// Sanitized name from the auth request
+    pub display_name: Option<String>,
+    /// Whether the member negotiated [`Features::MIXING`], others get forwarded streams even in mixed sessions
+    pub receives_mix: bool,
+    /// Reusable mixing buffers, sized to one frame when the member joins
+    channel: MixChannel,
+    /// The member's next mix, encoded and sent once the sessions are unlocked
+    outbox: Arc<Mutex<MixOutbox>>,
+}
+
+/// A member's mixed stream between the tick that mixed it and its encoding.
+/// Written under the sessions lock, only the session's own mixing loop encodes from it.
+#[derive(Default)]
+struct MixOutbox {
+    /// Recent room audio for a late joiner, sent ahead of its next mix
+    catch_up: Vec<Vec<i16>>,
+    /// The tick's mix, empty if the member heard nobody
+    frame: Vec<i16>,
+    /// Created once the member first receives a mix
+    encoder: Option<MixedStreamEncoder>,
+}
+
+impl MixOutbox {
+    /// Encodes and sends any catch-up audio, then the tick's mix or a silent frame.
+    /// Creates the encoder on first use, a frame failing to encode is skipped for this member only
+    fn send(
+        &mut self,
+        member_id: usize,
+        connection: &quinn::Connection,
+        frame_samples: usize,
+        opus: OpusSettings,
+    ) {
+        let encoder = match &mut self.encoder {
+            Some(encoder) => encoder,
+            None => match MixedStreamEncoder::new(frame_samples, opus) {
+                Ok(encoder) => self.encoder.insert(encoder),
+                Err(e) => {
+                    tracing::error!("Failed to create mix encoder for member {member_id}: {e}");
+                    return;
+                }
+            },
+        };
+        if !self.catch_up.is_empty() {
+            tracing::debug!(
+                "Sending {} frames of catch-up audio to member {member_id}",
+                self.catch_up.len()
+            );
+        }
+        for frame in self.catch_up.drain(..) {
+            send_mix(member_id, connection, encoder, Some(&frame));
+        }
+        let frame = (!self.frame.is_empty()).then_some(self.frame.as_slice());
+        send_mix(member_id, connection, encoder, frame);
+    }
+}
+
+/// Snapshot of a room for admin inspection, serializable to JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RoomInfo {
+    pub room_id: u32,
+    pub member_count: usize,
+    /// Whether the room is currently mixed instead of forwarded
+    pub mixing: bool,
+    /// Whether a moderator locked the room against new members
+    pub locked: bool,
+    /// Sorted by member id
+    pub members: Vec<MemberInfo>,
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemberInfo {
+    /// Connection id, as sent to the member in its auth response
+    pub member_id: u64,
+    pub display_name: Option<String>,
+    pub ssrc: Option<u32>,
+    pub moderator: bool,
+    pub muted: bool,
+    pub recording: bool,
+}
+
 pub struct GroupVoiceSession {
-    /// Members grouped by ssrc
-    _members: HashMap<u32, GroupVoiceSessionMember>,
+    /// Members keyed by connection id
+    members: HashMap<usize, GroupVoiceSessionMember>,
+    /// Cancelled when the session ends, stops its mixing and roster loops
+    ended: CancellationToken,
+    /// Joins, leaves and moderation, dropped with the session
+    events: RoomEventLog,
+    /// Sum of the current tick, reused across ticks
+    mixer: Mixer,
+    /// Recent full mixes for late joiners, None if catch-up is disabled
+    catch_up: Option<CatchUpBuffer>,
+    /// Locked by a moderator, lifted when the session ends
+    locked: bool,
+    /// Whether every member agreed to being recorded, updated as members come and go.
+    /// Shared with the members' playback loops, so they don't lock the sessions on every frame
+    all_consent: Arc<AtomicBool>,
+    /// When roster changes go out to the members
+    roster: PushSchedule,
+    /// Wakes the roster loop for changes it has to schedule a push for
+    roster_changed: Arc<Notify>,
+    /// Where roster sends run, see [`GroupVoiceSessions::with_task_tracker`]
+    tasks: TaskTracker,
+}
+
+impl GroupVoiceSession {
+    fn new(
+        frame_samples: usize,
+        catch_up: Option<Duration>,
+        roster: PushSchedule,
+        tasks: TaskTracker,
+    ) -> Self {
+        Self {
+            members: HashMap::new(),
+            ended: CancellationToken::new(),
+            events: RoomEventLog::default(),
+            mixer: Mixer::new(frame_samples),
+            catch_up: catch_up.map(|duration| {
+                CatchUpBuffer::new(duration, Duration::from_millis(FRAME_DURATION_MS))
+            }),
+            locked: false,
+            all_consent: Arc::new(AtomicBool::new(true)),
+            roster,
+            roster_changed: Arc::new(Notify::new()),
+            tasks,
+        }
+    }
+
+    /// Pushes the roster to every member but `gone` right away, or leaves it to the roster loop
+    /// if the push strategy holds changes back
+    fn roster_changed(&mut self, gone: Option<usize>) {
+        if self.roster.changed() {
+            broadcast_roster(self, gone);
+        } else {
+            self.roster_changed.notify_one();
+        }
+    }
+
+    fn update_consent(&self) {
+        let all_consent = self.members.values().all(|m| m.recording_consent);
+        self.all_consent.store(all_consent, Ordering::Relaxed);
+    }
+
+    /// Moves up to one frame of every member's pending audio into the mixer
+    fn mix(&mut self) {
+        self.mixer.reset();
+        for member in self.members.values_mut() {
+            self.mixer.add(&mut member.channel);
+        }
+    }
+
+    /// Mixes one frame of every member's pending audio, the room as heard from outside it.
+    /// Sums are clipped to the sample range, members with nothing pending add silence.
+    pub fn mix_frame(&mut self) -> Vec<i16> {
+        self.mix();
+        self.mixer.sum().collect()
+    }
+}
+
+/// Every active session, keyed by room id.
+pub struct GroupVoiceSessions {
+    sessions: Mutex<HashMap<u32, GroupVoiceSession>>,
+    /// Format of every room without a configured one, fixed from its first member until the session ends.
+    /// Always locked after `sessions`
+    formats: Mutex<HashMap<u32, ArsAudioFormat>>,
+    /// SSRCs handed out by [`Self::resolve_ssrc`] to members that have not joined yet, by room.
+    /// Always locked after `sessions`
+    reserved_ssrcs: Mutex<HashMap<u32, HashSet<u32>>>,
+    /// Sessions with more members than this are mixed instead of forwarded, never if not set
+    mixing_threshold: Option<usize>,
+    /// Samples per mixed frame, every session's buffers are sized to it
+    frame_samples: usize,
+    /// Recent mixed audio every session keeps for late joiners, none if not set
+    catch_up: Option<Duration>,
+    /// Settings of every member's mix encoder
+    opus: OpusSettings,
+    /// How and how often every session pushes its roster
+    roster_push: (PushStrategy, Duration),
+    /// Tracks the tasks sessions spawn to send rosters
+    tasks: TaskTracker,
+}
+
+impl GroupVoiceSessions {
+    pub fn new(mixing_threshold: Option<usize>) -> Self {
+        Self {
+            sessions: Mutex::default(),
+            formats: Mutex::default(),
+            reserved_ssrcs: Mutex::default(),
+            mixing_threshold,
+            frame_samples: frame_samples(FRAME_DURATION_MS),
+            catch_up: None,
+            opus: OpusSettings::default(),
+            roster_push: (PushStrategy::default(), Duration::ZERO),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    /// Spawns roster sends on `tasks`, the app's tracker so shutdown waits for them
+    pub fn with_task_tracker(mut self, tasks: TaskTracker) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    /// Pushes roster changes per `strategy`, see [`PushSchedule`]
+    pub fn with_roster_push(mut self, strategy: PushStrategy, interval: Duration) -> Self {
+        self.roster_push = (strategy, interval);
+        self
+    }
+
+    /// Whether sessions need a roster loop, see [`Self::push_due_roster`]
+    pub fn schedules_rosters(&self) -> bool {
+        self.roster_push.0 != PushStrategy::OnChange
+    }
+
+    /// Keeps up to `catch_up` of every mixed session's audio to send to members joining late
+    pub fn with_catch_up(mut self, catch_up: Option<Duration>) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+
+    /// Encodes every member's mix with `opus`
+    pub fn with_opus_settings(mut self, opus: OpusSettings) -> Self {
+        self.opus = opus;
+        self
+    }
+
+    /// Checks a member's format before it joins, fixing the room's format if it has none yet.
+    /// A `configured` format takes priority over the one of the first member.
+    pub fn admit_format(
+        &self,
+        room_id: u32,
+        format: ArsAudioFormat,
+        configured: Option<ArsAudioFormat>,
+    ) -> Result<(), ArsAuthError> {
+        let _sessions = self.sessions.lock().unwrap();
+        let fixed = match configured {
+            Some(configured) => configured,
+            None => *self
+                .formats
+                .lock()
+                .unwrap()
+                .entry(room_id)
+                .or_insert(format),
+        };
+        if fixed != format {
+            tracing::info!("Refusing {format:?} for room {room_id}, it is fixed to {fixed:?}");
+            return Err(ArsAuthError::FormatMismatch);
+        }
+        Ok(())
+    }
+
+    /// Returns a token for running the session's mixing and roster loops if this join created a session
+    /// that may need either.
+    /// The member's format has to be admitted by [`Self::admit_format`] first.
+    pub fn join(
+        &self,
+        member_id: usize,
+        connection: Option<quinn::Connection>,
+        member: &AuthenticatedMember,
+    ) -> Option<CancellationToken> {
+        let AuthenticatedMember {
+            room_id,
+            moderator,
+            format,
+            features,
+            recording_consent,
+            ssrc,
+            ..
+        } = *member;
+        let display_name = member.display_name.clone();
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(ssrc) = ssrc {
+            self.release_ssrc(room_id, ssrc);
+        }
+        // In case the session ended between admission and join
+        self.formats
+            .lock()
+            .unwrap()
+            .entry(room_id)
+            .or_insert(format);
+        let created = !sessions.contains_key(&room_id);
+        let session = sessions.entry(room_id).or_insert_with(|| {
+            let (strategy, interval) = self.roster_push;
+            GroupVoiceSession::new(
+                self.frame_samples,
+                self.catch_up,
+                PushSchedule::new(strategy, interval),
+                self.tasks.clone(),
+            )
+        });
+        let joined = GroupVoiceSessionMember {
+            connection,
+            moderator,
+            muted: false,
+            self_muted: false,
+            ssrc,
+            recording: false,
+            recording_consent,
+            display_name,
+            receives_mix: features.contains(Features::MIXING),
+            channel: MixChannel::new(self.frame_samples),
+            outbox: Arc::default(),
+        };
+        session.members.insert(member_id, joined);
+        session.update_consent();
+        session.roster_changed(None);
+        if self.is_mixing(session)
+            && let Some(catch_up) = &session.catch_up
+            && let Some(joined) = session.members.get_mut(&member_id)
+            && joined.receives_mix
+        {
+            // Goes out with the member's first mix, not here under the lock
+            joined.outbox.lock().unwrap().catch_up =
+                catch_up.frames().map(<[i16]>::to_vec).collect();
+        }
+        session.events.record(RoomEventKind::Joined {
+            member_id: member_id as u64,
+            moderator,
+        });
+        (created && (self.mixing_threshold.is_some() || self.schedules_rosters()))
+            .then(|| session.ended.clone())
+    }
+
+    fn is_mixing(&self, session: &GroupVoiceSession) -> bool {
+        self.mixing_threshold
+            .is_some_and(|threshold| session.members.len() > threshold)
+    }
+
+    pub fn leave(&self, room_id: u32, member_id: usize) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions.get_mut(&room_id) {
+            if session.members.remove(&member_id).is_some() {
+                session.events.record(RoomEventKind::Left {
+                    member_id: member_id as u64,
+                });
+                session.update_consent();
+                session.roster_changed(None);
+            }
+            if session.members.is_empty() {
+                session.ended.cancel();
+                sessions.remove(&room_id);
+                self.formats.lock().unwrap().remove(&room_id);
+            }
+        }
+    }
+
+    /// Sends a member's datagram to everyone else in the room, unless a moderator muted the member.
+    /// While the session is mixed only members receiving no mix get it.
+    pub fn forward(&self, room_id: u32, member_id: usize, datagram: &Bytes) {
+        let recipients: Vec<(usize, quinn::Connection)> = {
+            let sessions = self.sessions.lock().unwrap();
+            let Some(session) = sessions.get(&room_id) else {
+                return;
+            };
+            let mixing = self.is_mixing(session);
+            if session
+                .members
+                .get(&member_id)
+                .is_none_or(|member| member.muted)
+            {
+                return;
+            }
+            session
+                .members
+                .iter()
+                .filter(|(id, member)| **id != member_id && !(mixing && member.receives_mix))
+                .filter_map(|(id, member)| Some((*id, member.connection.clone()?)))
+                .collect()
+        };
+        for (id, connection) in recipients {
+            if let Err(e) = connection.send_datagram(datagram.clone()) {
+                tracing::debug!("Failed to forward audio to member {id}: {e}");
+            }
+        }
+    }
+
+    /// Queues a member's decoded audio for the next mix. Does nothing while the session is forwarded.
+    pub fn submit_frame(&self, room_id: u32, member_id: usize, pcm: &[i16]) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(&room_id) else {
+            return;
+        };
+        if !self.is_mixing(session) {
+            return;
+        }
+        let Some(member) = session.members.get_mut(&member_id) else {
+            return;
+        };
+        if member.muted {
+            return;
+        }
+        member.channel.push(pcm);
+    }
+
+    /// Mixes one frame of pending audio and sends every member its mix minus its own voice,
+    /// or a silent frame if it hears nobody. Returns false once the session is gone.
+    /// Only the mixing is done under the sessions lock, the mixes are encoded and sent after releasing it.
+    pub fn mix_tick(&self, room_id: u32) -> bool {
+        let recipients: Vec<(usize, quinn::Connection, Arc<Mutex<MixOutbox>>)> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let Some(session) = sessions.get_mut(&room_id) else {
+                return false;
+            };
+            if !self.is_mixing(session) {
+                return true;
+            }
+
+            session.mix();
+            let mixer = &session.mixer;
+            if let Some(catch_up) = session.catch_up.as_mut().filter(|_| mixer.speakers() > 0) {
+                catch_up.push(mixer.sum());
+            }
+            let mut recipients = Vec::new();
+            for (recipient, member) in session.members.iter_mut().filter(|(_, m)| m.receives_mix) {
+                let heard = mixer.mix_into(&mut member.channel);
+                let Some(connection) = &member.connection else {
+                    continue;
+                };
+                let mut outbox = member.outbox.lock().unwrap();
+                outbox.frame.clear();
+                if heard {
+                    outbox.frame.extend_from_slice(member.channel.mix());
+                }
+                drop(outbox);
+                recipients.push((*recipient, connection.clone(), member.outbox.clone()));
+            }
+            recipients
+        };
+        for (recipient, connection, outbox) in recipients {
+            outbox
+                .lock()
+                .unwrap()
+                .send(recipient, &connection, self.frame_samples, self.opus);
+        }
+        true
+    }
+
+    /// Notified on roster changes the room's push strategy holds back, None if the room has no session
+    pub fn roster_changes(&self, room_id: u32) -> Option<Arc<Notify>> {
+        let sessions = self.sessions.lock().unwrap();
+        Some(sessions.get(&room_id)?.roster_changed.clone())
+    }
+
+    /// Pushes the room's roster if its schedule says it's due.
+    /// Returns when to call again, None inside if only the next change schedules a push.
+    /// None once the session is gone
+    pub fn push_due_roster(&self, room_id: u32) -> Option<Option<Instant>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(&room_id)?;
+        if session.roster.poll() {
+            broadcast_roster(session, None);
+        }
+        Some(session.roster.next_push())
+    }
+
+    /// Mixes one frame of the room outside of its ticks, see [`GroupVoiceSession::mix_frame`].
+    /// Takes the audio the next tick would have mixed, None if the room has no session
+    pub fn mix_frame(&self, room_id: u32) -> Option<Vec<i16>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.get_mut(&room_id).map(GroupVoiceSession::mix_frame)
+    }
+
+    /// Applies a control message sent by `issuer`, only moderators may send any but their own mute state.
+    pub fn apply_control(
+        &self,
+        room_id: u32,
+        issuer: usize,
+        message: ArsControlMessage,
+    ) -> anyhow::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(&room_id) else {
+            bail!("room {room_id} has no active session");
+        };
+        let self_muting = matches!(message, ArsControlMessage::SetSelfMuted { .. });
+        if !self_muting && !session.members.get(&issuer).is_some_and(|m| m.moderator) {
+            bail!("member {issuer} is not a moderator of room {room_id}");
+        }
+        match &message {
+            &ArsControlMessage::SetSelfMuted { muted } => {
+                let Some(member) = session.members.get_mut(&issuer) else {
+                    bail!("member {issuer} is not in room {room_id}");
+                };
+                if member.self_muted == muted {
+                    return Ok(());
+                }
+                member.self_muted = muted;
+                session.events.record(RoomEventKind::SelfMuted {
+                    member_id: issuer as u64,
+                    muted,
+                });
+                session.roster_changed(None);
+            }
+            &ArsControlMessage::SetMemberMuted { member_id, muted } => {
+                let Some(member) = session.members.get_mut(&(member_id as usize)) else {
+                    bail!("member {member_id} is not in room {room_id}");
+                };
+                member.muted = muted;
+                session.events.record(RoomEventKind::MemberMuted {
+                    member_id,
+                    muted,
+                    by: issuer as u64,
+                });
+                session.roster_changed(None);
+            }
+            &ArsControlMessage::SetAllMuted { muted } => {
+                for member in session.members.values_mut().filter(|m| !m.moderator) {
+                    member.muted = muted;
+                }
+                session.events.record(RoomEventKind::AllMuted {
+                    muted,
+                    by: issuer as u64,
+                });
+                session.roster_changed(None);
+            }
+            ArsControlMessage::Kick { member_id, reason } => {
+                let Some(member) = session.members.get(&(*member_id as usize)) else {
+                    bail!("member {member_id} is not in room {room_id}");
+                };
+                match &member.connection {
+                    // The member leaves the session once its connection handler sees the close
+                    Some(connection) => {
+                        connection.close(CloseCode::Kicked.code().into(), reason.as_bytes())
+                    }
+                    // Its injection stops once it finds itself gone
+                    None => {
+                        session.members.remove(&(*member_id as usize));
+                        session.update_consent();
+                    }
+                }
+                session.events.record(RoomEventKind::Kicked {
+                    member_id: *member_id,
+                    by: issuer as u64,
+                    reason: reason.clone(),
+                });
+                session.roster_changed(Some(*member_id as usize));
+            }
+            &ArsControlMessage::SetRoomLocked { locked } => {
+                session.locked = locked;
+                session.events.record(RoomEventKind::RoomLocked {
+                    locked,
+                    by: issuer as u64,
+                });
+            }
+            ArsControlMessage::SessionEnding { .. } | ArsControlMessage::Roster { .. } => {
+                bail!("{message:?} is only sent by the server");
+            }
+        }
+        tracing::info!("Room {room_id}: applied {message:?} from member {issuer}");
+        Ok(())
+    }
+
+    pub fn set_member_ssrc(&self, room_id: u32, member_id: usize, ssrc: u32) {
+        self.update_member(room_id, member_id, |member| member.ssrc = Some(ssrc));
+    }
+
+    pub fn set_member_recording(&self, room_id: u32, member_id: usize, recording: bool) {
+        self.update_member(room_id, member_id, |member| member.recording = recording);
+    }
+
+    fn update_member(
+        &self,
+        room_id: u32,
+        member_id: usize,
+        update: impl FnOnce(&mut GroupVoiceSessionMember),
+    ) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(member) = sessions
+            .get_mut(&room_id)
+            .and_then(|session| session.members.get_mut(&member_id))
+        {
+            update(member);
+        }
+    }
+
+    /// Every active room sorted by id
+    pub fn describe(&self) -> Vec<RoomInfo> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut rooms: Vec<RoomInfo> = sessions
+            .iter()
+            .map(|(room_id, session)| {
+                let mut members: Vec<MemberInfo> = session
+                    .members
+                    .iter()
+                    .map(|(id, member)| MemberInfo {
+                        member_id: *id as u64,
+                        display_name: member.display_name.clone(),
+                        ssrc: member.ssrc,
+                        moderator: member.moderator,
+                        muted: member.muted,
+                        recording: member.recording,
+                    })
+                    .collect();
+                members.sort_by_key(|member| member.member_id);
+                RoomInfo {
+                    room_id: *room_id,
+                    member_count: members.len(),
+                    mixing: self.is_mixing(session),
+                    locked: session.locked,
+                    members,
+                }
+            })
+            .collect();
+        rooms.sort_by_key(|room| room.room_id);
+        rooms
+    }
+
+    /// The room's event log oldest first, None if the room has no active session
+    pub fn event_log(&self, room_id: u32) -> Option<Vec<RoomEvent>> {
+        let sessions = self.sessions.lock().unwrap();
+        Some(sessions.get(&room_id)?.events.entries())
+    }
+
+    /// Whether every member of the room agreed to being recorded, kept up to date for as long as
+    /// the session lasts. None without an active session
+    pub fn recording_consent(&self, room_id: u32) -> Option<Arc<AtomicBool>> {
+        let sessions = self.sessions.lock().unwrap();
+        Some(sessions.get(&room_id)?.all_consent.clone())
+    }
+
+    /// The SSRC a member joining `room_id` streams with, `requested` unless another member already does.
+    /// A clash is refused or resolved to the next SSRC nobody in the room uses, per `policy`.
+    /// The SSRC stays reserved until the member joins, or [`Self::release_ssrc`] if it never does.
+    pub fn resolve_ssrc(
+        &self,
+        room_id: u32,
+        requested: u32,
+        policy: SsrcCollisionPolicy,
+    ) -> Result<u32, ArsAuthError> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut reserved = self.reserved_ssrcs.lock().unwrap();
+        let in_use = |ssrc: u32| {
+            ssrc == MIXER_SSRC
+                || reserved
+                    .get(&room_id)
+                    .is_some_and(|reserved| reserved.contains(&ssrc))
+                || sessions.get(&room_id).is_some_and(|session| {
+                    session
+                        .members
+                        .values()
+                        .any(|member| member.ssrc == Some(ssrc))
+                })
+        };
+        let ssrc = if !in_use(requested) {
+            requested
+        } else {
+            match policy {
+                SsrcCollisionPolicy::Reject => {
+                    tracing::info!("Refusing SSRC {requested} for room {room_id}, it is in use");
+                    return Err(ArsAuthError::SsrcCollision);
+                }
+                SsrcCollisionPolicy::Reassign => {
+                    let mut ssrc = requested.wrapping_add(1);
+                    while in_use(ssrc) {
+                        ssrc = ssrc.wrapping_add(1);
+                    }
+                    tracing::info!("Reassigning SSRC {requested} in room {room_id} to {ssrc}");
+                    ssrc
+                }
+            }
+        };
+        reserved.entry(room_id).or_default().insert(ssrc);
+        Ok(ssrc)
+    }
+
+    /// Frees an SSRC reserved by [`Self::resolve_ssrc`] for a member that won't join after all
+    pub fn release_ssrc(&self, room_id: u32, ssrc: u32) {
+        let mut reserved = self.reserved_ssrcs.lock().unwrap();
+        if let Some(room) = reserved.get_mut(&room_id) {
+            room.remove(&ssrc);
+            if room.is_empty() {
+                reserved.remove(&room_id);
+            }
+        }
+    }
+
+    pub fn has_member(&self, room_id: u32, member_id: usize) -> bool {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(&room_id)
+            .is_some_and(|session| session.members.contains_key(&member_id))
+    }
+
+    /// Turns a joining member away from a locked room, moderators get in regardless
+    pub fn admit_member(&self, room_id: u32, moderator: bool) -> Result<(), ArsAuthError> {
+        let sessions = self.sessions.lock().unwrap();
+        if !moderator && sessions.get(&room_id).is_some_and(|session| session.locked) {
+            tracing::info!("Refusing a member of room {room_id}, it is locked");
+            return Err(ArsAuthError::RoomLocked);
+        }
+        Ok(())
+    }
+
+    /// None if the member is not in the room
+    pub fn is_muted(&self, room_id: u32, member_id: usize) -> Option<bool> {
+        let sessions = self.sessions.lock().unwrap();
+        Some(sessions.get(&room_id)?.members.get(&member_id)?.muted)
+    }
+}
+
+/// Tells every member but `gone` who is left in the room, by id and name, and who of them is muted
+fn broadcast_roster(session: &GroupVoiceSession, gone: Option<usize>) {
+    let present = || session.members.iter().filter(|(id, _)| Some(**id) != gone);
+    let mut listed: Vec<_> = present().collect();
+    listed.sort_unstable_by_key(|(id, _)| **id);
+    let roster = ArsControlMessage::Roster {
+        member_ids: listed.iter().map(|(id, _)| **id as u64).collect(),
+        muted: listed
+            .iter()
+            .filter(|(_, member)| member.muted || member.self_muted)
+            .map(|(id, _)| **id as u64)
+            .collect(),
+        display_names: listed
+            .iter()
+            .map(|(_, member)| member.display_name.clone())
+            .collect(),
+    };
+    for (id, member) in present() {
+        let Some(connection) = member.connection.clone() else {
+            continue;
+        };
+        let (id, roster) = (*id, roster.clone());
+        session.tasks.spawn(async move {
+            if let Err(e) = send_control_message(&connection, &roster).await {
+                tracing::debug!("Failed to send the roster to member {id}: {e}");
+            }
+        });
+    }
+}
+
+/// Encodes and sends one frame of the member's mix, silence if `frame` is None
+fn send_mix(
+    member_id: usize,
+    connection: &quinn::Connection,
+    encoder: &mut MixedStreamEncoder,
+    frame: Option<&[i16]>,
+) {
+    let packet = match frame {
+        Some(frame) => encoder.encode(frame),
+        None => encoder.encode_silence(),
+    };
+    // Only this member misses the frame, everyone else's mix has its own encoder
+    let packet = match packet {
+        Ok(packet) => packet,
+        Err(e) => {
+            tracing::warn!("Skipped a mix frame for member {member_id}: {e}");
+            return;
+        }
+    };
+    let datagram = packet.serialize().map_err(anyhow::Error::from);
+    let sent = datagram.and_then(|datagram| Ok(connection.send_datagram(datagram)?));
+    if let Err(e) = sent {
+        tracing::debug!("Failed to send mix to member {member_id}: {e}");
+    }
 }
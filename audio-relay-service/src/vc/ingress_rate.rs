@@ -0,0 +1,49 @@
+//! Sliding window over a connection's received bytes, for bandwidth accounting and caps.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Window the ingress rate is measured over
+pub const INGRESS_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct IngressRate {
+    window: Duration,
+    datagrams: VecDeque<(Instant, u64)>,
+    bytes_in_window: u64,
+}
+
+impl IngressRate {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            datagrams: VecDeque::new(),
+            bytes_in_window: 0,
+        }
+    }
+
+    /// Records `bytes` received now and returns the rate in bytes per second.
+    pub fn record(&mut self, bytes: u64) -> u64 {
+        self.record_at(bytes, Instant::now())
+    }
+
+    pub fn record_at(&mut self, bytes: u64, now: Instant) -> u64 {
+        self.datagrams.push_back((now, bytes));
+        self.bytes_in_window += bytes;
+        self.rate_at(now)
+    }
+
+    /// Rate in bytes per second over the window ending at `now`
+    pub fn rate_at(&mut self, now: Instant) -> u64 {
+        while let Some((at, bytes)) = self.datagrams.front().copied() {
+            if now.duration_since(at) < self.window {
+                break;
+            }
+            self.bytes_in_window -= bytes;
+            self.datagrams.pop_front();
+        }
+        (self.bytes_in_window as f64 / self.window.as_secs_f64()) as u64
+    }
+}
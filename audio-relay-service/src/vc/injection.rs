@@ -0,0 +1,164 @@
+//! Plays a WAV file into a room as a virtual member, for announcements and for exercising
+//! the members' receive path without a second client.
+//! The virtual member has no connection: its audio is encoded to Opus on the server and fed through
+//! the session like a member's decoded packets, so it is forwarded to everyone or mixed once the session is.
+//! It shows up in the room with the file name as its display name and leaves once the file ends,
+//! the app shuts down or a moderator kicks it.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use lib_common_voxoxide::types::{ArsAudioFormat, Features, sanitize_display_name};
+use rvoip_rtp_core::RtpPacket;
+
+use crate::app::App;
+use crate::common::app_config::{FRAME_DURATION_MS, SsrcCollisionPolicy};
+use crate::common::services::auth::AuthenticatedMember;
+use crate::vc::mixer::frame_samples;
+use crate::vc::stream_decoder::SAMPLE_RATE;
+
+/// SSRC an injected stream asks for, the next free one is used if a member of the room has it
+const INJECTED_SSRC: u32 = 0x7fff_0000;
+
+/// Longest file that can be injected, checked from its header before any of it is decoded
+pub const MAX_INJECTED_SECS: u32 = 600;
+
+/// Member ids of virtual members count down from the top, connection ids are addresses far below
+static NEXT_VIRTUAL_MEMBER_ID: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Joins `room_id` as a virtual member streaming the WAV file at `path` in real time.
+/// Returns the member id once it is in the room. The file has to be 48kHz, 16-bit or float,
+/// more than one channel is downmixed, and at most [`MAX_INJECTED_SECS`] long.
+/// The file is read on the blocking pool, it never stalls the runtime's workers.
+pub async fn inject_wav(app: &Arc<App>, room_id: u32, path: &Path) -> Result<usize> {
+    let file = path.to_path_buf();
+    let pcm = tokio::task::spawn_blocking(move || load_wav_mono(&file)).await??;
+    if pcm.is_empty() {
+        bail!("{path:?} contains no audio");
+    }
+    let format = ArsAudioFormat::default();
+    app.rooms
+        .admit_format(room_id, format, app.config.get_room_format(room_id))
+        .with_context(|| format!("room {room_id} can't take {path:?}"))?;
+    let mut encoder = opus::Encoder::new(
+        SAMPLE_RATE,
+        opus::Channels::Mono,
+        app.config.get_opus_settings().application.into(),
+    )?;
+    encoder.set_complexity(app.config.get_opus_settings().complexity.into())?;
+    // Reserved until the join below, nothing between them can fail
+    let ssrc = app
+        .rooms
+        .resolve_ssrc(room_id, INJECTED_SSRC, SsrcCollisionPolicy::Reassign)?;
+
+    let member_id = NEXT_VIRTUAL_MEMBER_ID.fetch_sub(1, Ordering::Relaxed);
+    let member = AuthenticatedMember {
+        room_id,
+        moderator: false,
+        user_id: None,
+        format,
+        features: Features::NONE,
+        display_name: path
+            .file_name()
+            .and_then(|name| sanitize_display_name(&name.to_string_lossy())),
+        // Audio the server plays itself never holds up a room's recording
+        recording_consent: true,
+        ssrc: Some(ssrc),
+        // Nobody connects as a virtual member, so there is no session to hand out
+        session_id: 0,
+        session_key: 0,
+    };
+    if let Some(session_ended) = app.rooms.join(member_id, None, &member) {
+        super::spawn_session_loops(app, room_id, session_ended);
+    }
+    tracing::info!("Injecting {path:?} into room {room_id} as member {member_id}, SSRC {ssrc}");
+    app.spawn_task(stream(app.clone(), room_id, member_id, ssrc, encoder, pcm));
+    Ok(member_id)
+}
+
+/// Feeds one frame per tick into the session until the audio ends or the member is gone
+async fn stream(
+    app: Arc<App>,
+    room_id: u32,
+    member_id: usize,
+    ssrc: u32,
+    mut encoder: opus::Encoder,
+    pcm: Vec<i16>,
+) {
+    let frame_samples = frame_samples(FRAME_DURATION_MS);
+    let mut interval = tokio::time::interval(Duration::from_millis(FRAME_DURATION_MS));
+    let mut output = vec![0u8; 4000];
+    for (sequence_number, chunk) in pcm.chunks(frame_samples).enumerate() {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = app.cancellation_token.cancelled() => break,
+        }
+        if !app.rooms.has_member(room_id, member_id) {
+            tracing::info!(
+                "Virtual member {member_id} left room {room_id}, stopping its injection"
+            );
+            return;
+        }
+        let mut frame = chunk.to_vec();
+        frame.resize(frame_samples, 0);
+        let len = match encoder.encode(&frame, &mut output) {
+            Ok(len) => len,
+            Err(e) => {
+                tracing::warn!("Skipped an injected frame for room {room_id}: {e}");
+                continue;
+            }
+        };
+        let packet = RtpPacket::new_with_payload(
+            111,
+            sequence_number as u16,
+            (sequence_number * frame_samples) as u32,
+            ssrc,
+            output[..len].to_vec().into(),
+        );
+        let datagram = match packet.serialize() {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                tracing::warn!("Skipped an injected frame for room {room_id}: {e}");
+                continue;
+            }
+        };
+        app.rooms.submit_frame(room_id, member_id, &frame);
+        app.rooms.forward(room_id, member_id, &datagram);
+    }
+    app.rooms.leave(room_id, member_id);
+    tracing::info!("Virtual member {member_id} finished playing into room {room_id}");
+}
+
+/// Reads the whole file as 48kHz mono, averaging the channels of each sample
+fn load_wav_mono(path: &Path) -> Result<Vec<i16>> {
+    let mut reader =
+        hound::WavReader::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    let spec = reader.spec();
+    if spec.sample_rate != SAMPLE_RATE {
+        bail!(
+            "{path:?} is {}Hz, injected audio has to be {SAMPLE_RATE}Hz",
+            spec.sample_rate
+        );
+    }
+    if reader.duration() > MAX_INJECTED_SECS * SAMPLE_RATE {
+        bail!("{path:?} is longer than the {MAX_INJECTED_SECS}s injected audio may last");
+    }
+    let samples: Vec<i16> = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 16) => reader.samples::<i16>().collect::<Result<_, _>>()?,
+        (hound::SampleFormat::Float, 32) => reader
+            .samples::<f32>()
+            .map(|sample| sample.map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+            .collect::<Result<_, _>>()?,
+        (format, bits) => bail!(
+            "{path:?} holds {bits}-bit {format:?} samples, injected audio has to be 16-bit or float"
+        ),
+    };
+    let channels = usize::from(spec.channels.max(1));
+    Ok(samples
+        .chunks(channels)
+        .map(|frame| (frame.iter().map(|s| i32::from(*s)).sum::<i32>() / frame.len() as i32) as i16)
+        .collect())
+}
@@ -0,0 +1,224 @@
+//! The jitter buffer putting a connection's packets back in order before they're decoded,
+//! and its state dumped on demand to tell network issues (losses, reorders) from buffer misconfiguration (depth, fill level).
+//! Whatever buffers a connection's packets registers a [`JitterBufferProbe`] with [`crate::common::services::metrics::Metrics`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rvoip_rtp_core::RtpPacket;
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::common::app_config::FRAME_DURATION_MS;
+use crate::vc::stats::ConnectionStats;
+use crate::vc::stream_decoder::MAX_CONCEALED_FRAMES;
+
+/// Reports the state of a connection's buffer for the dump
+pub trait JitterBufferProbe: Send + Sync + std::fmt::Debug {
+    fn state(&self) -> JitterBufferState;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct JitterBufferState {
+    /// Configured depth in frames
+    pub depth: usize,
+    /// Packets currently waiting for playout
+    pub buffered_packets: usize,
+    /// Frames that never arrived and were rebuilt from FEC or concealed
+    pub lost_packets: u64,
+    /// Packets that arrived after a later one and were dropped
+    pub reordered_packets: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct JitterBufferDump {
+    pub connection_id: u64,
+    #[serde(flatten)]
+    pub state: JitterBufferState,
+}
+
+/// What the buffer hands on for playout, in sequence order
+#[derive(Debug)]
+pub enum Playout {
+    /// The next packet to decode. Forwarding doesn't wait for the buffer, it happens on receipt
+    Packet { packet: RtpPacket },
+    /// Frames of `ssrc` that never arrived and are too many to rebuild from the next packet,
+    /// see [`crate::vc::stream_decoder::StreamDecoder::conceal_gap`]
+    Gap { ssrc: u32, frames: u64 },
+}
+
+/// Holds a connection's packets for `depth` frames and releases them in sequence order, each SSRC on its own.
+/// A packet is released once more than `depth` packets of its stream are waiting or it waited `depth` frames,
+/// so a reordered packet has that long to slot in before its successors.
+/// Duplicates and packets arriving after a later one was released are dropped and counted as reordered.
+/// At most `capacity` packets wait across all streams, so a flood of streams can't grow it without bound.
+/// Beyond that the packet that arrived first is dropped.
+#[derive(Debug)]
+pub struct JitterBuffer {
+    depth: usize,
+    max_wait: Duration,
+    capacity: usize,
+    streams: HashMap<u32, StreamBuffer>,
+    stats: Arc<ConnectionStats>,
+}
+
+#[derive(Debug, Default)]
+struct StreamBuffer {
+    /// By extended sequence number, with their arrival
+    packets: BTreeMap<u64, (RtpPacket, Instant)>,
+    /// Extended sequence number of the latest packet seen
+    highest: Option<u64>,
+    /// Extended sequence number due next, None until the first release
+    next: Option<u64>,
+}
+
+impl StreamBuffer {
+    /// Extends the 16 bit sequence number to the one closest to the latest packet
+    fn extend(&self, sequence: u16) -> u64 {
+        let Some(highest) = self.highest else {
+            // Far enough from 0 that packets just before the first one don't underflow
+            return (1 << 16) + sequence as u64;
+        };
+        let delta = sequence.wrapping_sub(highest as u16) as i16;
+        highest.saturating_add_signed(delta as i64)
+    }
+
+    fn release_first(&mut self, released: &mut Vec<Playout>) {
+        let Some((sequence, (packet, _))) = self.packets.pop_first() else {
+            return;
+        };
+        if let Some(next) = self.next {
+            let missing = sequence - next;
+            if missing > MAX_CONCEALED_FRAMES as u64 {
+                released.push(Playout::Gap {
+                    ssrc: packet.header.ssrc,
+                    frames: missing,
+                });
+            }
+        }
+        self.next = Some(sequence + 1);
+        released.push(Playout::Packet { packet });
+    }
+}
+
+impl JitterBuffer {
+    pub fn new(depth: usize, stats: Arc<ConnectionStats>) -> Self {
+        Self {
+            depth,
+            max_wait: Duration::from_millis(depth as u64 * FRAME_DURATION_MS),
+            capacity: usize::MAX,
+            streams: HashMap::new(),
+            stats,
+        }
+    }
+
+    /// Holds at most `capacity` packets, unbounded if not set
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Reports this buffer's state for the dump
+    pub fn probe(&self) -> Arc<dyn JitterBufferProbe> {
+        Arc::new(BufferProbe {
+            depth: self.depth,
+            stats: self.stats.clone(),
+        })
+    }
+
+    pub fn buffered_packets(&self) -> usize {
+        self.streams
+            .values()
+            .map(|stream| stream.packets.len())
+            .sum()
+    }
+
+    /// Buffers a packet that arrived at `now`, returns what it pushed out of the buffer
+    pub fn push(&mut self, packet: RtpPacket, now: Instant) -> Vec<Playout> {
+        let stream = self.streams.entry(packet.header.ssrc).or_default();
+        let sequence = stream.extend(packet.header.sequence_number);
+        if stream.next.is_some_and(|next| sequence < next) || stream.packets.contains_key(&sequence)
+        {
+            tracing::trace!(
+                "Dropping duplicate or late packet {}",
+                packet.header.sequence_number
+            );
+            self.stats.add_reordered(1);
+            return Vec::new();
+        }
+        stream.highest = stream.highest.max(Some(sequence));
+        stream.packets.insert(sequence, (packet, now));
+
+        let mut released = Vec::new();
+        while stream.packets.len() > self.depth {
+            stream.release_first(&mut released);
+        }
+        while self.buffered_packets() > self.capacity {
+            self.drop_oldest();
+        }
+        self.update_buffered();
+        released
+    }
+
+    /// Releases the packets that waited their full depth by `now`, called every frame
+    pub fn release_expired(&mut self, now: Instant) -> Vec<Playout> {
+        let mut released = Vec::new();
+        for stream in self.streams.values_mut() {
+            while stream
+                .packets
+                .first_key_value()
+                .is_some_and(|(_, (_, arrival))| now.duration_since(*arrival) >= self.max_wait)
+            {
+                stream.release_first(&mut released);
+            }
+        }
+        self.update_buffered();
+        released
+    }
+
+    /// Drops the packet that arrived first, it's left to the decoder to conceal
+    fn drop_oldest(&mut self) {
+        let oldest = self
+            .streams
+            .values_mut()
+            .filter_map(|stream| {
+                let arrival = stream.packets.first_key_value()?.1.1;
+                Some((arrival, stream))
+            })
+            .min_by_key(|(arrival, _)| *arrival);
+        if let Some((_, stream)) = oldest
+            && let Some((_, (packet, _))) = stream.packets.pop_first()
+        {
+            tracing::trace!(
+                "Jitter buffer full, dropping packet {}",
+                packet.header.sequence_number
+            );
+            self.stats.add_dropped_overflow(1);
+        }
+    }
+
+    fn update_buffered(&self) {
+        self.stats
+            .set_packets_buffered(self.buffered_packets() as u64);
+    }
+}
+
+/// Reads everything but the depth from the connection's stats
+#[derive(Debug)]
+struct BufferProbe {
+    depth: usize,
+    stats: Arc<ConnectionStats>,
+}
+
+impl JitterBufferProbe for BufferProbe {
+    fn state(&self) -> JitterBufferState {
+        let snapshot = self.stats.snapshot();
+        JitterBufferState {
+            depth: self.depth,
+            buffered_packets: snapshot.packets_buffered as usize,
+            lost_packets: snapshot.frames_recovered_fec + snapshot.frames_concealed_plc,
+            reordered_packets: snapshot.packets_reordered,
+        }
+    }
+}
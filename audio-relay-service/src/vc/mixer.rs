@@ -0,0 +1,278 @@
+//! Server-side mixing for rooms too large to forward every stream.
+//! Forwarding needs no codec work but sends n·(n-1) datagrams per frame.
+//! Mixing sums every speaker once, subtracts each speaker from the sum ("mix minus")
+//! and encodes once per recipient, for n datagrams per frame. Speakers are decoded anyway for recording.
+//! Mixing trades n·(n-1) datagram sends for n Opus encodes, so where `mixing_threshold` pays off
+//! depends on the host and should be measured there.
+//! Mixes are mono, stereo speakers are downmixed before they reach the mixer (see [`downmix`]).
+
+use std::borrow::Cow;
+
+use rvoip_rtp_core::RtpPacket;
+
+use crate::common::app_config::OpusSettings;
+use crate::vc::stream_decoder::{FRAME_SAMPLES, SAMPLE_RATE};
+
+/// SSRC of mixed streams sent by the server
+pub const MIXER_SSRC: u32 = 0;
+/// Frames buffered per speaker between mixes, older audio is dropped
+pub const MAX_PENDING_FRAMES: usize = 5;
+/// Frames failing to encode in a row before a recipient's Opus encoder is recreated
+pub const MIX_ENCODER_RESET_ERRORS: u32 = 3;
+
+/// Samples in one frame of `frame_duration_ms`
+pub const fn frame_samples(frame_duration_ms: u64) -> usize {
+    (SAMPLE_RATE as u64 * frame_duration_ms / 1000) as usize
+}
+
+/// Averages interleaved `channels` down to the mono the mixer works in
+pub fn downmix(pcm: &[i16], channels: usize) -> Cow<'_, [i16]> {
+    if channels <= 1 {
+        return Cow::Borrowed(pcm);
+    }
+    Cow::Owned(
+        pcm.chunks(channels)
+            .map(|frame| {
+                (frame.iter().map(|s| i32::from(*s)).sum::<i32>() / channels as i32) as i16
+            })
+            .collect(),
+    )
+}
+
+/// A member's audio on its way through the mixer. Its buffers are sized to one frame on creation
+/// and reused on every tick, so steady-state mixing does not allocate.
+pub struct MixChannel {
+    /// Decoded audio waiting for the next mix
+    pending: Vec<i16>,
+    /// The member's own frame of the current tick, empty if it had nothing pending
+    frame: Vec<i16>,
+    /// What the member hears on the current tick
+    mix: Vec<i16>,
+    frame_samples: usize,
+}
+
+impl MixChannel {
+    pub fn new(frame_samples: usize) -> Self {
+        Self {
+            // One frame of headroom, so pushing and trimming never grows it
+            pending: Vec::with_capacity((MAX_PENDING_FRAMES + 1) * frame_samples),
+            frame: Vec::with_capacity(frame_samples),
+            mix: Vec::with_capacity(frame_samples),
+            frame_samples,
+        }
+    }
+
+    /// Queues decoded audio, dropping the oldest beyond [`MAX_PENDING_FRAMES`]
+    pub fn push(&mut self, pcm: &[i16]) {
+        self.pending.extend_from_slice(pcm);
+        let excess = self
+            .pending
+            .len()
+            .saturating_sub(MAX_PENDING_FRAMES * self.frame_samples);
+        self.pending.drain(..excess);
+    }
+
+    /// The mix of the last [`Mixer::mix_into`]
+    pub fn mix(&self) -> &[i16] {
+        &self.mix
+    }
+}
+
+/// Sums a tick's frames once, then derives each channel's mix minus its own frame.
+/// Call [`Mixer::add`] on every channel before [`Mixer::mix_into`] on any.
+pub struct Mixer {
+    sum: Vec<i32>,
+    speakers: usize,
+}
+
+impl Mixer {
+    pub fn new(frame_samples: usize) -> Self {
+        Self {
+            sum: vec![0; frame_samples],
+            speakers: 0,
+        }
+    }
+
+    /// Starts a new tick
+    pub fn reset(&mut self) {
+        self.sum.fill(0);
+        self.speakers = 0;
+    }
+
+    /// Moves up to one frame of the channel's pending audio into the sum
+    pub fn add(&mut self, channel: &mut MixChannel) {
+        let len = channel.pending.len().min(self.sum.len());
+        channel.frame.clear();
+        channel.frame.extend(channel.pending.drain(..len));
+        if channel.frame.is_empty() {
+            return;
+        }
+        self.speakers += 1;
+        for (total, sample) in self.sum.iter_mut().zip(&channel.frame) {
+            *total += *sample as i32;
+        }
+    }
+
+    /// Channels that contributed audio this tick
+    pub fn speakers(&self) -> usize {
+        self.speakers
+    }
+
+    /// The tick's full mix, as heard by someone who is not speaking
+    pub fn sum(&self) -> impl Iterator<Item = i16> + '_ {
+        self.sum
+            .iter()
+            .map(|total| (*total).clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+    }
+
+    /// Writes the channel's mix, returns false if nobody but the channel itself spoke this tick.
+    /// Frames shorter than a full frame are padded with silence.
+    pub fn mix_into(&self, channel: &mut MixChannel) -> bool {
+        if self.speakers <= usize::from(!channel.frame.is_empty()) {
+            return false;
+        }
+        channel.mix.clear();
+        channel
+            .mix
+            .extend(self.sum.iter().enumerate().map(|(i, total)| {
+                let own = channel.frame.get(i).copied().unwrap_or(0) as i32;
+                (total - own).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+            }));
+        true
+    }
+}
+
+/// Returns what each recipient hears: the sum of every speaker's frame except its own.
+/// Frames shorter than [`FRAME_SAMPLES`] are padded with silence,
+/// recipients without anyone else speaking are left out.
+pub fn mix_minus(frames: &[(usize, &[i16])], recipients: &[usize]) -> Vec<(usize, Vec<i16>)> {
+    let mut mixer = Mixer::new(FRAME_SAMPLES);
+    let mut channels: Vec<(usize, MixChannel)> = recipients
+        .iter()
+        .map(|recipient| {
+            let mut channel = MixChannel::new(FRAME_SAMPLES);
+            if let Some((_, frame)) = frames.iter().find(|(speaker, _)| speaker == recipient) {
+                channel.push(frame);
+            }
+            (*recipient, channel)
+        })
+        .collect();
+    for (_, channel) in channels.iter_mut() {
+        mixer.add(channel);
+    }
+    // Speakers that are not recipients still count towards the sum
+    for (_, frame) in frames
+        .iter()
+        .filter(|(speaker, _)| !recipients.contains(speaker))
+    {
+        let mut channel = MixChannel::new(FRAME_SAMPLES);
+        channel.push(frame);
+        mixer.add(&mut channel);
+    }
+    channels
+        .into_iter()
+        .filter_map(|(recipient, mut channel)| {
+            mixer
+                .mix_into(&mut channel)
+                .then_some((recipient, channel.mix))
+        })
+        .collect()
+}
+
+/// Encodes the mixed stream of one recipient, as [`MIXER_SSRC`].
+/// The session's mixing timer feeds it one frame per tick, silent ones included,
+/// so sequence numbers and timestamps step steadily no matter when speakers' packets arrive.
+/// A frame that fails to encode is skipped but still advances both, so the recipient conceals it as a loss.
+pub struct MixedStreamEncoder {
+    encoder: opus::Encoder,
+    settings: OpusSettings,
+    sequence_number: u16,
+    timestamp: u32,
+    output: Vec<u8>,
+    /// One frame of silence, encoded on ticks where the recipient hears nobody.
+    /// Its length is also what the timestamp advances by per frame.
+    silence: Vec<i16>,
+    /// Frames failed in a row, the Opus encoder is recreated at [`MIX_ENCODER_RESET_ERRORS`]
+    consecutive_errors: u32,
+    restarts: u32,
+}
+
+impl MixedStreamEncoder {
+    pub fn new(frame_samples: usize, settings: OpusSettings) -> anyhow::Result<Self> {
+        Ok(Self {
+            encoder: Self::opus_encoder(settings)?,
+            settings,
+            sequence_number: 0,
+            timestamp: 0,
+            output: vec![0u8; 4000],
+            silence: vec![0; frame_samples],
+            consecutive_errors: 0,
+            restarts: 0,
+        })
+    }
+
+    fn opus_encoder(settings: OpusSettings) -> anyhow::Result<opus::Encoder> {
+        let mut encoder = opus::Encoder::new(
+            SAMPLE_RATE,
+            opus::Channels::Mono,
+            settings.application.into(),
+        )?;
+        encoder.set_complexity(settings.complexity.into())?;
+        Ok(encoder)
+    }
+
+    /// Times the Opus encoder was recreated after persistent failures
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Encodes a silent frame, keeping the stream continuous while the mix is silent
+    pub fn encode_silence(&mut self) -> anyhow::Result<RtpPacket> {
+        let encoded = self.encoder.encode(&self.silence, &mut self.output);
+        self.packetize(encoded)
+    }
+
+    pub fn encode(&mut self, frame: &[i16]) -> anyhow::Result<RtpPacket> {
+        let encoded = self.encoder.encode(frame, &mut self.output);
+        self.packetize(encoded)
+    }
+
+    /// Steps the stream by one frame, whether or not it encoded
+    fn packetize(&mut self, encoded: Result<usize, opus::Error>) -> anyhow::Result<RtpPacket> {
+        let sequence_number = self.sequence_number;
+        let timestamp = self.timestamp;
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(self.silence.len() as u32);
+        let len = match encoded {
+            Ok(len) => len,
+            Err(e) => {
+                self.on_error();
+                return Err(e.into());
+            }
+        };
+        self.consecutive_errors = 0;
+        Ok(RtpPacket::new_with_payload(
+            111,
+            sequence_number,
+            timestamp,
+            MIXER_SSRC,
+            self.output[..len].to_vec().into(),
+        ))
+    }
+
+    /// Recreates the Opus encoder once failures persist, its state may be what keeps failing
+    fn on_error(&mut self) {
+        self.consecutive_errors += 1;
+        if self.consecutive_errors < MIX_ENCODER_RESET_ERRORS {
+            return;
+        }
+        match Self::opus_encoder(self.settings) {
+            Ok(encoder) => {
+                self.encoder = encoder;
+                self.consecutive_errors = 0;
+                self.restarts += 1;
+            }
+            Err(e) => tracing::error!("Failed to recreate mix encoder: {e}"),
+        }
+    }
+}
@@ -1,83 +1,659 @@
 //! Re-exports for voice-chat module handling audio parsing.
 
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use crate::app::App;
+use crate::common::app_config::{
+    ControlRateEnforcement, FRAME_DURATION_MS, MAX_PRE_AUTH_DATAGRAMS, PreAuthDatagrams,
+    RecordingConsent,
+};
+use crate::common::services::auth::AuthenticatedMember;
+use crate::common::services::events::LifecycleEvent;
 use anyhow::Result;
+use bytes::Bytes;
+use lib_common_voxoxide::types::{
+    ArsAuthError, ArsControlMessage, CloseCode, KEEPALIVE_DATAGRAM, VoxoxideError,
+};
 use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::vc::control_rate::ControlRate;
+use crate::vc::control_stream::{ControlRecvStream, MAX_CONTROL_MESSAGE_LEN};
+use crate::vc::decode_errors::DecodeErrorWindow;
+use crate::vc::ingress_rate::{INGRESS_RATE_WINDOW, IngressRate};
+use crate::vc::jitter_buffer::{JitterBuffer, Playout};
+use crate::vc::mixer::downmix;
+use crate::vc::recording::Recording;
+use crate::vc::ssrc_filter::{SsrcCheck, SsrcFilter};
+use crate::vc::stats::ConnectionStats;
+use crate::vc::stream_decoder::{SsrcDecoders, channels_of};
+pub mod catch_up;
+pub mod codec_check;
+pub mod comfort_noise;
+pub mod control_rate;
+pub mod control_stream;
+pub mod decode_errors;
+pub mod decode_pool;
 pub mod group_voice_session;
+pub mod ingress_rate;
+pub mod injection;
+pub mod jitter_buffer;
+pub mod mixer;
+pub mod push_schedule;
+pub mod recording;
+pub mod room_events;
+pub mod ssrc_filter;
+pub mod stats;
+pub mod stream_decoder;
 
-pub async fn handle_connection(app: &'static App, conn: quinn::Incoming) -> Result<()> {
-    let mut connection = conn.await?;
-    if let Err(auth_error) =
-        crate::common::services::auth::auth_user_for_session(app, &mut connection).await
-    {
-        tracing::warn!("Unable to authenticate user: {auth_error}");
-        connection.close(0u8.into(), auth_error.to_string().as_bytes());
-        return Err(auth_error.into());
+/// Fixed RTP header size, anything shorter can't be a packet
+const RTP_HEADER_LEN: usize = 12;
+
+pub async fn handle_connection(app: Arc<App>, conn: quinn::Incoming) -> Result<()> {
+    let connection = conn.await?;
+    // Negotiated in the handshake, without it not a single audio frame would get through
+    if connection.max_datagram_size().is_none() {
+        tracing::warn!(
+            "{} doesn't support datagrams, closing",
+            connection.remote_address()
+        );
+        connection.close(
+            CloseCode::ProtocolError.code().into(),
+            b"datagrams are required",
+        );
+        return Err(VoxoxideError::Transport("peer doesn't support QUIC datagrams".into()).into());
     }
+    let connection_id = connection.stable_id();
+    app.events.emit(LifecycleEvent::ConnectionAccepted {
+        connection_id,
+        remote: connection.remote_address(),
+    });
+    let stats = app.metrics.register_connection(connection_id);
+    let jitter_buffer = JitterBuffer::new(
+        app.config.get_latency_settings().jitter_buffer_depth,
+        stats.clone(),
+    )
+    .with_capacity(app.config.get_max_buffered_packets());
+    app.metrics
+        .register_jitter_buffer(connection_id, jitter_buffer.probe());
+
+    let (member, control_stream, pre_auth) = match authenticate(&app, &connection, &stats).await {
+        Ok(authenticated) => authenticated,
+        Err(auth_error) => {
+            tracing::warn!("Unable to authenticate user: {auth_error}");
+            connection.close(
+                CloseCode::AuthFailed.code().into(),
+                auth_error.to_string().as_bytes(),
+            );
+            app.metrics.unregister_connection(connection_id);
+            app.events
+                .emit(LifecycleEvent::ConnectionClosed { connection_id });
+            return Err(VoxoxideError::Auth(auth_error).into());
+        }
+    };
 
     tracing::info!("established");
+    app.events
+        .emit(LifecycleEvent::Authenticated { connection_id });
+    if let Some(session_ended) = app
+        .rooms
+        .join(connection_id, Some(connection.clone()), &member)
+    {
+        spawn_session_loops(&app, member.room_id, session_ended);
+    }
+    app.metrics.room_joined(member.room_id);
+    app.events.emit(LifecycleEvent::JoinedRoom {
+        connection_id,
+        room_id: member.room_id,
+    });
 
-    tokio::select! {
-        _ = playback_loop(&mut connection) => {
+    let result = tokio::select! {
+        _ = playback_loop(&app, &connection, &member, stats.clone(), jitter_buffer, pre_auth) => {
+            Ok(())
+        }
+        _ = reject_extra_control_streams(&connection) => {
+            Ok(())
+        }
+        _ = handle_control_messages(&app, &connection, member.room_id, control_stream) => {
+            Ok(())
+        }
+        _ = enforce_session_limit(&app, &connection) => {
             Ok(())
         }
         _ = app.cancellation_token.cancelled() => {
             tracing::debug!("Shutting down connection with {}", connection.remote_address());
-            connection.close(1u32.into(), b"server shutdown");
+            connection.close(CloseCode::ServerShutdown.code().into(), b"server shutdown");
             Ok(())
         }
+    };
+    app.rooms.leave(member.room_id, connection_id);
+    if let Some(user_id) = member.user_id {
+        app.users.release(user_id, connection_id);
     }
+    app.metrics.room_left(member.room_id);
+    app.events.emit(LifecycleEvent::LeftRoom {
+        connection_id,
+        room_id: member.room_id,
+    });
+    app.metrics.unregister_connection(connection_id);
+    tracing::info!("Connection {connection_id} summary: {}", stats.snapshot());
+    app.events
+        .emit(LifecycleEvent::ConnectionClosed { connection_id });
+    result
 }
 
-async fn playback_loop(connection: &mut quinn::Connection) -> anyhow::Result<()> {
-    let mut decoder = opus::Decoder::new(48000, opus::Channels::Mono)?;
-    let mut pcm_buf = vec![0i16; 960]; // 20ms @ 48kHz
-    const SAMPLE_RATE: f32 = 48_000.0;
-    let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: SAMPLE_RATE as u32,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
+/// Runs the auth handshake. Audio arriving before it completes is dropped,
+/// or buffered per `pre_auth_datagrams` and returned along with the member.
+/// Playback only starts once this returns Ok, so unauthenticated audio is never decoded.
+/// A handshake still incomplete after the auth timeout fails with [`ArsAuthError::AuthTimedOut`].
+async fn authenticate(
+    app: &App,
+    connection: &quinn::Connection,
+    stats: &ConnectionStats,
+) -> Result<(AuthenticatedMember, ControlRecvStream, Vec<Bytes>), ArsAuthError> {
+    let auth = crate::common::services::auth::auth_user_for_session(app, connection);
+    tokio::pin!(auth);
+    let mut buffered = Vec::new();
+    let handshake = async {
+        loop {
+            tokio::select! {
+                // Drain queued datagrams before looking at the auth result
+                biased;
+                datagram = connection.read_datagram() => match datagram {
+                    Ok(datagram) if app.config.pre_auth_datagrams == PreAuthDatagrams::Buffer
+                        && buffered.len() < MAX_PRE_AUTH_DATAGRAMS => buffered.push(datagram),
+                    Ok(_) => {
+                        tracing::trace!("Dropping datagram from unauthenticated {}", connection.remote_address());
+                        stats.add_dropped_unauthenticated(1);
+                    }
+                    // Connection is gone, the auth stream fails on its own
+                    Err(_) => return auth.await.map(|(member, control)| (member, control, buffered)),
+                },
+                result = &mut auth => return result.map(|(member, control)| (member, control, buffered)),
+            }
+        }
     };
-    let mut wav_writer =
-        hound::WavWriter::create(format!("test{}.wav", connection.stable_id()), spec)?;
+    tokio::time::timeout(app.config.get_auth_timeout(), handshake)
+        .await
+        .unwrap_or(Err(ArsAuthError::AuthTimedOut))
+}
 
-    let mut interval = tokio::time::interval(Duration::from_millis(20));
-    let mut last_write_time = Instant::now();
+/// The auth stream is the only control stream a connection gets.
+/// Opening another one closes the connection with a protocol error.
+async fn reject_extra_control_streams(connection: &quinn::Connection) {
+    match connection.accept_bi().await {
+        Ok(_) => {
+            tracing::warn!(
+                "{} opened an extra control stream, closing",
+                connection.remote_address()
+            );
+            connection.close(
+                CloseCode::ProtocolError.code().into(),
+                b"only one control stream is allowed",
+            );
+        }
+        // Connection is gone, the playback loop reports why
+        Err(_) => std::future::pending().await,
+    }
+}
+
+/// Starts what a new session needs beside its members: mixing and scheduled roster pushes.
+fn spawn_session_loops(app: &Arc<App>, room_id: u32, session_ended: CancellationToken) {
+    if app.config.mixing_threshold.is_some() {
+        app.spawn_task(mixing_loop(app.clone(), room_id, session_ended.clone()));
+    }
+    if app.rooms.schedules_rosters() {
+        app.spawn_task(roster_loop(app.clone(), room_id, session_ended));
+    }
+}
+
+/// Pushes the room's roster whenever its push schedule has one due, until its session ends.
+async fn roster_loop(app: Arc<App>, room_id: u32, session_ended: CancellationToken) {
+    let Some(changed) = app.rooms.roster_changes(room_id) else {
+        return;
+    };
+    loop {
+        let Some(next_push) = app.rooms.push_due_roster(room_id) else {
+            return;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_push.unwrap_or_else(Instant::now)),
+                if next_push.is_some() => {}
+            _ = changed.notified() => {}
+            _ = session_ended.cancelled() => return,
+            _ = app.cancellation_token.cancelled() => return,
+        }
+    }
+}
+
+/// Mixes the room once per frame until its session ends.
+async fn mixing_loop(app: Arc<App>, room_id: u32, session_ended: CancellationToken) {
+    let mut interval = tokio::time::interval(Duration::from_millis(FRAME_DURATION_MS));
     loop {
         tokio::select! {
-        read_res = connection.read_datagram() => {
+            _ = interval.tick() => {
+                let mixed = match &app.decode_pool {
+                    Some(pool) => {
+                        let app = app.clone();
+                        pool.run(move || app.rooms.mix_tick(room_id)).await
+                    }
+                    None => Ok(app.rooms.mix_tick(room_id)),
+                };
+                match mixed {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(e) => tracing::error!("Failed to mix room {room_id}: {e}"),
+                }
+            }
+            _ = session_ended.cancelled() => return,
+            _ = app.cancellation_token.cancelled() => return,
+        }
+    }
+}
+
+/// Warns the member `session_warning_secs` ahead of `max_session_secs`, then closes the connection.
+/// Never returns without a limit.
+async fn enforce_session_limit(app: &App, connection: &quinn::Connection) {
+    let Some(limit) = app.config.get_max_session() else {
+        return std::future::pending().await;
+    };
+    let warning = app.config.get_session_warning();
+    let started = Instant::now();
+    tokio::time::sleep_until(started + (limit - warning)).await;
+    let ending = ArsControlMessage::SessionEnding {
+        remaining_secs: warning.as_secs(),
+    };
+    if let Err(e) = send_control_message(connection, &ending).await {
+        tracing::debug!(
+            "Failed to warn {} of the session limit: {e}",
+            connection.remote_address()
+        );
+    }
+    tokio::time::sleep_until(started + limit).await;
+    tracing::info!(
+        "{} reached the session time limit, closing",
+        connection.remote_address()
+    );
+    connection.close(
+        CloseCode::SessionTimeLimit.code().into(),
+        b"session time limit reached",
+    );
+}
+
+/// Sends a control message to the member on its own unidirectional stream
+pub(crate) async fn send_control_message(
+    connection: &quinn::Connection,
+    message: &ArsControlMessage,
+) -> Result<()> {
+    let mut send = connection.open_uni().await?;
+    send.write_all(&serde_json::to_vec(message)?).await?;
+    send.finish()?;
+    Ok(())
+}
+
+/// A control message about to be read
+enum IncomingControl {
+    /// Sent on its own unidirectional stream, still unread
+    Stream(quinn::RecvStream),
+    /// Sent on the auth stream after the request
+    AuthStream(Vec<u8>),
+}
+
+/// Applies control messages, each one sent on its own unidirectional stream
+/// or framed on the auth stream after the request.
+/// Messages beyond `max_control_messages_per_sec` are dropped unread or close the connection.
+async fn handle_control_messages(
+    app: &App,
+    connection: &quinn::Connection,
+    room_id: u32,
+    mut control_stream: ControlRecvStream,
+) {
+    let mut rate = app
+        .config
+        .max_control_messages_per_sec
+        .map(ControlRate::new);
+    let mut control_stream_open = true;
+    loop {
+        let incoming = tokio::select! {
+            accepted = connection.accept_uni() => match accepted {
+                Ok(recv) => IncomingControl::Stream(recv),
+                // Connection is gone, the playback loop reports why
+                Err(_) => return std::future::pending().await,
+            },
+            message = control_stream.next_message(MAX_CONTROL_MESSAGE_LEN), if control_stream_open => {
+                match message {
+                    Ok(Some(message)) => IncomingControl::AuthStream(message),
+                    Ok(None) => {
+                        control_stream_open = false;
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Closing the auth stream of {}: {e}",
+                            connection.remote_address()
+                        );
+                        control_stream_open = false;
+                        continue;
+                    }
+                }
+            }
+        };
+        if rate.as_mut().is_some_and(|rate| !rate.allow()) {
+            match app.config.control_rate_enforcement {
+                ControlRateEnforcement::Drop => {
+                    tracing::debug!(
+                        "Dropping control message from {}, over the rate limit",
+                        connection.remote_address()
+                    );
+                    if let IncomingControl::Stream(mut recv) = incoming {
+                        let _ = recv.stop(0u32.into());
+                    }
+                    continue;
+                }
+                ControlRateEnforcement::Close => {
+                    tracing::warn!(
+                        "{} exceeded the control message rate, closing",
+                        connection.remote_address()
+                    );
+                    connection.close(
+                        CloseCode::ProtocolError.code().into(),
+                        b"control message rate exceeded",
+                    );
+                    return std::future::pending().await;
+                }
+            }
+        }
+        let bytes = match incoming {
+            IncomingControl::Stream(mut recv) => recv
+                .read_to_end(MAX_CONTROL_MESSAGE_LEN)
+                .await
+                .map_err(anyhow::Error::from),
+            IncomingControl::AuthStream(bytes) => Ok(bytes),
+        };
+        let message = bytes.and_then(|bytes| {
+            serde_json::from_slice::<ArsControlMessage>(&bytes).map_err(anyhow::Error::from)
+        });
+        let applied = message.and_then(|message| {
+            app.rooms
+                .apply_control(room_id, connection.stable_id(), message)
+        });
+        if let Err(e) = applied {
+            tracing::warn!(
+                "Rejected control message from {}: {e}",
+                connection.remote_address()
+            );
+        }
+    }
+}
+
+/// Takes the datagrams buffered before auth first, then reads from the connection
+async fn next_datagram(
+    connection: &quinn::Connection,
+    pre_auth: &mut std::vec::IntoIter<Bytes>,
+) -> Result<Bytes, quinn::ConnectionError> {
+    match pre_auth.next() {
+        Some(datagram) => Ok(datagram),
+        None => connection.read_datagram().await,
+    }
+}
+
+async fn playback_loop(
+    app: &App,
+    connection: &quinn::Connection,
+    member: &AuthenticatedMember,
+    stats: Arc<ConnectionStats>,
+    mut jitter_buffer: JitterBuffer,
+    pre_auth: Vec<Bytes>,
+) -> anyhow::Result<()> {
+    let config = &app.config;
+    let room_id = member.room_id;
+    let channels = channels_of(member.format);
+    let mut decoders: SsrcDecoders = SsrcDecoders::with_channels(stats.clone(), channels);
+    let mut decode_errors = DecodeErrorWindow::new(
+        config.get_max_decode_errors(),
+        config.get_decode_error_window(),
+    );
+    let mut ingress_rate = IngressRate::new(INGRESS_RATE_WINDOW);
+    // Finalized on drop, so also when the loop is cancelled.
+    // Recording is best effort, failing to create or write it never ends the call
+    let recording_path = config
+        .get_recording_dir()
+        .join(format!("test{}.wav", connection.stable_id()));
+    let mut recording = match Recording::create_with_channels(
+        &recording_path,
+        channels as u16,
+        config.wav_sample_rate_correction,
+    ) {
+        Ok(recording) => Some(
+            recording
+                .with_flush_interval(config.get_wav_flush_interval())
+                .with_comfort_noise(config.comfort_noise_level_db),
+        ),
+        Err(e) => {
+            tracing::warn!("Not recording to {recording_path:?}: {e}");
+            None
+        }
+    };
+    // Paused while anyone in a room requiring consent hasn't given it
+    let consent_required = config.get_recording_consent(room_id) == Some(RecordingConsent::Disable);
+    let all_consent = app.rooms.recording_consent(room_id);
+    let recording_allowed = || {
+        !consent_required
+            || all_consent
+                .as_ref()
+                .is_none_or(|all_consent| all_consent.load(Ordering::Relaxed))
+    };
+    let mut recording_paused = !recording_allowed();
+    app.rooms.set_member_recording(
+        room_id,
+        connection.stable_id(),
+        recording.is_some() && !recording_paused,
+    );
+
+    let mut interval = tokio::time::interval(Duration::from_millis(FRAME_DURATION_MS));
+    let mut ssrc_filter = SsrcFilter::new(config.unknown_ssrc_policy).with_declared(member.ssrc);
+    let mut pre_auth = pre_auth.into_iter();
+    let datagram_timeout = config.get_datagram_timeout();
+    let mut last_datagram = Instant::now();
+    loop {
+        let released = tokio::select! {
+        read_res = next_datagram(connection, &mut pre_auth) => {
             let bytes = match read_res {
                 Err(quinn::ConnectionError::ApplicationClosed(frame)) => {
-                    tracing::info!("connection closed: {}", frame);
+                    match CloseCode::from_code(frame.error_code.into_inner()) {
+                        Some(CloseCode::ClientLeft) => tracing::info!(
+                            "{} left: {}",
+                            connection.remote_address(),
+                            String::from_utf8_lossy(&frame.reason)
+                        ),
+                        _ => tracing::info!("connection closed: {}", frame),
+                    }
                     return Ok(());
                 }
                 Err(e) => return Err(e.into()),
                 Ok(dgram) => dgram,
             };
-            let rtp_packet = rvoip_rtp_core::RtpPacket::parse(&bytes)?;
-            tracing::trace!(
-                "Packet {} from {}",
-                rtp_packet.header.sequence_number,
-                rtp_packet.header.ssrc
-            );
-            last_write_time = Instant::now();
-
-            let len = decoder.decode(&rtp_packet.payload, &mut pcm_buf, false)?;
-            for sample in pcm_buf[0..len].iter_mut() {
-                wav_writer.write_sample(*sample)?;
+            last_datagram = Instant::now();
+            stats.add_bytes_received(bytes.len() as u64);
+            let rate = ingress_rate.record(bytes.len() as u64);
+            stats.set_ingress_bytes_per_second(rate);
+            if config.max_ingress_bytes_per_sec.is_some_and(|cap| rate > cap) {
+                tracing::warn!(
+                    "{} exceeded the ingress cap at {rate}B/s, closing",
+                    connection.remote_address()
+                );
+                connection.close(
+                    CloseCode::BandwidthExceeded.code().into(),
+                    b"bandwidth cap exceeded",
+                );
+                return Ok(());
             }
-
+            if bytes == KEEPALIVE_DATAGRAM {
+                tracing::trace!("Keepalive from {}", connection.remote_address());
+                stats.add_keepalives(1);
+                continue;
+            }
+            if bytes.len() < RTP_HEADER_LEN {
+                // Empty datagrams are valid QUIC, so short ones are dropped and counted rather than
+                // treated as bad audio, the connection keeps going
+                tracing::trace!(
+                    "Dropping {}B datagram from {}",
+                    bytes.len(),
+                    connection.remote_address()
+                );
+                stats.add_dropped_short(1);
+                continue;
+            }
+            let rtp_packet = match rvoip_rtp_core::RtpPacket::parse(&bytes) {
+                Ok(rtp_packet) => rtp_packet,
+                Err(e) => {
+                    if decode_failed(connection, &stats, &mut decode_errors, e.into()) {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+            let ssrc = rtp_packet.header.ssrc;
+            tracing::trace!("Packet {} from {ssrc}", rtp_packet.header.sequence_number);
+            match ssrc_filter.check(ssrc) {
+                SsrcCheck::Known => {}
+                SsrcCheck::Registered => {
+                    app.rooms.set_member_ssrc(room_id, connection.stable_id(), ssrc);
+                }
+                SsrcCheck::Unknown => {
+                    tracing::debug!(
+                        "Dropping packet with unknown SSRC {ssrc} from {}",
+                        connection.remote_address()
+                    );
+                    stats.add_dropped_unknown_ssrc(1);
+                    continue;
+                }
+            }
+            // The others hear it right away, the jitter buffer only orders it for decoding and mixing
+            app.rooms.forward(room_id, connection.stable_id(), &bytes);
+            jitter_buffer.push(rtp_packet, Instant::now())
         }
         _ = interval.tick() => {
-            let silence_duration = last_write_time.elapsed();
-            for _ in 0..(silence_duration.as_millis() * (SAMPLE_RATE as u128 / 1000)) {
-                wav_writer.write_sample(0)?
+            let now = Instant::now();
+            if let Some(timeout) = datagram_timeout
+                && now.duration_since(last_datagram) > timeout
+            {
+                tracing::info!(
+                    "{} sent nothing for {timeout:?}, closing as inactive",
+                    connection.remote_address()
+                );
+                connection.close(CloseCode::Inactive.code().into(), b"no datagrams received");
+                return Ok(());
             }
-            last_write_time = Instant::now();
+            stats.set_ingress_bytes_per_second(ingress_rate.rate_at(now));
+            jitter_buffer.release_expired(now)
         }
+        };
+
+        for playout in released {
+            // Gaps are concealed inline, they're rare and at most a few frames of decoding
+            let (decoded, silence) = match playout {
+                Playout::Packet { packet } => {
+                    let decoded = match &app.decode_pool {
+                        Some(pool) => {
+                            // The decoders travel to the pool thread and back with the PCM
+                            let job = pool.run(move || {
+                                let decoded = decoders.decode(&packet).map(<[i16]>::to_vec);
+                                (decoders, decoded)
+                            });
+                            let (returned, decoded) = job.await?;
+                            decoders = returned;
+                            decoded.map(Cow::Owned)
+                        }
+                        None => decoders.decode(&packet).map(Cow::Borrowed),
+                    };
+                    (decoded, 0)
+                }
+                Playout::Gap { ssrc, frames } => match decoders.conceal_gap(ssrc, frames) {
+                    Ok(gap) => (Ok(Cow::Borrowed(gap.pcm)), gap.silence),
+                    Err(e) => (Err(e), 0),
+                },
+            };
+            match decoded {
+                Ok(samples) => {
+                    if recording.is_some()
+                        && consent_required
+                        && recording_paused == recording_allowed()
+                    {
+                        recording_paused = !recording_paused;
+                        if recording_paused {
+                            tracing::info!(
+                                "Pausing the recording of {}, not everyone in room {room_id} consents",
+                                connection.remote_address()
+                            );
+                        } else {
+                            tracing::info!(
+                                "Resuming the recording of {}, everyone in room {room_id} consents",
+                                connection.remote_address()
+                            );
+                        }
+                        app.rooms.set_member_recording(
+                            room_id,
+                            connection.stable_id(),
+                            !recording_paused,
+                        );
+                    }
+                    // Paused audio is recorded as silence, so the recording keeps its timeline
+                    let written = recording.as_mut().map(|r| {
+                        if recording_paused {
+                            r.write_silence(samples.len())
+                        } else {
+                            r.write_samples(&samples)
+                        }
+                        // The rest of a gap too long to conceal keeps the recording's timeline
+                        .and_then(|()| r.write_silence(silence))
+                    });
+                    if let Some(Err(e)) = written {
+                        tracing::warn!("Recording to {recording_path:?} aborted: {e}");
+                        app.rooms
+                            .set_member_recording(room_id, connection.stable_id(), false);
+                    }
+                    app.rooms.submit_frame(
+                        room_id,
+                        connection.stable_id(),
+                        &downmix(&samples, channels as usize),
+                    );
+                }
+                Err(e) => {
+                    if decode_failed(connection, &stats, &mut decode_errors, e) {
+                        return Ok(());
+                    }
+                }
+            }
         }
     }
 }
+
+/// Counts a datagram that failed to decode, closes the connection and returns true once it hit the limit
+fn decode_failed(
+    connection: &quinn::Connection,
+    stats: &ConnectionStats,
+    decode_errors: &mut DecodeErrorWindow,
+    e: anyhow::Error,
+) -> bool {
+    tracing::debug!(
+        "Failed to decode datagram from {}: {e}",
+        connection.remote_address()
+    );
+    stats.add_decode_errors(1);
+    if !decode_errors.record() {
+        return false;
+    }
+    tracing::warn!(
+        "{} exceeded the decode error limit, closing",
+        connection.remote_address()
+    );
+    connection.close(
+        CloseCode::ProtocolError.code().into(),
+        b"too many decode errors",
+    );
+    true
+}
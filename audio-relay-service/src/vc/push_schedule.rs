@@ -0,0 +1,77 @@
+//! Decides when roster and level updates go out to a room's members.
+//! Pushing every change is the most responsive but chatty in busy rooms,
+//! pushing periodically bounds the bandwidth but lags, debouncing sits in between.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::common::app_config::PushStrategy;
+
+#[derive(Debug)]
+pub struct PushSchedule {
+    strategy: PushStrategy,
+    interval: Duration,
+    /// When the oldest change not pushed yet happened
+    pending_since: Option<Instant>,
+    last_push: Option<Instant>,
+}
+
+impl PushSchedule {
+    pub fn new(strategy: PushStrategy, interval: Duration) -> Self {
+        Self {
+            strategy,
+            interval,
+            pending_since: None,
+            last_push: None,
+        }
+    }
+
+    /// Records a change now, returns true if it has to be pushed right away.
+    pub fn changed(&mut self) -> bool {
+        self.changed_at(Instant::now())
+    }
+
+    pub fn changed_at(&mut self, now: Instant) -> bool {
+        if self.strategy == PushStrategy::OnChange {
+            self.last_push = Some(now);
+            return true;
+        }
+        self.pending_since.get_or_insert(now);
+        false
+    }
+
+    /// When [`Self::poll_at`] has to be called next, None while nothing is scheduled
+    pub fn next_push(&self) -> Option<Instant> {
+        match self.strategy {
+            PushStrategy::OnChange => None,
+            PushStrategy::Periodic => Some(
+                self.last_push
+                    .map_or_else(Instant::now, |last| last + self.interval),
+            ),
+            PushStrategy::Debounced => self.pending_since.map(|since| since + self.interval),
+        }
+    }
+
+    /// Returns true if a push is due now.
+    pub fn poll(&mut self) -> bool {
+        self.poll_at(Instant::now())
+    }
+
+    pub fn poll_at(&mut self, now: Instant) -> bool {
+        let due = match self.strategy {
+            PushStrategy::OnChange => false,
+            PushStrategy::Periodic => self
+                .last_push
+                .is_none_or(|last| now.duration_since(last) >= self.interval),
+            PushStrategy::Debounced => self
+                .pending_since
+                .is_some_and(|since| now.duration_since(since) >= self.interval),
+        };
+        if due {
+            self.pending_since = None;
+            self.last_push = Some(now);
+        }
+        due
+    }
+}
@@ -0,0 +1,229 @@
+//! WAV recording of a connection's decoded audio.
+//! The relay writes at whatever pace packets arrive plus silence fill,
+//! so on finalize the effective sample rate is measured against wall-clock time
+//! and the header can optionally be rewritten to match.
+//! A failed write aborts the recording instead of the connection, later writes are no-ops.
+//! With a flush interval the header and buffered samples are flushed to the OS periodically,
+//! so a crashed server loses at most one interval of audio.
+//! With comfort noise, silence fill is noise at the level of the last written audio instead of zeros.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::vc::comfort_noise::ComfortNoise;
+use crate::vc::stream_decoder::{SAMPLE_RATE, Sample};
+
+/// Drift below this fraction of the declared rate is considered noise
+pub const DRIFT_TOLERANCE: f64 = 0.001;
+/// Recordings shorter than this many frames (100ms) keep the declared rate, too short to measure
+pub const MIN_MEASURED_FRAMES: u64 = SAMPLE_RATE as u64 / 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingSummary {
+    pub samples: u64,
+    pub elapsed: Duration,
+    pub measured_sample_rate: f64,
+    /// Relative deviation from the declared rate, positive if more samples were written than time passed
+    pub drift: f64,
+    pub header_rewritten: bool,
+}
+
+pub struct Recording {
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+    path: PathBuf,
+    channels: u16,
+    /// Samples of all channels
+    samples_written: u64,
+    started: Instant,
+    correct_sample_rate: bool,
+    flush_interval: Option<Duration>,
+    last_flush: Instant,
+    comfort_noise: Option<ComfortNoise>,
+}
+
+impl Recording {
+    pub fn create(path: impl AsRef<Path>, correct_sample_rate: bool) -> anyhow::Result<Self> {
+        Self::create_with_channels(path, 1, correct_sample_rate)
+    }
+
+    /// Records `channels` interleaved channels, as the decoder of the recorded stream produces them
+    pub fn create_with_channels(
+        path: impl AsRef<Path>,
+        channels: u16,
+        correct_sample_rate: bool,
+    ) -> anyhow::Result<Self> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let path = path.as_ref().to_path_buf();
+        Ok(Self {
+            writer: Some(hound::WavWriter::create(&path, spec)?),
+            path,
+            channels,
+            samples_written: 0,
+            started: Instant::now(),
+            correct_sample_rate,
+            flush_interval: None,
+            last_flush: Instant::now(),
+            comfort_noise: None,
+        })
+    }
+
+    /// Flush at most every `interval` while writing, never if None
+    pub fn with_flush_interval(mut self, interval: Option<Duration>) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Fill silence with comfort noise instead of zeros, see [`ComfortNoise::new`] for the level
+    pub fn with_comfort_noise(mut self, level_db: Option<f32>) -> Self {
+        self.comfort_noise = level_db.map(ComfortNoise::new);
+        self
+    }
+
+    /// Writes decoded samples of either format, the file itself is always 16 bit PCM
+    pub fn write_samples<S: Sample>(&mut self, samples: &[S]) -> hound::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            let written = samples
+                .iter()
+                .try_for_each(|sample| writer.write_sample(sample.to_i16()));
+            self.abort_on_error(written)?;
+            self.samples_written += samples.len() as u64;
+            if let Some(noise) = self.comfort_noise.as_mut() {
+                noise.observe(samples);
+            }
+        }
+        self.flush_if_due()
+    }
+
+    /// Writes `samples` samples of all channels, a multiple of the channel count
+    pub fn write_silence(&mut self, samples: usize) -> hound::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            let written = match self.comfort_noise.as_mut() {
+                Some(noise) => {
+                    (0..samples).try_for_each(|_| writer.write_sample(noise.next_sample()))
+                }
+                None => (0..samples).try_for_each(|_| writer.write_sample(0i16)),
+            };
+            self.abort_on_error(written)?;
+            self.samples_written += samples as u64;
+        }
+        self.flush_if_due()
+    }
+
+    fn flush_if_due(&mut self) -> hound::Result<()> {
+        let Some(interval) = self.flush_interval else {
+            return Ok(());
+        };
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        if self.last_flush.elapsed() < interval {
+            return Ok(());
+        }
+        self.last_flush = Instant::now();
+        let flushed = writer.flush();
+        self.abort_on_error(flushed)
+    }
+
+    /// False once finalized or aborted by a failed write
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Drops the writer if `result` failed, leaving whatever made it to disk
+    fn abort_on_error(&mut self, result: hound::Result<()>) -> hound::Result<()> {
+        if result.is_err() {
+            self.writer = None;
+        }
+        result
+    }
+
+    pub fn finalize(self) -> anyhow::Result<RecordingSummary> {
+        let elapsed = self.started.elapsed();
+        self.finalize_with_elapsed(elapsed)
+    }
+
+    /// Finalizes as if `elapsed` wall-clock time had passed since creation.
+    pub fn finalize_with_elapsed(mut self, elapsed: Duration) -> anyhow::Result<RecordingSummary> {
+        self.finish(elapsed)
+    }
+
+    fn finish(&mut self, elapsed: Duration) -> anyhow::Result<RecordingSummary> {
+        let Some(writer) = self.writer.take() else {
+            anyhow::bail!("recording {:?} already finalized", self.path);
+        };
+        writer.finalize()?;
+
+        let frames = self.samples_written / u64::from(self.channels);
+        let measured_sample_rate = if elapsed.is_zero() || frames < MIN_MEASURED_FRAMES {
+            SAMPLE_RATE as f64
+        } else {
+            frames as f64 / elapsed.as_secs_f64()
+        };
+        let drift = measured_sample_rate / SAMPLE_RATE as f64 - 1.0;
+        let drifted = drift.abs() > DRIFT_TOLERANCE;
+        if drifted {
+            tracing::info!(
+                "Recording {:?} drifted {:+.3}%: measured {measured_sample_rate:.0}Hz, declared {SAMPLE_RATE}Hz",
+                self.path,
+                drift * 100.0
+            );
+        }
+
+        let header_rewritten = drifted && self.correct_sample_rate;
+        if header_rewritten {
+            // A WAV header can't declare 0Hz, however long the recording sat idle
+            rewrite_sample_rate(&self.path, measured_sample_rate.round().max(1.0) as u32)?;
+        }
+        Ok(RecordingSummary {
+            samples: self.samples_written,
+            elapsed,
+            measured_sample_rate,
+            drift,
+            header_rewritten,
+        })
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if self.writer.is_some()
+            && let Err(e) = self.finish(self.started.elapsed())
+        {
+            tracing::warn!("Failed to finalize recording {:?}: {e}", self.path);
+        }
+    }
+}
+
+/// Patches the sample rate and byte rate in the `fmt ` chunk of a finalized WAV file.
+fn rewrite_sample_rate(path: &Path, sample_rate: u32) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        anyhow::bail!("{path:?} is not a WAV file");
+    }
+    loop {
+        let mut chunk_header = [0u8; 8];
+        file.read_exact(&mut chunk_header)?;
+        let chunk_len = u32::from_le_bytes(chunk_header[4..8].try_into()?);
+        if &chunk_header[0..4] != b"fmt " {
+            file.seek(SeekFrom::Current(chunk_len as i64))?;
+            continue;
+        }
+        // format tag (2), channels (2), sample rate (4), byte rate (4), block align (2)
+        let mut fmt = [0u8; 14];
+        file.read_exact(&mut fmt)?;
+        let block_align = u16::from_le_bytes(fmt[12..14].try_into()?) as u32;
+        file.seek(SeekFrom::Current(-10))?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align).to_le_bytes())?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,90 @@
+//! Bounded audit log of what happened in a room, kept for as long as its session lives.
+//! The oldest entries are dropped once [`ROOM_EVENT_LOG_CAPACITY`] is reached.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+pub const ROOM_EVENT_LOG_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "PascalCase")]
+pub enum RoomEventKind {
+    Joined {
+        member_id: u64,
+        moderator: bool,
+    },
+    Left {
+        member_id: u64,
+    },
+    MemberMuted {
+        member_id: u64,
+        muted: bool,
+        by: u64,
+    },
+    SelfMuted {
+        member_id: u64,
+        muted: bool,
+    },
+    AllMuted {
+        muted: bool,
+        by: u64,
+    },
+    Kicked {
+        member_id: u64,
+        by: u64,
+        reason: String,
+    },
+    RoomLocked {
+        locked: bool,
+        by: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RoomEvent {
+    /// Wall-clock time of the event
+    pub at_unix_ms: u64,
+    #[serde(flatten)]
+    pub kind: RoomEventKind,
+}
+
+#[derive(Debug)]
+pub struct RoomEventLog {
+    capacity: usize,
+    events: VecDeque<RoomEvent>,
+}
+
+impl Default for RoomEventLog {
+    fn default() -> Self {
+        Self::with_capacity(ROOM_EVENT_LOG_CAPACITY)
+    }
+}
+
+impl RoomEventLog {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn record(&mut self, kind: RoomEventKind) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        let at_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        self.events.push_back(RoomEvent { at_unix_ms, kind });
+    }
+
+    /// Oldest first
+    pub fn entries(&self) -> Vec<RoomEvent> {
+        self.events.iter().cloned().collect()
+    }
+}
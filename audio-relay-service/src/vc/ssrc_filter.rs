@@ -0,0 +1,49 @@
+//! Tracks the SSRC a connection streams with.
+//! The first packet registers its SSRC, packets with any other one are either dropped
+//! or register another stream of the connection per the policy.
+//! An SSRC declared on auth is registered up front, in place of the first packet's.
+
+use std::collections::HashSet;
+
+use crate::common::app_config::UnknownSsrcPolicy;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SsrcCheck {
+    /// The SSRC is registered
+    Known,
+    /// The SSRC was registered by this packet
+    Registered,
+    /// The SSRC is not registered, drop the packet
+    Unknown,
+}
+
+pub struct SsrcFilter {
+    policy: UnknownSsrcPolicy,
+    registered: HashSet<u32>,
+}
+
+impl SsrcFilter {
+    pub fn new(policy: UnknownSsrcPolicy) -> Self {
+        Self {
+            policy,
+            registered: HashSet::new(),
+        }
+    }
+
+    /// Registers the SSRC the connection declared on auth, or was reassigned
+    pub fn with_declared(mut self, ssrc: Option<u32>) -> Self {
+        self.registered.extend(ssrc);
+        self
+    }
+
+    pub fn check(&mut self, ssrc: u32) -> SsrcCheck {
+        if self.registered.contains(&ssrc) {
+            SsrcCheck::Known
+        } else if !self.registered.is_empty() && self.policy == UnknownSsrcPolicy::Drop {
+            SsrcCheck::Unknown
+        } else {
+            self.registered.insert(ssrc);
+            SsrcCheck::Registered
+        }
+    }
+}
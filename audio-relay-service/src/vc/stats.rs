@@ -0,0 +1,139 @@
+//! Per-connection audio statistics.
+//! Counters are atomics so the metrics endpoint can read them while the connection task writes.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    /// Packets that arrived in order and were decoded normally
+    pub packets_received: AtomicU64,
+    /// Duplicate or late packets dropped by the jitter buffer or decoder
+    pub packets_reordered: AtomicU64,
+    /// Lost frames rebuilt from the in-band FEC data of the following packet
+    pub frames_recovered_fec: AtomicU64,
+    /// Lost frames synthesized by the decoder's packet loss concealment
+    pub frames_concealed_plc: AtomicU64,
+    /// Datagrams that arrived before the auth handshake completed
+    pub datagrams_dropped_unauthenticated: AtomicU64,
+    /// Datagrams carrying another SSRC than the connection's registered one
+    pub datagrams_dropped_unknown_ssrc: AtomicU64,
+    /// Datagrams too short to hold an RTP header, empty ones included, dropped as no-ops
+    pub datagrams_dropped_short: AtomicU64,
+    /// Keepalive datagrams of a client with nothing to send, see [`lib_common_voxoxide::types::KEEPALIVE_DATAGRAM`]
+    pub keepalives_received: AtomicU64,
+    /// Datagrams that failed to parse as RTP or decode as Opus
+    pub decode_errors: AtomicU64,
+    /// Datagram payload bytes received, whether they decoded or not
+    pub bytes_received: AtomicU64,
+    /// Ingress rate over the last bandwidth window, a gauge
+    pub ingress_bytes_per_second: AtomicU64,
+    /// Packets waiting in the jitter buffer, a gauge
+    pub packets_buffered: AtomicU64,
+    /// Oldest packets dropped from a full jitter buffer
+    pub packets_dropped_overflow: AtomicU64,
+}
+
+/// Plain copy of [`ConnectionStats`] at some point in time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionStatsSnapshot {
+    pub packets_received: u64,
+    pub packets_reordered: u64,
+    pub frames_recovered_fec: u64,
+    pub frames_concealed_plc: u64,
+    pub datagrams_dropped_unauthenticated: u64,
+    pub datagrams_dropped_unknown_ssrc: u64,
+    pub datagrams_dropped_short: u64,
+    pub keepalives_received: u64,
+    pub decode_errors: u64,
+    pub bytes_received: u64,
+    pub ingress_bytes_per_second: u64,
+    pub packets_buffered: u64,
+    pub packets_dropped_overflow: u64,
+}
+
+impl ConnectionStats {
+    pub fn snapshot(&self) -> ConnectionStatsSnapshot {
+        ConnectionStatsSnapshot {
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            packets_reordered: self.packets_reordered.load(Ordering::Relaxed),
+            frames_recovered_fec: self.frames_recovered_fec.load(Ordering::Relaxed),
+            frames_concealed_plc: self.frames_concealed_plc.load(Ordering::Relaxed),
+            datagrams_dropped_unauthenticated: self
+                .datagrams_dropped_unauthenticated
+                .load(Ordering::Relaxed),
+            datagrams_dropped_unknown_ssrc: self
+                .datagrams_dropped_unknown_ssrc
+                .load(Ordering::Relaxed),
+            datagrams_dropped_short: self.datagrams_dropped_short.load(Ordering::Relaxed),
+            keepalives_received: self.keepalives_received.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            ingress_bytes_per_second: self.ingress_bytes_per_second.load(Ordering::Relaxed),
+            packets_buffered: self.packets_buffered.load(Ordering::Relaxed),
+            packets_dropped_overflow: self.packets_dropped_overflow.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn add_received(&self, n: u64) {
+        self.packets_received.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_reordered(&self, n: u64) {
+        self.packets_reordered.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_recovered_fec(&self, n: u64) {
+        self.frames_recovered_fec.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_concealed_plc(&self, n: u64) {
+        self.frames_concealed_plc.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_dropped_unauthenticated(&self, n: u64) {
+        self.datagrams_dropped_unauthenticated
+            .fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_dropped_unknown_ssrc(&self, n: u64) {
+        self.datagrams_dropped_unknown_ssrc
+            .fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_dropped_short(&self, n: u64) {
+        self.datagrams_dropped_short.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_keepalives(&self, n: u64) {
+        self.keepalives_received.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_decode_errors(&self, n: u64) {
+        self.decode_errors.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_bytes_received(&self, n: u64) {
+        self.bytes_received.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn set_ingress_bytes_per_second(&self, rate: u64) {
+        self.ingress_bytes_per_second.store(rate, Ordering::Relaxed);
+    }
+    pub(crate) fn set_packets_buffered(&self, n: u64) {
+        self.packets_buffered.store(n, Ordering::Relaxed);
+    }
+    pub(crate) fn add_dropped_overflow(&self, n: u64) {
+        self.packets_dropped_overflow
+            .fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Display for ConnectionStatsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "received={} reordered={} fec_recovered={} plc_concealed={} dropped_unauthenticated={} dropped_unknown_ssrc={} dropped_short={} keepalives={} decode_errors={} bytes={} dropped_overflow={}",
+            self.packets_received,
+            self.packets_reordered,
+            self.frames_recovered_fec,
+            self.frames_concealed_plc,
+            self.datagrams_dropped_unauthenticated,
+            self.datagrams_dropped_unknown_ssrc,
+            self.datagrams_dropped_short,
+            self.keepalives_received,
+            self.decode_errors,
+            self.bytes_received,
+            self.packets_dropped_overflow
+        )
+    }
+}
@@ -0,0 +1,265 @@
+//! Decoding of a single RTP/Opus stream.
+//! Gaps in the sequence numbers are filled before the next real frame is decoded:
+//! the frame right before the received packet comes from its in-band FEC data,
+//! any older missing frames come from the decoder's packet loss concealment.
+//! Lost frames are assumed to be as long as the last decoded one, so streams with short
+//! (eg. 2.5ms low-latency) frames are concealed correctly too.
+//! Streams decode to `i16` by default, or to `f32` for processing in float,
+//! converting to `i16` only where 16 bit PCM is needed (see [`Sample::to_i16`]).
+//! A connection may carry several streams, [`SsrcDecoders`] keeps a decoder per SSRC for that.
+//! Stereo streams decode to interleaved samples, Opus converts packets coded in the other layout.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+
+use anyhow::bail;
+
+use lib_common_voxoxide::types::ArsAudioFormat;
+use rvoip_rtp_core::RtpPacket;
+
+use crate::common::app_config::FRAME_DURATION_MS;
+use crate::vc::stats::ConnectionStats;
+
+pub const SAMPLE_RATE: u32 = 48_000;
+/// One frame of [`FRAME_DURATION_MS`] @ 48kHz
+pub const FRAME_SAMPLES: usize = (SAMPLE_RATE as u64 * FRAME_DURATION_MS / 1000) as usize;
+/// Largest frame an Opus packet can carry (120ms @ 48kHz)
+const MAX_FRAME_SAMPLES: usize = 5760;
+/// Gaps longer than this are treated as a discontinuity instead of being concealed
+pub const MAX_CONCEALED_MS: u64 = 100;
+/// [`MAX_CONCEALED_MS`] in frames of [`FRAME_DURATION_MS`], for those counting packets rather than samples.
+/// Streams with other frame durations conceal up to [`StreamDecoder::max_concealed_frames`]
+pub const MAX_CONCEALED_FRAMES: u16 = (MAX_CONCEALED_MS / FRAME_DURATION_MS) as u16;
+/// Streams decoded per connection, packets of further SSRCs fail to decode
+pub const MAX_STREAMS_PER_CONNECTION: usize = 4;
+
+/// PCM sample format a [`StreamDecoder`] produces.
+pub trait Sample: Copy + Default + Send + 'static {
+    fn decode(
+        decoder: &mut opus::Decoder,
+        input: &[u8],
+        output: &mut [Self],
+        fec: bool,
+    ) -> Result<usize, opus::Error>;
+    /// Converts to 16 bit PCM, as written to recordings
+    fn to_i16(self) -> i16;
+}
+
+impl Sample for i16 {
+    fn decode(
+        decoder: &mut opus::Decoder,
+        input: &[u8],
+        output: &mut [Self],
+        fec: bool,
+    ) -> Result<usize, opus::Error> {
+        decoder.decode(input, output, fec)
+    }
+    fn to_i16(self) -> i16 {
+        self
+    }
+}
+
+/// Full scale is [-1, 1], louder samples are clipped on conversion
+impl Sample for f32 {
+    fn decode(
+        decoder: &mut opus::Decoder,
+        input: &[u8],
+        output: &mut [Self],
+        fec: bool,
+    ) -> Result<usize, opus::Error> {
+        decoder.decode_float(input, output, fec)
+    }
+    fn to_i16(self) -> i16 {
+        (self * 32768.0)
+            .round()
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+}
+
+/// Decoder layout for a member's declared format, anything but 2 channels decodes to mono
+pub fn channels_of(format: ArsAudioFormat) -> opus::Channels {
+    match format.channels {
+        2 => opus::Channels::Stereo,
+        _ => opus::Channels::Mono,
+    }
+}
+
+pub struct StreamDecoder<S: Sample = i16> {
+    decoder: opus::Decoder,
+    channels: usize,
+    last_sequence: Option<u16>,
+    /// Samples of all channels in the last decoded frame, the length lost frames are concealed at
+    frame_len: usize,
+    pcm: Vec<S>,
+    stats: Arc<ConnectionStats>,
+}
+
+impl<S: Sample> StreamDecoder<S> {
+    pub fn new(stats: Arc<ConnectionStats>) -> anyhow::Result<Self> {
+        Self::with_channels(stats, opus::Channels::Mono)
+    }
+
+    /// Decodes to `channels` interleaved samples
+    pub fn with_channels(
+        stats: Arc<ConnectionStats>,
+        channels: opus::Channels,
+    ) -> anyhow::Result<Self> {
+        let channels_len = channels as usize;
+        Ok(Self {
+            decoder: opus::Decoder::new(SAMPLE_RATE, channels)?,
+            channels: channels_len,
+            last_sequence: None,
+            frame_len: FRAME_SAMPLES * channels_len,
+            pcm: Vec::with_capacity(MAX_FRAME_SAMPLES * channels_len),
+            stats,
+        })
+    }
+
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    /// Decodes the packet and returns the PCM of any recovered frames followed by the packet's own frame.
+    /// Duplicate and late packets are dropped and yield no samples.
+    pub fn decode(&mut self, packet: &RtpPacket) -> anyhow::Result<&[S]> {
+        self.pcm.clear();
+        let sequence = packet.header.sequence_number;
+        let missing = match self.last_sequence {
+            None => 0,
+            Some(last) => {
+                let delta = sequence.wrapping_sub(last);
+                if delta == 0 || delta >= 0x8000 {
+                    tracing::trace!("Dropping duplicate or late packet {sequence}");
+                    self.stats.add_reordered(1);
+                    return Ok(&[]);
+                }
+                delta - 1
+            }
+        };
+        self.last_sequence = Some(sequence);
+
+        if missing > 0 && missing <= self.max_concealed_frames() {
+            for _ in 1..missing {
+                self.decode_into(&[], false, self.frame_len)?;
+                self.stats.add_concealed_plc(1);
+            }
+            self.decode_into(&packet.payload, true, self.frame_len)?;
+            self.stats.add_recovered_fec(1);
+        } else if missing > 0 {
+            tracing::debug!("Gap of {missing} frames before packet {sequence}, not concealing");
+        }
+
+        let start = self.pcm.len();
+        self.decode_into(&packet.payload, false, MAX_FRAME_SAMPLES * self.channels)?;
+        self.frame_len = self.pcm.len() - start;
+        self.stats.add_received(1);
+        Ok(&self.pcm)
+    }
+
+    /// Conceals a gap of `frames` lost frames the jitter buffer gave up waiting for.
+    /// The first [`StreamDecoder::max_concealed_frames`] come from the decoder's packet loss concealment, so the audio fades out
+    /// instead of cutting off, the rest of the gap is left to silence. The packet after the gap then decodes
+    /// as the next in sequence.
+    pub fn conceal_gap(&mut self, frames: u64) -> anyhow::Result<ConcealedGap<'_, S>> {
+        self.pcm.clear();
+        let Some(last) = self.last_sequence else {
+            return Ok(ConcealedGap {
+                pcm: &[],
+                silence: 0,
+            });
+        };
+        self.last_sequence = Some(last.wrapping_add(frames as u16));
+        let concealed = frames.min(u64::from(self.max_concealed_frames()));
+        for _ in 0..concealed {
+            self.decode_into(&[], false, self.frame_len)?;
+            self.stats.add_concealed_plc(1);
+        }
+        Ok(ConcealedGap {
+            pcm: &self.pcm,
+            silence: (frames - concealed) as usize * self.frame_len,
+        })
+    }
+
+    /// Lost frames of the stream's last decoded length that fit in [`MAX_CONCEALED_MS`], at least one
+    pub fn max_concealed_frames(&self) -> u16 {
+        let samples = MAX_CONCEALED_MS as usize * SAMPLE_RATE as usize / 1000 * self.channels;
+        (samples / self.frame_len.max(1)).clamp(1, u16::MAX as usize) as u16
+    }
+
+    fn decode_into(&mut self, payload: &[u8], fec: bool, frame_len: usize) -> anyhow::Result<()> {
+        let start = self.pcm.len();
+        self.pcm.resize(start + frame_len, S::default());
+        // Opus counts samples per channel
+        let len = S::decode(&mut self.decoder, payload, &mut self.pcm[start..], fec)?;
+        self.pcm.truncate(start + len * self.channels);
+        Ok(())
+    }
+}
+
+/// Audio standing in for a gap, see [`StreamDecoder::conceal_gap`]
+#[derive(Debug)]
+pub struct ConcealedGap<'a, S> {
+    /// Concealment frames, played right after the last packet
+    pub pcm: &'a [S],
+    /// Samples of silence following them
+    pub silence: usize,
+}
+
+/// One [`StreamDecoder`] per SSRC, since decoder and sequence state must not mix between streams.
+/// Every decoder reports to the connection's stats.
+pub struct SsrcDecoders<S: Sample = i16> {
+    decoders: HashMap<u32, StreamDecoder<S>>,
+    stats: Arc<ConnectionStats>,
+    channels: opus::Channels,
+}
+
+impl<S: Sample> SsrcDecoders<S> {
+    pub fn new(stats: Arc<ConnectionStats>) -> Self {
+        Self::with_channels(stats, opus::Channels::Mono)
+    }
+
+    /// Every stream decodes to `channels`, see [`StreamDecoder::with_channels`]
+    pub fn with_channels(stats: Arc<ConnectionStats>, channels: opus::Channels) -> Self {
+        Self {
+            decoders: HashMap::new(),
+            stats,
+            channels,
+        }
+    }
+
+    /// Decodes the packet with its SSRC's decoder, creating one on first sight of the SSRC.
+    /// See [`StreamDecoder::decode`].
+    pub fn decode(&mut self, packet: &RtpPacket) -> anyhow::Result<&[S]> {
+        let ssrc = packet.header.ssrc;
+        let stream_count = self.decoders.len();
+        let decoder = match self.decoders.entry(ssrc) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(_) if stream_count >= MAX_STREAMS_PER_CONNECTION => {
+                bail!("already decoding {stream_count} streams, not adding SSRC {ssrc}")
+            }
+            Entry::Vacant(entry) => entry.insert(StreamDecoder::with_channels(
+                self.stats.clone(),
+                self.channels,
+            )?),
+        };
+        decoder.decode(packet)
+    }
+
+    /// Conceals a gap in the stream of `ssrc`, see [`StreamDecoder::conceal_gap`].
+    /// Nothing to conceal for a stream that never decoded a packet
+    pub fn conceal_gap(&mut self, ssrc: u32, frames: u64) -> anyhow::Result<ConcealedGap<'_, S>> {
+        match self.decoders.get_mut(&ssrc) {
+            Some(decoder) => decoder.conceal_gap(frames),
+            None => Ok(ConcealedGap {
+                pcm: &[],
+                silence: 0,
+            }),
+        }
+    }
+
+    /// Number of SSRCs seen so far
+    pub fn stream_count(&self) -> usize {
+        self.decoders.len()
+    }
+}
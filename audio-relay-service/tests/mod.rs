@@ -1 +1,45 @@
+// Test files are also built as standalone crates, each pulling in `support` itself
+#![allow(clippy::duplicate_mod)]
+
+mod test_app_lifetime;
+mod test_auth_flow;
+mod test_auth_gate;
+mod test_auth_tokens;
+mod test_catch_up;
+mod test_certs;
+mod test_codec_check;
+mod test_codec_policy;
+mod test_comfort_noise;
 mod test_config;
+mod test_control_rate;
+mod test_control_streams;
+mod test_datagram_support;
+mod test_decode_errors;
+mod test_decode_pool;
+mod test_display_names;
+mod test_draining;
+mod test_duplicate_users;
+mod test_endpoint_config;
+mod test_ingress_rate;
+mod test_injection;
+mod test_jitter_buffer;
+mod test_jitter_dump;
+mod test_keepalives;
+mod test_lifecycle_events;
+mod test_logging;
+mod test_mixer_allocations;
+mod test_mixing;
+mod test_moderation;
+mod test_push_schedule;
+mod test_reconnect_tokens;
+mod test_recording;
+mod test_recording_consent;
+mod test_room_format;
+mod test_room_info;
+mod test_room_metrics;
+mod test_session_limit;
+mod test_shutdown;
+mod test_ssrc_collisions;
+mod test_stateless_retry;
+mod test_stream_decoder;
+mod test_unknown_ssrc;
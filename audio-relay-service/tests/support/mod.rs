@@ -0,0 +1,259 @@
+//! Helpers for tests that need a live QUIC connection to the relay.
+//! Include with `#[path = "support/mod.rs"] mod support;`
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use audio_relay_service::app::App;
+use audio_relay_service::common::app_config::AppConfig;
+use audio_relay_service::common::security::{certs, endpoint_config};
+use audio_relay_service::vc::stream_decoder::{FRAME_SAMPLES, SAMPLE_RATE};
+use lib_common_voxoxide::types::{
+    ARS_ALPN, ArsAuthRequest, ArsAuthResponse, ArsControlMessage, CloseCode, frame_message,
+};
+use quinn::crypto::rustls::QuicClientConfig;
+use rustls::pki_types::CertificateDer;
+use rvoip_rtp_core::RtpPacket;
+use tempfile::TempDir;
+
+pub struct TestServer {
+    pub app: Arc<App>,
+    pub addr: SocketAddr,
+    pub cert: CertificateDer<'static>,
+    _cert_dir: TempDir,
+}
+
+pub fn install_crypto_provider() {
+    let _ = rustls::crypto::CryptoProvider::install_default(
+        rustls::crypto::aws_lc_rs::default_provider(),
+    );
+}
+
+/// Config with a freshly generated self-signed certificate for `localhost`.
+pub fn test_config() -> (AppConfig, TempDir, CertificateDer<'static>) {
+    let dir = tempfile::tempdir().unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = dir.path().join("key.pem");
+    let cert = dir.path().join("cert.pem");
+    std::fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+    std::fs::write(&cert, certified.cert.pem()).unwrap();
+
+    let config = AppConfig {
+        key,
+        cert,
+        listen: "127.0.0.1:0".parse().unwrap(),
+        connection_limit: 10,
+        log_level: "debug".to_string(),
+        ..Default::default()
+    };
+    (config, dir, certified.cert.der().clone())
+}
+
+pub async fn start_server() -> TestServer {
+    start_server_with_config(|_| {}).await
+}
+
+/// Starts a server on [`test_config`] after `configure` adjusted it
+pub async fn start_server_with_config(configure: impl FnOnce(&mut AppConfig)) -> TestServer {
+    let (mut config, dir, cert) = test_config();
+    configure(&mut config);
+    start_server_with(config, dir, cert).await
+}
+
+/// Runs the app's accept loop on a random port.
+/// For configs pointing into `cert_dir`, everything else goes through [`start_server_with_config`].
+pub async fn start_server_with(
+    config: AppConfig,
+    cert_dir: TempDir,
+    cert: CertificateDer<'static>,
+) -> TestServer {
+    install_crypto_provider();
+    let app = App::new(config);
+    let (certs, key) = certs::load_certs(&app.config).unwrap();
+    let server_config = endpoint_config::create_server_config(&app.config, certs, key).unwrap();
+    let endpoint = quinn::Endpoint::server(server_config, app.config.listen).unwrap();
+    let addr = endpoint.local_addr().unwrap();
+
+    tokio::spawn(app.clone().main_loop(endpoint));
+
+    TestServer {
+        app,
+        addr,
+        cert,
+        _cert_dir: cert_dir,
+    }
+}
+
+pub async fn connect(server: &TestServer) -> quinn::Connection {
+    try_connect(server).await.unwrap()
+}
+
+pub async fn try_connect(server: &TestServer) -> Result<quinn::Connection, quinn::ConnectionError> {
+    try_connect_with_transport(server, quinn::TransportConfig::default()).await
+}
+
+/// Connects with the client's transport parameters set by `transport`
+pub async fn try_connect_with_transport(
+    server: &TestServer,
+    transport: quinn::TransportConfig,
+) -> Result<quinn::Connection, quinn::ConnectionError> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(server.cert.clone()).unwrap();
+    let mut client_crypto = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_crypto.alpn_protocols = vec![ARS_ALPN.to_vec()];
+    let mut client_config =
+        quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(client_crypto).unwrap()));
+    client_config.transport_config(Arc::new(transport));
+
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(client_config);
+    endpoint.connect(server.addr, "localhost").unwrap().await
+}
+
+/// Runs the auth handshake for `room_id` on the control stream, panicking unless it is accepted.
+pub async fn authenticate(connection: &quinn::Connection, room_id: u32) -> ArsAuthResponse {
+    authenticate_with(connection, ArsAuthRequest::for_room(room_id)).await
+}
+
+pub async fn authenticate_with(
+    connection: &quinn::Connection,
+    request: ArsAuthRequest,
+) -> ArsAuthResponse {
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    send.write_all(&frame_message(&serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    send.finish().unwrap();
+    let response = recv.read_to_end(1024).await.unwrap();
+    serde_json::from_slice(&response).unwrap()
+}
+
+/// Sends the auth request expecting the server to refuse it, returns the close code and reason.
+pub async fn authenticate_refused(
+    connection: &quinn::Connection,
+    request: ArsAuthRequest,
+) -> (Option<CloseCode>, String) {
+    let (mut send, _recv) = connection.open_bi().await.unwrap();
+    send.write_all(&frame_message(&serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    send.finish().unwrap();
+    closed_with(connection).await
+}
+
+/// Waits for the server to close the connection, returns the close code and reason.
+pub async fn closed_with(connection: &quinn::Connection) -> (Option<CloseCode>, String) {
+    let error = tokio::time::timeout(std::time::Duration::from_secs(5), connection.closed())
+        .await
+        .expect("connection was not closed");
+    match error {
+        quinn::ConnectionError::ApplicationClosed(close) => (
+            CloseCode::from_code(close.error_code.into_inner()),
+            String::from_utf8_lossy(&close.reason).into_owned(),
+        ),
+        other => panic!("unexpected close: {other:?}"),
+    }
+}
+
+/// Sends a control message on its own unidirectional stream.
+pub async fn send_control(connection: &quinn::Connection, message: &ArsControlMessage) {
+    let mut send = connection.open_uni().await.unwrap();
+    send.write_all(&serde_json::to_vec(message).unwrap())
+        .await
+        .unwrap();
+    send.finish().unwrap();
+}
+
+/// Reads the control messages the server sends until `wanted` accepts one,
+/// skipping the roster updates every join, leave and mute pushes in between.
+/// None if nothing it accepts arrives `within`
+pub async fn wait_for_control(
+    connection: &quinn::Connection,
+    within: Duration,
+    wanted: impl Fn(&ArsControlMessage) -> bool,
+) -> Option<ArsControlMessage> {
+    tokio::time::timeout(within, async {
+        loop {
+            let mut recv = connection.accept_uni().await.ok()?;
+            let message: ArsControlMessage =
+                serde_json::from_slice(&recv.read_to_end(1024).await.ok()?).unwrap();
+            if wanted(&message) {
+                return Some(message);
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Serves the app's metrics endpoint on a free local port, returns its address
+pub async fn serve_metrics(app: &Arc<App>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(audio_relay_service::common::services::metrics::serve(
+        app.clone(),
+        listener,
+    ));
+    addr
+}
+
+/// Sends one raw HTTP request to `addr`, returns the response's status line and body
+pub async fn http_request(addr: SocketAddr, request: &str) -> (String, String) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+/// Encodes `count` frames of a 440Hz tone with in-band FEC enabled.
+pub fn encode_tone_packets(count: u16) -> Vec<RtpPacket> {
+    encode_tone_packets_with(440.0, 1234, count)
+}
+
+/// Encodes `count` frames of a `frequency` tone sent as `ssrc`, with in-band FEC enabled.
+pub fn encode_tone_packets_with(frequency: f32, ssrc: u32, count: u16) -> Vec<RtpPacket> {
+    let mut encoder =
+        opus::Encoder::new(SAMPLE_RATE, opus::Channels::Mono, opus::Application::Voip).unwrap();
+    encoder.set_inband_fec(true).unwrap();
+    encoder.set_packet_loss_perc(30).unwrap();
+
+    let mut output = vec![0u8; 4000];
+    (0..count)
+        .map(|seq| {
+            let frame: Vec<i16> = (0..FRAME_SAMPLES)
+                .map(|i| {
+                    let t = (seq as usize * FRAME_SAMPLES + i) as f32 / SAMPLE_RATE as f32;
+                    ((t * frequency * std::f32::consts::TAU).sin() * 8000.0) as i16
+                })
+                .collect();
+            let len = encoder.encode(&frame, &mut output).unwrap();
+            RtpPacket::new_with_payload(
+                111,
+                seq,
+                seq as u32 * FRAME_SAMPLES as u32,
+                ssrc,
+                output[..len].to_vec().into(),
+            )
+        })
+        .collect()
+}
+
+/// Polls `condition` until it holds, panicking after 5 seconds.
+pub async fn wait_until(mut condition: impl FnMut() -> bool) {
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("condition not reached in time");
+}
@@ -0,0 +1,34 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::sync::Arc;
+
+use audio_relay_service::app::App;
+
+#[test]
+fn apps_are_freed_once_dropped() {
+    let (config, _dir, _cert) = support::test_config();
+    let first = App::new(config.clone());
+    let second = App::new(config);
+    let freed = Arc::downgrade(&first);
+
+    drop(first);
+
+    assert!(freed.upgrade().is_none());
+    assert!(!second.is_draining());
+}
+
+#[tokio::test]
+async fn shut_down_server_releases_its_app() {
+    for room_id in 0..2 {
+        let server = support::start_server().await;
+        let connection = support::connect(&server).await;
+        support::authenticate(&connection, room_id).await;
+
+        server.app.cancellation_token.cancel();
+        support::closed_with(&connection).await;
+
+        // The accept loop and the connection's tasks let go of their handles as they end
+        support::wait_until(|| Arc::strong_count(&server.app) == 1).await;
+    }
+}
@@ -0,0 +1,318 @@
+//! The auth handshake driven through an in-memory connection, no QUIC involved.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use audio_relay_service::app::App;
+use audio_relay_service::common::app_config::{
+    AppConfig, AuthSecret, RecordingConsent, RoomConfig, SsrcCollisionPolicy,
+};
+use audio_relay_service::common::services::auth::{
+    AuthConnection, AuthRecvStream, AuthSendStream, AuthenticatedMember, MAX_AUTH_REQUEST_LEN,
+    auth_user_for_session, receive_auth_request, send_auth_response,
+};
+use audio_relay_service::common::services::users::UserConnection;
+use lib_common_voxoxide::types::{
+    ArsAudioFormat, ArsAuthError, ArsAuthRequest, ArsAuthResponse, ArsControlMessage, CloseCode,
+    Features,
+};
+
+const ROOM: u32 = 7;
+
+static NEXT_CONNECTION_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Connection whose control stream carries `request`, or that never opens one if it is None.
+/// Whatever the handshake responds with ends up in `response`.
+#[derive(Clone)]
+struct InMemoryConnection {
+    id: usize,
+    request: Option<Vec<u8>>,
+    response: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+struct InMemoryRecv(Vec<u8>);
+
+struct InMemorySend(Arc<Mutex<Option<Vec<u8>>>>);
+
+impl InMemoryConnection {
+    fn new(request: Option<Vec<u8>>) -> Self {
+        Self {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            request,
+            response: Arc::default(),
+        }
+    }
+
+    fn sending(request: &ArsAuthRequest) -> Self {
+        Self::new(Some(serde_json::to_vec(request).unwrap()))
+    }
+}
+
+impl AuthConnection for InMemoryConnection {
+    type SendStream = InMemorySend;
+    type RecvStream = InMemoryRecv;
+
+    async fn accept_control_stream(&self) -> Option<(InMemorySend, InMemoryRecv)> {
+        let request = self.request.clone()?;
+        Some((InMemorySend(self.response.clone()), InMemoryRecv(request)))
+    }
+}
+
+impl UserConnection for InMemoryConnection {
+    fn stable_id(&self) -> usize {
+        self.id
+    }
+    fn remote_address(&self) -> SocketAddr {
+        "127.0.0.1:4433".parse().unwrap()
+    }
+    fn is_open(&self) -> bool {
+        true
+    }
+    fn close(&self, _code: CloseCode, _reason: &[u8]) {}
+}
+
+impl AuthRecvStream for InMemoryRecv {
+    async fn read_request(&mut self, limit: usize) -> Option<Vec<u8>> {
+        (self.0.len() <= limit).then(|| std::mem::take(&mut self.0))
+    }
+}
+
+impl AuthSendStream for InMemorySend {
+    async fn write_response(&mut self, response: &[u8]) -> anyhow::Result<()> {
+        *self.0.lock().unwrap() = Some(response.to_vec());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn valid_request_is_received_and_answered() {
+    let mut request = ArsAuthRequest::for_room(7);
+    request.display_name = Some("  Ada \t Lovelace ".to_string());
+    let connection = InMemoryConnection::sending(&request);
+
+    let (mut send, _, received) = receive_auth_request(&connection).await.unwrap();
+    assert_eq!(received.room_id, 7);
+    assert_eq!(received.display_name.as_deref(), Some("Ada Lovelace"));
+
+    let response = ArsAuthResponse {
+        member_id: 3,
+        ..Default::default()
+    };
+    send_auth_response(&mut send, &response).await.unwrap();
+    let sent = connection.response.lock().unwrap().clone().unwrap();
+    assert_eq!(
+        serde_json::from_slice::<ArsAuthResponse>(&sent).unwrap(),
+        response
+    );
+}
+
+#[tokio::test]
+async fn missing_control_stream_is_no_request() {
+    let connection = InMemoryConnection::new(None);
+
+    let error = receive_auth_request(&connection).await.unwrap_err();
+
+    assert!(matches!(error, ArsAuthError::NoAuthRequestReceived));
+}
+
+#[tokio::test]
+async fn oversized_request_is_invalid() {
+    let mut request = ArsAuthRequest::for_room(1);
+    request.moderator_token = Some("x".repeat(MAX_AUTH_REQUEST_LEN));
+    let connection = InMemoryConnection::sending(&request);
+
+    let error = receive_auth_request(&connection).await.unwrap_err();
+
+    assert!(matches!(error, ArsAuthError::InvalidAuthRequestReceived));
+}
+
+#[tokio::test]
+async fn malformed_request_is_invalid() {
+    let connection = InMemoryConnection::new(Some(b"{\"room_id\": \"seven\"}".to_vec()));
+
+    let error = receive_auth_request(&connection).await.unwrap_err();
+
+    assert!(matches!(error, ArsAuthError::InvalidAuthRequestReceived));
+}
+
+#[tokio::test]
+async fn control_characters_in_display_name_are_refused() {
+    let mut request = ArsAuthRequest::for_room(1);
+    request.display_name = Some("bell\u{7}".to_string());
+    let connection = InMemoryConnection::sending(&request);
+
+    let error = receive_auth_request(&connection).await.unwrap_err();
+
+    assert!(matches!(error, ArsAuthError::InvalidDisplayName));
+    assert!(connection.response.lock().unwrap().is_none());
+}
+
+/// A moderator already in `ROOM`, with no connection of its own
+fn present_member(ssrc: Option<u32>) -> AuthenticatedMember {
+    AuthenticatedMember {
+        room_id: ROOM,
+        moderator: true,
+        user_id: None,
+        format: ArsAudioFormat::default(),
+        features: Features::NONE,
+        display_name: None,
+        recording_consent: true,
+        ssrc,
+        session_id: 0,
+        session_key: 0,
+    }
+}
+
+fn room_config(room: RoomConfig) -> AppConfig {
+    AppConfig {
+        rooms: HashMap::from([(ROOM, room)]),
+        ..Default::default()
+    }
+}
+
+async fn refused(app: &App, request: &ArsAuthRequest) -> ArsAuthError {
+    let connection = InMemoryConnection::sending(request);
+    let error = auth_user_for_session(app, &connection).await.unwrap_err();
+    assert!(connection.response.lock().unwrap().is_none());
+    error
+}
+
+#[tokio::test]
+async fn accepted_member_is_answered_with_its_session() {
+    let app = App::new(AppConfig::default());
+    let connection = InMemoryConnection::sending(&ArsAuthRequest::for_room(ROOM));
+
+    let (member, _) = auth_user_for_session(&app, &connection).await.unwrap();
+
+    let sent = connection.response.lock().unwrap().clone().unwrap();
+    let response: ArsAuthResponse = serde_json::from_slice(&sent).unwrap();
+    assert_eq!(response.member_id, connection.id as u64);
+    assert_eq!(
+        (response.session_id, response.session_key),
+        (member.session_id, member.session_key)
+    );
+}
+
+#[tokio::test]
+async fn unsigned_request_is_unauthorized() {
+    let app = App::new(AppConfig {
+        auth_secret: Some(AuthSecret("secret".to_string())),
+        ..Default::default()
+    });
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.user_id = Some(42);
+
+    let error = refused(&app, &request).await;
+
+    assert!(matches!(error, ArsAuthError::Unauthorized));
+}
+
+#[tokio::test]
+async fn room_requiring_consent_refuses_members_without_it() {
+    let app = App::new(room_config(RoomConfig {
+        recording_consent: Some(RecordingConsent::Reject),
+        ..Default::default()
+    }));
+
+    let error = refused(&app, &ArsAuthRequest::for_room(ROOM)).await;
+
+    assert!(matches!(error, ArsAuthError::RecordingConsentRequired));
+}
+
+#[tokio::test]
+async fn locked_room_refuses_members() {
+    let app = App::new(AppConfig::default());
+    app.rooms.join(1, None, &present_member(None));
+    app.rooms
+        .apply_control(ROOM, 1, ArsControlMessage::SetRoomLocked { locked: true })
+        .unwrap();
+
+    let error = refused(&app, &ArsAuthRequest::for_room(ROOM)).await;
+
+    assert!(matches!(error, ArsAuthError::RoomLocked));
+}
+
+#[tokio::test]
+async fn ssrc_in_use_is_refused_under_the_reject_policy() {
+    let app = App::new(AppConfig {
+        ssrc_collision_policy: SsrcCollisionPolicy::Reject,
+        ..Default::default()
+    });
+    app.rooms.join(1, None, &present_member(Some(5)));
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.ssrc = Some(5);
+
+    let error = refused(&app, &request).await;
+
+    assert!(matches!(error, ArsAuthError::SsrcCollision));
+}
+
+#[tokio::test]
+async fn ssrc_of_a_member_yet_to_join_is_reserved() {
+    for (policy, reassigned) in [
+        (SsrcCollisionPolicy::Reject, None),
+        (SsrcCollisionPolicy::Reassign, Some(6)),
+    ] {
+        let app = App::new(AppConfig {
+            ssrc_collision_policy: policy,
+            ..Default::default()
+        });
+        let mut request = ArsAuthRequest::for_room(ROOM);
+        request.ssrc = Some(5);
+        let first = InMemoryConnection::sending(&request);
+        let (member, _) = auth_user_for_session(&app, &first).await.unwrap();
+        assert_eq!(member.ssrc, Some(5));
+
+        // The first member has not joined yet, its SSRC is taken all the same
+        let second = InMemoryConnection::sending(&request);
+        match auth_user_for_session(&app, &second).await {
+            Ok((member, _)) => assert_eq!(member.ssrc, reassigned),
+            Err(error) => {
+                assert!(reassigned.is_none());
+                assert!(matches!(error, ArsAuthError::SsrcCollision));
+            }
+        }
+
+        // Joining keeps it taken, by the member now instead of the reservation
+        app.rooms.join(first.id, None, &member);
+        assert!(
+            app.rooms
+                .resolve_ssrc(ROOM, 5, SsrcCollisionPolicy::Reject)
+                .is_err()
+        );
+    }
+}
+
+#[tokio::test]
+async fn second_connection_of_a_user_is_refused() {
+    let app = App::new(AppConfig::default());
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.user_id = Some(42);
+    let first = InMemoryConnection::sending(&request);
+    auth_user_for_session(&app, &first).await.unwrap();
+
+    let error = refused(&app, &request).await;
+
+    assert!(matches!(error, ArsAuthError::DuplicateUser));
+    assert!(app.users.is_claimed(42));
+}
+
+#[tokio::test]
+async fn other_format_than_the_rooms_is_refused_without_claiming_the_user() {
+    let app = App::new(room_config(RoomConfig {
+        format: Some(ArsAudioFormat {
+            sample_rate: 16_000,
+            channels: 1,
+        }),
+        ..Default::default()
+    }));
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.user_id = Some(42);
+
+    let error = refused(&app, &request).await;
+
+    assert!(matches!(error, ArsAuthError::FormatMismatch));
+    assert!(!app.users.is_claimed(42));
+}
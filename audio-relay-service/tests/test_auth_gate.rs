@@ -0,0 +1,67 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use lib_common_voxoxide::types::CloseCode;
+
+#[tokio::test]
+async fn datagrams_before_auth_are_dropped() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    let packets = support::encode_tone_packets(8);
+
+    for packet in &packets[..3] {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+    }
+    support::authenticate(&connection, 0).await;
+    for packet in &packets[3..] {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+    }
+
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().packets_received == 5).await;
+    assert_eq!(snapshot().datagrams_dropped_unauthenticated, 3);
+    // Post-auth stream starts at packet 3, nothing before it counts as lost
+    assert_eq!(snapshot().frames_concealed_plc, 0);
+    assert_eq!(snapshot().frames_recovered_fec, 0);
+}
+
+#[tokio::test]
+async fn accepted_sessions_get_their_own_id() {
+    let server = support::start_server().await;
+    let first = support::connect(&server).await;
+    let second = support::connect(&server).await;
+
+    let first = support::authenticate(&first, 0).await;
+    let second = support::authenticate(&second, 0).await;
+
+    assert_ne!(first.session_id, 0);
+    assert_ne!(first.session_id, second.session_id);
+    // Counted per app, a fresh server starts over
+    let restarted = support::start_server().await;
+    let third = support::connect(&restarted).await;
+    assert_eq!(support::authenticate(&third, 0).await.session_id, 1);
+}
+
+#[tokio::test]
+async fn connections_that_never_authenticate_are_closed() {
+    let server =
+        support::start_server_with_config(|config| config.auth_timeout_ms = Some(300)).await;
+    let connection = support::connect(&server).await;
+
+    // Audio alone doesn't keep an unauthenticated connection around
+    for packet in support::encode_tone_packets(5) {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+    }
+
+    assert_eq!(
+        support::closed_with(&connection).await,
+        (Some(CloseCode::AuthFailed), "AuthTimedOut".to_string())
+    );
+    support::wait_until(|| server.app.metrics.connection_snapshots().is_empty()).await;
+}
@@ -0,0 +1,74 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::app_config::{AppConfig, AuthSecret};
+use audio_relay_service::common::services::auth_tokens;
+use lib_common_voxoxide::types::{ArsAuthRequest, CloseCode};
+
+const USER: u64 = 42;
+const ROOM: u32 = 5;
+
+fn secret() -> AuthSecret {
+    AuthSecret("correct horse battery staple".to_string())
+}
+
+fn with_secret(config: &mut AppConfig) {
+    config.auth_secret = Some(secret());
+}
+
+fn request(user_id: Option<u64>, token: Option<String>) -> ArsAuthRequest {
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.user_id = user_id;
+    request.token = token;
+    request
+}
+
+#[test]
+fn tokens_are_bound_to_user_room_and_secret() {
+    let token = auth_tokens::issue(&secret(), USER, ROOM);
+
+    assert_eq!(token.len(), 64);
+    assert!(auth_tokens::verify(&secret(), USER, ROOM, &token));
+    assert!(!auth_tokens::verify(&secret(), USER + 1, ROOM, &token));
+    assert!(!auth_tokens::verify(&secret(), USER, ROOM + 1, &token));
+    let other = AuthSecret("another secret".to_string());
+    assert!(!auth_tokens::verify(&other, USER, ROOM, &token));
+    assert!(!auth_tokens::verify(&secret(), USER, ROOM, "not hex"));
+    assert!(!auth_tokens::verify(&secret(), USER, ROOM, &token[..63]));
+}
+
+#[test]
+fn the_secret_is_never_printed() {
+    let printed = format!("{:?}", secret());
+    assert!(!printed.contains("horse"));
+}
+
+#[tokio::test]
+async fn valid_tokens_are_admitted() {
+    let server = support::start_server_with_config(with_secret).await;
+    let connection = support::connect(&server).await;
+
+    let token = auth_tokens::issue(&secret(), USER, ROOM);
+    support::authenticate_with(&connection, request(Some(USER), Some(token))).await;
+    assert!(connection.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn missing_or_forged_tokens_are_unauthorized() {
+    let server = support::start_server_with_config(with_secret).await;
+    let forged = auth_tokens::issue(&AuthSecret("guess".to_string()), USER, ROOM);
+    let other_room = auth_tokens::issue(&secret(), USER, ROOM + 1);
+
+    for request in [
+        request(None, None),
+        request(Some(USER), None),
+        request(Some(USER), Some(forged)),
+        request(Some(USER), Some(other_room)),
+    ] {
+        let connection = support::connect(&server).await;
+        assert_eq!(
+            support::authenticate_refused(&connection, request).await,
+            (Some(CloseCode::AuthFailed), "Unauthorized".to_string())
+        );
+    }
+}
@@ -0,0 +1,101 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::FRAME_DURATION_MS;
+use audio_relay_service::vc::catch_up::CatchUpBuffer;
+use audio_relay_service::vc::mixer::MIXER_SSRC;
+use rvoip_rtp_core::RtpPacket;
+
+const ROOM: u32 = 5;
+const FRAME: Duration = Duration::from_millis(FRAME_DURATION_MS);
+
+fn frame(value: i16) -> Vec<i16> {
+    vec![value; 4]
+}
+
+#[test]
+fn buffer_retains_at_most_the_configured_duration() {
+    let mut buffer = CatchUpBuffer::new(Duration::from_millis(500), FRAME);
+    assert_eq!(buffer.capacity(), 25);
+
+    for value in 0..40 {
+        buffer.push(frame(value));
+    }
+
+    assert_eq!(buffer.len(), 25);
+    // The 15 oldest frames were evicted
+    let kept: Vec<Vec<i16>> = buffer.frames().map(<[i16]>::to_vec).collect();
+    let expected: Vec<Vec<i16>> = (15..40).map(frame).collect();
+    assert_eq!(kept, expected);
+}
+
+#[test]
+fn partial_frames_do_not_count_towards_the_duration() {
+    let buffer = CatchUpBuffer::new(Duration::from_millis(50), FRAME);
+    assert_eq!(buffer.capacity(), 2);
+
+    let mut buffer = CatchUpBuffer::new(Duration::from_millis(10), FRAME);
+    buffer.push(frame(1));
+    assert!(buffer.is_empty());
+}
+
+/// Mixes a few frames in a room, then returns the datagrams a member joining afterwards gets
+/// ahead of its first live mix
+async fn heard_by_late_joiner(catch_up_ms: Option<u64>) -> Vec<RtpPacket> {
+    let server = support::start_server_with_config(|config| {
+        config.mixing_threshold = Some(1);
+        config.catch_up_ms = catch_up_ms;
+    })
+    .await;
+    let speaker = support::connect(&server).await;
+    support::authenticate(&speaker, ROOM).await;
+    let listener = support::connect(&server).await;
+    support::authenticate(&listener, ROOM).await;
+
+    for packet in support::encode_tone_packets(5) {
+        speaker.send_datagram(packet.serialize().unwrap()).unwrap();
+    }
+    for _ in 0..5 {
+        tokio::time::timeout(Duration::from_secs(2), listener.read_datagram())
+            .await
+            .expect("the room was not mixed")
+            .unwrap();
+    }
+
+    let late = support::connect(&server).await;
+    support::authenticate(&late, ROOM).await;
+    // The live mix keeps sending silence, it picks up the sequence right after the catch-up
+    let catch_up_frames = catch_up_ms.unwrap_or(0) / FRAME_DURATION_MS;
+    let mut heard = Vec::new();
+    loop {
+        let datagram = tokio::time::timeout(Duration::from_secs(2), late.read_datagram())
+            .await
+            .expect("the late joiner got no live mix")
+            .unwrap();
+        let packet = RtpPacket::parse(&datagram).unwrap();
+        if u64::from(packet.header.sequence_number) >= catch_up_frames {
+            return heard;
+        }
+        heard.push(packet);
+    }
+}
+
+#[tokio::test]
+async fn late_joiner_gets_the_most_recent_mix() {
+    let heard = heard_by_late_joiner(Some(3 * FRAME_DURATION_MS)).await;
+
+    assert_eq!(heard.len(), 3);
+    assert!(heard.iter().all(|packet| packet.header.ssrc == MIXER_SSRC));
+    let sequence: Vec<u16> = heard
+        .iter()
+        .map(|packet| packet.header.sequence_number)
+        .collect();
+    assert_eq!(sequence, vec![0, 1, 2]);
+}
+
+#[tokio::test]
+async fn late_joiner_gets_nothing_without_catch_up() {
+    assert!(heard_by_late_joiner(None).await.is_empty());
+}
@@ -0,0 +1,47 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::app_config::AppConfig;
+use audio_relay_service::common::security::certs::load_certs;
+
+#[test]
+fn cert_and_key_load_from_an_env_var_and_inline_pem() {
+    let (config, _dir, cert) = support::test_config();
+    let key_pem = std::fs::read_to_string(&config.key).unwrap();
+    let cert_pem = std::fs::read_to_string(&config.cert).unwrap();
+    unsafe { std::env::set_var("ARS_TEST_KEY_PEM", key_pem) };
+
+    let config = AppConfig {
+        key: "env:ARS_TEST_KEY_PEM".into(),
+        cert: cert_pem.into(),
+        ..config
+    };
+    let (chain, _key) = load_certs(&config).unwrap();
+
+    assert_eq!(chain, [cert]);
+}
+
+#[test]
+fn inline_keys_are_refused() {
+    let (config, _dir, _cert) = support::test_config();
+    let key_pem = std::fs::read_to_string(&config.key).unwrap();
+
+    let config = AppConfig {
+        key: key_pem.into(),
+        ..config
+    };
+
+    assert!(load_certs(&config).is_err());
+}
+
+#[test]
+fn unset_env_var_fails_naming_it() {
+    let (config, _dir, _cert) = support::test_config();
+    let config = AppConfig {
+        cert: "env:ARS_TEST_UNSET_CERT_PEM".into(),
+        ..config
+    };
+
+    let error = load_certs(&config).unwrap_err();
+    assert!(format!("{error:#}").contains("$ARS_TEST_UNSET_CERT_PEM"));
+}
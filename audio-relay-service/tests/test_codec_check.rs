@@ -0,0 +1,69 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::HashMap;
+
+use audio_relay_service::app::App;
+use audio_relay_service::common::app_config::{AppConfig, OpusSettings, RoomConfig};
+use audio_relay_service::vc::codec_check;
+use lib_common_voxoxide::types::ArsAudioFormat;
+use tempfile::TempDir;
+
+/// The config with room 7 set to the format, along with the directory its key and certificate live in
+fn config_with_format(sample_rate: u32, channels: u8) -> (AppConfig, TempDir) {
+    let (config, dir, _cert) = support::test_config();
+    let config = AppConfig {
+        rooms: HashMap::from([(
+            7,
+            RoomConfig {
+                format: Some(ArsAudioFormat {
+                    sample_rate,
+                    channels,
+                }),
+                ..Default::default()
+            },
+        )]),
+        ..config
+    };
+    (config, dir)
+}
+
+#[test]
+fn rates_opus_takes_round_trip() {
+    for sample_rate in [8_000, 12_000, 16_000, 24_000, 48_000] {
+        for channels in [1, 2] {
+            let format = ArsAudioFormat {
+                sample_rate,
+                channels,
+            };
+            codec_check::round_trip(format, OpusSettings::default()).unwrap();
+        }
+    }
+    codec_check::check(&config_with_format(16_000, 2).0).unwrap();
+}
+
+#[test]
+fn unsupported_formats_fail_naming_the_room() {
+    let error = codec_check::check(&config_with_format(44_100, 1).0).unwrap_err();
+    assert!(error.to_string().contains("room 7 at 44100Hz"), "{error}");
+
+    assert!(codec_check::check(&config_with_format(48_000, 3).0).is_err());
+}
+
+#[tokio::test]
+async fn startup_fails_before_listening() {
+    support::install_crypto_provider();
+    // The key and certificate exist, so only the self-check can stop it
+    let (config, _dir) = config_with_format(44_100, 1);
+    let app = App::new(config);
+
+    let error = app.run().await.unwrap_err();
+    assert!(error.to_string().contains("room 7 at 44100Hz"), "{error}");
+
+    let disabled = AppConfig {
+        codec_self_check: Some(false),
+        ..support::test_config().0
+    };
+    assert!(!disabled.is_codec_self_check_enabled());
+    assert!(support::test_config().0.is_codec_self_check_enabled());
+}
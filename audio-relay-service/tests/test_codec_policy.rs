@@ -0,0 +1,96 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::HashMap;
+
+use audio_relay_service::common::app_config::{AppConfig, RoomConfig};
+use audio_relay_service::common::services::auth::SERVER_FEATURES;
+use lib_common_voxoxide::types::{ArsAuthRequest, ArsCodecPolicy, Features};
+
+const POLICY: ArsCodecPolicy = ArsCodecPolicy {
+    bitrate: Some(32_000),
+    channels: Some(2),
+    fec: Some(true),
+};
+
+fn with_policy(config: &mut AppConfig) {
+    config.rooms = HashMap::from([(
+        10,
+        RoomConfig {
+            codec_policy: Some(POLICY),
+            ..Default::default()
+        },
+    )]);
+}
+
+#[tokio::test]
+async fn room_policy_is_advertised_on_auth() {
+    let server = support::start_server_with_config(with_policy).await;
+    let connection = support::connect(&server).await;
+
+    let response = support::authenticate(&connection, 10).await;
+
+    assert_eq!(response.codec_policy, Some(POLICY));
+}
+
+#[tokio::test]
+async fn room_without_policy_advertises_none() {
+    let server = support::start_server_with_config(with_policy).await;
+    let connection = support::connect(&server).await;
+
+    let response = support::authenticate(&connection, 11).await;
+
+    assert_eq!(response.codec_policy, None);
+}
+
+#[test]
+fn rooms_are_read_from_yaml() {
+    let config: AppConfig = serde_yaml::from_str(
+        r#"
+environment: development
+key: "key.pem"
+cert: "cert.pem"
+listen: "[::1]:5555"
+connection_limit: 100
+log_level: "info"
+rooms:
+  10:
+    codec_policy: { bitrate: 32000, channels: 2, fec: true }
+  11: {}
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.get_codec_policy(10), Some(POLICY));
+    assert_eq!(config.get_codec_policy(11), None);
+    assert_eq!(config.get_codec_policy(12), None);
+}
+
+#[tokio::test]
+async fn fec_policy_is_dropped_for_members_without_fec() {
+    let server = support::start_server_with_config(with_policy).await;
+    let connection = support::connect(&server).await;
+    let mut request = ArsAuthRequest::for_room(10);
+    request.features = Some(Features::DTX);
+
+    let response = support::authenticate_with(&connection, request).await;
+
+    assert_eq!(response.features, Features::NONE);
+    assert_eq!(
+        response.codec_policy,
+        Some(ArsCodecPolicy {
+            fec: Some(false),
+            ..POLICY
+        })
+    );
+}
+
+#[tokio::test]
+async fn members_predating_negotiation_get_every_server_feature() {
+    let server = support::start_server_with_config(with_policy).await;
+    let connection = support::connect(&server).await;
+
+    let response = support::authenticate(&connection, 10).await;
+
+    assert_eq!(response.features, SERVER_FEATURES);
+}
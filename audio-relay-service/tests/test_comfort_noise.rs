@@ -0,0 +1,62 @@
+use audio_relay_service::vc::comfort_noise::ComfortNoise;
+use audio_relay_service::vc::recording::Recording;
+use audio_relay_service::vc::stream_decoder::FRAME_SAMPLES;
+
+fn rms(samples: &[i16]) -> f32 {
+    let energy: f64 = samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+    (energy / samples.len() as f64).sqrt() as f32
+}
+
+fn tone(amplitude: f32) -> Vec<i16> {
+    (0..FRAME_SAMPLES)
+        .map(|i| ((i as f32 * 0.1).sin() * amplitude) as i16)
+        .collect()
+}
+
+#[test]
+fn noise_tracks_last_active_frame() {
+    let mut noise = ComfortNoise::new(-20.0);
+    for amplitude in [8000.0, 2000.0] {
+        let frame = tone(amplitude);
+        noise.observe(&frame);
+
+        let generated: Vec<i16> = (0..48_000).map(|_| noise.next_sample()).collect();
+        let expected = rms(&frame) / 10.0;
+        let measured = rms(&generated);
+        assert!(
+            (measured - expected).abs() < expected * 0.1,
+            "measured {measured}, expected {expected}"
+        );
+    }
+}
+
+#[test]
+fn noise_is_silent_before_any_frame() {
+    let mut noise = ComfortNoise::new(-20.0);
+    assert!((0..100).all(|_| noise.next_sample() == 0));
+}
+
+#[test]
+fn recording_fills_gaps_with_comfort_noise() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("noise.wav");
+    let frame = tone(8000.0);
+    let mut recording = Recording::create(&path, false)
+        .unwrap()
+        .with_comfort_noise(Some(-30.0));
+    recording.write_samples(&frame).unwrap();
+    recording.write_silence(FRAME_SAMPLES * 10).unwrap();
+    drop(recording);
+
+    let samples: Vec<i16> = hound::WavReader::open(&path)
+        .unwrap()
+        .into_samples()
+        .map(Result::unwrap)
+        .collect();
+    let gap = rms(&samples[FRAME_SAMPLES..]);
+    let expected = rms(&frame) * 10f32.powf(-30.0 / 20.0);
+    assert!(
+        (gap - expected).abs() < expected * 0.1,
+        "gap {gap}, expected {expected}"
+    );
+}
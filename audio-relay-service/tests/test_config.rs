@@ -1,10 +1,30 @@
 use std::env;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
 
 use audio_relay_service::common::app_config::{
-    AppConfig, AppConfigArgs, CONFIG_PATH_ENV, Environment,
+    AppConfig, AppConfigArgs, CONFIG_PATH_ENV, ControlRateEnforcement, DEFAULT_AUTH_TIMEOUT_MS,
+    DEFAULT_CONNECTION_LIMIT, DEFAULT_MAX_BUFFERED_PACKETS, DEFAULT_MAX_DECODE_ERRORS,
+    DEFAULT_TARGET_LATENCY_MS, DuplicateUserPolicy, Environment, LatencySettings,
+    MAX_OPUS_COMPLEXITY, OpusApplication, OpusSettings, PreAuthDatagrams, PushStrategy,
+    SsrcCollisionPolicy, UnknownSsrcPolicy,
 };
 
 use clap::Parser;
+use lib_common_voxoxide::types::VoxoxideError;
+
+/// `from_args` reads the process-wide config path variable, tests loading a config hold this
+/// so one setting it doesn't redirect another's load
+static CONFIG_PATH_ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Locks the config path variable, cleared for whoever holds it
+fn lock_env() -> MutexGuard<'static, ()> {
+    let guard = CONFIG_PATH_ENV_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    unsafe { env::remove_var(CONFIG_PATH_ENV) };
+    guard
+}
 
 fn build_args(config_path: &str) -> AppConfigArgs {
     AppConfigArgs::parse_from(["test-bin", "--config", config_path])
@@ -12,6 +32,7 @@ fn build_args(config_path: &str) -> AppConfigArgs {
 
 #[test]
 fn loads_valid_yaml_config() {
+    let _env = lock_env();
     let mut args = build_args("tests/resources/valid-test-config.yaml");
 
     let config = AppConfig::from_args(&mut args).unwrap();
@@ -22,9 +43,75 @@ fn loads_valid_yaml_config() {
     assert_eq!(config.listen.to_string(), "[::1]:5555");
 }
 
+#[test]
+fn minimal_yaml_takes_defaults_for_the_rest() {
+    let _env = lock_env();
+    let mut args = build_args("tests/resources/minimal-test-config.yaml");
+
+    let config = AppConfig::from_args(&mut args).unwrap();
+
+    assert_eq!(config.key.to_str(), Some("key.pem"));
+    assert_eq!(config.cert.to_str(), Some("cert.pem"));
+    assert_eq!(config.environment, Environment::Development);
+    assert_eq!(config.listen.to_string(), "[::1]:4433");
+    assert_eq!(config.connection_limit, DEFAULT_CONNECTION_LIMIT);
+    assert_eq!(config.log_level, "info");
+    assert_eq!(config.log_file, None);
+    assert!(config.is_stateless_retry_enabled());
+    assert!(config.cipher_suites.is_empty());
+    assert_eq!(config.metrics_listen, None);
+    assert_eq!(
+        config.get_latency_settings(),
+        LatencySettings::from_target(DEFAULT_TARGET_LATENCY_MS)
+    );
+    assert_eq!(config.get_recording_dir(), std::path::PathBuf::new());
+    assert_eq!(config.get_max_decode_errors(), DEFAULT_MAX_DECODE_ERRORS);
+    assert_eq!(
+        config.get_max_buffered_packets(),
+        DEFAULT_MAX_BUFFERED_PACKETS
+    );
+    assert_eq!(
+        config.control_rate_enforcement,
+        ControlRateEnforcement::Drop
+    );
+    assert_eq!(config.pre_auth_datagrams, PreAuthDatagrams::Drop);
+    assert_eq!(config.duplicate_user_policy, DuplicateUserPolicy::Reject);
+    assert_eq!(config.unknown_ssrc_policy, UnknownSsrcPolicy::Drop);
+    assert_eq!(config.ssrc_collision_policy, SsrcCollisionPolicy::Reassign);
+    assert_eq!(config.roster_push_strategy, PushStrategy::default());
+    assert_eq!(config.mixing_threshold, None);
+    assert_eq!(config.get_max_session(), None);
+    assert_eq!(
+        config.get_auth_timeout(),
+        Duration::from_millis(DEFAULT_AUTH_TIMEOUT_MS)
+    );
+    assert_eq!(
+        config.get_opus_settings(),
+        OpusSettings::for_environment(Environment::Development)
+    );
+    assert!(config.rooms.is_empty());
+}
+
+#[test]
+fn cli_fills_in_a_minimal_yaml() {
+    let _env = lock_env();
+    let mut args = AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/minimal-test-config.yaml",
+        "--log-level",
+        "debug",
+    ]);
+
+    let config = AppConfig::from_args(&mut args).unwrap();
+
+    assert_eq!(config.log_level, "debug");
+    assert_eq!(config.connection_limit, DEFAULT_CONNECTION_LIMIT);
+}
+
 #[test]
 fn cli_overrides_yaml_values() {
-    unsafe { env::remove_var(CONFIG_PATH_ENV) };
+    let _env = lock_env();
 
     let mut args = AppConfigArgs::parse_from([
         "test-bin",
@@ -40,8 +127,26 @@ fn cli_overrides_yaml_values() {
     assert_eq!(config.connection_limit, 999);
 }
 
+#[test]
+fn cli_listen_overrides_yaml_listen() {
+    let _env = lock_env();
+    let mut args = AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "--listen",
+        "127.0.0.1:6666",
+    ]);
+
+    let config = AppConfig::from_args(&mut args).unwrap();
+
+    // The YAML says [::1]:5555
+    assert_eq!(config.listen.to_string(), "127.0.0.1:6666");
+}
+
 #[test]
 fn env_var_overrides_cli_config_path() {
+    let _env = lock_env();
     // CLI path should be ignored
     unsafe { env::set_var(CONFIG_PATH_ENV, "tests/resources/valid-test-config.yaml") };
     let mut args = build_args("tests/resources/invalid-test-config-missing-field.yaml");
@@ -61,7 +166,7 @@ fn env_var_overrides_cli_config_path() {
 
 #[test]
 fn fails_on_invalid_yaml() {
-    unsafe { env::remove_var(CONFIG_PATH_ENV) };
+    let _env = lock_env();
 
     let mut args = build_args("tests/resources/invalid-test-config-missing-field.yaml");
 
@@ -72,7 +177,7 @@ fn fails_on_invalid_yaml() {
 
 #[test]
 fn fails_if_file_does_not_exist() {
-    unsafe { env::remove_var(CONFIG_PATH_ENV) };
+    let _env = lock_env();
 
     let mut args = build_args("tests/resources/does-not-exist.yaml");
 
@@ -80,3 +185,167 @@ fn fails_if_file_does_not_exist() {
     println!("{:?}", result);
     assert!(result.is_err());
 }
+
+#[test]
+fn stray_positional_argument_is_rejected() {
+    // e.g. a flag missing its dashes
+    let result = AppConfigArgs::try_parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "connection-limit",
+    ]);
+
+    let error = result.unwrap_err();
+    assert_eq!(error.kind(), clap::error::ErrorKind::UnknownArgument);
+}
+
+#[test]
+fn target_latency_derives_jitter_buffer_depth() {
+    let _env = lock_env();
+    let mut args = AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "--target-latency-ms",
+        "100",
+    ]);
+
+    let latency = AppConfig::from_args(&mut args)
+        .unwrap()
+        .get_latency_settings();
+
+    // 100ms of 20ms frames
+    assert_eq!(latency.jitter_buffer_depth, 5);
+    assert_eq!(latency.inactivity_timeout, Duration::from_secs(10));
+    assert!(latency.keepalive_interval < latency.inactivity_timeout);
+}
+
+#[test]
+fn explicit_latency_settings_override_derived_ones() {
+    let _env = lock_env();
+    let mut args = AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "--target-latency-ms",
+        "100",
+        "--jitter-buffer-depth",
+        "2",
+    ]);
+
+    let latency = AppConfig::from_args(&mut args)
+        .unwrap()
+        .get_latency_settings();
+
+    assert_eq!(latency.jitter_buffer_depth, 2);
+    assert_eq!(
+        latency,
+        LatencySettings {
+            jitter_buffer_depth: 2,
+            ..LatencySettings::from_target(100)
+        }
+    );
+}
+
+#[test]
+fn default_latency_target_is_three_frames() {
+    let latency = LatencySettings::from_target(DEFAULT_TARGET_LATENCY_MS);
+    assert_eq!(latency.jitter_buffer_depth, 3);
+    assert_eq!(latency.inactivity_timeout, Duration::from_secs(6));
+    // Timeout never drops below the floor for low targets
+    let latency = LatencySettings::from_target(20);
+    assert_eq!(latency.jitter_buffer_depth, 1);
+    assert_eq!(latency.inactivity_timeout, Duration::from_secs(5));
+    // Absurd targets saturate instead of overflowing
+    let latency = LatencySettings::from_target(u64::MAX);
+    assert_eq!(latency.inactivity_timeout, Duration::from_millis(u64::MAX));
+}
+
+#[test]
+fn opus_defaults_depend_on_the_environment() {
+    let _env = lock_env();
+    let development =
+        AppConfig::from_args(&mut build_args("tests/resources/valid-test-config.yaml"))
+            .unwrap()
+            .get_opus_settings();
+    let production = AppConfig::from_args(&mut AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "--environment",
+        "production",
+    ]))
+    .unwrap()
+    .get_opus_settings();
+
+    assert_ne!(production, development);
+    assert_eq!(
+        production,
+        OpusSettings::for_environment(Environment::Production)
+    );
+    assert_eq!(production.application, OpusApplication::Voip);
+    assert!(production.complexity > development.complexity);
+}
+
+#[test]
+fn explicit_opus_settings_override_the_environment_defaults() {
+    let _env = lock_env();
+    let mut args = AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "--environment",
+        "production",
+        "--opus-application",
+        "audio",
+        "--opus-complexity",
+        "2",
+    ]);
+
+    let opus = AppConfig::from_args(&mut args).unwrap().get_opus_settings();
+
+    assert_eq!(
+        opus,
+        OpusSettings {
+            application: OpusApplication::Audio,
+            complexity: 2,
+        }
+    );
+}
+
+#[test]
+fn opus_complexity_out_of_range_is_rejected() {
+    let _env = lock_env();
+    let mut args = AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "--opus-complexity",
+        "42",
+    ]);
+
+    let error = AppConfig::from_args(&mut args).unwrap_err();
+
+    assert!(error.to_string().contains(&MAX_OPUS_COMPLEXITY.to_string()));
+}
+
+#[test]
+fn comfort_noise_above_the_active_level_is_rejected() {
+    let _env = lock_env();
+    let mut args = AppConfigArgs::parse_from([
+        "test-bin",
+        "--config",
+        "tests/resources/valid-test-config.yaml",
+        "--comfort-noise-level-db",
+        "6",
+    ]);
+
+    let error = AppConfig::from_args(&mut args).unwrap_err();
+
+    assert!(matches!(
+        error.downcast_ref::<VoxoxideError>(),
+        Some(VoxoxideError::Config(_))
+    ));
+    assert!(error.to_string().contains("comfort_noise_level_db"));
+}
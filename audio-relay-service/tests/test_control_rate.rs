@@ -0,0 +1,90 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::{ControlRateEnforcement, RoomConfig};
+use audio_relay_service::vc::control_rate::{CONTROL_RATE_WINDOW, ControlRate};
+use audio_relay_service::vc::room_events::RoomEventKind;
+use lib_common_voxoxide::types::{ArsAuthRequest, ArsControlMessage, CloseCode};
+use tokio::time::Instant;
+
+const ROOM: u32 = 12;
+
+#[test]
+fn rate_allows_up_to_the_limit_per_window() {
+    let mut rate = ControlRate::new(2);
+    let start = Instant::now();
+
+    assert!(rate.allow_at(start));
+    assert!(rate.allow_at(start + Duration::from_millis(100)));
+    assert!(!rate.allow_at(start + Duration::from_millis(200)));
+    // The first message leaves the window, the rejected one never counted
+    assert!(rate.allow_at(start + CONTROL_RATE_WINDOW));
+    assert!(!rate.allow_at(start + CONTROL_RATE_WINDOW));
+}
+
+/// Sends `count` control messages back to back with a limit of 2 per second
+async fn flood_as_moderator(
+    enforcement: ControlRateEnforcement,
+    count: usize,
+) -> (support::TestServer, quinn::Connection) {
+    let server = support::start_server_with_config(|config| {
+        config.max_control_messages_per_sec = Some(2);
+        config.control_rate_enforcement = enforcement;
+        config.rooms = HashMap::from([(
+            ROOM,
+            RoomConfig {
+                moderator_token: Some("secret".to_string()),
+                ..Default::default()
+            },
+        )]);
+    })
+    .await;
+    let moderator = support::connect(&server).await;
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.moderator_token = Some("secret".to_string());
+    support::authenticate_with(&moderator, request).await;
+
+    for i in 0..count {
+        let muted = i % 2 == 0;
+        support::send_control(&moderator, &ArsControlMessage::SetAllMuted { muted }).await;
+    }
+    (server, moderator)
+}
+
+fn all_muted_events(server: &support::TestServer) -> usize {
+    server
+        .app
+        .rooms
+        .event_log(ROOM)
+        .unwrap_or_default()
+        .iter()
+        .filter(|event| matches!(event.kind, RoomEventKind::AllMuted { .. }))
+        .count()
+}
+
+#[tokio::test]
+async fn messages_over_the_rate_are_dropped() {
+    let (server, moderator) = flood_as_moderator(ControlRateEnforcement::Drop, 5).await;
+
+    support::wait_until(|| all_muted_events(&server) == 2).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(all_muted_events(&server), 2);
+    assert!(moderator.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn close_enforcement_closes_the_connection() {
+    // The third message is the first over the limit, sending more would race the close
+    let (_server, moderator) = flood_as_moderator(ControlRateEnforcement::Close, 3).await;
+
+    assert_eq!(
+        support::closed_with(&moderator).await,
+        (
+            Some(CloseCode::ProtocolError),
+            "control message rate exceeded".to_string()
+        )
+    );
+}
@@ -0,0 +1,135 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::{PreAuthDatagrams, RoomConfig};
+use lib_common_voxoxide::types::{
+    ArsAuthRequest, ArsAuthResponse, ArsControlMessage, CloseCode, frame_message,
+};
+
+const ROOM: u32 = 5;
+
+#[tokio::test]
+async fn second_control_stream_is_rejected() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    let (mut send, _recv) = tokio::time::timeout(Duration::from_secs(5), connection.open_bi())
+        .await
+        .expect("stream credit was not returned after auth")
+        .unwrap();
+    // Streams are only announced to the peer once data is sent
+    send.write_all(b"{}").await.unwrap();
+
+    let error = tokio::time::timeout(Duration::from_secs(5), connection.closed())
+        .await
+        .expect("second control stream did not close the connection");
+    match error {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(
+                CloseCode::from_code(close.error_code.into_inner()),
+                Some(CloseCode::ProtocolError)
+            );
+        }
+        other => panic!("unexpected close: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn second_control_stream_is_rejected_while_the_auth_stream_is_open() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    let (mut auth_send, mut auth_recv) = connection.open_bi().await.unwrap();
+    auth_send
+        .write_all(&framed(&ArsAuthRequest::for_room(ROOM)))
+        .await
+        .unwrap();
+    auth_recv.read_to_end(1024).await.unwrap();
+
+    let (mut send, _recv) = tokio::time::timeout(Duration::from_secs(5), connection.open_bi())
+        .await
+        .expect("no credit for a second stream while the auth stream is open")
+        .unwrap();
+    send.write_all(b"{}").await.unwrap();
+
+    assert_eq!(
+        support::closed_with(&connection).await.0,
+        Some(CloseCode::ProtocolError)
+    );
+}
+
+#[tokio::test]
+async fn control_message_on_the_auth_stream_is_applied() {
+    let server = support::start_server_with_config(|config| {
+        config.rooms = HashMap::from([(
+            ROOM,
+            RoomConfig {
+                moderator_token: Some("secret".to_string()),
+                ..Default::default()
+            },
+        )])
+    })
+    .await;
+    let member = support::connect(&server).await;
+    let member_id = support::authenticate(&member, ROOM).await.member_id as usize;
+    let moderator = support::connect(&server).await;
+
+    // The request is one framed message, the stream stays open for control messages
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.moderator_token = Some("secret".to_string());
+    let (mut send, mut recv) = moderator.open_bi().await.unwrap();
+    send.write_all(&framed(&request)).await.unwrap();
+    let response: ArsAuthResponse =
+        serde_json::from_slice(&recv.read_to_end(1024).await.unwrap()).unwrap();
+    assert!(response.moderator);
+    send.write_all(&framed(&ArsControlMessage::SetAllMuted { muted: true }))
+        .await
+        .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while server.app.rooms.is_muted(ROOM, member_id) != Some(true) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the control message on the auth stream was not applied");
+    assert!(moderator.close_reason().is_none());
+}
+
+/// Pretty printed, so the messages span several lines
+fn framed(message: &impl serde::Serialize) -> Vec<u8> {
+    frame_message(&serde_json::to_vec_pretty(message).unwrap())
+}
+
+/// Sends a tone before authenticating, returns how many of its packets another member hears
+async fn heard_from_pre_auth_audio(pre_auth_datagrams: PreAuthDatagrams) -> usize {
+    let server =
+        support::start_server_with_config(|config| config.pre_auth_datagrams = pre_auth_datagrams)
+            .await;
+    let listener = support::connect(&server).await;
+    support::authenticate(&listener, ROOM).await;
+    let speaker = support::connect(&server).await;
+
+    for packet in support::encode_tone_packets(5) {
+        speaker.send_datagram(packet.serialize().unwrap()).unwrap();
+    }
+    support::authenticate(&speaker, ROOM).await;
+
+    let mut heard = 0;
+    while tokio::time::timeout(Duration::from_millis(300), listener.read_datagram())
+        .await
+        .is_ok()
+    {
+        heard += 1;
+    }
+    heard
+}
+
+#[tokio::test]
+async fn pre_auth_datagrams_are_dropped_or_buffered() {
+    assert_eq!(heard_from_pre_auth_audio(PreAuthDatagrams::Drop).await, 0);
+    assert_eq!(heard_from_pre_auth_audio(PreAuthDatagrams::Buffer).await, 5);
+}
@@ -0,0 +1,24 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use lib_common_voxoxide::types::CloseCode;
+
+#[tokio::test]
+async fn peers_without_datagram_support_are_closed_with_a_clear_reason() {
+    let server = support::start_server().await;
+    let mut transport = quinn::TransportConfig::default();
+    transport.datagram_receive_buffer_size(None);
+
+    let connection = support::try_connect_with_transport(&server, transport)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        support::closed_with(&connection).await,
+        (
+            Some(CloseCode::ProtocolError),
+            "datagrams are required".to_string()
+        )
+    );
+    assert!(server.app.metrics.connection_snapshots().is_empty());
+}
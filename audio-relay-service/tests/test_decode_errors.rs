@@ -0,0 +1,121 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::AppConfig;
+use audio_relay_service::vc::decode_errors::DecodeErrorWindow;
+use lib_common_voxoxide::types::CloseCode;
+use rvoip_rtp_core::RtpPacket;
+use tokio::time::Instant;
+
+/// Valid RTP carrying an Opus code 3 packet that declares zero frames
+fn garbage_packet(seq: u16) -> Vec<u8> {
+    RtpPacket::new_with_payload(111, seq, 0, 1234, vec![0xff, 0x00].into())
+        .serialize()
+        .unwrap()
+        .to_vec()
+}
+
+fn limited_to(max_decode_errors: usize) -> impl FnOnce(&mut AppConfig) {
+    move |config| {
+        config.max_decode_errors = Some(max_decode_errors);
+        config.decode_error_window_ms = Some(10_000);
+    }
+}
+
+#[test]
+fn window_only_trips_on_errors_within_it() {
+    let mut window = DecodeErrorWindow::new(3, Duration::from_secs(1));
+    let start = Instant::now();
+
+    // Sporadic errors age out before the limit is reached
+    for i in 0..10 {
+        assert!(!window.record_at(start + Duration::from_millis(400 * i)));
+    }
+
+    let burst = start + Duration::from_secs(10);
+    for i in 0..3 {
+        assert!(!window.record_at(burst + Duration::from_millis(i)));
+    }
+    assert!(window.record_at(burst + Duration::from_millis(3)));
+}
+
+#[tokio::test]
+async fn sustained_decode_errors_close_the_connection() {
+    let server = support::start_server_with_config(limited_to(5)).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    for seq in 0..20 {
+        if connection
+            .send_datagram(garbage_packet(seq).into())
+            .is_err()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let error = tokio::time::timeout(Duration::from_secs(5), connection.closed())
+        .await
+        .expect("decode errors did not close the connection");
+    match error {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(
+                CloseCode::from_code(close.error_code.into_inner()),
+                Some(CloseCode::ProtocolError)
+            );
+        }
+        other => panic!("unexpected close: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn sporadic_decode_errors_are_tolerated() {
+    let server = support::start_server_with_config(limited_to(5)).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    // Every fourth datagram is broken, 5 errors in total stays at the limit
+    for packet in support::encode_tone_packets(20) {
+        let seq = packet.header.sequence_number;
+        let datagram = if seq % 4 == 0 {
+            garbage_packet(seq).into()
+        } else {
+            packet.serialize().unwrap()
+        };
+        connection.send_datagram(datagram).unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().decode_errors == 5 && snapshot().packets_received == 15)
+        .await;
+    assert!(connection.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn short_datagrams_are_dropped_without_counting_as_errors() {
+    // A single decode error would close the connection
+    let server = support::start_server_with_config(limited_to(0)).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    for packet in support::encode_tone_packets(6) {
+        connection.send_datagram(bytes::Bytes::new()).unwrap();
+        connection
+            .send_datagram(bytes::Bytes::from_static(&[0x80, 111, 0]))
+            .unwrap();
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().packets_received == 6).await;
+    assert_eq!(snapshot().datagrams_dropped_short, 12);
+    assert_eq!(snapshot().decode_errors, 0);
+    assert!(connection.close_reason().is_none());
+}
@@ -0,0 +1,89 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use audio_relay_service::common::app_config::FRAME_DURATION_MS;
+use audio_relay_service::vc::decode_pool::DecodePool;
+use audio_relay_service::vc::stats::ConnectionStats;
+use audio_relay_service::vc::stream_decoder::{FRAME_SAMPLES, SAMPLE_RATE, SsrcDecoders};
+
+#[tokio::test]
+async fn jobs_run_on_the_pool_threads() {
+    let pool = DecodePool::new(2).unwrap();
+
+    let thread = pool
+        .run(|| std::thread::current().name().map(String::from))
+        .await
+        .unwrap();
+
+    assert!(thread.unwrap().starts_with("opus-decode-"));
+}
+
+#[tokio::test]
+async fn pooled_decoding_matches_inline_decoding() {
+    let pool = DecodePool::new(1).unwrap();
+    let packets = support::encode_tone_packets(10);
+    let mut inline = SsrcDecoders::<i16>::new(Arc::new(ConnectionStats::default()));
+    let mut decoders = SsrcDecoders::<i16>::new(Arc::new(ConnectionStats::default()));
+
+    for packet in packets {
+        let expected = inline.decode(&packet).unwrap().to_vec();
+        let (returned, decoded) = pool
+            .run(move || {
+                let decoded = decoders.decode(&packet).unwrap().to_vec();
+                (decoders, decoded)
+            })
+            .await
+            .unwrap();
+        decoders = returned;
+        assert_eq!(decoded, expected);
+    }
+}
+
+#[tokio::test]
+async fn panicking_job_fails_only_its_caller() {
+    let pool = DecodePool::new(1).unwrap();
+
+    assert!(pool.run(|| panic!("bad frame")).await.is_err());
+    assert_eq!(pool.run(|| 7).await.unwrap(), 7);
+}
+
+#[tokio::test]
+async fn server_with_decode_threads_decodes_every_packet() {
+    let server = support::start_server_with_config(|config| config.decode_threads = Some(2)).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    for packet in support::encode_tone_packets(8) {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+    }
+
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().packets_received == 8).await;
+    assert_eq!(snapshot().decode_errors, 0);
+}
+
+/// Checks decoding keeps well ahead of real time on this machine, see the `vc::decode_pool` docs
+#[test]
+#[ignore]
+fn measure_decode_cost() {
+    let packets = support::encode_tone_packets(1000);
+    let mut decoders = SsrcDecoders::<i16>::new(Arc::new(ConnectionStats::default()));
+    let started = Instant::now();
+    for packet in &packets {
+        decoders.decode(packet).unwrap();
+    }
+    let per_frame = started.elapsed() / packets.len() as u32;
+    // Each connection decodes one frame per frame duration
+    let frames_per_sec = (SAMPLE_RATE as usize / FRAME_SAMPLES) as f64;
+    let connections = 0.1 / (per_frame.as_secs_f64() * frames_per_sec);
+    assert!(
+        per_frame < Duration::from_millis(FRAME_DURATION_MS) / 10,
+        "Decoding a frame takes {per_frame:?}, \
+         decoding takes 10% of one runtime worker at {connections:.0} connections"
+    );
+}
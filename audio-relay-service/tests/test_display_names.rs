@@ -0,0 +1,97 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use lib_common_voxoxide::types::{
+    ArsAuthRequest, ArsControlMessage, CloseCode, MAX_DISPLAY_NAME_CHARS,
+};
+
+fn request_named(name: &str) -> ArsAuthRequest {
+    let mut request = ArsAuthRequest::for_room(4);
+    request.display_name = Some(name.to_string());
+    request
+}
+
+#[tokio::test]
+async fn valid_name_is_shown_in_the_roster() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+
+    let auth = support::authenticate_with(&connection, request_named("  Ada   Lovelace ")).await;
+
+    let roster = || {
+        server
+            .app
+            .describe_rooms()
+            .first()
+            .and_then(|room| room.members.first().cloned())
+    };
+    support::wait_until(|| roster().is_some()).await;
+    let member = roster().unwrap();
+    assert_eq!(member.member_id, auth.member_id);
+    assert_eq!(member.display_name.as_deref(), Some("Ada Lovelace"));
+    let json = serde_json::to_value(server.app.describe_rooms()).unwrap();
+    assert_eq!(json[0]["members"][0]["display_name"], "Ada Lovelace");
+}
+
+#[tokio::test]
+async fn names_are_pushed_with_the_roster() {
+    let server = support::start_server().await;
+    let named = support::connect(&server).await;
+    let named_id = support::authenticate_with(&named, request_named("Ada"))
+        .await
+        .member_id;
+    let anonymous = support::connect(&server).await;
+    let anonymous_id = support::authenticate(&anonymous, 4).await.member_id;
+
+    let roster = support::wait_for_control(&named, Duration::from_secs(2), |message| {
+        matches!(message, ArsControlMessage::Roster { member_ids, .. } if member_ids.len() == 2)
+    })
+    .await;
+    let Some(ArsControlMessage::Roster {
+        member_ids,
+        display_names,
+        ..
+    }) = roster
+    else {
+        panic!("no roster listing both members: {roster:?}");
+    };
+    let name_of = |id| {
+        let index = member_ids.iter().position(|member| *member == id).unwrap();
+        display_names[index].clone()
+    };
+    assert_eq!(name_of(named_id).as_deref(), Some("Ada"));
+    assert_eq!(name_of(anonymous_id), None);
+}
+
+#[tokio::test]
+async fn oversized_name_is_rejected() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+
+    let name = "x".repeat(MAX_DISPLAY_NAME_CHARS + 1);
+
+    assert_eq!(
+        support::authenticate_refused(&connection, request_named(&name)).await,
+        (
+            Some(CloseCode::AuthFailed),
+            "InvalidDisplayName".to_string()
+        )
+    );
+    assert!(server.app.describe_rooms().is_empty());
+}
+
+#[tokio::test]
+async fn control_characters_are_rejected() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+
+    assert_eq!(
+        support::authenticate_refused(&connection, request_named("ada\u{1b}[31m")).await,
+        (
+            Some(CloseCode::AuthFailed),
+            "InvalidDisplayName".to_string()
+        )
+    );
+}
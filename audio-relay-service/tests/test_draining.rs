@@ -0,0 +1,36 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use lib_common_voxoxide::types::CloseCode;
+
+#[tokio::test]
+async fn draining_refuses_new_connections_but_keeps_existing_ones() {
+    let server = support::start_server().await;
+    let existing = support::connect(&server).await;
+    support::authenticate(&existing, 0).await;
+    let packets = support::encode_tone_packets(10);
+
+    let drain = tokio::spawn(server.app.drain());
+    support::wait_until(|| server.app.is_draining()).await;
+
+    assert!(matches!(
+        support::try_connect(&server).await,
+        Err(quinn::ConnectionError::ConnectionClosed(_))
+    ));
+
+    for packet in &packets {
+        existing.send_datagram(packet.serialize().unwrap()).unwrap();
+    }
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().packets_received == 10).await;
+    assert!(!drain.is_finished());
+
+    // Draining completes once the last call ends on its own
+    existing.close(CloseCode::Normal.code().into(), b"bye");
+    tokio::time::timeout(Duration::from_secs(5), drain)
+        .await
+        .expect("drain did not finish after the last connection ended")
+        .unwrap();
+}
@@ -0,0 +1,156 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::app_config::{AppConfig, AuthSecret, DuplicateUserPolicy};
+use audio_relay_service::common::services::auth_tokens;
+use lib_common_voxoxide::types::{ArsAudioFormat, ArsAuthRequest, CloseCode};
+
+const USER: u64 = 42;
+
+fn secret() -> AuthSecret {
+    AuthSecret("duplicate users".to_string())
+}
+
+fn with_policy(
+    policy: DuplicateUserPolicy,
+    auth_secret: Option<AuthSecret>,
+) -> impl FnOnce(&mut AppConfig) {
+    move |config| {
+        config.duplicate_user_policy = policy;
+        config.auth_secret = auth_secret;
+    }
+}
+
+fn request_as(user_id: Option<u64>) -> ArsAuthRequest {
+    let mut request = ArsAuthRequest::for_room(0);
+    request.user_id = user_id;
+    request
+}
+
+/// Proves the user id to servers with [`secret`]
+fn signed_request_as(user_id: u64) -> ArsAuthRequest {
+    let mut request = request_as(Some(user_id));
+    request.token = Some(auth_tokens::issue(&secret(), user_id, 0));
+    request
+}
+
+#[tokio::test]
+async fn reject_policy_refuses_second_connection() {
+    let server =
+        support::start_server_with_config(with_policy(DuplicateUserPolicy::Reject, None)).await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, request_as(Some(USER))).await;
+
+    let second = support::connect(&server).await;
+
+    assert_eq!(
+        support::authenticate_refused(&second, request_as(Some(USER))).await,
+        (Some(CloseCode::AuthFailed), "DuplicateUser".to_string())
+    );
+    assert!(first.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn replace_policy_closes_older_connection() {
+    let server = support::start_server_with_config(with_policy(
+        DuplicateUserPolicy::Replace,
+        Some(secret()),
+    ))
+    .await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, signed_request_as(USER)).await;
+
+    let second = support::connect(&server).await;
+    support::authenticate_with(&second, signed_request_as(USER)).await;
+
+    assert_eq!(
+        support::closed_with(&first).await.0,
+        Some(CloseCode::Replaced)
+    );
+    assert!(second.close_reason().is_none());
+    // The replaced connection's cleanup must not release the newer claim
+    let members = || {
+        server
+            .app
+            .describe_rooms()
+            .first()
+            .map(|room| room.member_count)
+    };
+    support::wait_until(|| members() == Some(1)).await;
+    assert!(server.app.users.is_claimed(USER));
+}
+
+#[tokio::test]
+async fn refused_replacement_leaves_the_older_connection_open() {
+    let server = support::start_server_with_config(with_policy(
+        DuplicateUserPolicy::Replace,
+        Some(secret()),
+    ))
+    .await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, signed_request_as(USER)).await;
+
+    let second = support::connect(&server).await;
+    let mut request = signed_request_as(USER);
+    request.format = Some(ArsAudioFormat {
+        sample_rate: 16_000,
+        channels: 1,
+    });
+
+    assert_eq!(
+        support::authenticate_refused(&second, request).await,
+        (Some(CloseCode::AuthFailed), "FormatMismatch".to_string())
+    );
+    assert!(first.close_reason().is_none());
+    assert!(server.app.users.is_claimed(USER));
+}
+
+#[tokio::test]
+async fn replace_policy_rejects_without_an_auth_secret() {
+    // Anyone could claim the user id and take over the session otherwise
+    let server =
+        support::start_server_with_config(with_policy(DuplicateUserPolicy::Replace, None)).await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, request_as(Some(USER))).await;
+
+    let second = support::connect(&server).await;
+
+    assert_eq!(
+        support::authenticate_refused(&second, request_as(Some(USER))).await,
+        (Some(CloseCode::AuthFailed), "DuplicateUser".to_string())
+    );
+    assert!(first.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn user_is_released_when_connection_ends() {
+    let server =
+        support::start_server_with_config(with_policy(DuplicateUserPolicy::Reject, None)).await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, request_as(Some(USER))).await;
+    first.close(0u32.into(), b"bye");
+    support::wait_until(|| !server.app.users.is_claimed(USER)).await;
+
+    let second = support::connect(&server).await;
+    support::authenticate_with(&second, request_as(Some(USER))).await;
+    assert!(second.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn anonymous_connections_never_conflict() {
+    let server =
+        support::start_server_with_config(with_policy(DuplicateUserPolicy::Reject, None)).await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, request_as(None)).await;
+    let second = support::connect(&server).await;
+    support::authenticate_with(&second, request_as(None)).await;
+
+    let members = || {
+        server
+            .app
+            .describe_rooms()
+            .first()
+            .map(|room| room.member_count)
+    };
+    support::wait_until(|| members() == Some(2)).await;
+}
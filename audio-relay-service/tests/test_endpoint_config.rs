@@ -0,0 +1,97 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::app_config::AppConfig;
+use audio_relay_service::common::security::{certs, endpoint_config};
+
+#[test]
+fn server_config_builds_for_any_latency_settings() {
+    support::install_crypto_provider();
+    let (config, _dir, _cert) = support::test_config();
+
+    for target_latency_ms in [None, Some(0), Some(20), Some(60), Some(10_000)] {
+        let config = AppConfig {
+            target_latency_ms,
+            ..config.clone()
+        };
+        let (certs, key) = certs::load_certs(&config).unwrap();
+        // Built twice from the same config, nothing about the transport step is one-shot
+        for _ in 0..2 {
+            endpoint_config::create_server_config(&config, certs.clone(), key.clone_key()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn unrepresentable_idle_timeout_is_an_error() {
+    let (config, _dir, _cert) = support::test_config();
+    let config = AppConfig {
+        inactivity_timeout_ms: Some(u64::MAX),
+        ..config
+    };
+
+    let result = std::panic::catch_unwind(|| endpoint_config::create_transport_config(&config));
+    assert!(result.expect("transport config panicked").is_err());
+}
+
+#[test]
+fn unsatisfiable_cipher_policy_is_rejected() {
+    support::install_crypto_provider();
+    let (config, _dir, _cert) = support::test_config();
+    let (certs, key) = certs::load_certs(&config).unwrap();
+
+    // Unknown outright, a TLS 1.2 suite the provider has but QUIC can't use,
+    // and a valid policy lacking the suite QUIC initial packets need
+    for (policy, reported) in [
+        (
+            vec!["TLS13_AES_128_GCM_SHA256", "TLS13_MADE_UP_SHA1"],
+            "TLS13_MADE_UP_SHA1",
+        ),
+        (
+            vec![
+                "TLS13_AES_128_GCM_SHA256",
+                "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+            ],
+            "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        ),
+        (vec!["TLS13_AES_256_GCM_SHA384"], "TLS13_AES_128_GCM_SHA256"),
+    ] {
+        let config = AppConfig {
+            cipher_suites: policy.into_iter().map(str::to_string).collect(),
+            ..config.clone()
+        };
+        let error = endpoint_config::create_server_config(&config, certs.clone(), key.clone_key())
+            .unwrap_err();
+        assert!(error.to_string().contains(reported), "{error}");
+    }
+}
+
+#[test]
+fn cipher_policy_restricts_the_provider() {
+    support::install_crypto_provider();
+    let (config, _dir, _cert) = support::test_config();
+    let config = AppConfig {
+        cipher_suites: vec![
+            "TLS13_AES_256_GCM_SHA384".to_string(),
+            "TLS13_AES_128_GCM_SHA256".to_string(),
+        ],
+        ..config
+    };
+
+    let provider = endpoint_config::create_crypto_provider(&config).unwrap();
+    let names: Vec<_> = provider
+        .cipher_suites
+        .iter()
+        .map(|suite| suite.suite().as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            Some("TLS13_AES_256_GCM_SHA384"),
+            Some("TLS13_AES_128_GCM_SHA256")
+        ]
+    );
+
+    let (certs, key) = certs::load_certs(&config).unwrap();
+    endpoint_config::create_server_config(&config, certs, key).unwrap();
+}
@@ -0,0 +1,86 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use audio_relay_service::vc::ingress_rate::IngressRate;
+use lib_common_voxoxide::types::CloseCode;
+use tokio::time::Instant;
+
+#[test]
+fn rate_only_counts_bytes_within_the_window() {
+    let mut rate = IngressRate::new(Duration::from_secs(1));
+    let start = Instant::now();
+
+    assert_eq!(rate.record_at(500, start), 500);
+    assert_eq!(
+        rate.record_at(500, start + Duration::from_millis(500)),
+        1000
+    );
+    // The first datagram has left the window
+    assert_eq!(
+        rate.record_at(100, start + Duration::from_millis(1000)),
+        600
+    );
+    assert_eq!(rate.rate_at(start + Duration::from_secs(5)), 0);
+}
+
+#[tokio::test]
+async fn connection_exceeding_the_cap_is_closed() {
+    let server =
+        support::start_server_with_config(|config| config.max_ingress_bytes_per_sec = Some(2_000))
+            .await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    for packet in support::encode_tone_packets(50) {
+        if connection
+            .send_datagram(packet.serialize().unwrap())
+            .is_err()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+
+    let error = tokio::time::timeout(Duration::from_secs(5), connection.closed())
+        .await
+        .expect("exceeding the cap did not close the connection");
+    match error {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(
+                CloseCode::from_code(close.error_code.into_inner()),
+                Some(CloseCode::BandwidthExceeded)
+            );
+        }
+        other => panic!("unexpected close: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn ingress_rate_is_exported_without_a_cap() {
+    let server =
+        support::start_server_with_config(|config| config.max_ingress_bytes_per_sec = None).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    let packets = support::encode_tone_packets(50);
+    let total: usize = packets.iter().map(|p| p.serialize().unwrap().len()).sum();
+    for packet in packets {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().bytes_received == total as u64).await;
+    assert!(snapshot().ingress_bytes_per_second > 0);
+    assert!(connection.close_reason().is_none());
+    let rendered = server.app.metrics.render();
+    assert!(rendered.contains("# TYPE ars_ingress_bytes_per_second gauge"));
+    let id = server.app.metrics.connection_snapshots()[0].0;
+    assert!(rendered.contains(&format!(
+        "ars_bytes_received_total{{connection=\"{id}\"}} {total}"
+    )));
+}
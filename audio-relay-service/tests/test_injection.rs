@@ -0,0 +1,152 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::path::Path;
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::AuthSecret;
+use audio_relay_service::vc::stream_decoder::{FRAME_SAMPLES, SAMPLE_RATE};
+use rvoip_rtp_core::RtpPacket;
+
+const ROOM: u32 = 14;
+
+/// Writes `frames` frames of a 440Hz tone as 48kHz 16-bit stereo
+fn write_tone_wav(path: &Path, frames: usize) {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for i in 0..frames * FRAME_SAMPLES {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        let sample = ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16;
+        writer.write_sample(sample).unwrap();
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+#[tokio::test]
+async fn injected_wav_reaches_room_members() {
+    let server = support::start_server().await;
+    let listener = support::connect(&server).await;
+    let listener_id = support::authenticate(&listener, ROOM).await.member_id;
+    support::wait_until(|| server.app.describe_rooms().len() == 1).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("announcement.wav");
+    write_tone_wav(&path, 25);
+
+    let virtual_id = server.app.inject_wav(ROOM, &path).await.unwrap();
+
+    let room = &server.app.describe_rooms()[0];
+    assert_eq!(room.member_count, 2);
+    let injected = room
+        .members
+        .iter()
+        .find(|member| member.member_id == virtual_id)
+        .unwrap();
+    assert_ne!(virtual_id, listener_id);
+    assert_eq!(injected.display_name.as_deref(), Some("announcement.wav"));
+
+    let datagram = tokio::time::timeout(Duration::from_secs(2), listener.read_datagram())
+        .await
+        .expect("no injected audio arrived")
+        .unwrap();
+    let packet = RtpPacket::parse(&datagram).unwrap();
+    assert_eq!(Some(packet.header.ssrc), injected.ssrc);
+    let mut decoder = opus::Decoder::new(SAMPLE_RATE, opus::Channels::Mono).unwrap();
+    let mut pcm = vec![0i16; FRAME_SAMPLES];
+    let len = decoder.decode(&packet.payload, &mut pcm, false).unwrap();
+    assert_eq!(len, FRAME_SAMPLES);
+
+    // The virtual member leaves once the file is played
+    support::wait_until(|| server.app.describe_rooms()[0].member_count == 1).await;
+}
+
+#[tokio::test]
+async fn unreadable_file_is_not_injected() {
+    let server = support::start_server().await;
+    let dir = tempfile::tempdir().unwrap();
+
+    assert!(
+        server
+            .app
+            .inject_wav(ROOM, dir.path().join("missing.wav"))
+            .await
+            .is_err()
+    );
+    assert!(server.app.describe_rooms().is_empty());
+}
+
+#[tokio::test]
+async fn admins_inject_through_the_metrics_endpoint() {
+    let server = support::start_server_with_config(|config| {
+        config.admin_token = Some(AuthSecret("admin".to_string()))
+    })
+    .await;
+    let listener = support::connect(&server).await;
+    support::authenticate(&listener, ROOM).await;
+    let addr = support::serve_metrics(&server.app).await;
+    let files = tempfile::tempdir().unwrap();
+    let path = files.path().join("announcement.wav");
+    write_tone_wav(&path, 25);
+    let path = path.to_str().unwrap();
+    let inject = |token: &str| {
+        format!(
+            "POST /admin/inject?room={ROOM} HTTP/1.1\r\nAuthorization: Bearer {token}\r\nContent-Length: {}\r\n\r\n{path}",
+            path.len()
+        )
+    };
+
+    let (status, _) = support::http_request(addr, &inject("wrong")).await;
+    assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+    assert_eq!(server.app.describe_rooms()[0].member_count, 1);
+
+    let (status, body) = support::http_request(addr, &inject("admin")).await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let member_id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["member_id"]
+        .as_u64()
+        .unwrap();
+    let room = &server.app.describe_rooms()[0];
+    assert!(
+        room.members
+            .iter()
+            .any(|member| member.member_id == member_id)
+    );
+}
+
+#[tokio::test]
+async fn admin_routes_are_hidden_without_a_token() {
+    let server = support::start_server().await;
+    let addr = support::serve_metrics(&server.app).await;
+
+    let (status, _) = support::http_request(
+        addr,
+        "POST /admin/inject?room=1 HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+    )
+    .await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[tokio::test]
+async fn oversized_content_length_is_refused() {
+    let server = support::start_server_with_config(|config| {
+        config.admin_token = Some(AuthSecret("admin".to_string()))
+    })
+    .await;
+    let addr = support::serve_metrics(&server.app).await;
+
+    for length in [u64::MAX, 1 << 20] {
+        let (status, _) = support::http_request(
+            addr,
+            &format!(
+                "POST /admin/inject?room={ROOM} HTTP/1.1\r\nAuthorization: Bearer admin\r\nContent-Length: {length}\r\n\r\n"
+            ),
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    }
+    assert!(server.app.describe_rooms().is_empty());
+}
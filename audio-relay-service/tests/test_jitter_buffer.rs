@@ -0,0 +1,159 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use audio_relay_service::vc::jitter_buffer::{JitterBuffer, Playout};
+use audio_relay_service::vc::stats::ConnectionStats;
+use bytes::Bytes;
+use rvoip_rtp_core::RtpPacket;
+use tokio::time::Instant;
+
+fn packet(sequence: u16) -> RtpPacket {
+    RtpPacket::new_with_payload(111, sequence, sequence as u32 * 960, 1234, Bytes::new())
+}
+
+/// Sequence numbers of the released packets, gaps as their negated frame count
+fn sequences(released: Vec<Playout>) -> Vec<i64> {
+    released
+        .into_iter()
+        .map(|playout| match playout {
+            Playout::Packet { packet, .. } => packet.header.sequence_number as i64,
+            Playout::Gap { frames, .. } => -(frames as i64),
+        })
+        .collect()
+}
+
+fn push_all(buffer: &mut JitterBuffer, order: &[u16], now: Instant) -> Vec<i64> {
+    order
+        .iter()
+        .flat_map(|&sequence| sequences(buffer.push(packet(sequence), now)))
+        .collect()
+}
+
+#[test]
+fn reordered_packets_are_released_in_order() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut buffer = JitterBuffer::new(3, stats.clone());
+    let now = Instant::now();
+
+    let released = push_all(&mut buffer, &[0, 2, 1, 4, 3, 5, 6], now);
+
+    assert_eq!(released, [0, 1, 2, 3]);
+    assert_eq!(buffer.buffered_packets(), 3);
+    assert_eq!(stats.snapshot().packets_reordered, 0);
+}
+
+#[test]
+fn duplicates_and_packets_behind_the_playout_are_dropped() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut buffer = JitterBuffer::new(2, stats.clone());
+    let now = Instant::now();
+
+    // 1 is still buffered the second time, 0 was already released
+    let released = push_all(&mut buffer, &[0, 1, 1, 2, 0, 3], now);
+
+    assert_eq!(released, [0, 1]);
+    assert_eq!(stats.snapshot().packets_reordered, 2);
+}
+
+#[test]
+fn packets_are_released_once_they_waited_the_full_depth() {
+    let mut buffer = JitterBuffer::new(3, Arc::new(ConnectionStats::default()));
+    let start = Instant::now();
+    push_all(&mut buffer, &[0, 1], start);
+    push_all(&mut buffer, &[3], start + Duration::from_millis(40));
+
+    assert!(
+        buffer
+            .release_expired(start + Duration::from_millis(59))
+            .is_empty()
+    );
+    assert_eq!(
+        sequences(buffer.release_expired(start + Duration::from_millis(60))),
+        [0, 1]
+    );
+    // 2 never arrived, 3 goes out once it waited too, the decoder conceals the frame in between
+    assert_eq!(
+        sequences(buffer.release_expired(start + Duration::from_millis(100))),
+        [3]
+    );
+    assert_eq!(buffer.buffered_packets(), 0);
+}
+
+#[test]
+fn long_gaps_are_released_as_gaps() {
+    let mut buffer = JitterBuffer::new(1, Arc::new(ConnectionStats::default()));
+    let now = Instant::now();
+
+    let released = push_all(&mut buffer, &[0, 1, 12, 13], now);
+
+    assert_eq!(released, [0, 1, -10, 12]);
+}
+
+#[test]
+fn full_buffer_drops_the_oldest_packets() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut buffer = JitterBuffer::new(10, stats.clone()).with_capacity(4);
+    let start = Instant::now();
+
+    for sequence in 0..6 {
+        let arrival = start + Duration::from_millis(sequence as u64);
+        assert!(buffer.push(packet(sequence), arrival).is_empty());
+    }
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.packets_dropped_overflow, 2);
+    assert_eq!(snapshot.packets_buffered, 4);
+    assert_eq!(
+        sequences(buffer.release_expired(start + Duration::from_secs(1))),
+        [2, 3, 4, 5]
+    );
+    assert_eq!(stats.snapshot().packets_buffered, 0);
+}
+
+#[test]
+fn sequence_numbers_wrap_around() {
+    let mut buffer = JitterBuffer::new(2, Arc::new(ConnectionStats::default()));
+    let now = Instant::now();
+
+    let released = push_all(&mut buffer, &[65534, 0, 65535, 1, 2], now);
+
+    assert_eq!(released, [65534, 65535, 0]);
+}
+
+#[test]
+fn streams_are_buffered_separately() {
+    let mut buffer = JitterBuffer::new(1, Arc::new(ConnectionStats::default()));
+    let now = Instant::now();
+    let other = |sequence: u16| RtpPacket::new_with_payload(111, sequence, 0, 99, Bytes::new());
+
+    assert!(buffer.push(packet(0), now).is_empty());
+    assert!(buffer.push(other(500), now).is_empty());
+    assert_eq!(buffer.buffered_packets(), 2);
+    assert_eq!(sequences(buffer.push(packet(1), now)), [0]);
+}
+
+#[tokio::test]
+async fn relay_reorders_before_decoding() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    let mut packets = support::encode_tone_packets(10);
+    packets.swap(2, 3);
+    packets.swap(6, 7);
+    for packet in &packets {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+    }
+
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().packets_received == 10).await;
+    // Nothing was late for the decoder, so nothing had to be concealed
+    assert_eq!(snapshot().packets_reordered, 0);
+    assert_eq!(snapshot().frames_recovered_fec, 0);
+    assert_eq!(snapshot().frames_concealed_plc, 0);
+}
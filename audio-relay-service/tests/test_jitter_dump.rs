@@ -0,0 +1,141 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+use audio_relay_service::common::services::metrics::Metrics;
+use audio_relay_service::vc::jitter_buffer::{JitterBufferProbe, JitterBufferState};
+
+/// Buffers sequence numbers until played, counting gaps and out of order arrivals
+#[derive(Debug, Default)]
+struct FakeJitterBuffer {
+    depth: usize,
+    inner: Mutex<FakeState>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    buffered: BTreeSet<u16>,
+    highest: Option<u16>,
+    lost: u64,
+    reordered: u64,
+}
+
+impl FakeJitterBuffer {
+    fn feed(&self, sequence: u16) {
+        let mut state = self.inner.lock().unwrap();
+        match state.highest {
+            Some(highest) if sequence < highest => {
+                state.reordered += 1;
+                // Arrived late, the gap it left was no loss after all
+                state.lost -= 1;
+            }
+            Some(highest) => state.lost += u64::from(sequence - highest - 1),
+            None => {}
+        }
+        state.highest = state.highest.max(Some(sequence));
+        state.buffered.insert(sequence);
+    }
+
+    fn play(&self, count: usize) {
+        let mut state = self.inner.lock().unwrap();
+        for _ in 0..count {
+            state.buffered.pop_first();
+        }
+    }
+}
+
+impl JitterBufferProbe for FakeJitterBuffer {
+    fn state(&self) -> JitterBufferState {
+        let state = self.inner.lock().unwrap();
+        JitterBufferState {
+            depth: self.depth,
+            buffered_packets: state.buffered.len(),
+            lost_packets: state.lost,
+            reordered_packets: state.reordered,
+        }
+    }
+}
+
+#[test]
+fn dump_reflects_buffered_state() {
+    let metrics = Metrics::default();
+    let buffer = Arc::new(FakeJitterBuffer {
+        depth: 4,
+        ..Default::default()
+    });
+    metrics.register_jitter_buffer(7, buffer.clone());
+    metrics.register_jitter_buffer(3, Arc::new(FakeJitterBuffer::default()));
+
+    for sequence in [0, 1, 3, 2, 6] {
+        buffer.feed(sequence);
+    }
+    buffer.play(2);
+
+    let dump = metrics.jitter_dump();
+    let ids: Vec<u64> = dump.iter().map(|entry| entry.connection_id).collect();
+    assert_eq!(ids, [3, 7]);
+    assert_eq!(
+        dump[1].state,
+        JitterBufferState {
+            depth: 4,
+            buffered_packets: 3,
+            lost_packets: 2,
+            reordered_packets: 1,
+        }
+    );
+
+    metrics.unregister_connection(7);
+    assert_eq!(metrics.jitter_dump().len(), 1);
+}
+
+#[tokio::test]
+async fn relay_dump_reports_decoder_losses_and_reorders() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+    let packets = support::encode_tone_packets(5);
+
+    // 3 is lost, 1 arrives again after 2
+    for index in [0, 1, 2, 1, 4] {
+        connection
+            .send_datagram(packets[index].serialize().unwrap())
+            .unwrap();
+    }
+
+    let state = || server.app.dump_jitter_buffers()[0].state;
+    support::wait_until(|| state().reordered_packets == 1 && state().lost_packets == 1).await;
+    let latency = server.app.config.get_latency_settings();
+    assert_eq!(state().depth, latency.jitter_buffer_depth);
+    assert_eq!(state().buffered_packets, 0);
+}
+
+#[tokio::test]
+async fn dump_is_served_as_json_next_to_the_metrics() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+    support::wait_until(|| server.app.dump_jitter_buffers().len() == 1).await;
+    let addr = support::serve_metrics(&server.app).await;
+
+    let (status, body) =
+        support::http_request(addr, "GET /debug/jitter HTTP/1.1\r\nHost: relay\r\n\r\n").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let dump: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let entry = &dump.as_array().unwrap()[0];
+    assert_eq!(
+        entry["connection_id"],
+        server.app.dump_jitter_buffers()[0].connection_id
+    );
+    assert_eq!(
+        entry["depth"],
+        server.app.config.get_latency_settings().jitter_buffer_depth
+    );
+
+    let (status, body) = support::http_request(addr, "GET /metrics HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(body.contains("ars_rooms_active 1"));
+    let (status, _) = support::http_request(addr, "GET /debug/nothing HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
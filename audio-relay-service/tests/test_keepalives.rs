@@ -0,0 +1,59 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use lib_common_voxoxide::types::{CloseCode, KEEPALIVE_DATAGRAM};
+
+#[tokio::test]
+async fn keepalives_are_counted_not_decoded() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    for _ in 0..3 {
+        connection
+            .send_datagram(Bytes::from_static(KEEPALIVE_DATAGRAM))
+            .unwrap();
+    }
+
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().keepalives_received == 3).await;
+    assert_eq!(snapshot().decode_errors, 0);
+    assert_eq!(snapshot().datagrams_dropped_short, 0);
+    assert_eq!(snapshot().packets_received, 0);
+}
+
+#[tokio::test]
+async fn silent_peers_are_closed_as_inactive() {
+    let server =
+        support::start_server_with_config(|config| config.datagram_timeout_ms = Some(300)).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    assert_eq!(
+        support::closed_with(&connection).await,
+        (
+            Some(CloseCode::Inactive),
+            "no datagrams received".to_string()
+        )
+    );
+}
+
+#[tokio::test]
+async fn keepalives_hold_off_the_datagram_timeout() {
+    let server =
+        support::start_server_with_config(|config| config.datagram_timeout_ms = Some(300)).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    for _ in 0..8 {
+        connection
+            .send_datagram(Bytes::from_static(KEEPALIVE_DATAGRAM))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert!(connection.close_reason().is_none());
+}
@@ -0,0 +1,59 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use audio_relay_service::common::services::events::LifecycleEvent;
+use lib_common_voxoxide::types::CloseCode;
+use tokio::sync::broadcast;
+
+async fn next_event(events: &mut broadcast::Receiver<LifecycleEvent>) -> LifecycleEvent {
+    tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("no lifecycle event in time")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn connection_cycle_emits_events_in_order() {
+    let server = support::start_server().await;
+    let mut events = server.app.events.subscribe();
+
+    let connection = support::connect(&server).await;
+    let auth = support::authenticate(&connection, 7).await;
+    connection.close(CloseCode::Normal.code().into(), b"bye");
+
+    let connection_id = auth.member_id as usize;
+    match next_event(&mut events).await {
+        LifecycleEvent::ConnectionAccepted {
+            connection_id: id,
+            remote,
+        } => {
+            assert_eq!(id, connection_id);
+            assert!(remote.ip().is_loopback());
+        }
+        other => panic!("expected ConnectionAccepted, got {other:?}"),
+    }
+    assert_eq!(
+        next_event(&mut events).await,
+        LifecycleEvent::Authenticated { connection_id }
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        LifecycleEvent::JoinedRoom {
+            connection_id,
+            room_id: 7
+        }
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        LifecycleEvent::LeftRoom {
+            connection_id,
+            room_id: 7
+        }
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        LifecycleEvent::ConnectionClosed { connection_id }
+    );
+}
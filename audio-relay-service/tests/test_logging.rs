@@ -0,0 +1,35 @@
+use std::io::Write;
+
+use audio_relay_service::common::logging::open_log_file;
+
+#[test]
+fn missing_log_directories_are_created() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("logs/ars/server.log");
+
+    let mut file = open_log_file(&path).unwrap();
+    file.write_all(b"line\n").unwrap();
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "line\n");
+}
+
+#[test]
+fn existing_log_file_is_appended_to() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("server.log");
+    std::fs::write(&path, "old\n").unwrap();
+
+    open_log_file(&path).unwrap().write_all(b"new\n").unwrap();
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\nnew\n");
+}
+
+#[test]
+fn uncreatable_log_directory_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    // A regular file where a directory would have to be created
+    let blocker = dir.path().join("blocker");
+    std::fs::write(&blocker, "").unwrap();
+
+    assert!(open_log_file(&blocker.join("server.log")).is_err());
+}
@@ -0,0 +1,76 @@
+//! Counts heap allocations on the test thread to check the mixing hot path reuses its buffers.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use audio_relay_service::common::app_config::FRAME_DURATION_MS;
+use audio_relay_service::vc::mixer::{MixChannel, Mixer, frame_samples};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[test]
+fn steady_state_mixing_does_not_allocate() {
+    let samples = frame_samples(FRAME_DURATION_MS);
+    let frame = vec![100i16; samples];
+    let mut mixer = Mixer::new(samples);
+    let mut channels: Vec<MixChannel> = (0..10).map(|_| MixChannel::new(samples)).collect();
+
+    let before = allocations();
+    for _ in 0..100 {
+        for channel in channels.iter_mut() {
+            channel.push(&frame);
+        }
+        mixer.reset();
+        for channel in channels.iter_mut() {
+            mixer.add(channel);
+        }
+        for channel in channels.iter_mut() {
+            assert!(mixer.mix_into(channel));
+        }
+    }
+
+    assert_eq!(allocations() - before, 0);
+    assert!(channels.iter().all(|channel| channel.mix() == [900; 960]));
+}
+
+#[test]
+fn backlog_is_trimmed_without_growing() {
+    let samples = frame_samples(FRAME_DURATION_MS);
+    let frame = vec![1i16; samples];
+    let mut channel = MixChannel::new(samples);
+
+    // Nobody drains the channel, so it keeps trimming the oldest frame
+    let before = allocations();
+    for _ in 0..100 {
+        channel.push(&frame);
+    }
+
+    assert_eq!(allocations() - before, 0);
+}
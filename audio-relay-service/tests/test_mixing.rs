@@ -0,0 +1,302 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::OpusSettings;
+use audio_relay_service::common::services::auth::AuthenticatedMember;
+use audio_relay_service::vc::group_voice_session::GroupVoiceSessions;
+use audio_relay_service::vc::mixer::{
+    MIX_ENCODER_RESET_ERRORS, MIXER_SSRC, MixedStreamEncoder, mix_minus,
+};
+use audio_relay_service::vc::stats::ConnectionStats;
+use audio_relay_service::vc::stream_decoder::{FRAME_SAMPLES, SAMPLE_RATE, StreamDecoder};
+use lib_common_voxoxide::types::{ArsAudioFormat, ArsAuthRequest, Features};
+use rvoip_rtp_core::RtpPacket;
+use support::encode_tone_packets_with;
+
+const ROOM: u32 = 3;
+
+/// Decodes a speaker's stream the way the server does before mixing
+fn server_decode(packets: &[RtpPacket]) -> Vec<Vec<i16>> {
+    let mut decoder = StreamDecoder::new(Arc::new(ConnectionStats::default())).unwrap();
+    packets
+        .iter()
+        .map(|packet| decoder.decode(packet).unwrap().to_vec())
+        .collect()
+}
+
+/// Decodes a forwarded stream the way a client does
+fn client_decode(packets: &[RtpPacket]) -> Vec<Vec<i16>> {
+    let mut decoder = opus::Decoder::new(SAMPLE_RATE, opus::Channels::Mono).unwrap();
+    let mut pcm = vec![0i16; FRAME_SAMPLES];
+    packets
+        .iter()
+        .map(|packet| {
+            let len = decoder.decode(&packet.payload, &mut pcm, false).unwrap();
+            pcm[..len].to_vec()
+        })
+        .collect()
+}
+
+#[test]
+fn mix_minus_matches_summed_forwarded_streams() {
+    let streams: Vec<(usize, Vec<RtpPacket>)> = [(1, 300.0), (2, 500.0), (3, 700.0)]
+        .into_iter()
+        .map(|(id, frequency)| (id, encode_tone_packets_with(frequency, id as u32, 6)))
+        .collect();
+    let decoded: Vec<(usize, Vec<Vec<i16>>)> = streams
+        .iter()
+        .map(|(id, packets)| (*id, server_decode(packets)))
+        .collect();
+
+    for frame in 0..6 {
+        let frames: Vec<(usize, &[i16])> = decoded
+            .iter()
+            .map(|(id, frames)| (*id, frames[frame].as_slice()))
+            .collect();
+        let mixes = mix_minus(&frames, &[1, 2, 3]);
+        assert_eq!(mixes.len(), 3);
+
+        for (recipient, mix) in mixes {
+            // With forwarding the recipient decodes everyone else and plays the sum
+            let mut expected = vec![0i16; FRAME_SAMPLES];
+            for (_, packets) in streams.iter().filter(|(id, _)| *id != recipient) {
+                let forwarded = &client_decode(packets)[frame];
+                for (total, sample) in expected.iter_mut().zip(forwarded) {
+                    *total = total.saturating_add(*sample);
+                }
+            }
+            assert_eq!(mix, expected, "recipient {recipient}, frame {frame}");
+        }
+    }
+}
+
+#[test]
+fn lone_speaker_only_reaches_others() {
+    let frame = vec![1000i16; FRAME_SAMPLES];
+
+    let mixes = mix_minus(&[(1, &frame)], &[1, 2]);
+
+    assert_eq!(mixes, vec![(2, frame)]);
+}
+
+#[test]
+fn mix_encoder_produces_mixer_stream() {
+    let mut encoder = MixedStreamEncoder::new(FRAME_SAMPLES, OpusSettings::default()).unwrap();
+    let frame = vec![0i16; FRAME_SAMPLES];
+
+    let first = encoder.encode(&frame).unwrap();
+    let second = encoder.encode(&frame).unwrap();
+
+    assert_eq!(first.header.ssrc, MIXER_SSRC);
+    assert_eq!(second.header.sequence_number, 1);
+    assert_eq!(second.header.timestamp, FRAME_SAMPLES as u32);
+    assert_eq!(client_decode(&[first])[0].len(), FRAME_SAMPLES);
+}
+
+#[test]
+fn mix_encode_failure_only_skips_that_recipients_frames() {
+    let mut failing = MixedStreamEncoder::new(FRAME_SAMPLES, OpusSettings::default()).unwrap();
+    let mut others: Vec<MixedStreamEncoder> = (0..2)
+        .map(|_| MixedStreamEncoder::new(FRAME_SAMPLES, OpusSettings::default()).unwrap())
+        .collect();
+    let frame = vec![0i16; FRAME_SAMPLES];
+    // Opus refuses frames of a duration it can't code
+    let malformed = vec![0i16; 7];
+
+    for tick in 0..MIX_ENCODER_RESET_ERRORS as u16 {
+        assert!(failing.encode(&malformed).is_err());
+        for other in &mut others {
+            assert_eq!(other.encode(&frame).unwrap().header.sequence_number, tick);
+        }
+    }
+    assert_eq!(failing.restarts(), 1);
+
+    // The skipped frames show up as a loss the recipient conceals
+    let recovered = failing.encode(&frame).unwrap();
+    assert_eq!(
+        recovered.header.sequence_number,
+        MIX_ENCODER_RESET_ERRORS as u16
+    );
+    assert_eq!(
+        recovered.header.timestamp,
+        MIX_ENCODER_RESET_ERRORS * FRAME_SAMPLES as u32
+    );
+    assert_eq!(client_decode(&[recovered])[0].len(), FRAME_SAMPLES);
+}
+
+/// Streams a tone from one member and returns the SSRC of the first datagram another member hears
+async fn ssrc_heard_by_listener(mixing_threshold: Option<usize>) -> u32 {
+    ssrc_heard_by_listener_with(mixing_threshold, None).await
+}
+
+async fn ssrc_heard_by_listener_with(
+    mixing_threshold: Option<usize>,
+    listener_features: Option<Features>,
+) -> u32 {
+    let server =
+        support::start_server_with_config(|config| config.mixing_threshold = mixing_threshold)
+            .await;
+    let speaker = support::connect(&server).await;
+    support::authenticate(&speaker, ROOM).await;
+    let listener = support::connect(&server).await;
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.features = listener_features;
+    support::authenticate_with(&listener, request).await;
+
+    for packet in encode_tone_packets_with(440.0, 1234, 5) {
+        speaker.send_datagram(packet.serialize().unwrap()).unwrap();
+    }
+    let datagram = tokio::time::timeout(Duration::from_secs(2), listener.read_datagram())
+        .await
+        .expect("no audio reached the listener")
+        .unwrap();
+    RtpPacket::parse(&datagram).unwrap().header.ssrc
+}
+
+#[tokio::test]
+async fn rooms_past_the_threshold_are_mixed() {
+    assert_eq!(ssrc_heard_by_listener(Some(1)).await, MIXER_SSRC);
+}
+
+#[tokio::test]
+async fn rooms_within_the_threshold_are_forwarded() {
+    assert_eq!(ssrc_heard_by_listener(Some(2)).await, 1234);
+    assert_eq!(ssrc_heard_by_listener(None).await, 1234);
+}
+
+#[tokio::test]
+async fn members_without_mixing_are_forwarded_in_mixed_rooms() {
+    assert_eq!(
+        ssrc_heard_by_listener_with(Some(1), Some(Features::FEC)).await,
+        1234
+    );
+    assert_eq!(
+        ssrc_heard_by_listener_with(Some(1), Some(Features::MIXING)).await,
+        MIXER_SSRC
+    );
+}
+
+fn virtual_member() -> AuthenticatedMember {
+    AuthenticatedMember {
+        room_id: ROOM,
+        moderator: false,
+        user_id: None,
+        format: ArsAudioFormat::default(),
+        features: Features::NONE,
+        display_name: None,
+        recording_consent: true,
+        ssrc: None,
+        session_id: 0,
+        session_key: 0,
+    }
+}
+
+fn sine(frequency: f32, amplitude: f32) -> Vec<i16> {
+    (0..FRAME_SAMPLES)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            ((t * frequency * std::f32::consts::TAU).sin() * amplitude) as i16
+        })
+        .collect()
+}
+
+#[test]
+fn room_frame_sums_every_member_with_clipping() {
+    let rooms = GroupVoiceSessions::new(Some(1));
+    rooms.join(1, None, &virtual_member());
+    rooms.join(2, None, &virtual_member());
+    let (low, high) = (sine(440.0, 8000.0), sine(660.0, 8000.0));
+
+    rooms.submit_frame(ROOM, 1, &low);
+    rooms.submit_frame(ROOM, 2, &high);
+    let mixed = rooms.mix_frame(ROOM).unwrap();
+
+    let expected: Vec<i16> = low.iter().zip(&high).map(|(a, b)| a + b).collect();
+    assert_eq!(mixed, expected);
+
+    let (loud_low, loud_high) = (sine(440.0, 30000.0), sine(440.0, 30000.0));
+    rooms.submit_frame(ROOM, 1, &loud_low);
+    rooms.submit_frame(ROOM, 2, &loud_high);
+    let clipped = rooms.mix_frame(ROOM).unwrap();
+    assert!(clipped.contains(&i16::MAX) && clipped.contains(&i16::MIN));
+    assert!(
+        clipped
+            .iter()
+            .zip(&loud_low)
+            .all(|(mixed, own)| mixed.signum() == own.signum())
+    );
+
+    // Both frames were taken, the next one is silent
+    assert_eq!(rooms.mix_frame(ROOM).unwrap(), vec![0; FRAME_SAMPLES]);
+    assert_eq!(rooms.mix_frame(ROOM + 1), None);
+}
+
+#[test]
+fn mixed_stream_steps_steadily_across_silence() {
+    let mut encoder = MixedStreamEncoder::new(FRAME_SAMPLES, OpusSettings::default()).unwrap();
+    let tone = client_decode(&encode_tone_packets_with(440.0, 1, 1)).remove(0);
+
+    let packets = [
+        encoder.encode_silence().unwrap(),
+        encoder.encode(&tone).unwrap(),
+        encoder.encode_silence().unwrap(),
+        encoder.encode_silence().unwrap(),
+        encoder.encode(&tone).unwrap(),
+    ];
+
+    for (i, packet) in packets.iter().enumerate() {
+        assert_eq!(packet.header.ssrc, MIXER_SSRC);
+        assert_eq!(packet.header.sequence_number, i as u16);
+        assert_eq!(packet.header.timestamp, (i * FRAME_SAMPLES) as u32);
+    }
+    let decoded = client_decode(&packets);
+    assert!(decoded[0].iter().all(|sample| *sample == 0));
+    assert!(decoded.iter().all(|frame| frame.len() == FRAME_SAMPLES));
+}
+
+#[tokio::test]
+async fn mixed_return_stream_is_continuous_while_the_room_is_silent() {
+    let server =
+        support::start_server_with_config(|config| config.mixing_threshold = Some(1)).await;
+    let speaker = support::connect(&server).await;
+    support::authenticate(&speaker, ROOM).await;
+    let listener = support::connect(&server).await;
+    support::authenticate(&listener, ROOM).await;
+
+    // Silence first, then a short burst of speech, then silence again
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for packet in encode_tone_packets_with(440.0, 1234, 5) {
+        speaker.send_datagram(packet.serialize().unwrap()).unwrap();
+    }
+    let mut heard = Vec::new();
+    while heard.len() < 20 {
+        let datagram = tokio::time::timeout(Duration::from_secs(2), listener.read_datagram())
+            .await
+            .expect("the return stream stalled")
+            .unwrap();
+        heard.push(RtpPacket::parse(&datagram).unwrap());
+    }
+
+    for pair in heard.windows(2) {
+        assert_eq!(
+            pair[1].header.sequence_number,
+            pair[0].header.sequence_number.wrapping_add(1)
+        );
+        assert_eq!(
+            pair[1].header.timestamp,
+            pair[0].header.timestamp.wrapping_add(FRAME_SAMPLES as u32)
+        );
+    }
+    let decoded = client_decode(&heard);
+    let audible = decoded
+        .iter()
+        .filter(|frame| frame.iter().any(|sample| sample.abs() > 100))
+        .count();
+    assert!(
+        audible > 0 && audible < heard.len(),
+        "{audible} audible frames"
+    );
+}
@@ -0,0 +1,388 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::{AppConfig, RoomConfig};
+use audio_relay_service::vc::room_events::{RoomEventKind, RoomEventLog};
+use lib_common_voxoxide::types::{ArsAuthRequest, ArsAuthResponse, ArsControlMessage, CloseCode};
+
+const ROOM: u32 = 10;
+
+fn moderated(config: &mut AppConfig) {
+    config.rooms = HashMap::from([(
+        ROOM,
+        RoomConfig {
+            moderator_token: Some("secret".to_string()),
+            ..Default::default()
+        },
+    )]);
+}
+
+async fn join(
+    server: &support::TestServer,
+    moderator_token: Option<&str>,
+) -> (quinn::Connection, ArsAuthResponse) {
+    let connection = support::connect(server).await;
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.moderator_token = moderator_token.map(str::to_string);
+    let response = support::authenticate_with(&connection, request).await;
+    (connection, response)
+}
+
+/// Sends the packets from `speaker` and returns how many `listener` got within a short wait
+async fn forwarded_count(
+    speaker: &quinn::Connection,
+    listener: &quinn::Connection,
+    packets: &[rvoip_rtp_core::RtpPacket],
+) -> usize {
+    for packet in packets {
+        speaker.send_datagram(packet.serialize().unwrap()).unwrap();
+    }
+    let mut received = 0;
+    while tokio::time::timeout(Duration::from_millis(300), listener.read_datagram())
+        .await
+        .is_ok()
+    {
+        received += 1;
+    }
+    received
+}
+
+#[tokio::test]
+async fn moderator_mute_suppresses_forwarded_audio() {
+    let server = support::start_server_with_config(moderated).await;
+    let (moderator, moderator_auth) = join(&server, Some("secret")).await;
+    let (speaker, speaker_auth) = join(&server, None).await;
+    let (listener, listener_auth) = join(&server, Some("wrong")).await;
+    assert!(moderator_auth.moderator);
+    assert!(!speaker_auth.moderator);
+    assert!(!listener_auth.moderator);
+    let speaker_id = speaker_auth.member_id as usize;
+    let packets = support::encode_tone_packets(10);
+
+    assert_eq!(forwarded_count(&speaker, &listener, &packets[..5]).await, 5);
+
+    // A non-moderator's mute is rejected
+    let mute = ArsControlMessage::SetMemberMuted {
+        member_id: speaker_auth.member_id,
+        muted: true,
+    };
+    support::send_control(&listener, &mute).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(server.app.rooms.is_muted(ROOM, speaker_id), Some(false));
+
+    support::send_control(&moderator, &mute).await;
+    support::wait_until(|| server.app.rooms.is_muted(ROOM, speaker_id) == Some(true)).await;
+    assert_eq!(forwarded_count(&speaker, &listener, &packets[5..]).await, 0);
+    // The speaker is still heard by the server itself
+    let snapshot = || {
+        server
+            .app
+            .metrics
+            .connection_snapshots()
+            .into_iter()
+            .find(|(id, _)| *id == speaker_id)
+            .unwrap()
+            .1
+    };
+    support::wait_until(|| snapshot().packets_received == 10).await;
+}
+
+#[tokio::test]
+async fn mute_all_spares_moderators() {
+    let server = support::start_server_with_config(moderated).await;
+    let (moderator, moderator_auth) = join(&server, Some("secret")).await;
+    let (member, member_auth) = join(&server, None).await;
+
+    support::send_control(&moderator, &ArsControlMessage::SetAllMuted { muted: true }).await;
+    support::wait_until(|| {
+        server
+            .app
+            .rooms
+            .is_muted(ROOM, member_auth.member_id as usize)
+            == Some(true)
+    })
+    .await;
+    assert_eq!(
+        server
+            .app
+            .rooms
+            .is_muted(ROOM, moderator_auth.member_id as usize),
+        Some(false)
+    );
+
+    let packets = support::encode_tone_packets(3);
+    assert_eq!(forwarded_count(&moderator, &member, &packets).await, 3);
+}
+
+#[tokio::test]
+async fn member_actions_are_logged_in_order() {
+    let server = support::start_server_with_config(moderated).await;
+    let (moderator, moderator_auth) = join(&server, Some("secret")).await;
+    let (member, member_auth) = join(&server, None).await;
+    let kinds = || {
+        server
+            .app
+            .rooms
+            .event_log(ROOM)
+            .unwrap()
+            .into_iter()
+            .map(|event| event.kind)
+            .collect::<Vec<_>>()
+    };
+
+    let mute = ArsControlMessage::SetMemberMuted {
+        member_id: member_auth.member_id,
+        muted: true,
+    };
+    support::send_control(&moderator, &mute).await;
+    support::wait_until(|| kinds().len() == 3).await;
+    support::send_control(&moderator, &ArsControlMessage::SetAllMuted { muted: false }).await;
+    support::wait_until(|| kinds().len() == 4).await;
+    member.close(0u32.into(), b"bye");
+    support::wait_until(|| kinds().len() == 5).await;
+
+    let by = moderator_auth.member_id;
+    assert_eq!(
+        kinds(),
+        [
+            RoomEventKind::Joined {
+                member_id: by,
+                moderator: true
+            },
+            RoomEventKind::Joined {
+                member_id: member_auth.member_id,
+                moderator: false
+            },
+            RoomEventKind::MemberMuted {
+                member_id: member_auth.member_id,
+                muted: true,
+                by
+            },
+            RoomEventKind::AllMuted { muted: false, by },
+            RoomEventKind::Left {
+                member_id: member_auth.member_id
+            },
+        ]
+    );
+    let events = server.app.rooms.event_log(ROOM).unwrap();
+    assert!(
+        events
+            .windows(2)
+            .all(|pair| pair[0].at_unix_ms <= pair[1].at_unix_ms)
+    );
+}
+
+#[test]
+fn event_log_drops_oldest_entries_when_full() {
+    let mut log = RoomEventLog::with_capacity(2);
+    for member_id in 0..3 {
+        log.record(RoomEventKind::Left { member_id });
+    }
+
+    let kept: Vec<_> = log.entries().into_iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kept,
+        [
+            RoomEventKind::Left { member_id: 1 },
+            RoomEventKind::Left { member_id: 2 }
+        ]
+    );
+}
+
+/// Waits for the server to send `expected`, skipping earlier roster updates
+async fn expect_control(connection: &quinn::Connection, expected: &ArsControlMessage) {
+    assert_eq!(
+        support::wait_for_control(connection, Duration::from_secs(2), |m| m == expected).await,
+        Some(expected.clone())
+    );
+}
+
+#[tokio::test]
+async fn moderator_kick_closes_the_target_and_updates_the_roster() {
+    let server = support::start_server_with_config(moderated).await;
+    let (moderator, moderator_auth) = join(&server, Some("secret")).await;
+    let (target, target_auth) = join(&server, None).await;
+    let (member, member_auth) = join(&server, None).await;
+
+    // A non-moderator's kick is rejected
+    let kick_moderator = ArsControlMessage::Kick {
+        member_id: moderator_auth.member_id,
+        reason: "coup".to_string(),
+    };
+    support::send_control(&member, &kick_moderator).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(moderator.close_reason().is_none());
+
+    let kick = ArsControlMessage::Kick {
+        member_id: target_auth.member_id,
+        reason: "spamming the room".to_string(),
+    };
+    support::send_control(&moderator, &kick).await;
+
+    assert_eq!(
+        support::closed_with(&target).await,
+        (Some(CloseCode::Kicked), "spamming the room".to_string())
+    );
+    let mut remaining = vec![moderator_auth.member_id, member_auth.member_id];
+    remaining.sort_unstable();
+    let roster = ArsControlMessage::Roster {
+        member_ids: remaining,
+        muted: Vec::new(),
+        display_names: vec![None, None],
+    };
+    expect_control(&member, &roster).await;
+    expect_control(&moderator, &roster).await;
+    support::wait_until(|| {
+        server
+            .app
+            .rooms
+            .event_log(ROOM)
+            .unwrap()
+            .iter()
+            .any(|event| {
+                event.kind
+                    == RoomEventKind::Kicked {
+                        member_id: target_auth.member_id,
+                        by: moderator_auth.member_id,
+                        reason: "spamming the room".to_string(),
+                    }
+            })
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn self_mute_shows_in_the_roster_without_stopping_audio() {
+    let server = support::start_server_with_config(moderated).await;
+    let (speaker, speaker_auth) = join(&server, None).await;
+    let (listener, listener_auth) = join(&server, None).await;
+    let mut member_ids = vec![speaker_auth.member_id, listener_auth.member_id];
+    member_ids.sort_unstable();
+
+    support::send_control(&speaker, &ArsControlMessage::SetSelfMuted { muted: true }).await;
+    expect_control(
+        &listener,
+        &ArsControlMessage::Roster {
+            member_ids: member_ids.clone(),
+            muted: vec![speaker_auth.member_id],
+            display_names: vec![None, None],
+        },
+    )
+    .await;
+    // Only the room is told, audio the client still sends is forwarded
+    assert_eq!(
+        forwarded_count(&speaker, &listener, &support::encode_tone_packets(5)).await,
+        5
+    );
+
+    support::send_control(&speaker, &ArsControlMessage::SetSelfMuted { muted: false }).await;
+    expect_control(
+        &listener,
+        &ArsControlMessage::Roster {
+            member_ids,
+            muted: Vec::new(),
+            display_names: vec![None, None],
+        },
+    )
+    .await;
+    assert!(
+        server
+            .app
+            .rooms
+            .event_log(ROOM)
+            .unwrap()
+            .iter()
+            .any(|event| event.kind
+                == RoomEventKind::SelfMuted {
+                    member_id: speaker_auth.member_id,
+                    muted: true,
+                })
+    );
+}
+
+#[tokio::test]
+async fn locked_rooms_turn_new_members_away_until_unlocked() {
+    let server = support::start_server_with_config(moderated).await;
+    let (moderator, moderator_auth) = join(&server, Some("secret")).await;
+    let (member, _) = join(&server, None).await;
+    let locked = || {
+        server
+            .app
+            .describe_rooms()
+            .iter()
+            .any(|room| room.room_id == ROOM && room.locked)
+    };
+
+    // Only moderators lock
+    support::send_control(&member, &ArsControlMessage::SetRoomLocked { locked: true }).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!locked());
+
+    support::send_control(
+        &moderator,
+        &ArsControlMessage::SetRoomLocked { locked: true },
+    )
+    .await;
+    support::wait_until(locked).await;
+    let late = support::connect(&server).await;
+    assert_eq!(
+        support::authenticate_refused(&late, ArsAuthRequest::for_room(ROOM)).await,
+        (Some(CloseCode::AuthFailed), "RoomLocked".to_string())
+    );
+    // Members already in the room stay, moderators still get in
+    assert!(member.close_reason().is_none());
+    let (_second_moderator, second_auth) = join(&server, Some("secret")).await;
+    assert!(second_auth.moderator);
+
+    support::send_control(
+        &moderator,
+        &ArsControlMessage::SetRoomLocked { locked: false },
+    )
+    .await;
+    support::wait_until(|| !locked()).await;
+    let (welcome, _) = join(&server, None).await;
+    assert!(welcome.close_reason().is_none());
+    let log = server.app.rooms.event_log(ROOM).unwrap();
+    assert!(log.iter().any(|event| event.kind
+        == RoomEventKind::RoomLocked {
+            locked: false,
+            by: moderator_auth.member_id,
+        }));
+}
+
+#[tokio::test]
+async fn joins_and_moderator_mutes_push_the_roster() {
+    let server = support::start_server_with_config(moderated).await;
+    let (moderator, moderator_auth) = join(&server, Some("secret")).await;
+    let (member, member_auth) = join(&server, None).await;
+    let mut member_ids = vec![moderator_auth.member_id, member_auth.member_id];
+    member_ids.sort_unstable();
+
+    expect_control(
+        &moderator,
+        &ArsControlMessage::Roster {
+            member_ids: member_ids.clone(),
+            muted: Vec::new(),
+            display_names: vec![None, None],
+        },
+    )
+    .await;
+
+    let mute = ArsControlMessage::SetMemberMuted {
+        member_id: member_auth.member_id,
+        muted: true,
+    };
+    support::send_control(&moderator, &mute).await;
+    expect_control(
+        &member,
+        &ArsControlMessage::Roster {
+            member_ids,
+            muted: vec![member_auth.member_id],
+            display_names: vec![None, None],
+        },
+    )
+    .await;
+}
@@ -0,0 +1,96 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::{AppConfig, PushStrategy};
+use audio_relay_service::vc::push_schedule::PushSchedule;
+use lib_common_voxoxide::types::ArsControlMessage;
+use tokio::time::Instant;
+
+const INTERVAL: Duration = Duration::from_millis(250);
+
+#[test]
+fn debounced_pushes_coalesce_changes_within_the_window() {
+    let mut schedule = PushSchedule::new(PushStrategy::Debounced, INTERVAL);
+    let start = Instant::now();
+
+    for i in 0..10 {
+        assert!(!schedule.changed_at(start + Duration::from_millis(20 * i)));
+    }
+    assert_eq!(schedule.next_push(), Some(start + INTERVAL));
+    assert!(!schedule.poll_at(start + Duration::from_millis(249)));
+    assert!(schedule.poll_at(start + INTERVAL));
+    // Ten changes, one push
+    assert!(!schedule.poll_at(start + 2 * INTERVAL));
+    assert_eq!(schedule.next_push(), None);
+
+    // A later change opens a new window
+    let later = start + Duration::from_secs(1);
+    assert!(!schedule.changed_at(later));
+    assert!(schedule.poll_at(later + INTERVAL));
+}
+
+#[test]
+fn on_change_pushes_every_change_right_away() {
+    let mut schedule = PushSchedule::new(PushStrategy::OnChange, INTERVAL);
+    let start = Instant::now();
+
+    assert!(schedule.changed_at(start));
+    assert!(schedule.changed_at(start + Duration::from_millis(1)));
+    assert!(!schedule.poll_at(start + INTERVAL));
+    assert_eq!(schedule.next_push(), None);
+}
+
+#[test]
+fn periodic_pushes_every_interval_changed_or_not() {
+    let mut schedule = PushSchedule::new(PushStrategy::Periodic, INTERVAL);
+    let start = Instant::now();
+
+    assert!(schedule.poll_at(start));
+    assert!(!schedule.changed_at(start + Duration::from_millis(10)));
+    assert!(!schedule.poll_at(start + Duration::from_millis(100)));
+    assert!(schedule.poll_at(start + INTERVAL));
+    assert!(schedule.poll_at(start + 2 * INTERVAL));
+}
+
+#[test]
+fn push_interval_defaults_when_not_configured() {
+    let (config, _dir, _cert) = support::test_config();
+    assert_eq!(config.roster_push_strategy, PushStrategy::OnChange);
+    assert_eq!(config.get_roster_push_interval(), INTERVAL);
+
+    let config = AppConfig {
+        roster_push_interval_ms: Some(1000),
+        ..config
+    };
+    assert_eq!(config.get_roster_push_interval(), Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn debounced_rooms_push_joins_in_a_window_as_one_roster() {
+    let server = support::start_server_with_config(|config| {
+        config.roster_push_strategy = PushStrategy::Debounced;
+        config.roster_push_interval_ms = Some(300);
+    })
+    .await;
+    let first = support::connect(&server).await;
+    let first_id = support::authenticate(&first, 0).await.member_id;
+    let second = support::connect(&server).await;
+    let second_id = support::authenticate(&second, 0).await.member_id;
+    let mut member_ids = vec![first_id, second_id];
+    member_ids.sort_unstable();
+
+    // The first join alone never goes out
+    let roster = support::wait_for_control(&first, Duration::from_secs(2), |_| true).await;
+    assert_eq!(
+        roster,
+        Some(ArsControlMessage::Roster {
+            member_ids,
+            muted: Vec::new(),
+            display_names: vec![None, None],
+        })
+    );
+    let again = support::wait_for_control(&first, Duration::from_millis(500), |_| true).await;
+    assert_eq!(again, None);
+}
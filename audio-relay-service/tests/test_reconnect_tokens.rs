@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use audio_relay_service::common::services::auth::AuthenticatedMember;
+use audio_relay_service::common::services::reconnect_tokens::ReconnectTokenStore;
+use lib_common_voxoxide::types::{ArsAudioFormat, Features};
+use tokio::time::Instant;
+
+fn member(room_id: u32) -> AuthenticatedMember {
+    AuthenticatedMember {
+        room_id,
+        moderator: false,
+        user_id: None,
+        format: ArsAudioFormat::default(),
+        features: Features::NONE,
+        display_name: None,
+        recording_consent: false,
+        ssrc: None,
+        session_id: 0,
+        session_key: 0,
+    }
+}
+
+#[test]
+fn lookup_hits_stored_tokens_only() {
+    let store = ReconnectTokenStore::new(4, Duration::from_secs(60));
+    store.insert("a".to_string(), member(1));
+
+    assert_eq!(store.lookup("a"), Some(member(1)));
+    assert_eq!(store.lookup("b"), None);
+    assert_eq!(store.remove("a"), Some(member(1)));
+    assert_eq!(store.lookup("a"), None);
+}
+
+#[test]
+fn tokens_expire_after_ttl() {
+    let store = ReconnectTokenStore::new(4, Duration::from_secs(60));
+    let start = Instant::now();
+    store.insert_at("a".to_string(), member(1), start);
+
+    // Using a token doesn't extend its lifetime
+    assert!(
+        store
+            .lookup_at("a", start + Duration::from_secs(59))
+            .is_some()
+    );
+    assert!(
+        store
+            .lookup_at("a", start + Duration::from_secs(60))
+            .is_none()
+    );
+    assert!(store.is_empty());
+}
+
+#[test]
+fn full_store_evicts_least_recently_used() {
+    let store = ReconnectTokenStore::new(2, Duration::from_secs(60));
+    let start = Instant::now();
+    store.insert_at("a".to_string(), member(1), start);
+    store.insert_at("b".to_string(), member(2), start + Duration::from_secs(1));
+    store.lookup_at("a", start + Duration::from_secs(2));
+
+    store.insert_at("c".to_string(), member(3), start + Duration::from_secs(3));
+
+    assert_eq!(store.len(), 2);
+    let now = start + Duration::from_secs(4);
+    assert_eq!(store.lookup_at("a", now), Some(member(1)));
+    assert_eq!(store.lookup_at("b", now), None);
+    assert_eq!(store.lookup_at("c", now), Some(member(3)));
+}
+
+#[test]
+fn expired_tokens_are_evicted_before_live_ones() {
+    let store = ReconnectTokenStore::new(2, Duration::from_secs(10));
+    let start = Instant::now();
+    store.insert_at("old".to_string(), member(1), start);
+    store.insert_at(
+        "live".to_string(),
+        member(2),
+        start + Duration::from_secs(8),
+    );
+    // "old" was used most recently but has expired by the time the store is full
+    store.lookup_at("old", start + Duration::from_secs(9));
+
+    store.insert_at(
+        "new".to_string(),
+        member(3),
+        start + Duration::from_secs(12),
+    );
+
+    let now = start + Duration::from_secs(13);
+    assert_eq!(store.lookup_at("live", now), Some(member(2)));
+    assert_eq!(store.lookup_at("new", now), Some(member(3)));
+    assert_eq!(store.len(), 2);
+}
@@ -0,0 +1,165 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use audio_relay_service::common::app_config::AppConfig;
+use audio_relay_service::vc::recording::Recording;
+use audio_relay_service::vc::stream_decoder::SAMPLE_RATE;
+
+#[test]
+fn drifted_stream_reports_measured_rate() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("drift.wav");
+    let mut recording = Recording::create(&path, false).unwrap();
+
+    // 2% more samples than one second at the declared rate
+    recording.write_samples(&vec![100i16; 48_960]).unwrap();
+    let summary = recording
+        .finalize_with_elapsed(Duration::from_secs(1))
+        .unwrap();
+
+    assert_eq!(summary.samples, 48_960);
+    assert!((summary.measured_sample_rate - 48_960.0).abs() < 1e-6);
+    assert!((summary.drift - 0.02).abs() < 1e-9);
+    assert!(!summary.header_rewritten);
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+}
+
+#[test]
+fn sample_rate_correction_rewrites_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("corrected.wav");
+    let mut recording = Recording::create(&path, true).unwrap();
+
+    recording.write_samples(&vec![100i16; 24_000]).unwrap();
+    recording.write_silence(23_040).unwrap();
+    let summary = recording
+        .finalize_with_elapsed(Duration::from_secs(1))
+        .unwrap();
+
+    assert!(summary.header_rewritten);
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().sample_rate, 47_040);
+    assert_eq!(reader.len(), 47_040);
+}
+
+#[test]
+fn too_short_recording_keeps_the_declared_rate() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("short.wav");
+    let mut recording = Recording::create(&path, true).unwrap();
+
+    // A single sample over an hour would measure well below 1Hz
+    recording.write_samples(&[100]).unwrap();
+    let summary = recording
+        .finalize_with_elapsed(Duration::from_secs(3600))
+        .unwrap();
+
+    assert_eq!(summary.measured_sample_rate, SAMPLE_RATE as f64);
+    assert!(!summary.header_rewritten);
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+}
+
+#[test]
+fn stream_within_tolerance_keeps_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("steady.wav");
+    let mut recording = Recording::create(&path, true).unwrap();
+
+    recording.write_samples(&vec![100i16; 48_010]).unwrap();
+    let summary = recording
+        .finalize_with_elapsed(Duration::from_secs(1))
+        .unwrap();
+
+    assert!(!summary.header_rewritten);
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+}
+
+#[test]
+fn failed_write_aborts_recording() {
+    // Every write to /dev/full fails with ENOSPC once the writer's buffer is flushed
+    let mut recording = Recording::create("/dev/full", false).unwrap();
+
+    assert!(recording.write_samples(&vec![100i16; 48_000]).is_err());
+    assert!(!recording.is_recording());
+    // Later writes are dropped without reporting the same failure again
+    assert!(recording.write_samples(&vec![100i16; 960]).is_ok());
+    assert!(recording.write_silence(960).is_ok());
+}
+
+#[test]
+fn flush_interval_puts_samples_on_disk_before_finalize() {
+    let dir = tempfile::tempdir().unwrap();
+    let bytes_on_disk = |name: &str| std::fs::metadata(dir.path().join(name)).unwrap().len();
+
+    let mut unflushed = Recording::create(dir.path().join("unflushed.wav"), false).unwrap();
+    let mut flushed = Recording::create(dir.path().join("flushed.wav"), false)
+        .unwrap()
+        .with_flush_interval(Some(Duration::from_millis(50)));
+    for recording in [&mut unflushed, &mut flushed] {
+        recording.write_samples(&vec![100i16; 960]).unwrap();
+    }
+    // Within the interval, everything is still buffered
+    assert_eq!(bytes_on_disk("flushed.wav"), 0);
+
+    std::thread::sleep(Duration::from_millis(60));
+    for recording in [&mut unflushed, &mut flushed] {
+        recording.write_samples(&vec![100i16; 960]).unwrap();
+    }
+
+    let reader = hound::WavReader::open(dir.path().join("flushed.wav")).unwrap();
+    assert_eq!(reader.len(), 1920);
+    assert_eq!(bytes_on_disk("unflushed.wav"), 0);
+    assert!(flushed.is_recording());
+}
+
+#[tokio::test]
+async fn connection_continues_without_recording() {
+    let (config, dir, cert) = support::test_config();
+    let config = AppConfig {
+        recording_dir: Some(dir.path().join("missing")),
+        ..config
+    };
+    let server = support::start_server_with(config, dir, cert).await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    for packet in support::encode_tone_packets(10) {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    support::wait_until(|| {
+        server.app.metrics.connection_snapshots()[0]
+            .1
+            .packets_received
+            == 10
+    })
+    .await;
+    assert!(connection.close_reason().is_none());
+}
+
+#[test]
+fn stereo_recording_measures_the_rate_per_channel() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stereo.wav");
+    let mut recording = Recording::create_with_channels(&path, 2, true).unwrap();
+
+    // One second of both channels
+    recording.write_samples(&vec![100i16; 96_000]).unwrap();
+    let summary = recording
+        .finalize_with_elapsed(Duration::from_secs(1))
+        .unwrap();
+
+    assert!((summary.measured_sample_rate - SAMPLE_RATE as f64).abs() < 1e-6);
+    assert!(!summary.header_rewritten);
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.duration(), SAMPLE_RATE);
+}
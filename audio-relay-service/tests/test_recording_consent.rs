@@ -0,0 +1,92 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::HashMap;
+
+use audio_relay_service::common::app_config::{AppConfig, RecordingConsent, RoomConfig};
+use lib_common_voxoxide::types::{ArsAuthRequest, CloseCode};
+use rvoip_rtp_core::RtpPacket;
+
+const ROOM: u32 = 12;
+
+async fn start_server(recording_consent: RecordingConsent) -> support::TestServer {
+    let (config, dir, cert) = support::test_config();
+    let config = AppConfig {
+        recording_dir: Some(dir.path().to_path_buf()),
+        rooms: HashMap::from([(
+            ROOM,
+            RoomConfig {
+                recording_consent: Some(recording_consent),
+                ..Default::default()
+            },
+        )]),
+        ..config
+    };
+    support::start_server_with(config, dir, cert).await
+}
+
+/// Returns the connection and the member id the server assigned
+async fn join(server: &support::TestServer, recording_consent: bool) -> (quinn::Connection, u64) {
+    let connection = support::connect(server).await;
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.recording_consent = recording_consent;
+    let member_id = support::authenticate_with(&connection, request)
+        .await
+        .member_id;
+    (connection, member_id)
+}
+
+/// Streams from `speaker` until the server reports it `recorded` or not
+async fn wait_until_recorded(
+    server: &support::TestServer,
+    (speaker, speaker_id): &(quinn::Connection, u64),
+    packets: &mut impl Iterator<Item = RtpPacket>,
+    recorded: bool,
+) {
+    let is_recorded = || {
+        server.app.describe_rooms()[0]
+            .members
+            .iter()
+            .any(|member| member.member_id == *speaker_id && member.recording)
+    };
+    // The flag follows the room's consent as the speaker's audio is processed
+    for packet in packets.take(100) {
+        speaker.send_datagram(packet.serialize().unwrap()).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        if is_recorded() == recorded {
+            return;
+        }
+    }
+    panic!("the speaker's recording never became {recorded}");
+}
+
+#[tokio::test]
+async fn non_consenting_member_pauses_the_recording() {
+    let server = start_server(RecordingConsent::Disable).await;
+    let speaker = join(&server, true).await;
+    let mut packets = support::encode_tone_packets(300).into_iter();
+    wait_until_recorded(&server, &speaker, &mut packets, true).await;
+
+    let (member, _) = join(&server, false).await;
+    wait_until_recorded(&server, &speaker, &mut packets, false).await;
+
+    member.close(0u32.into(), b"bye");
+    wait_until_recorded(&server, &speaker, &mut packets, true).await;
+}
+
+#[tokio::test]
+async fn non_consenting_member_is_rejected() {
+    let server = start_server(RecordingConsent::Reject).await;
+    join(&server, true).await;
+
+    let connection = support::connect(&server).await;
+    let refused = support::authenticate_refused(&connection, ArsAuthRequest::for_room(ROOM)).await;
+
+    assert_eq!(
+        refused,
+        (
+            Some(CloseCode::AuthFailed),
+            "RecordingConsentRequired".to_string()
+        )
+    );
+}
@@ -0,0 +1,75 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::collections::HashMap;
+
+use audio_relay_service::common::app_config::RoomConfig;
+use lib_common_voxoxide::types::{ArsAudioFormat, ArsAuthRequest, CloseCode};
+
+const ROOM: u32 = 4;
+const WIDEBAND: ArsAudioFormat = ArsAudioFormat {
+    sample_rate: 16_000,
+    channels: 1,
+};
+
+fn request_with(format: Option<ArsAudioFormat>) -> ArsAuthRequest {
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.format = format;
+    request
+}
+
+fn format_mismatch() -> (Option<CloseCode>, String) {
+    (Some(CloseCode::AuthFailed), "FormatMismatch".to_string())
+}
+
+#[tokio::test]
+async fn member_with_other_sample_rate_is_refused() {
+    let server = support::start_server().await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, request_with(None)).await;
+
+    let second = support::connect(&server).await;
+    assert_eq!(
+        support::authenticate_refused(&second, request_with(Some(WIDEBAND))).await,
+        format_mismatch()
+    );
+    // Declaring the default format explicitly matches an undeclared one
+    let third = support::connect(&server).await;
+    support::authenticate_with(&third, request_with(Some(ArsAudioFormat::default()))).await;
+    assert!(first.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn configured_format_overrides_first_member() {
+    let server = support::start_server_with_config(|config| {
+        config.rooms = HashMap::from([(
+            ROOM,
+            RoomConfig {
+                format: Some(WIDEBAND),
+                ..Default::default()
+            },
+        )])
+    })
+    .await;
+
+    let default = support::connect(&server).await;
+    assert_eq!(
+        support::authenticate_refused(&default, request_with(None)).await,
+        format_mismatch()
+    );
+    let wideband = support::connect(&server).await;
+    support::authenticate_with(&wideband, request_with(Some(WIDEBAND))).await;
+}
+
+#[tokio::test]
+async fn format_is_released_with_the_session() {
+    let server = support::start_server().await;
+    let first = support::connect(&server).await;
+    support::authenticate_with(&first, request_with(Some(WIDEBAND))).await;
+    first.close(0u32.into(), b"bye");
+    support::wait_until(|| server.app.describe_rooms().is_empty()).await;
+
+    let second = support::connect(&server).await;
+    support::authenticate_with(&second, request_with(None)).await;
+    support::wait_until(|| server.app.describe_rooms().len() == 1).await;
+}
@@ -0,0 +1,73 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::app_config::AppConfig;
+use audio_relay_service::vc::group_voice_session::{MemberInfo, RoomInfo};
+
+#[tokio::test]
+async fn description_reflects_rooms_and_members() {
+    let (config, dir, cert) = support::test_config();
+    let config = AppConfig {
+        recording_dir: Some(dir.path().to_path_buf()),
+        ..config
+    };
+    let server = support::start_server_with(config, dir, cert).await;
+    let mut joined = Vec::new();
+    for room_id in [7, 7, 3] {
+        let connection = support::connect(&server).await;
+        let response = support::authenticate(&connection, room_id).await;
+        joined.push((connection, response.member_id));
+    }
+    for packet in support::encode_tone_packets(3) {
+        joined[0]
+            .0
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+    }
+
+    // Members report their recording state and SSRC from their own tasks
+    let settled = || {
+        let rooms = server.app.describe_rooms();
+        let mut members = rooms.iter().flat_map(|room| &room.members);
+        members.clone().all(|member| member.recording) && members.any(|m| m.ssrc.is_some())
+    };
+    support::wait_until(settled).await;
+    let member = |index: usize, ssrc: Option<u32>| MemberInfo {
+        member_id: joined[index].1,
+        display_name: None,
+        ssrc,
+        moderator: false,
+        muted: false,
+        recording: true,
+    };
+    let mut room_7 = vec![member(0, Some(1234)), member(1, None)];
+    room_7.sort_by_key(|member| member.member_id);
+    assert_eq!(
+        server.app.describe_rooms(),
+        vec![
+            RoomInfo {
+                room_id: 3,
+                member_count: 1,
+                mixing: false,
+                locked: false,
+                members: vec![member(2, None)],
+            },
+            RoomInfo {
+                room_id: 7,
+                member_count: 2,
+                mixing: false,
+                locked: false,
+                members: room_7,
+            },
+        ]
+    );
+
+    let json = serde_json::to_value(server.app.describe_rooms()).unwrap();
+    assert_eq!(json[1]["members"].as_array().unwrap().len(), 2);
+    assert_eq!(json[0]["room_id"], 3);
+
+    // Leaving members drop out, empty rooms disappear
+    joined.pop().unwrap().0.close(0u32.into(), b"bye");
+    support::wait_until(|| server.app.describe_rooms().len() == 1).await;
+    assert_eq!(server.app.describe_rooms()[0].room_id, 7);
+}
@@ -0,0 +1,69 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::services::metrics::{Metrics, ROOM_SIZE_BUCKETS};
+
+#[test]
+fn histogram_reflects_room_sizes() {
+    let metrics = Metrics::default();
+    // Rooms of 1, 3 and 10 members
+    for (room_id, members) in [(1, 1), (2, 3), (3, 10)] {
+        for _ in 0..members {
+            metrics.room_joined(room_id);
+        }
+    }
+
+    assert_eq!(metrics.active_rooms(), 3);
+    let histogram = metrics.room_size_histogram();
+    assert_eq!(histogram.count, 3);
+    assert_eq!(histogram.sum, 14);
+    let expected: Vec<(usize, u64)> = ROOM_SIZE_BUCKETS
+        .iter()
+        .zip([1, 1, 2, 2, 3, 3])
+        .map(|(le, rooms)| (*le, rooms))
+        .collect();
+    assert_eq!(histogram.buckets, expected);
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("ars_rooms_active 3"));
+    assert!(rendered.contains("ars_room_members_bucket{le=\"4\"} 2"));
+    assert!(rendered.contains("ars_room_members_bucket{le=\"+Inf\"} 3"));
+    assert!(rendered.contains("ars_room_members_sum 14"));
+}
+
+#[test]
+fn leaving_shrinks_and_removes_rooms() {
+    let metrics = Metrics::default();
+    metrics.room_joined(1);
+    metrics.room_joined(1);
+    metrics.room_joined(2);
+
+    metrics.room_left(1);
+    metrics.room_left(2);
+    // Unknown rooms are ignored
+    metrics.room_left(9);
+
+    assert_eq!(metrics.active_rooms(), 1);
+    let histogram = metrics.room_size_histogram();
+    assert_eq!((histogram.count, histogram.sum), (1, 1));
+    assert_eq!(histogram.buckets[0], (1, 1));
+}
+
+#[tokio::test]
+async fn joins_and_leaves_update_the_gauge() {
+    let server = support::start_server().await;
+    let first = support::connect(&server).await;
+    support::authenticate(&first, 1).await;
+    let second = support::connect(&server).await;
+    support::authenticate(&second, 1).await;
+    let third = support::connect(&server).await;
+    support::authenticate(&third, 2).await;
+
+    let metrics = &server.app.metrics;
+    support::wait_until(|| metrics.room_size_histogram().sum == 3).await;
+    assert_eq!(metrics.active_rooms(), 2);
+
+    third.close(0u32.into(), b"bye");
+    support::wait_until(|| metrics.active_rooms() == 1).await;
+    assert_eq!(metrics.room_size_histogram().sum, 2);
+}
@@ -0,0 +1,53 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::{Duration, Instant};
+
+use lib_common_voxoxide::types::{ArsControlMessage, CloseCode};
+
+#[tokio::test]
+async fn member_is_warned_then_closed_at_the_limit() {
+    let server = support::start_server_with_config(|config| {
+        config.max_session_secs = Some(2);
+        config.session_warning_secs = Some(1);
+    })
+    .await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+    let started = Instant::now();
+
+    let warning = ArsControlMessage::SessionEnding { remaining_secs: 1 };
+    assert_eq!(
+        support::wait_for_control(&connection, Duration::from_secs(5), |m| *m == warning).await,
+        Some(warning)
+    );
+    let warned_after = started.elapsed();
+    assert!(connection.close_reason().is_none());
+
+    let (code, _) = support::closed_with(&connection).await;
+    let closed_after = started.elapsed();
+    assert_eq!(code, Some(CloseCode::SessionTimeLimit));
+    assert!(
+        warned_after < Duration::from_millis(1500),
+        "{warned_after:?}"
+    );
+    assert!(
+        closed_after >= Duration::from_millis(1500),
+        "{closed_after:?}"
+    );
+}
+
+#[tokio::test]
+async fn sessions_without_a_limit_stay_open() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    let warning = support::wait_for_control(&connection, Duration::from_millis(500), |m| {
+        matches!(m, ArsControlMessage::SessionEnding { .. })
+    })
+    .await;
+
+    assert_eq!(warning, None);
+    assert!(connection.close_reason().is_none());
+}
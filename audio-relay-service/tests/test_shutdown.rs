@@ -0,0 +1,47 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::time::Duration;
+
+use lib_common_voxoxide::types::CloseCode;
+
+/// Well above a frame interval, well below anything a blocked loop would get through
+const SHUTDOWN_BOUND: Duration = Duration::from_millis(500);
+
+#[tokio::test]
+async fn cancellation_closes_streaming_connections_promptly() {
+    let server = support::start_server().await;
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    // Keeps streaming at the real frame rate until the connection goes away
+    let sender = connection.clone();
+    let streaming = tokio::spawn(async move {
+        for packet in support::encode_tone_packets(500) {
+            if sender.send_datagram(packet.serialize().unwrap()).is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    });
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| snapshot().packets_received >= 5).await;
+
+    server.app.cancellation_token.cancel();
+    let error = tokio::time::timeout(SHUTDOWN_BOUND, connection.closed())
+        .await
+        .expect("cancellation did not close the connection in time");
+
+    match error {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(
+                CloseCode::from_code(close.error_code.into_inner()),
+                Some(CloseCode::ServerShutdown)
+            );
+        }
+        other => panic!("unexpected close: {other:?}"),
+    }
+    streaming.await.unwrap();
+    // The connection task ran its cleanup instead of being stuck in the loop
+    support::wait_until(|| server.app.metrics.connection_snapshots().is_empty()).await;
+}
@@ -0,0 +1,65 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::app_config::SsrcCollisionPolicy;
+use lib_common_voxoxide::types::{ArsAuthRequest, ArsAuthResponse, CloseCode};
+
+const ROOM: u32 = 13;
+const SSRC: u32 = 1234;
+
+fn declaring(ssrc: u32) -> ArsAuthRequest {
+    let mut request = ArsAuthRequest::for_room(ROOM);
+    request.ssrc = Some(ssrc);
+    request
+}
+
+/// Returns once the member is in the room, so the next one to join collides with it
+async fn join(server: &support::TestServer, ssrc: u32) -> (quinn::Connection, ArsAuthResponse) {
+    let members = room_ssrcs(server).len();
+    let connection = support::connect(server).await;
+    let response = support::authenticate_with(&connection, declaring(ssrc)).await;
+    support::wait_until(|| room_ssrcs(server).len() > members).await;
+    (connection, response)
+}
+
+fn room_ssrcs(server: &support::TestServer) -> Vec<Option<u32>> {
+    let mut ssrcs: Vec<Option<u32>> = server
+        .app
+        .describe_rooms()
+        .iter()
+        .flat_map(|room| room.members.iter().map(|member| member.ssrc))
+        .collect();
+    ssrcs.sort();
+    ssrcs
+}
+
+#[tokio::test]
+async fn colliding_ssrc_is_reassigned() {
+    let server = support::start_server_with_config(|config| {
+        config.ssrc_collision_policy = SsrcCollisionPolicy::Reassign
+    })
+    .await;
+    let (_first, response) = join(&server, SSRC).await;
+    assert_eq!(response.reassigned_ssrc, None);
+
+    let (_second, response) = join(&server, SSRC).await;
+    assert_eq!(response.reassigned_ssrc, Some(SSRC + 1));
+    assert_eq!(room_ssrcs(&server), vec![Some(SSRC), Some(SSRC + 1)]);
+}
+
+#[tokio::test]
+async fn colliding_ssrc_is_rejected() {
+    let server = support::start_server_with_config(|config| {
+        config.ssrc_collision_policy = SsrcCollisionPolicy::Reject
+    })
+    .await;
+    join(&server, SSRC).await;
+
+    let connection = support::connect(&server).await;
+    let refused = support::authenticate_refused(&connection, declaring(SSRC)).await;
+
+    assert_eq!(
+        refused,
+        (Some(CloseCode::AuthFailed), "SsrcCollision".to_string())
+    );
+}
@@ -0,0 +1,89 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::app::{Admission, IncomingState, admission};
+
+#[tokio::test]
+async fn disabled_retry_accepts_without_round_trip() {
+    let server =
+        support::start_server_with_config(|config| config.stateless_retry = Some(false)).await;
+
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    assert_eq!(server.app.metrics.stateless_retries(), 0);
+    assert!(
+        server
+            .app
+            .metrics
+            .render()
+            .contains("ars_stateless_retries_total 0")
+    );
+}
+
+#[tokio::test]
+async fn retry_is_enabled_by_default() {
+    let server = support::start_server_with_config(|config| config.stateless_retry = None).await;
+
+    let connection = support::connect(&server).await;
+    support::authenticate(&connection, 0).await;
+
+    assert_eq!(server.app.metrics.stateless_retries(), 1);
+}
+
+const FRESH: IncomingState = IncomingState {
+    draining: false,
+    at_connection_limit: false,
+    address_validated: false,
+    may_retry: true,
+};
+
+#[test]
+fn fresh_incoming_is_retried_once() {
+    assert_eq!(admission(FRESH, true), Admission::Retry);
+    assert_eq!(admission(FRESH, false), Admission::Accept);
+}
+
+#[test]
+fn validated_incoming_is_not_retried_again() {
+    let validated = IncomingState {
+        address_validated: true,
+        may_retry: false,
+        ..FRESH
+    };
+    assert_eq!(admission(validated, true), Admission::Accept);
+
+    // Carried a token that did not validate the address, retrying would only send it around again
+    let retried = IncomingState {
+        may_retry: false,
+        ..FRESH
+    };
+    assert_eq!(admission(retried, true), Admission::Accept);
+}
+
+#[test]
+fn refusal_takes_priority_over_retry() {
+    let draining = IncomingState {
+        draining: true,
+        ..FRESH
+    };
+    let full = IncomingState {
+        at_connection_limit: true,
+        ..FRESH
+    };
+    assert_eq!(admission(draining, true), Admission::Refuse);
+    assert_eq!(admission(full, false), Admission::Refuse);
+}
+
+#[tokio::test]
+async fn reconnecting_clients_are_retried_once_each() {
+    let server =
+        support::start_server_with_config(|config| config.stateless_retry = Some(true)).await;
+
+    for _ in 0..3 {
+        let connection = support::connect(&server).await;
+        support::authenticate(&connection, 0).await;
+    }
+
+    assert_eq!(server.app.metrics.stateless_retries(), 3);
+}
@@ -0,0 +1,249 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use std::sync::Arc;
+
+use audio_relay_service::common::services::metrics::Metrics;
+use audio_relay_service::vc::stats::ConnectionStats;
+use audio_relay_service::vc::stream_decoder::{
+    FRAME_SAMPLES, MAX_CONCEALED_FRAMES, MAX_STREAMS_PER_CONNECTION, SAMPLE_RATE, Sample,
+    SsrcDecoders, StreamDecoder,
+};
+use rvoip_rtp_core::RtpPacket;
+use support::encode_tone_packets;
+
+#[test]
+fn injected_losses_increment_recovery_counters() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut decoder: StreamDecoder = StreamDecoder::new(stats.clone()).unwrap();
+
+    // Drop 3 (one lost frame, FEC only) and 6..=8 (three lost frames, two PLC + one FEC)
+    let dropped = [3, 6, 7, 8];
+    let mut samples = 0;
+    for packet in encode_tone_packets(12) {
+        if dropped.contains(&packet.header.sequence_number) {
+            continue;
+        }
+        samples += decoder.decode(&packet).unwrap().len();
+    }
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.packets_received, 8);
+    assert_eq!(snapshot.frames_recovered_fec, 2);
+    assert_eq!(snapshot.frames_concealed_plc, 2);
+    // Every frame of the stream is accounted for in the output
+    assert_eq!(samples, 12 * FRAME_SAMPLES);
+}
+
+#[test]
+fn long_gaps_fade_out_through_concealment() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut decoders: SsrcDecoders = SsrcDecoders::new(stats.clone());
+    let packets = encode_tone_packets(20);
+    for packet in &packets[..4] {
+        decoders.decode(packet).unwrap();
+    }
+
+    // 4..=13 never arrive, the jitter buffer hands on the gap before 14
+    let gap = decoders.conceal_gap(1234, 10).unwrap();
+    assert_eq!(gap.pcm.len(), MAX_CONCEALED_FRAMES as usize * FRAME_SAMPLES);
+    assert_eq!(gap.silence, 5 * FRAME_SAMPLES);
+    // The concealment carries on the tone rather than cutting it off
+    assert!(gap.pcm[..FRAME_SAMPLES].iter().any(|&sample| sample != 0));
+    assert_eq!(stats.snapshot().frames_concealed_plc, 5);
+
+    // The stream picks up after the gap as if nothing was missing
+    assert_eq!(decoders.decode(&packets[14]).unwrap().len(), FRAME_SAMPLES);
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.frames_recovered_fec, 0);
+    assert_eq!(snapshot.frames_concealed_plc, 5);
+    // Streams that never decoded anything have nothing to conceal
+    assert!(decoders.conceal_gap(99, 10).unwrap().pcm.is_empty());
+}
+
+#[test]
+fn duplicate_packets_are_not_counted() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut decoder: StreamDecoder = StreamDecoder::new(stats.clone()).unwrap();
+    let packets = encode_tone_packets(3);
+
+    for packet in [
+        &packets[0],
+        &packets[1],
+        &packets[1],
+        &packets[0],
+        &packets[2],
+    ] {
+        decoder.decode(packet).unwrap();
+    }
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.packets_received, 3);
+    assert_eq!(snapshot.frames_recovered_fec, 0);
+    assert_eq!(snapshot.frames_concealed_plc, 0);
+}
+
+#[test]
+fn metrics_render_connection_counters() {
+    let metrics = Metrics::default();
+    let stats = metrics.register_connection(7);
+    let mut decoder: StreamDecoder = StreamDecoder::new(stats).unwrap();
+    for packet in encode_tone_packets(4) {
+        if packet.header.sequence_number != 2 {
+            decoder.decode(&packet).unwrap();
+        }
+    }
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("ars_packets_received_total{connection=\"7\"} 3"));
+    assert!(rendered.contains("ars_frames_recovered_fec_total{connection=\"7\"} 1"));
+    assert!(rendered.contains("ars_frames_concealed_plc_total{connection=\"7\"} 0"));
+
+    metrics.unregister_connection(7);
+    assert!(!metrics.render().contains("connection=\"7\""));
+}
+
+#[test]
+fn float_decode_matches_scaled_i16_decode() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut int_decoder: StreamDecoder<i16> = StreamDecoder::new(stats.clone()).unwrap();
+    let mut float_decoder: StreamDecoder<f32> = StreamDecoder::new(stats).unwrap();
+
+    // Includes a lost frame so the FEC path is compared as well
+    for packet in encode_tone_packets(6) {
+        if packet.header.sequence_number == 3 {
+            continue;
+        }
+        let int_samples = int_decoder.decode(&packet).unwrap().to_vec();
+        let float_samples = float_decoder.decode(&packet).unwrap();
+
+        assert_eq!(int_samples.len(), float_samples.len());
+        for (int_sample, float_sample) in int_samples.iter().zip(float_samples) {
+            assert!(
+                (*float_sample * 32768.0 - *int_sample as f32).abs() <= 1.0,
+                "{float_sample} vs {int_sample}"
+            );
+            assert!((float_sample.to_i16() - int_sample).abs() <= 1);
+        }
+    }
+}
+
+#[test]
+fn interleaved_ssrcs_decode_with_their_own_state() {
+    let low = support::encode_tone_packets_with(300.0, 1, 6);
+    let high = support::encode_tone_packets_with(700.0, 2, 6);
+    let decode_alone = |packets: &[rvoip_rtp_core::RtpPacket]| -> Vec<Vec<i16>> {
+        let mut decoder: StreamDecoder =
+            StreamDecoder::new(Arc::new(ConnectionStats::default())).unwrap();
+        packets
+            .iter()
+            .map(|packet| decoder.decode(packet).unwrap().to_vec())
+            .collect()
+    };
+
+    let stats = Arc::new(ConnectionStats::default());
+    let mut decoders: SsrcDecoders = SsrcDecoders::new(stats.clone());
+    let mut interleaved = (Vec::new(), Vec::new());
+    for (low, high) in low.iter().zip(&high) {
+        interleaved.0.push(decoders.decode(low).unwrap().to_vec());
+        interleaved.1.push(decoders.decode(high).unwrap().to_vec());
+    }
+
+    assert_eq!(decoders.stream_count(), 2);
+    assert_eq!(interleaved.0, decode_alone(&low));
+    assert_eq!(interleaved.1, decode_alone(&high));
+    // Equal sequence numbers of the other stream are neither duplicates nor gaps
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.packets_received, 12);
+    assert_eq!(snapshot.frames_concealed_plc, 0);
+    assert_eq!(snapshot.frames_recovered_fec, 0);
+}
+
+#[test]
+fn streams_per_connection_are_capped() {
+    let mut decoders: SsrcDecoders = SsrcDecoders::new(Arc::new(ConnectionStats::default()));
+    for ssrc in 0..MAX_STREAMS_PER_CONNECTION as u32 {
+        let packet = &support::encode_tone_packets_with(440.0, ssrc, 1)[0];
+        decoders.decode(packet).unwrap();
+    }
+
+    let extra = &support::encode_tone_packets_with(440.0, 99, 1)[0];
+    assert!(decoders.decode(extra).is_err());
+    assert_eq!(decoders.stream_count(), MAX_STREAMS_PER_CONNECTION);
+}
+
+#[test]
+fn short_frames_are_concealed_at_their_own_length() {
+    // 2.5ms frames, as sent by clients with `--expert-frame-duration-ms 2.5`
+    const SHORT_FRAME: usize = 120;
+    let mut encoder =
+        opus::Encoder::new(48_000, opus::Channels::Mono, opus::Application::Voip).unwrap();
+    let mut output = vec![0u8; 4000];
+    let packets: Vec<_> = (0..12u16)
+        .map(|seq| {
+            let len = encoder.encode(&[0i16; SHORT_FRAME], &mut output).unwrap();
+            rvoip_rtp_core::RtpPacket::new_with_payload(
+                111,
+                seq,
+                seq as u32 * SHORT_FRAME as u32,
+                1,
+                output[..len].to_vec().into(),
+            )
+        })
+        .collect();
+    let mut decoder: StreamDecoder =
+        StreamDecoder::new(Arc::new(ConnectionStats::default())).unwrap();
+
+    // 8 lost frames are only 20ms of audio, more frames than a stream of 20ms frames gets concealed
+    let mut samples = 0;
+    for packet in packets
+        .iter()
+        .filter(|p| !(2..10).contains(&p.header.sequence_number))
+    {
+        samples += decoder.decode(packet).unwrap().len();
+    }
+
+    assert_eq!(samples, 12 * SHORT_FRAME);
+    assert_eq!(decoder.max_concealed_frames(), 40);
+}
+
+#[test]
+fn stereo_streams_decode_to_interleaved_frames() {
+    let stats = Arc::new(ConnectionStats::default());
+    let mut decoder: StreamDecoder =
+        StreamDecoder::with_channels(stats.clone(), opus::Channels::Stereo).unwrap();
+    let mut encoder = opus::Encoder::new(
+        SAMPLE_RATE,
+        opus::Channels::Stereo,
+        opus::Application::Audio,
+    )
+    .unwrap();
+    // Left and right differ, so a mono decode would lose them
+    let frame: Vec<i16> = (0..FRAME_SAMPLES)
+        .flat_map(|i| {
+            let left = ((i as f32 / 48.0).sin() * 8000.0) as i16;
+            [left, -left]
+        })
+        .collect();
+    let mut output = vec![0u8; 4000];
+
+    let mut samples = 0;
+    for sequence in 0..6u16 {
+        let len = encoder.encode(&frame, &mut output).unwrap();
+        // A lost frame is concealed at the stereo frame length too
+        if sequence == 3 {
+            continue;
+        }
+        let packet = RtpPacket::new_with_payload(
+            111,
+            sequence,
+            u32::from(sequence) * FRAME_SAMPLES as u32,
+            1234,
+            output[..len].to_vec().into(),
+        );
+        samples += decoder.decode(&packet).unwrap().len();
+    }
+
+    assert_eq!(samples, 6 * FRAME_SAMPLES * 2);
+    assert_eq!(stats.snapshot().frames_recovered_fec, 1);
+}
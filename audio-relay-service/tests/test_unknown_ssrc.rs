@@ -0,0 +1,72 @@
+#[path = "support/mod.rs"]
+mod support;
+
+use audio_relay_service::common::app_config::UnknownSsrcPolicy;
+use audio_relay_service::vc::ssrc_filter::{SsrcCheck, SsrcFilter};
+use support::{TestServer, encode_tone_packets_with};
+
+/// Streams 4 packets as SSRC 1234, then continues the sequence as SSRC 9999
+async fn stream_with_switched_ssrc(server: &TestServer) {
+    let connection = support::connect(server).await;
+    support::authenticate(&connection, 0).await;
+    let first = encode_tone_packets_with(440.0, 1234, 4);
+    let second = encode_tone_packets_with(440.0, 9999, 8);
+    for packet in first.iter().chain(&second[4..]) {
+        connection
+            .send_datagram(packet.serialize().unwrap())
+            .unwrap();
+    }
+    // Keep the connection open until the server saw every datagram
+    let snapshot = || server.app.metrics.connection_snapshots()[0].1;
+    support::wait_until(|| {
+        snapshot().packets_received + snapshot().datagrams_dropped_unknown_ssrc == 8
+    })
+    .await;
+}
+
+fn member_ssrc(server: &TestServer) -> Option<u32> {
+    server.app.describe_rooms()[0].members[0].ssrc
+}
+
+#[test]
+fn filter_registers_first_ssrc() {
+    let mut strict = SsrcFilter::new(UnknownSsrcPolicy::Drop);
+    assert_eq!(strict.check(1), SsrcCheck::Registered);
+    assert_eq!(strict.check(1), SsrcCheck::Known);
+    assert_eq!(strict.check(2), SsrcCheck::Unknown);
+    assert_eq!(strict.check(1), SsrcCheck::Known);
+
+    let mut permissive = SsrcFilter::new(UnknownSsrcPolicy::Register);
+    assert_eq!(permissive.check(1), SsrcCheck::Registered);
+    assert_eq!(permissive.check(2), SsrcCheck::Registered);
+    assert_eq!(permissive.check(2), SsrcCheck::Known);
+    assert_eq!(permissive.check(1), SsrcCheck::Known);
+}
+
+#[tokio::test]
+async fn strict_policy_drops_unregistered_ssrc() {
+    let server = support::start_server_with_config(|config| {
+        config.unknown_ssrc_policy = UnknownSsrcPolicy::Drop
+    })
+    .await;
+    stream_with_switched_ssrc(&server).await;
+
+    let snapshot = server.app.metrics.connection_snapshots()[0].1;
+    assert_eq!(snapshot.packets_received, 4);
+    assert_eq!(snapshot.datagrams_dropped_unknown_ssrc, 4);
+    assert_eq!(member_ssrc(&server), Some(1234));
+}
+
+#[tokio::test]
+async fn permissive_policy_registers_new_ssrc() {
+    let server = support::start_server_with_config(|config| {
+        config.unknown_ssrc_policy = UnknownSsrcPolicy::Register
+    })
+    .await;
+    stream_with_switched_ssrc(&server).await;
+
+    let snapshot = server.app.metrics.connection_snapshots()[0].1;
+    assert_eq!(snapshot.packets_received, 8);
+    assert_eq!(snapshot.datagrams_dropped_unknown_ssrc, 0);
+    assert_eq!(member_ssrc(&server), Some(9999));
+}
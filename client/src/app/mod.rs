@@ -1,6 +1,6 @@
 use crate::{
     app_config::AppConfig,
-    audio::audio_manager::{self, AudioManager},
+    audio::audio_manager::{self, AudioManager, ConnectionPhase},
 };
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
 /// This file has all code related to TUI.
@@ -15,7 +15,7 @@ use ratatui::{prelude::*, widgets::Block};
 #[derive(Debug)]
 pub struct App {
     audio_manager: audio_manager::AudioManager,
-    config: AppConfig,
+    _config: AppConfig,
     exit: bool,
     pub counter: i32,
 }
@@ -23,7 +23,7 @@ impl App {
     pub fn new(audio_manager: AudioManager, config: AppConfig) -> Self {
         Self {
             audio_manager,
-            config,
+            _config: config,
             exit: false,
             counter: 0,
         }
@@ -67,6 +67,58 @@ impl App {
         }
     }
 }
+impl App {
+    #[cfg(feature = "audio")]
+    fn encoder_status(&self) -> String {
+        let stats = self.audio_manager.get_stats();
+        let encoder = match stats.encoder {
+            Some(encoder) => match encoder.bitrate {
+                opus::Bitrate::Bits(bits) => format!(
+                    "Encoder: {} kbps, {:?} bandwidth",
+                    bits / 1000,
+                    encoder.bandwidth
+                ),
+                other => format!(
+                    "Encoder: {other:?} bitrate, {:?} bandwidth",
+                    encoder.bandwidth
+                ),
+            },
+            None => return "Encoder: idle".to_string(),
+        };
+        match stats.frame_drop_rate {
+            Some(rate) => format!("{encoder}, {:.1}% dropped locally", rate * 100.0),
+            None => encoder,
+        }
+    }
+
+    #[cfg(not(feature = "audio"))]
+    fn encoder_status(&self) -> String {
+        "Encoder: audio disabled in this build".to_string()
+    }
+
+    #[cfg(feature = "audio")]
+    fn playback_status(&self) -> String {
+        match self.audio_manager.get_stats().jitter_buffer_depth {
+            Some(depth) => format!("Jitter buffer: {depth} frames"),
+            None => "Jitter buffer: idle".to_string(),
+        }
+    }
+
+    #[cfg(not(feature = "audio"))]
+    fn playback_status(&self) -> String {
+        "Jitter buffer: audio disabled in this build".to_string()
+    }
+
+    fn roster_status(&self) -> String {
+        let roster = self.audio_manager.get_roster();
+        if roster.is_empty() {
+            return "In the room: nobody listed yet".to_string();
+        }
+        let members: Vec<String> = roster.iter().map(ToString::to_string).collect();
+        format!("In the room: {}", members.join(", "))
+    }
+}
+
 impl Widget for &App {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let title = Line::from(" Counter App Tutorial ".bold());
@@ -85,18 +137,22 @@ impl Widget for &App {
 
         let counter_text = Text::from(vec![
             Line::from(vec!["Value: ".into(), self.counter.to_string().yellow()]),
-            Line::from(
-                if self.audio_manager.get_active() && !self.audio_manager.is_errored() {
-                    "Now recording audio..."
-                } else {
-                    "Audio recording stopped: "
-                },
-            ),
+            Line::from(match self.audio_manager.get_phase() {
+                ConnectionPhase::Active => "Now recording audio...".to_string(),
+                ConnectionPhase::Errored => format!(
+                    "Audio recording stopped: {}",
+                    self.audio_manager.get_error().unwrap_or_default()
+                ),
+                phase => format!("Audio recording stopped: {phase}"),
+            }),
             Line::from(if self.audio_manager.get_muted() {
                 "Press M to unmute"
             } else {
                 "Press M to mute self"
             }),
+            Line::from(self.encoder_status()),
+            Line::from(self.playback_status()),
+            Line::from(self.roster_status()),
         ]);
         Paragraph::new(counter_text)
             .centered()
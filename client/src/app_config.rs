@@ -2,14 +2,29 @@ use anyhow::anyhow;
 use std::{
     net::{SocketAddr, ToSocketAddrs},
     path::PathBuf,
+    str::FromStr,
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use lib_common_voxoxide::types::{MAX_DISPLAY_NAME_CHARS, sanitize_display_name};
+
+#[cfg(feature = "audio")]
+use crate::audio::adaptive_fec::LossThreshold;
+#[cfg(feature = "audio")]
+use crate::audio::audio_source::{BITRATE_RANGE, COMPLEXITY_RANGE};
+#[cfg(feature = "audio")]
+use crate::audio::jitter_buffer::PLAYOUT_INTERVAL;
+#[cfg(feature = "audio")]
+use crate::audio::oversized_frames::OversizedFramePolicy;
 
 /// HTTP/0.9 over QUIC client
 #[derive(Parser, Debug, Clone)]
 #[clap(name = "client")]
 pub struct AppConfig {
+    /// Runs the TUI if not given
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     #[clap(long = "url", default_value = "quic://[::1]:4433")]
     pub url: url::Url,
 
@@ -17,8 +32,13 @@ pub struct AppConfig {
     #[clap(long = "host")]
     pub host: Option<String>,
 
-    /// Certificate path
-    #[clap(long = "pem", default_value = "../dev-certs/dev-ca.pem")]
+    /// CA certificate the server is verified against, a path, `env:NAME`, `-` for stdin or inline PEM.
+    /// Debug builds default to the dev CA, release builds use the certificate embedded at build time if not given
+    #[cfg_attr(
+        debug_assertions,
+        clap(long = "pem", default_value = "../dev-certs/dev-ca.pem")
+    )]
+    #[cfg_attr(not(debug_assertions), clap(long = "pem"))]
     pub cert_path: Option<PathBuf>,
 
     /// Address to bind on
@@ -26,6 +46,326 @@ pub struct AppConfig {
     pub bind: SocketAddr,
     #[clap(long = "log-file", short, default_value = "/dev/null")]
     pub log_file: PathBuf,
+
+    /// Audio to stream: `mic` for the default input device or `file:<path>` for a WAV file
+    #[clap(long = "source", default_value = "mic")]
+    pub source: AudioSourceConfig,
+    /// Input device to capture from by name, the default input device if not set.
+    /// Joining fails if it's missing, the error lists the devices present.
+    /// Unplugged during a call, the default device fills in until it's plugged in again
+    #[cfg(feature = "audio")]
+    #[clap(long = "input-device")]
+    pub input_device: Option<String>,
+    /// Output device to play received audio on by name, the default output device if not set.
+    /// Joining fails if it's missing, the error lists the devices present
+    #[cfg(feature = "audio")]
+    #[clap(long = "output-device")]
+    pub output_device: Option<String>,
+    /// Start a file source over when it ends instead of stopping
+    #[clap(long = "loop-source")]
+    pub loop_source: bool,
+    /// Transmit mono even if the input device only offers stereo, the encoder downmixes it
+    #[clap(long = "force-mono")]
+    pub force_mono: bool,
+    /// Capture and transmit stereo, eg. for music. Input devices offering only mono are upmixed.
+    /// The relay decodes and records in the layout declared on joining, rooms are fixed to their first member's
+    #[clap(long = "stereo", conflicts_with = "force_mono")]
+    pub stereo: bool,
+    /// Widest band the encoder may pick while adapting: `nb`, `mb`, `wb`, `swb` or `fb`
+    #[cfg(feature = "audio")]
+    #[clap(long = "max-bandwidth")]
+    pub max_bandwidth: Option<MaxBandwidth>,
+    /// Expert setting: keep Opus in one coding mode, `celt` or `silk`. Opus picks per frame if not set
+    #[cfg(feature = "audio")]
+    #[clap(long = "force-opus-mode")]
+    pub force_opus_mode: Option<OpusMode>,
+    /// Significant bits in the captured samples, from 8 to 24, so the encoder spends no bits on
+    /// the noise floor below them. Can't exceed what the input device captures
+    #[cfg(feature = "audio")]
+    #[clap(long = "lsb-depth")]
+    pub lsb_depth: Option<LsbDepth>,
+    /// Reset the encoder when unmuting, so the first frames carry no stale prediction
+    #[clap(long = "reset-encoder-on-unmute")]
+    pub reset_encoder_on_unmute: bool,
+    /// Expert setting: Opus frame duration in ms for low-latency setups,
+    /// one of `2.5`, `5`, `10`, `20`, `40` or `60`. 20ms if not set
+    #[clap(long = "expert-frame-duration-ms")]
+    pub expert_frame_duration: Option<FrameDuration>,
+    /// Bits per second to encode at, from 6000 to 510000, unless the room's codec policy sets one.
+    /// Opus picks one if not set
+    #[cfg(feature = "audio")]
+    #[clap(long = "bitrate")]
+    pub bitrate: Option<OpusBitrate>,
+    /// CPU the encoder spends per frame, from 0 to 10. Lower saves CPU at some quality
+    #[cfg(feature = "audio")]
+    #[clap(long = "complexity")]
+    pub complexity: Option<OpusComplexity>,
+    /// Send next to nothing while the input is silent, the receivers conceal the gaps
+    #[clap(long = "dtx")]
+    pub dtx: bool,
+    /// Bits per second the encoder drops to while the input is quiet, eg. `8000`.
+    /// The bitrate stays put through pauses if not set
+    #[cfg(feature = "audio")]
+    #[clap(long = "bitrate-floor")]
+    pub bitrate_floor: Option<OpusBitrate>,
+    /// Name other members of the room see, at most 32 characters
+    #[clap(long = "display-name")]
+    pub display_name: Option<DisplayName>,
+    /// Agree to being recorded by the server, rooms requiring consent don't record anyone otherwise
+    #[clap(long = "consent-to-recording")]
+    pub consent_to_recording: bool,
+    /// User id to join as, required with `--auth-token` by relays that authenticate their users
+    #[clap(long = "user-id", requires = "auth_token")]
+    pub user_id: Option<u64>,
+    /// Token proving `--user-id` for the room, as handed out alongside the user id
+    #[clap(long = "auth-token", requires = "user_id")]
+    pub auth_token: Option<String>,
+    /// Also write the received audio to this WAV file, finalized when leaving the room
+    #[clap(long = "record-local")]
+    pub record_local: Option<PathBuf>,
+    /// Turn in-band FEC on while the measured loss exceeds this percentage, and off once the link is clean again.
+    /// Only applies in rooms whose codec policy leaves FEC open
+    #[cfg(feature = "audio")]
+    #[clap(long = "adaptive-fec")]
+    pub adaptive_fec: Option<LossThreshold>,
+    /// `drop` frames too large for a datagram, or `reduce-bitrate` once as well should they recur
+    #[cfg(feature = "audio")]
+    #[clap(long = "oversized-frames", default_value = "reduce-bitrate")]
+    pub oversized_frames: OversizedFramePolicy,
+    /// Milliseconds a received frame may arrive past its playout deadline and still play instead of
+    /// being concealed, below the 20ms frame duration. Missing frames are concealed right away if not set
+    #[cfg(feature = "audio")]
+    #[clap(long = "late-grace-ms")]
+    pub late_grace: Option<LateGrace>,
+    /// Send one frame per frame duration off a timer instead of whenever the input device hands it over,
+    /// smoothing bursty capture at the cost of a frame of latency
+    #[cfg(feature = "audio")]
+    #[clap(long = "pace-sends")]
+    pub pace_sends: bool,
+    /// Keep the encoder's expected packet loss at the measured loss, smoothed, so the FEC strength
+    /// follows the link instead of staying fixed
+    #[cfg(feature = "audio")]
+    #[clap(long = "track-loss")]
+    pub track_loss: bool,
+    /// Milliseconds between the keepalive datagrams sent while muted, so the connection doesn't go idle
+    /// with no audio flowing. `0` sends none
+    #[cfg(feature = "audio")]
+    #[clap(long = "keepalive-interval-ms", default_value = "1000")]
+    pub keepalive_interval_ms: u64,
+    /// Print the input and output devices with the configurations they support, then exit
+    #[clap(long = "list-devices")]
+    pub list_devices: bool,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Checks the audio setup and prints a pass/fail report
+    Selftest {
+        /// Also connect to the configured server and authenticate
+        #[clap(long = "connect")]
+        connect: bool,
+    },
+    /// Encodes synthetic audio at the configured encoder settings and reports the CPU cost,
+    /// without touching the network or audio devices.
+    /// Allocations are only counted in builds with the `bench` feature
+    BenchEncode {
+        /// Seconds of audio to encode
+        #[clap(long = "seconds", default_value = "10")]
+        seconds: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioSourceConfig {
+    Mic,
+    File(PathBuf),
+}
+
+impl FromStr for AudioSourceConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "mic" => Ok(Self::Mic),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(PathBuf::from(path))),
+            _ => Err(anyhow!("expected `mic` or `file:<path>`, got `{s}`")),
+        }
+    }
+}
+
+/// Ceiling for the Opus bandpass, unlike a fixed bandwidth the encoder still adapts below it.
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxBandwidth(pub opus::Bandwidth);
+
+#[cfg(feature = "audio")]
+impl FromStr for MaxBandwidth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(match s {
+            "nb" => opus::Bandwidth::Narrowband,
+            "mb" => opus::Bandwidth::Mediumband,
+            "wb" => opus::Bandwidth::Wideband,
+            "swb" => opus::Bandwidth::Superwideband,
+            "fb" => opus::Bandwidth::Fullband,
+            _ => return Err(anyhow!("expected one of nb, mb, wb, swb, fb, got `{s}`")),
+        }))
+    }
+}
+
+/// Opus coding mode to stay in. Opus normally switches between SILK for speech at low bitrates,
+/// CELT at high bitrates and a hybrid of both per frame.
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpusMode {
+    /// Transform coding only, lower latency and better for music
+    Celt,
+    /// Linear prediction only, efficient for speech at low bitrates
+    Silk,
+}
+
+#[cfg(feature = "audio")]
+impl FromStr for OpusMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "celt" => Ok(Self::Celt),
+            "silk" => Ok(Self::Silk),
+            _ => Err(anyhow!("expected `celt` or `silk`, got `{s}`")),
+        }
+    }
+}
+
+/// Bit depth of the signal fed to the encoder, Opus accepts 8 to 24 bits.
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LsbDepth(pub u8);
+
+#[cfg(feature = "audio")]
+impl FromStr for LsbDepth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(depth @ 8..=24) => Ok(Self(depth)),
+            _ => Err(anyhow!("expected a bit depth from 8 to 24, got `{s}`")),
+        }
+    }
+}
+
+/// Target bitrate of the encoder in bits per second, within what Opus accepts.
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusBitrate(pub i32);
+
+#[cfg(feature = "audio")]
+impl FromStr for OpusBitrate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(bits) if BITRATE_RANGE.contains(&bits) => Ok(Self(bits)),
+            _ => Err(anyhow!(
+                "expected a bitrate from {} to {}, got `{s}`",
+                BITRATE_RANGE.start(),
+                BITRATE_RANGE.end()
+            )),
+        }
+    }
+}
+
+/// Opus encoder complexity, from 0 (fastest) to 10 (best quality).
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusComplexity(pub i32);
+
+#[cfg(feature = "audio")]
+impl FromStr for OpusComplexity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(complexity) if COMPLEXITY_RANGE.contains(&complexity) => Ok(Self(complexity)),
+            _ => Err(anyhow!("expected a complexity from 0 to 10, got `{s}`")),
+        }
+    }
+}
+
+/// How long the jitter buffer waits for a late packet, shorter than a frame slot
+/// so a pending frame is decided before the next one is due.
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LateGrace(pub std::time::Duration);
+
+#[cfg(feature = "audio")]
+impl FromStr for LateGrace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let max = PLAYOUT_INTERVAL.as_millis() as u64;
+        match s.parse() {
+            Ok(millis) if millis < max => Ok(Self(std::time::Duration::from_millis(millis))),
+            _ => Err(anyhow!(
+                "expected a grace window from 0 to {}ms, got `{s}`",
+                max - 1
+            )),
+        }
+    }
+}
+
+/// Duration of one Opus frame. Opus takes its frame duration from the size of the frame it encodes,
+/// so the duration is applied by handing the encoder frames of [`FrameDuration::frame_size`] samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameDuration {
+    micros: u32,
+}
+
+impl FrameDuration {
+    /// Every duration Opus can encode
+    const SUPPORTED_MICROS: [u32; 6] = [2_500, 5_000, 10_000, 20_000, 40_000, 60_000];
+
+    /// Samples per channel in one frame, fails if the duration isn't a whole number of samples at `sample_rate`
+    pub fn frame_size(self, sample_rate: u32) -> anyhow::Result<usize> {
+        let scaled = u64::from(sample_rate) * u64::from(self.micros);
+        if scaled % 1_000_000 != 0 {
+            return Err(anyhow!(
+                "a {}ms frame is not a whole number of samples at {sample_rate}Hz",
+                self.micros as f64 / 1000.0
+            ));
+        }
+        Ok((scaled / 1_000_000) as usize)
+    }
+}
+
+impl FromStr for FrameDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let millis: f64 = s
+            .parse()
+            .map_err(|_| anyhow!("expected a frame duration in ms, got `{s}`"))?;
+        let micros = (millis * 1000.0).round() as u32;
+        if !Self::SUPPORTED_MICROS.contains(&micros) {
+            return Err(anyhow!("expected one of 2.5, 5, 10, 20, 40, 60, got `{s}`"));
+        }
+        Ok(Self { micros })
+    }
+}
+
+/// A display name as the server accepts it, sanitized on parsing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayName(pub String);
+
+impl FromStr for DisplayName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        sanitize_display_name(s).map(Self).ok_or_else(|| {
+            anyhow!("expected 1 to {MAX_DISPLAY_NAME_CHARS} characters without control characters, got `{s}`")
+        })
+    }
 }
 
 impl AppConfig {
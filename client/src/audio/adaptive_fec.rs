@@ -0,0 +1,254 @@
+//! In-band FEC only while the link loses packets, set by `--adaptive-fec`.
+//! FEC costs bitrate on every frame, so on a clean link it's wasted. The relay sends no receiver reports,
+//! so the loss is taken from QUIC's own loss detection: the path's lost and sent packet counters,
+//! sampled every [`SAMPLE_INTERVAL`]. Datagrams share those packets with the control stream,
+//! which is close enough since the audio makes up nearly all of them.
+//! FEC turns on as soon as a sample's loss exceeds the threshold and off only after
+//! [`CLEAN_SAMPLES`] samples in a row below half of it, so a link hovering around the threshold doesn't flap.
+//! With `--track-loss` the encoder's expected loss follows the same samples, see [`ExpectedLoss`].
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+use crate::audio::audio_source::SharedEncoder;
+
+/// How often the loss is sampled
+pub(crate) const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Clean samples in a row before FEC turns off again, 5s at 1s
+pub(crate) const CLEAN_SAMPLES: usize = 5;
+/// Highest expected loss handed to the encoder, beyond it FEC eats the bitrate without saving much more
+pub(crate) const MAX_EXPECTED_LOSS: i32 = 25;
+/// Weight of a new sample in the smoothed loss
+const LOSS_SMOOTHING: f32 = 0.3;
+
+/// Loss in percent above which FEC turns on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossThreshold(pub f32);
+
+impl FromStr for LossThreshold {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f32>() {
+            Ok(percent) if percent > 0.0 && percent < 100.0 => Ok(Self(percent)),
+            _ => Err(anyhow!(
+                "expected a loss percentage above 0 and below 100, got `{s}`"
+            )),
+        }
+    }
+}
+
+/// Turns the connection's cumulative packet counters into a loss per sample
+#[derive(Debug, Default)]
+struct LossSampler {
+    /// Sent and lost packets at the last sample
+    last_counters: Option<(u64, u64)>,
+}
+
+impl LossSampler {
+    /// Loss in percent since the last call, None on the first call and while nothing was sent
+    fn sample(&mut self, sent: u64, lost: u64) -> Option<f32> {
+        let (last_sent, last_lost) = self.last_counters.replace((sent, lost))?;
+        let sent = sent.saturating_sub(last_sent);
+        if sent == 0 {
+            return None;
+        }
+        Some(lost.saturating_sub(last_lost) as f32 / sent as f32 * 100.0)
+    }
+}
+
+/// Decides from the loss samples of one call whether FEC is on
+#[derive(Debug)]
+pub struct AdaptiveFec {
+    threshold: f32,
+    enabled: bool,
+    /// Samples in a row below half the threshold while enabled
+    clean_samples: usize,
+    sampler: LossSampler,
+}
+
+impl AdaptiveFec {
+    /// Starts with FEC off
+    pub fn new(threshold: LossThreshold) -> Self {
+        Self {
+            threshold: threshold.0,
+            enabled: false,
+            clean_samples: 0,
+            sampler: LossSampler::default(),
+        }
+    }
+
+    /// Takes the connection's cumulative packet counters and samples the loss since the last call.
+    /// Returns the new FEC state and the loss in percent if it changed
+    pub fn observe_counters(&mut self, sent: u64, lost: u64) -> Option<(bool, f32)> {
+        let loss = self.sampler.sample(sent, lost)?;
+        self.observe(loss).map(|enabled| (enabled, loss))
+    }
+
+    /// Takes one loss sample in percent, returns the new FEC state if it changed
+    pub fn observe(&mut self, loss: f32) -> Option<bool> {
+        if !self.enabled {
+            if loss > self.threshold {
+                self.enabled = true;
+                self.clean_samples = 0;
+                return Some(true);
+            }
+            return None;
+        }
+        if loss >= self.threshold / 2.0 {
+            self.clean_samples = 0;
+            return None;
+        }
+        self.clean_samples += 1;
+        if self.clean_samples < CLEAN_SAMPLES {
+            return None;
+        }
+        self.enabled = false;
+        Some(false)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// The encoder's expected loss following the measured one, set by `--track-loss`.
+/// Opus sizes its FEC data by the expected loss, so a fixed one is either too weak or wasteful.
+/// Samples are smoothed exponentially and the result capped at [`MAX_EXPECTED_LOSS`],
+/// the encoder is only touched when the whole percentage changes.
+#[derive(Debug, Default)]
+pub struct ExpectedLoss {
+    sampler: LossSampler,
+    smoothed: Option<f32>,
+    applied: Option<i32>,
+}
+
+impl ExpectedLoss {
+    /// Takes the connection's cumulative packet counters, returns the new expected loss if it changed
+    pub fn observe_counters(&mut self, sent: u64, lost: u64) -> Option<i32> {
+        let loss = self.sampler.sample(sent, lost)?;
+        self.observe(loss)
+    }
+
+    /// Takes one loss sample in percent, returns the new expected loss if it changed
+    pub fn observe(&mut self, loss: f32) -> Option<i32> {
+        let smoothed = match self.smoothed {
+            Some(smoothed) => smoothed + (loss - smoothed) * LOSS_SMOOTHING,
+            None => loss,
+        };
+        self.smoothed = Some(smoothed);
+        let expected = (smoothed.round() as i32).clamp(0, MAX_EXPECTED_LOSS);
+        if self.applied == Some(expected) {
+            return None;
+        }
+        self.applied = Some(expected);
+        Some(expected)
+    }
+}
+
+/// Hands the expected loss in percent to the encoder
+pub fn apply_expected_loss(encoder: &SharedEncoder, percent: i32) -> opus::Result<()> {
+    encoder.lock().unwrap().set_packet_loss_perc(percent)
+}
+
+/// Switches the encoder's FEC. Opus only spends bits on FEC for a non-zero expected loss,
+/// so the measured one is passed along while it's on
+pub fn apply(encoder: &SharedEncoder, enabled: bool, loss: f32) -> opus::Result<()> {
+    let mut encoder = encoder.lock().unwrap();
+    encoder.set_inband_fec(enabled)?;
+    let expected_loss = if enabled {
+        (loss.ceil() as i32).clamp(1, 100)
+    } else {
+        0
+    };
+    encoder.set_packet_loss_perc(expected_loss)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::audio_source::EncoderSettings;
+
+    #[test]
+    fn fec_follows_a_synthetic_loss_sequence_with_hysteresis() {
+        let mut fec = AdaptiveFec::new("5".parse().unwrap());
+        let changes: Vec<Option<bool>> = [
+            0.0, 1.0, 6.0, // lossy, on
+            3.0, // below the threshold but not clean, stays on
+            1.0, 1.0, 1.0, 1.0, // clean but one short
+            2.0, // fifth clean sample in a row, off
+            4.9, 5.0, // at most the threshold, stays off
+            8.0,
+        ]
+        .into_iter()
+        .map(|loss| fec.observe(loss))
+        .collect();
+
+        let expected = [
+            None,
+            None,
+            Some(true),
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(false),
+            None,
+            None,
+            Some(true),
+        ];
+        assert_eq!(changes, expected);
+        assert!(fec.enabled());
+    }
+
+    #[test]
+    fn loss_is_sampled_from_the_counter_deltas() {
+        let mut fec = AdaptiveFec::new(LossThreshold(5.0));
+
+        assert_eq!(fec.observe_counters(1000, 100), None);
+        // Nothing sent since, no sample
+        assert_eq!(fec.observe_counters(1000, 100), None);
+        assert_eq!(fec.observe_counters(1050, 110), Some((true, 20.0)));
+
+        let encoder = EncoderSettings::default().build_encoder().unwrap();
+        apply(&encoder, true, 20.0).unwrap();
+        assert!(encoder.lock().unwrap().get_inband_fec().unwrap());
+        assert_eq!(encoder.lock().unwrap().get_packet_loss_perc().unwrap(), 20);
+        apply(&encoder, false, 0.0).unwrap();
+        assert!(!encoder.lock().unwrap().get_inband_fec().unwrap());
+    }
+
+    #[test]
+    fn expected_loss_follows_smoothed_samples() {
+        let mut loss = ExpectedLoss::default();
+        let encoder = EncoderSettings::default().build_encoder().unwrap();
+
+        assert_eq!(loss.observe_counters(1000, 0), None);
+        // 10% lost over the first sample
+        let expected = loss.observe_counters(1100, 10).unwrap();
+        assert_eq!(expected, 10);
+        apply_expected_loss(&encoder, expected).unwrap();
+        assert_eq!(encoder.lock().unwrap().get_packet_loss_perc().unwrap(), 10);
+
+        // A clean sample pulls it down only part of the way
+        assert_eq!(loss.observe(0.0), Some(7));
+        // Rounding to the same percentage leaves the encoder alone
+        assert_eq!(loss.observe(7.0), None);
+        // A burst is capped
+        let capped = [90.0, 90.0, 90.0, 90.0, 90.0]
+            .into_iter()
+            .filter_map(|sample| loss.observe(sample))
+            .last();
+        assert_eq!(capped, Some(MAX_EXPECTED_LOSS));
+    }
+
+    #[test]
+    fn threshold_must_be_a_percentage() {
+        assert!("0".parse::<LossThreshold>().is_err());
+        assert!("100".parse::<LossThreshold>().is_err());
+        assert_eq!("2.5".parse::<LossThreshold>().unwrap(), LossThreshold(2.5));
+    }
+}
@@ -1,32 +1,128 @@
 use std::sync::Arc;
 use std::sync::Mutex;
+#[cfg(feature = "audio")]
+use std::time::Instant;
 
-use lib_common_voxoxide::types::ArsAuthRequest;
-use quinn::{Connection, VarInt};
+#[cfg(feature = "audio")]
+use lib_common_voxoxide::types::{
+    ArsAudioFormat, ArsControlMessage, CloseCode, KEEPALIVE_DATAGRAM, VoxoxideError,
+};
+use lib_common_voxoxide::types::{ArsAuthRequest, ArsAuthResponse, Features, frame_message};
+#[cfg(feature = "audio")]
+use opus::Bitrate;
+use quinn::Connection;
+#[cfg(feature = "audio")]
+use rvoip_rtp_core::RtpPacket;
 use tokio::sync::mpsc::Receiver;
 
-use crate::{
-    app_config::AppConfig,
-    audio::{self, create_audio_connection},
+use crate::app_config::AppConfig;
+#[cfg(feature = "audio")]
+use crate::audio::{
+    self,
+    adaptive_fec::{self, AdaptiveFec, ExpectedLoss},
+    audio_sink::{AudioSink, PlaybackMixer},
+    audio_source::{EncoderSettings, EncoderStats, SharedEncoder, SharedFrameDrops},
+    create_audio_connection,
+    jitter_buffer::{JitterBuffers, PLAYOUT_INTERVAL, SharedJitterBuffers},
+    local_recording::LocalRecording,
+    oversized_frames::OversizedFrames,
+    send_pacer::{self, SendPacer},
 };
 
+/// Optional features announced to the server, a mixed room comes back as one stream to play
+pub const CLIENT_FEATURES: Features = Features::FEC.union(Features::MIXING);
+/// Sent along with [`CloseCode::ClientLeft`] when leaving a room
+#[cfg(feature = "audio")]
+pub const LEAVE_REASON: &[u8] = b"left the room";
+/// Longest control message read from the server, rosters of large rooms with long names included
+#[cfg(feature = "audio")]
+const MAX_CONTROL_MESSAGE_LEN: usize = 64 * 1024;
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
 pub enum AudioManagerSignal {
-    EXIT,
-    MUTE,
-    UNMUTE,
+    Exit,
+    Mute,
+    Unmute,
 }
 impl std::fmt::Display for AudioManagerSignal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
-            AudioManagerSignal::EXIT => "EXIT",
-            AudioManagerSignal::MUTE => "MUTE",
-            AudioManagerSignal::UNMUTE => "UNMUTE",
+            AudioManagerSignal::Exit => "EXIT",
+            AudioManagerSignal::Mute => "MUTE",
+            AudioManagerSignal::Unmute => "UNMUTE",
         })
     }
 }
 
+/// Failures of this client worth telling apart from a generic error.
+/// The server closing the connection surfaces as a [`VoxoxideError`] instead, see [`AudioManager::connection_lost`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+pub enum AudioManagerError {
+    /// The server doesn't speak the protocol this build offered, it needs a matching build
+    IncompatibleServer { protocol: String },
+    /// The server didn't negotiate QUIC datagrams, which all audio travels in
+    DatagramsUnsupported,
+    /// Built without the `audio` feature, there is nothing to capture or encode with
+    #[cfg_attr(feature = "audio", allow(dead_code))]
+    AudioDisabled,
+}
+impl std::fmt::Display for AudioManagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioManagerError::IncompatibleServer { protocol } => write!(
+                f,
+                "incompatible server: it doesn't support protocol {protocol}, a matching client build is needed"
+            ),
+            AudioManagerError::DatagramsUnsupported => write!(
+                f,
+                "the server doesn't support QUIC datagrams, audio can't be sent or received"
+            ),
+            AudioManagerError::AudioDisabled => write!(
+                f,
+                "audio is disabled: this client was built without the `audio` feature"
+            ),
+        }
+    }
+}
+impl std::error::Error for AudioManagerError {}
+
+/// Where the connection to the relay stands, as the TUI shows it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionPhase {
+    /// Not in a room
+    #[default]
+    Idle,
+    /// Opening the QUIC connection
+    Connecting,
+    /// Connected, waiting for the server to accept the auth request
+    Authenticating,
+    /// In a room and streaming audio
+    Active,
+    /// Lost the connection and trying again, `attempt` out of `max_attempts`
+    #[allow(dead_code)] // not entered until the client reconnects on its own
+    Reconnecting { attempt: u32, max_attempts: u32 },
+    /// The connection failed, the reason is in [`AudioManagerState::stream_error`]
+    Errored,
+}
+impl std::fmt::Display for ConnectionPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionPhase::Idle => f.write_str("idle"),
+            ConnectionPhase::Connecting => f.write_str("connecting"),
+            ConnectionPhase::Authenticating => f.write_str("authenticating"),
+            ConnectionPhase::Active => f.write_str("active"),
+            ConnectionPhase::Reconnecting {
+                attempt,
+                max_attempts,
+            } => write!(f, "reconnecting (attempt {attempt}/{max_attempts})"),
+            ConnectionPhase::Errored => f.write_str("errored"),
+        }
+    }
+}
+
+#[allow(dead_code)] // kept for the session's lifetime, nothing reads it back yet
 #[derive(Debug, Default)]
 pub struct RoomActiveAudioSession {
     session_id: u32,
@@ -35,12 +131,92 @@ pub struct RoomActiveAudioSession {
     mixing: u8,
     room_id: u32,
 }
+
+impl RoomActiveAudioSession {
+    /// The session the server assigned on accepting us into `room_id`
+    #[cfg(feature = "audio")]
+    fn accepted(room_id: u32, response: &ArsAuthResponse) -> Self {
+        Self {
+            session_id: response.session_id,
+            session_key: response.session_key,
+            room_id,
+            ..Default::default()
+        }
+    }
+}
+/// A member of the room, as the server's last roster listed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RosterMember {
+    pub member_id: u64,
+    /// None if the member gave no name when joining
+    pub display_name: Option<String>,
+    /// Muted by itself or a moderator
+    pub muted: bool,
+}
+
+impl RosterMember {
+    /// Pairs the roster's ids with their names, servers predating names send none
+    #[cfg_attr(not(feature = "audio"), allow(dead_code))]
+    fn list(member_ids: &[u64], muted: &[u64], display_names: &[Option<String>]) -> Vec<Self> {
+        member_ids
+            .iter()
+            .enumerate()
+            .map(|(i, member_id)| Self {
+                member_id: *member_id,
+                display_name: display_names.get(i).cloned().flatten(),
+                muted: muted.contains(member_id),
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for RosterMember {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.display_name {
+            Some(name) => f.write_str(name)?,
+            None => write!(f, "member {}", self.member_id)?,
+        }
+        if self.muted {
+            f.write_str(" (muted)")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct AudioManagerState {
+    pub phase: ConnectionPhase,
     pub active_session: Option<RoomActiveAudioSession>,
+    /// Who is in the room, empty until the server sent a roster
+    pub roster: Vec<RosterMember>,
     pub stream_error: Option<anyhow::Error>,
     pub muted: bool,
     pub signal_sender: Option<tokio::sync::mpsc::Sender<AudioManagerSignal>>,
+    /// Encoder of the running audio source, kept so its state can be inspected
+    #[cfg(feature = "audio")]
+    pub encoder: Option<SharedEncoder>,
+    /// Local frame drops of the running audio source
+    #[cfg(feature = "audio")]
+    pub frame_drops: Option<SharedFrameDrops>,
+    /// Jitter buffers of the received streams
+    #[cfg(feature = "audio")]
+    pub jitter_buffers: Option<SharedJitterBuffers>,
+}
+
+/// Snapshot of the audio manager for the TUI
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioStats {
+    pub active: bool,
+    pub muted: bool,
+    /// None while no audio source is running
+    #[cfg(feature = "audio")]
+    pub encoder: Option<EncoderStats>,
+    /// Fraction of recent frames dropped because the send queue was full, None while no audio source is running
+    #[cfg(feature = "audio")]
+    pub frame_drop_rate: Option<f32>,
+    /// Frames the playback jitter buffer holds back, None while not in a room
+    #[cfg(feature = "audio")]
+    pub jitter_buffer_depth: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -67,6 +243,7 @@ impl AudioManager {
         tracing::info!("Joining room {}", room_id);
 
         state.stream_error = None;
+        state.phase = ConnectionPhase::Connecting;
 
         let (sender, receiver) = tokio::sync::mpsc::channel(12);
         state.signal_sender = Some(sender.clone());
@@ -78,84 +255,328 @@ impl AudioManager {
 
         tokio::spawn(async move {
             if let Err(e) =
-                Self::handle_audio_streaming(config, receiver, shared_state.clone()).await
+                Self::handle_audio_streaming(config, room_id, receiver, shared_state.clone()).await
             {
                 tracing::error!("ARS Connection error: {e}");
 
                 let mut state = shared_state.lock().unwrap();
+                state.phase = ConnectionPhase::Errored;
                 state.stream_error = Some(e);
                 state.active_session = None;
                 state.signal_sender = None;
+                state.roster.clear();
+                #[cfg(feature = "audio")]
+                {
+                    state.encoder = None;
+                    state.frame_drops = None;
+                    state.jitter_buffers = None;
+                }
             }
         });
     }
 
+    #[cfg(not(feature = "audio"))]
+    async fn handle_audio_streaming(
+        _config: AppConfig,
+        _room_id: u32,
+        _receiver: Receiver<AudioManagerSignal>,
+        _shared_state: Arc<Mutex<AudioManagerState>>,
+    ) -> anyhow::Result<()> {
+        Err(AudioManagerError::AudioDisabled.into())
+    }
+
+    #[cfg(feature = "audio")]
     async fn handle_audio_streaming(
         config: AppConfig,
+        room_id: u32,
         mut receiver: Receiver<AudioManagerSignal>,
         shared_state: Arc<Mutex<AudioManagerState>>,
     ) -> anyhow::Result<()> {
-        let mut connection = create_audio_connection(config).await?;
+        let mut connection = create_audio_connection(config.clone()).await?;
+        shared_state.lock().unwrap().phase = ConnectionPhase::Authenticating;
         let play = !shared_state.lock().unwrap().muted;
-        Self::authenticate_audio_connection(&mut connection)
+        let mut request = ArsAuthRequest::for_room(room_id);
+        request.display_name = config.display_name.as_ref().map(|name| name.0.clone());
+        request.recording_consent = config.consent_to_recording;
+        request.user_id = config.user_id;
+        request.token = config.auth_token.clone();
+        let ssrc = rand::random_range(0..u32::MAX / 2);
+        request.ssrc = Some(ssrc);
+        // The relay decodes and records our stream in this layout
+        request.format = Some(ArsAudioFormat {
+            sample_rate: audio::audio_source::SAMPLE_RATE,
+            channels: if config.stereo { 2 } else { 1 },
+        });
+        let auth_response = Self::authenticate_audio_connection(&mut connection, request)
             .await
-            .map_err(|e| {
-                anyhow::anyhow!(
-                    "Failed authentication: {e}: close reason: {:?}",
-                    connection.close_reason()
-                )
+            .map_err(|e| match connection.close_reason() {
+                Some(reason) => Self::connection_lost(reason),
+                None => anyhow::anyhow!("Failed authentication: {e}"),
             })?;
+        tracing::info!("Negotiated features: {:?}", auth_response.features);
+        // Another member of the room already streams with ours
+        let ssrc = auth_response.reassigned_ssrc.unwrap_or(ssrc);
         // only after authenticating are we in a session
-        shared_state.lock().unwrap().active_session = Some(RoomActiveAudioSession::default());
+        tracing::info!(
+            "Joined room {room_id} as session {}",
+            auth_response.session_id
+        );
+        shared_state.lock().unwrap().active_session =
+            Some(RoomActiveAudioSession::accepted(room_id, &auth_response));
 
-        let mut audio_source = audio::audio_source::RTPOpusAudioSource::new(play)?;
+        // The room decides how we encode, our defaults only fill what it leaves open
+        let settings = EncoderSettings::default()
+            .with_policy(auth_response.codec_policy.as_ref())
+            .with_config(&config)?;
+        tracing::info!("Encoder settings for room {room_id}: {settings:?}");
+        let mut send_pacer = config
+            .pace_sends
+            .then(|| SendPacer::new(settings.frame_duration()));
+        let mut audio_source =
+            audio::audio_source::AudioSource::open(&config, play, settings, ssrc)?;
+        let late_grace = config.late_grace.map(|grace| grace.0).unwrap_or_default();
+        let jitter_buffers: SharedJitterBuffers =
+            Arc::new(Mutex::new(JitterBuffers::with_late_grace(late_grace)));
+        {
+            let mut state = shared_state.lock().unwrap();
+            state.encoder = Some(audio_source.encoder());
+            state.frame_drops = Some(audio_source.frame_drops());
+            state.jitter_buffers = Some(jitter_buffers.clone());
+            state.phase = ConnectionPhase::Active;
+        }
+        // Joining muted, the room would show us as unmuted otherwise
+        if !play {
+            Self::announce_muted(&connection, true);
+        }
+        let mut local_recording = match &config.record_local {
+            Some(path) => Some(LocalRecording::create(path)?),
+            None => None,
+        };
+        let mut playout = tokio::time::interval(PLAYOUT_INTERVAL);
+        let mut playback = PlaybackMixer::default();
+        let mut audio_sink = AudioSink::open(config.output_device.as_deref())?;
+        let encoder = audio_source.encoder();
+        let mut oversized_frames = OversizedFrames::new(config.oversized_frames);
+        // A room demanding or forbidding FEC keeps it as it says
+        let fec_fixed = auth_response
+            .codec_policy
+            .as_ref()
+            .is_some_and(|policy| policy.fec.is_some());
+        let mut adaptive_fec = config
+            .adaptive_fec
+            .filter(|_| !fec_fixed)
+            .map(AdaptiveFec::new);
+        let mut expected_loss = config.track_loss.then(ExpectedLoss::default);
+        let mut loss_sample = tokio::time::interval(adaptive_fec::SAMPLE_INTERVAL);
+        // Muted, the audio source sends nothing that would keep the connection busy
+        let mut muted = !play;
+        let keepalive_period = (config.keepalive_interval_ms > 0)
+            .then(|| std::time::Duration::from_millis(config.keepalive_interval_ms));
+        let mut keepalive =
+            tokio::time::interval(keepalive_period.unwrap_or(std::time::Duration::from_secs(1)));
+        let control_messages = Self::receive_control_messages(&connection, &shared_state);
+        tokio::pin!(control_messages);
 
         loop {
+            let grace_deadline = jitter_buffers.lock().unwrap().grace_deadline();
             tokio::select! {
 
                 Some(signal) = receiver.recv() => {
                     tracing::info!("Received signal: {}", signal);
 
                     match signal {
-                        AudioManagerSignal::EXIT => {
-                            connection.close(VarInt::from_u32(0), b"done");
+                        AudioManagerSignal::Exit => {
+                            Self::leave(&connection);
                             break;
                         }
-                        AudioManagerSignal::MUTE => {
+                        AudioManagerSignal::Mute => {
                             audio_source.set_playing(false).await;
+                            Self::announce_muted(&connection, true);
+                            muted = true;
                             let mut state = shared_state.lock().unwrap();
                             state.muted = true;
                         }
-                        AudioManagerSignal::UNMUTE => {
+                        AudioManagerSignal::Unmute => {
                             audio_source.set_playing(true).await;
+                            Self::announce_muted(&connection, false);
+                            muted = false;
                             let mut state = shared_state.lock().unwrap();
                             state.muted = false;
                         }
                     }
                 }
 
-                Some(packet) = audio_source.read() => {
-                    let bytes = packet.serialize().unwrap();
-                    if let Err(e) = connection.send_datagram(bytes) {
-                        return Err(e.into());
+                datagram = connection.read_datagram() => {
+                    let datagram = datagram.map_err(Self::connection_lost)?;
+                    if let Some(Err(e)) = local_recording.as_mut().map(|r| r.write_datagram(&datagram, Instant::now())) {
+                        tracing::debug!("Not recording a received datagram: {e}");
+                    }
+                    match RtpPacket::parse(&datagram) {
+                        Ok(packet) => jitter_buffers.lock().unwrap().push(packet),
+                        Err(e) => tracing::debug!("Dropping a received datagram that isn't RTP: {e}"),
+                    }
+                }
+
+                now = playout.tick() => {
+                    let playouts = jitter_buffers.lock().unwrap().pop(now.into_std());
+                    if let Some(pcm) = playback.mix(playouts) {
+                        audio_sink.push_pcm(&pcm);
+                    }
+                }
+
+                // Streams whose frame was late get the rest of the grace window before it's concealed
+                _ = tokio::time::sleep_until(grace_deadline.unwrap_or_else(Instant::now).into()),
+                    if grace_deadline.is_some() => {
+                    let playouts = jitter_buffers.lock().unwrap().pop_pending(Instant::now());
+                    if let Some(pcm) = playback.mix(playouts) {
+                        audio_sink.push_pcm(&pcm);
+                    }
+                }
+
+                Some(packet) = audio_source.read() => match send_pacer.as_mut() {
+                    Some(pacer) => pacer.push(packet),
+                    None => oversized_frames.send(&connection, &packet, &encoder)?,
+                },
+
+                packet = send_pacer::next_paced(send_pacer.as_mut()) => {
+                    oversized_frames.send(&connection, &packet, &encoder)?;
+                }
+
+                () = &mut control_messages => {}
+
+                _ = keepalive.tick(), if muted && keepalive_period.is_some() => {
+                    if let Err(e) = connection.send_datagram(bytes::Bytes::from_static(KEEPALIVE_DATAGRAM)) {
+                        tracing::debug!("Failed to send a keepalive: {e}");
+                    }
+                }
+
+                _ = loss_sample.tick(), if adaptive_fec.is_some() || expected_loss.is_some() => {
+                    let path = connection.stats().path;
+                    let changed = adaptive_fec
+                        .as_mut()
+                        .and_then(|fec| fec.observe_counters(path.sent_packets, path.lost_packets));
+                    if let Some((enabled, loss)) = changed {
+                        tracing::info!("Turning FEC {} at {loss:.1}% loss", if enabled { "on" } else { "off" });
+                        let switched = match &expected_loss {
+                            // The tracked expected loss stays as it is
+                            Some(_) => encoder.lock().unwrap().set_inband_fec(enabled),
+                            None => adaptive_fec::apply(&encoder, enabled, loss),
+                        };
+                        if let Err(e) = switched {
+                            tracing::warn!("Failed to switch FEC: {e}");
+                        }
+                    }
+                    let tracked = expected_loss
+                        .as_mut()
+                        .and_then(|loss| loss.observe_counters(path.sent_packets, path.lost_packets));
+                    if let Some(percent) = tracked {
+                        tracing::debug!("Expecting {percent}% loss");
+                        if let Err(e) = adaptive_fec::apply_expected_loss(&encoder, percent) {
+                            tracing::warn!("Failed to set the expected loss: {e}");
+                        }
                     }
                 }
             }
         }
 
+        if let Some(recording) = local_recording {
+            recording.finalize()?;
+        }
         Ok(())
     }
 
+    /// Reports a lost connection as a [`VoxoxideError`], a close by the server keeps its code and reason
+    #[cfg(feature = "audio")]
+    fn connection_lost(error: quinn::ConnectionError) -> anyhow::Error {
+        match error {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                VoxoxideError::from_close(close.error_code.into_inner(), &close.reason)
+            }
+            quinn::ConnectionError::TimedOut => VoxoxideError::Transport(
+                "the connection timed out, nothing got through between us and the server".into(),
+            ),
+            other => VoxoxideError::Transport(other.to_string()),
+        }
+        .into()
+    }
+
+    /// Applies the control messages the server sends, one per unidirectional stream, in the order they arrive.
+    /// Never completes, the loop reading datagrams reports a lost connection
+    #[cfg(feature = "audio")]
+    async fn receive_control_messages(
+        connection: &Connection,
+        shared_state: &Mutex<AudioManagerState>,
+    ) {
+        while let Ok(mut recv) = connection.accept_uni().await {
+            let message = match recv.read_to_end(MAX_CONTROL_MESSAGE_LEN).await {
+                Ok(bytes) => serde_json::from_slice::<ArsControlMessage>(&bytes),
+                Err(e) => {
+                    tracing::debug!("Failed to read a control message: {e}");
+                    continue;
+                }
+            };
+            match message {
+                Ok(ArsControlMessage::Roster {
+                    member_ids,
+                    muted,
+                    display_names,
+                }) => {
+                    shared_state.lock().unwrap().roster =
+                        RosterMember::list(&member_ids, &muted, &display_names);
+                }
+                Ok(ArsControlMessage::SessionEnding { remaining_secs }) => {
+                    tracing::warn!("The server ends the call in {remaining_secs}s");
+                }
+                Ok(other) => tracing::debug!("Ignoring control message {other:?}"),
+                Err(e) => tracing::debug!("Dropping a malformed control message: {e}"),
+            }
+        }
+        std::future::pending().await
+    }
+
+    /// Tells the room whether we muted ourselves, its members show it next to us.
+    /// Sent on a stream of its own in the background, so the audio loop doesn't wait on it
+    #[cfg(feature = "audio")]
+    fn announce_muted(connection: &Connection, muted: bool) {
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            let message = ArsControlMessage::SetSelfMuted { muted };
+            let sent = async {
+                let mut send = connection.open_uni().await?;
+                send.write_all(&serde_json::to_vec(&message)?).await?;
+                send.finish()?;
+                anyhow::Ok(())
+            };
+            if let Err(e) = sent.await {
+                tracing::debug!("Failed to announce our mute state: {e}");
+            }
+        });
+    }
+
+    /// Closes the connection as a deliberate leave, so the server can tell it from a dropped connection
+    #[cfg(feature = "audio")]
+    fn leave(connection: &Connection) {
+        connection.close(CloseCode::ClientLeft.code().into(), LEAVE_REASON);
+    }
+
     pub fn exit_room(&self) {
         let mut state = self.state.lock().unwrap();
 
         if let Some(sender) = &state.signal_sender {
-            let _ = sender.try_send(AudioManagerSignal::EXIT);
+            let _ = sender.try_send(AudioManagerSignal::Exit);
         }
 
+        state.phase = ConnectionPhase::Idle;
         state.active_session = None;
         state.signal_sender = None;
         state.stream_error = None;
+        state.roster.clear();
+        #[cfg(feature = "audio")]
+        {
+            state.encoder = None;
+            state.frame_drops = None;
+            state.jitter_buffers = None;
+        }
     }
 
     pub fn set_muted(&self, muted: bool) {
@@ -164,9 +585,9 @@ impl AudioManager {
 
         if let Some(sender) = &state.signal_sender {
             let _ = sender.try_send(if muted {
-                AudioManagerSignal::MUTE
+                AudioManagerSignal::Mute
             } else {
-                AudioManagerSignal::UNMUTE
+                AudioManagerSignal::Unmute
             });
         }
     }
@@ -174,11 +595,13 @@ impl AudioManager {
     pub fn get_muted(&self) -> bool {
         return self.state.lock().unwrap().muted;
     }
-    pub fn get_active(&self) -> bool {
-        self.state.lock().unwrap().active_session.is_some()
+    pub fn get_phase(&self) -> ConnectionPhase {
+        self.state.lock().unwrap().phase
     }
-    pub fn is_errored(&self) -> bool {
-        self.state.lock().unwrap().stream_error.is_some()
+
+    /// Who is in the room, empty while not in one
+    pub fn get_roster(&self) -> Vec<RosterMember> {
+        self.state.lock().unwrap().roster.clone()
     }
 
     pub fn get_error(&self) -> Option<String> {
@@ -190,13 +613,408 @@ impl AudioManager {
             .map(|e| e.to_string())
     }
 
-    async fn authenticate_audio_connection(connection: &mut Connection) -> anyhow::Result<()> {
+    pub fn get_stats(&self) -> AudioStats {
+        let state = self.state.lock().unwrap();
+        #[cfg(feature = "audio")]
+        let encoder = state.encoder.as_ref().and_then(|encoder| {
+            EncoderStats::read(encoder)
+                .inspect_err(|e| tracing::warn!("Failed to read encoder stats: {e}"))
+                .ok()
+        });
+        AudioStats {
+            active: state.active_session.is_some(),
+            muted: state.muted,
+            #[cfg(feature = "audio")]
+            encoder,
+            #[cfg(feature = "audio")]
+            frame_drop_rate: state
+                .frame_drops
+                .as_ref()
+                .map(|drops| drops.lock().unwrap().rate()),
+            #[cfg(feature = "audio")]
+            jitter_buffer_depth: state
+                .jitter_buffers
+                .as_ref()
+                .map(|buffers| buffers.lock().unwrap().depth()),
+        }
+    }
+
+    /// Overrides the bitrate of the running encoder, no-op while not streaming
+    #[cfg(feature = "audio")]
+    pub fn set_bitrate(&self, bitrate: Bitrate) -> anyhow::Result<()> {
+        if let Some(encoder) = &self.state.lock().unwrap().encoder {
+            encoder.lock().unwrap().set_bitrate(bitrate)?;
+        }
+        Ok(())
+    }
+
+    /// Sends `request` announcing [`CLIENT_FEATURES`], framed as the first message of the auth stream
+    pub(crate) async fn authenticate_audio_connection(
+        connection: &mut Connection,
+        mut request: ArsAuthRequest,
+    ) -> anyhow::Result<ArsAuthResponse> {
         let (mut rx, mut tx) = connection.open_bi().await?;
-        rx.write_all(&serde_json::ser::to_vec(&ArsAuthRequest::new()).unwrap()[..])
+        request.features = Some(CLIENT_FEATURES);
+        rx.write_all(&frame_message(&serde_json::ser::to_vec(&request).unwrap()))
             .await?;
         rx.finish()?;
+        // Not logged, it carries the session key. The caller logs the session and features
         let response = tx.read_to_end(1024).await?;
-        tracing::info!("{}", String::from_utf8_lossy(&response));
-        Ok(())
+        Ok(serde_json::from_slice(&response)?)
+    }
+}
+
+#[cfg(all(test, feature = "audio"))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use clap::Parser;
+    use opus::{Application, Encoder};
+
+    use super::*;
+    use crate::app_config::AudioSourceConfig;
+    use crate::audio::audio_source::{
+        CHANNELS, FRAME_DROP_WINDOW, FRAME_SIZE, FrameDrops, SAMPLE_RATE,
+    };
+    use crate::audio::file_audio_source::tests::write_tone_wav;
+    use crate::audio::jitter_buffer::MIN_DEPTH;
+    use crate::audio::tests::{config_for, start_server};
+    use lib_common_voxoxide::types::{ARS_ALPN, split_frame};
+
+    #[test]
+    fn stats_reflect_bitrate_set_on_encoder() {
+        let manager = AudioManager::new(AppConfig::parse_from(["client"]));
+        assert_eq!(manager.get_stats().encoder, None);
+
+        let encoder = Encoder::new(SAMPLE_RATE, CHANNELS, Application::Voip).unwrap();
+        manager.state.lock().unwrap().encoder = Some(Arc::new(Mutex::new(encoder)));
+
+        manager.set_bitrate(Bitrate::Bits(24_000)).unwrap();
+        assert_eq!(
+            manager.get_stats().encoder.unwrap().bitrate,
+            Bitrate::Bits(24_000)
+        );
+        manager.set_bitrate(Bitrate::Bits(12_000)).unwrap();
+        assert_eq!(
+            manager.get_stats().encoder.unwrap().bitrate,
+            Bitrate::Bits(12_000)
+        );
+    }
+
+    #[test]
+    fn stats_report_local_frame_drops() {
+        let manager = AudioManager::new(AppConfig::parse_from(["client"]));
+        assert_eq!(manager.get_stats().frame_drop_rate, None);
+
+        let drops = Arc::new(Mutex::new(FrameDrops::new(FRAME_DROP_WINDOW)));
+        manager.state.lock().unwrap().frame_drops = Some(drops.clone());
+        for dropped in [false, false, false, true] {
+            drops.lock().unwrap().record(dropped);
+        }
+
+        assert_eq!(manager.get_stats().frame_drop_rate, Some(0.25));
+    }
+
+    #[tokio::test]
+    async fn leaving_closes_with_client_left() {
+        let (server, mut accepted) = start_server(ARS_ALPN);
+        let connection = create_audio_connection(config_for(&server)).await.unwrap();
+        let server_side = accepted.recv().await.unwrap();
+
+        AudioManager::leave(&connection);
+
+        match server_side.closed().await {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(
+                    CloseCode::from_code(close.error_code.into_inner()),
+                    Some(CloseCode::ClientLeft)
+                );
+                assert_eq!(close.reason.as_ref(), LEAVE_REASON);
+            }
+            other => panic!("expected an application close, got {other}"),
+        }
+    }
+
+    /// Polls until the manager reaches `phase`
+    async fn reaches(manager: &AudioManager, phase: ConnectionPhase) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while manager.get_phase() != phase {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("stuck in {} on the way to {phase}", manager.get_phase()));
+    }
+
+    #[tokio::test]
+    async fn joining_goes_through_connecting_and_authenticating() {
+        let dir = tempfile::tempdir().unwrap();
+        let wav = dir.path().join("tone.wav");
+        write_tone_wav(&wav, SAMPLE_RATE, 1, 10 * FRAME_SIZE);
+        let (server, mut accepted) = start_server(ARS_ALPN);
+        let mut config = config_for(&server);
+        config.source = AudioSourceConfig::File(wav);
+        config.loop_source = true;
+        let manager = AudioManager::new(config);
+
+        manager.join_room(4);
+        assert_eq!(manager.get_phase(), ConnectionPhase::Connecting);
+        let server_side = accepted.recv().await.unwrap();
+        // The test server holds off answering, so the auth stays pending
+        reaches(&manager, ConnectionPhase::Authenticating).await;
+
+        let (mut send, mut recv) = server_side.accept_bi().await.unwrap();
+        let framed = recv.read_to_end(1024).await.unwrap();
+        let (request, rest) = split_frame(&framed).unwrap();
+        assert!(rest.is_empty());
+        let request: ArsAuthRequest = serde_json::from_slice(request).unwrap();
+        assert_eq!(request.room_id, 4);
+        let response = ArsAuthResponse {
+            session_id: 9,
+            session_key: 1234,
+            ..Default::default()
+        };
+        send.write_all(&serde_json::to_vec(&response).unwrap())
+            .await
+            .unwrap();
+        send.finish().unwrap();
+        reaches(&manager, ConnectionPhase::Active).await;
+        {
+            let state = manager.state.lock().unwrap();
+            let session = state.active_session.as_ref().unwrap();
+            assert_eq!((session.session_id, session.session_key), (9, 1234));
+            assert_eq!(session.room_id, 4);
+        }
+
+        manager.exit_room();
+        assert_eq!(manager.get_phase(), ConnectionPhase::Idle);
+    }
+
+    #[tokio::test]
+    async fn roster_names_who_is_in_the_room() {
+        let dir = tempfile::tempdir().unwrap();
+        let wav = dir.path().join("tone.wav");
+        write_tone_wav(&wav, SAMPLE_RATE, 1, 10 * FRAME_SIZE);
+        let (server, mut accepted) = start_server(ARS_ALPN);
+        let mut config = config_for(&server);
+        config.source = AudioSourceConfig::File(wav);
+        config.loop_source = true;
+        let manager = AudioManager::new(config);
+
+        manager.join_room(4);
+        let server_side = accepted.recv().await.unwrap();
+        let (mut send, mut recv) = server_side.accept_bi().await.unwrap();
+        recv.read_to_end(1024).await.unwrap();
+        send.write_all(&serde_json::to_vec(&ArsAuthResponse::default()).unwrap())
+            .await
+            .unwrap();
+        send.finish().unwrap();
+        let roster = ArsControlMessage::Roster {
+            member_ids: vec![1, 2],
+            muted: vec![2],
+            display_names: vec![Some("Ada".to_string()), None],
+        };
+        let mut control = server_side.open_uni().await.unwrap();
+        control
+            .write_all(&serde_json::to_vec(&roster).unwrap())
+            .await
+            .unwrap();
+        control.finish().unwrap();
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while manager.get_roster().is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the roster was not applied");
+        let listed: Vec<String> = manager
+            .get_roster()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(listed, ["Ada", "member 2 (muted)"]);
+
+        manager.exit_room();
+        assert!(manager.get_roster().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mute_toggles_are_announced_to_the_room() {
+        let dir = tempfile::tempdir().unwrap();
+        let wav = dir.path().join("tone.wav");
+        write_tone_wav(&wav, SAMPLE_RATE, 1, 10 * FRAME_SIZE);
+        let (server, mut accepted) = start_server(ARS_ALPN);
+        let mut config = config_for(&server);
+        config.source = AudioSourceConfig::File(wav);
+        config.loop_source = true;
+        let manager = AudioManager::new(config);
+
+        manager.join_room(4);
+        let server_side = accepted.recv().await.unwrap();
+        let (mut send, mut recv) = server_side.accept_bi().await.unwrap();
+        recv.read_to_end(1024).await.unwrap();
+        send.write_all(&serde_json::to_vec(&ArsAuthResponse::default()).unwrap())
+            .await
+            .unwrap();
+        send.finish().unwrap();
+        reaches(&manager, ConnectionPhase::Active).await;
+
+        let next_announcement = || async {
+            let mut recv = tokio::time::timeout(Duration::from_secs(2), server_side.accept_uni())
+                .await
+                .expect("the mute state was not announced")
+                .unwrap();
+            serde_json::from_slice::<ArsControlMessage>(&recv.read_to_end(1024).await.unwrap())
+                .unwrap()
+        };
+        manager.set_muted(true);
+        assert_eq!(
+            next_announcement().await,
+            ArsControlMessage::SetSelfMuted { muted: true }
+        );
+        manager.set_muted(false);
+        assert_eq!(
+            next_announcement().await,
+            ArsControlMessage::SetSelfMuted { muted: false }
+        );
+
+        manager.exit_room();
+    }
+
+    #[tokio::test]
+    async fn muted_clients_send_keepalives() {
+        let dir = tempfile::tempdir().unwrap();
+        let wav = dir.path().join("tone.wav");
+        write_tone_wav(&wav, SAMPLE_RATE, 1, 10 * FRAME_SIZE);
+        let (server, mut accepted) = start_server(ARS_ALPN);
+        let mut config = config_for(&server);
+        config.source = AudioSourceConfig::File(wav);
+        config.loop_source = true;
+        config.keepalive_interval_ms = 20;
+        let manager = AudioManager::new(config);
+        manager.set_muted(true);
+
+        manager.join_room(4);
+        let server_side = accepted.recv().await.unwrap();
+        let (mut send, mut recv) = server_side.accept_bi().await.unwrap();
+        recv.read_to_end(1024).await.unwrap();
+        send.write_all(&serde_json::to_vec(&ArsAuthResponse::default()).unwrap())
+            .await
+            .unwrap();
+        send.finish().unwrap();
+
+        // Nothing but keepalives while muted, no audio
+        for _ in 0..3 {
+            let datagram =
+                tokio::time::timeout(Duration::from_secs(2), server_side.read_datagram())
+                    .await
+                    .expect("no keepalive was sent")
+                    .unwrap();
+            assert_eq!(datagram, KEEPALIVE_DATAGRAM);
+        }
+
+        manager.exit_room();
+    }
+
+    #[tokio::test]
+    async fn kick_reason_reaches_the_error() {
+        let (server, mut accepted) = start_server(ARS_ALPN);
+        let connection = create_audio_connection(config_for(&server)).await.unwrap();
+        let server_side = accepted.recv().await.unwrap();
+
+        server_side.close(CloseCode::Kicked.code().into(), b"too loud");
+        let error = AudioManager::connection_lost(connection.closed().await);
+
+        assert!(matches!(
+            error.downcast_ref::<VoxoxideError>(),
+            Some(VoxoxideError::Closed { code: CloseCode::Kicked, reason }) if reason == "too loud"
+        ));
+    }
+
+    #[test]
+    fn connection_losses_are_voxoxide_errors() {
+        let closed = |code: u64| {
+            let error = AudioManager::connection_lost(quinn::ConnectionError::ApplicationClosed(
+                quinn::ApplicationClose {
+                    error_code: code.try_into().unwrap(),
+                    reason: b"because".to_vec().into(),
+                },
+            ));
+            error.downcast::<VoxoxideError>().unwrap()
+        };
+
+        for code in [
+            CloseCode::Kicked,
+            CloseCode::ServerShutdown,
+            CloseCode::AuthFailed,
+            CloseCode::Replaced,
+            CloseCode::ClientLeft,
+        ] {
+            let error = closed(code.code().into());
+            assert_eq!(error.close_code(), Some(code));
+            assert!(error.to_string().ends_with("because"), "{error}");
+        }
+        assert!(matches!(closed(999), VoxoxideError::Protocol(_)));
+
+        let timed_out = AudioManager::connection_lost(quinn::ConnectionError::TimedOut);
+        assert!(matches!(
+            timed_out.downcast_ref::<VoxoxideError>(),
+            Some(VoxoxideError::Transport(_))
+        ));
+        let reset = AudioManager::connection_lost(quinn::ConnectionError::Reset);
+        assert!(matches!(
+            reset.downcast_ref::<VoxoxideError>(),
+            Some(VoxoxideError::Transport(_))
+        ));
+    }
+
+    #[test]
+    fn stats_report_jitter_buffer_depth() {
+        let manager = AudioManager::new(AppConfig::parse_from(["client"]));
+        assert_eq!(manager.get_stats().jitter_buffer_depth, None);
+
+        let buffers = SharedJitterBuffers::default();
+        manager.state.lock().unwrap().jitter_buffers = Some(buffers.clone());
+        assert_eq!(manager.get_stats().jitter_buffer_depth, Some(MIN_DEPTH));
+
+        // Running dry deepens the buffer
+        for sequence in 0..MIN_DEPTH as u16 {
+            let packet = RtpPacket::new_with_payload(111, sequence, 0, 7, vec![0].into());
+            buffers.lock().unwrap().push(packet);
+        }
+        for _ in 0..MIN_DEPTH + 1 {
+            buffers.lock().unwrap().pop(Instant::now());
+        }
+        assert_eq!(manager.get_stats().jitter_buffer_depth, Some(MIN_DEPTH + 1));
+    }
+}
+
+#[cfg(all(test, not(feature = "audio")))]
+mod tests {
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn joining_without_audio_reports_it_disabled() {
+        let manager = AudioManager::new(AppConfig::parse_from(["client"]));
+
+        manager.join_room(1);
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while manager.get_phase() != ConnectionPhase::Errored {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert!(manager.state.lock().unwrap().active_session.is_none());
+        assert_eq!(
+            manager.get_error(),
+            Some(AudioManagerError::AudioDisabled.to_string())
+        );
     }
 }
@@ -0,0 +1,367 @@
+//! Playback of what the relay sends back, the room's mix or each member's own stream.
+//! Every frame slot the jitter buffers play out is decoded per SSRC and summed into one frame,
+//! which [`AudioSink`] queues for the output device, resampled to the device's rate if it doesn't run at 48kHz.
+//! The device's callback takes samples as it needs them and plays silence while the queue is empty.
+
+use std::{
+    collections::{HashMap, VecDeque, hash_map::Entry},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+
+use crate::audio::audio_source::{CHANNELS, FRAME_SIZE, SAMPLE_RATE};
+use crate::audio::device_watcher::find_output_device;
+use crate::audio::jitter_buffer::{MAX_DEPTH, Playout};
+
+/// Longest frame Opus produces, 120ms at 48kHz
+const MAX_FRAME_SIZE: usize = 5760;
+/// 48kHz samples queued for the device at most, the jitter buffers already absorb the network's jitter
+const MAX_QUEUED_SAMPLES: usize = MAX_DEPTH * FRAME_SIZE;
+
+/// Mono samples waiting for the output device
+type PlaybackQueue = Arc<Mutex<VecDeque<f32>>>;
+
+/// Decodes the frames of every received stream and sums them into one.
+/// A lost frame is concealed by its stream's decoder, streams still buffering add nothing.
+#[derive(Default)]
+pub struct PlaybackMixer {
+    decoders: HashMap<u32, opus::Decoder>,
+    pcm: Vec<i16>,
+}
+
+impl PlaybackMixer {
+    /// Mixes one frame slot as popped from the jitter buffers, None if no stream had anything to play
+    pub fn mix(&mut self, playouts: Vec<(u32, Playout)>) -> Option<Vec<i16>> {
+        self.pcm.resize(MAX_FRAME_SIZE, 0);
+        let mut mixed: Option<Vec<i32>> = None;
+        for (ssrc, playout) in playouts {
+            let (payload, output) = match &playout {
+                Playout::Frame(packet) => (&packet.payload[..], &mut self.pcm[..]),
+                // Concealed at the length the buffers assume, the decoder repeats its last frame's shape
+                Playout::Lost => (&[][..], &mut self.pcm[..FRAME_SIZE]),
+                Playout::Buffering | Playout::Pending => continue,
+            };
+            let decoder = match self.decoders.entry(ssrc) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                // Nothing was decoded yet, so there is nothing to conceal
+                Entry::Vacant(_) if payload.is_empty() => continue,
+                Entry::Vacant(entry) => match opus::Decoder::new(SAMPLE_RATE, CHANNELS) {
+                    Ok(decoder) => entry.insert(decoder),
+                    Err(e) => {
+                        tracing::warn!("Failed to create a decoder for stream {ssrc}: {e}");
+                        continue;
+                    }
+                },
+            };
+            let len = match decoder.decode(payload, output, false) {
+                Ok(len) => len,
+                Err(e) => {
+                    tracing::debug!("Dropping an undecodable frame of stream {ssrc}: {e}");
+                    continue;
+                }
+            };
+            let mixed = mixed.get_or_insert_with(Vec::new);
+            if mixed.len() < len {
+                mixed.resize(len, 0);
+            }
+            for (sum, sample) in mixed.iter_mut().zip(&self.pcm[..len]) {
+                *sum += i32::from(*sample);
+            }
+        }
+        Some(
+            mixed?
+                .into_iter()
+                .map(|sample| sample.clamp(i16::MIN.into(), i16::MAX.into()) as i16)
+                .collect(),
+        )
+    }
+}
+
+/// Linear interpolation from 48kHz to the output device's rate, carried across frames
+#[derive(Debug)]
+struct Resampler {
+    /// Input samples per output sample
+    step: f64,
+    /// Position of the next output sample, 0 being the last sample of the previous input
+    position: f64,
+    previous: f32,
+}
+
+impl Resampler {
+    fn new(output_rate: u32) -> Self {
+        Self {
+            step: f64::from(SAMPLE_RATE) / f64::from(output_rate),
+            position: 0.0,
+            previous: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut VecDeque<f32>) {
+        let Some(&last) = input.last() else {
+            return;
+        };
+        let sample = |i: usize| if i == 0 { self.previous } else { input[i - 1] };
+        while self.position < input.len() as f64 {
+            let i = self.position as usize;
+            let fraction = (self.position - i as f64) as f32;
+            output.push_back(sample(i) + (sample(i + 1) - sample(i)) * fraction);
+            self.position += self.step;
+        }
+        self.position -= input.len() as f64;
+        self.previous = last;
+    }
+}
+
+/// Plays received audio on the chosen or default output device, or nothing if there is none
+pub struct AudioSink {
+    queue: PlaybackQueue,
+    /// None while the device runs at 48kHz itself
+    resampler: Option<Resampler>,
+    /// Samples queued at the device's rate at most
+    max_queued: usize,
+    stream: Option<cpal::Stream>,
+}
+
+impl AudioSink {
+    /// Plays on `device` by name, the default output device if None.
+    /// Fails listing the devices present if `device` isn't one of them.
+    /// Without a name the call goes on if the default device can't be used, received audio is discarded then
+    pub fn open(device: Option<&str>) -> Result<Self> {
+        let host = cpal::default_host();
+        let queue = PlaybackQueue::default();
+        let device = match device {
+            Some(name) => Some(find_output_device(&host, name)?),
+            None => host.default_output_device(),
+        };
+        let opened = device
+            .ok_or_else(|| anyhow::anyhow!("No output device available"))
+            .and_then(|device| open_output(&device, queue.clone()));
+        Ok(match opened {
+            Ok((stream, rate)) => {
+                let rate_ratio = f64::from(rate) / f64::from(SAMPLE_RATE);
+                Self {
+                    queue,
+                    resampler: (rate != SAMPLE_RATE).then(|| Resampler::new(rate)),
+                    max_queued: (MAX_QUEUED_SAMPLES as f64 * rate_ratio) as usize,
+                    stream: Some(stream),
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Received audio won't be played: {e}");
+                Self {
+                    queue,
+                    resampler: None,
+                    max_queued: 0,
+                    stream: None,
+                }
+            }
+        })
+    }
+
+    /// Queues mono 48kHz samples for the device, dropping the oldest once it falls behind
+    pub fn push_pcm(&mut self, pcm: &[i16]) {
+        if self.stream.is_none() {
+            return;
+        }
+        enqueue(
+            &mut self.queue.lock().unwrap(),
+            self.resampler.as_mut(),
+            pcm,
+            self.max_queued,
+        );
+    }
+}
+
+/// Appends `pcm` at the device's rate, keeping the newest `max_queued` samples
+fn enqueue(
+    queue: &mut VecDeque<f32>,
+    resampler: Option<&mut Resampler>,
+    pcm: &[i16],
+    max_queued: usize,
+) {
+    let pcm: Vec<f32> = pcm
+        .iter()
+        .map(|&sample| f32::from(sample) / 32768.0)
+        .collect();
+    match resampler {
+        Some(resampler) => resampler.process(&pcm, queue),
+        None => queue.extend(pcm),
+    }
+    let excess = queue.len().saturating_sub(max_queued);
+    queue.drain(..excess);
+}
+
+/// Starts the output device at its own rate, layout and sample format, returns the stream and its rate
+fn open_output(device: &cpal::Device, queue: PlaybackQueue) -> Result<(cpal::Stream, u32)> {
+    tracing::info!("Selected output device {:?}", device.description());
+    let supported = device.default_output_config()?;
+    let config = cpal::StreamConfig {
+        channels: supported.channels(),
+        sample_rate: supported.sample_rate(),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build_output::<f32>(device, &config, queue)?,
+        SampleFormat::I16 => build_output::<i16>(device, &config, queue)?,
+        SampleFormat::U16 => build_output::<u16>(device, &config, queue)?,
+        SampleFormat::I32 => build_output::<i32>(device, &config, queue)?,
+        other => anyhow::bail!(
+            "the output device plays {other} samples, only f32, i16, u16 and i32 are supported"
+        ),
+    };
+    stream.play()?;
+    Ok((stream, config.sample_rate))
+}
+
+fn build_output<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: PlaybackQueue,
+) -> Result<cpal::Stream> {
+    // Devices rarely take mono, each sample goes out on every channel
+    let channels = config.channels as usize;
+    Ok(device.build_output_stream(
+        config,
+        move |data: &mut [T], _| fill(&queue, data, channels),
+        move |err| tracing::error!("Playback stream error: {:?}", err),
+        Some(Duration::from_secs(2)),
+    )?)
+}
+
+/// Fills an interleaved device buffer of `channels` channels, silence once the queue runs dry
+fn fill<T: FromSample<f32> + Copy>(queue: &PlaybackQueue, data: &mut [T], channels: usize) {
+    let mut queue = queue.lock().unwrap();
+    for frame in data.chunks_mut(channels) {
+        frame.fill(T::from_sample_(queue.pop_front().unwrap_or(0.0)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opus::{Application, Encoder};
+    use rvoip_rtp_core::RtpPacket;
+
+    use super::*;
+
+    fn tone_packet(frequency: f32, sequence: u16, ssrc: u32) -> RtpPacket {
+        let mut encoder = Encoder::new(SAMPLE_RATE, CHANNELS, Application::Voip).unwrap();
+        let tone: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| {
+                (i as f32 * frequency * std::f32::consts::TAU / SAMPLE_RATE as f32).sin() * 0.4
+            })
+            .collect();
+        let mut output = vec![0u8; 4000];
+        let len = encoder.encode_float(&tone, &mut output).unwrap();
+        RtpPacket::new_with_payload(
+            111,
+            sequence,
+            u32::from(sequence) * FRAME_SIZE as u32,
+            ssrc,
+            output[..len].to_vec().into(),
+        )
+    }
+
+    #[test]
+    fn streams_are_summed_into_one_frame() {
+        let mut alone = PlaybackMixer::default();
+        let low = alone
+            .mix(vec![(1, Playout::Frame(tone_packet(300.0, 0, 1)))])
+            .unwrap();
+
+        let mut mixer = PlaybackMixer::default();
+        let mixed = mixer
+            .mix(vec![
+                (1, Playout::Frame(tone_packet(300.0, 0, 1))),
+                (2, Playout::Frame(tone_packet(700.0, 0, 2))),
+                (3, Playout::Buffering),
+            ])
+            .unwrap();
+
+        assert_eq!(mixed.len(), FRAME_SIZE);
+        assert_ne!(mixed, low);
+    }
+
+    #[test]
+    fn lost_frames_are_concealed_only_after_a_decoded_one() {
+        let mut mixer = PlaybackMixer::default();
+        // An unseen stream has nothing to conceal, one still filling up plays nothing
+        assert_eq!(
+            mixer.mix(vec![(1, Playout::Lost), (2, Playout::Buffering)]),
+            None
+        );
+
+        mixer.mix(vec![(1, Playout::Frame(tone_packet(440.0, 0, 1)))]);
+        let concealed = mixer.mix(vec![(1, Playout::Lost)]).unwrap();
+        assert_eq!(concealed.len(), FRAME_SIZE);
+    }
+
+    #[test]
+    fn queue_is_played_on_every_channel() {
+        let queue = PlaybackQueue::default();
+        queue.lock().unwrap().extend([0.25, -0.5]);
+
+        let mut data = [1.0; 6];
+        fill(&queue, &mut data, 2);
+        // Runs dry after two samples, the rest is silence
+        assert_eq!(data, [0.25, 0.25, -0.5, -0.5, 0.0, 0.0]);
+
+        // Integer devices get the same samples converted, silence at their midpoint
+        queue.lock().unwrap().push_back(0.5);
+        let mut data = [0u16; 2];
+        fill(&queue, &mut data, 1);
+        assert_eq!(data, [49152, 32768]);
+    }
+
+    #[test]
+    fn queue_keeps_only_the_newest_samples_beyond_the_cap() {
+        let mut queue = VecDeque::new();
+        let max_queued = 3 * FRAME_SIZE;
+
+        for frame in 0..5 {
+            enqueue(&mut queue, None, &[frame * 1000; FRAME_SIZE], max_queued);
+        }
+
+        assert_eq!(queue.len(), max_queued);
+        // The two oldest frames were dropped
+        assert_eq!(queue.front(), Some(&(2000.0 / 32768.0)));
+    }
+
+    #[test]
+    fn resampling_keeps_the_duration_across_frames() {
+        let mut resampler = Resampler::new(44_100);
+        let mut output = VecDeque::new();
+        let ramp: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| i as f32 / FRAME_SIZE as f32)
+            .collect();
+        for _ in 0..50 {
+            resampler.process(&ramp, &mut output);
+        }
+
+        // A second of 48kHz input is a second at 44.1kHz, give or take the last sample
+        assert!(
+            output.len().abs_diff(44_100) <= 1,
+            "{} samples",
+            output.len()
+        );
+        // Interpolated samples stay between their neighbours, the ramp keeps rising within a frame
+        let first: Vec<f32> = output.iter().take(100).copied().collect();
+        assert!(first.windows(2).all(|pair| pair[1] >= pair[0]));
+    }
+
+    #[test]
+    fn a_sink_without_a_device_discards_the_audio() {
+        let mut sink = AudioSink {
+            queue: PlaybackQueue::default(),
+            resampler: None,
+            max_queued: 0,
+            stream: None,
+        };
+
+        sink.push_pcm(&[1000; FRAME_SIZE]);
+        assert!(sink.queue.lock().unwrap().is_empty());
+    }
+}
@@ -1,114 +1,738 @@
 use anyhow::Result;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use opus::{Application, Channels, Encoder};
+use lib_common_voxoxide::types::ArsCodecPolicy;
+use opus::{Application, Bandwidth, Bitrate, Channels, Encoder, Signal};
 use rvoip_rtp_core::{RtpHeader, RtpPacket, RtpSequenceNumber};
 use std::{
+    borrow::Cow,
+    collections::VecDeque,
     sync::{Arc, Mutex, atomic::AtomicBool},
     time::Duration,
 };
-use tokio::sync::mpsc::Receiver;
-const SAMPLE_RATE: u32 = 48000;
-const CHANNELS: Channels = Channels::Mono;
-const FRAME_SIZE: usize = 960; // 20ms at 48kHz
-const BUF_SIZE: usize = 10; // 0.2s jitter max
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::{JoinError, JoinHandle};
 
+use crate::app_config::{AppConfig, AudioSourceConfig, OpusMode};
+use crate::audio::device_watcher::{
+    DeviceWatcher, InputDevices, POLL_INTERVAL, available_devices, device_name, find_input_device,
+};
+use crate::audio::file_audio_source::FileAudioSource;
+pub(crate) const SAMPLE_RATE: u32 = 48000;
+/// Channels captured from the input, the encoder may upmix to what the room asks for
+pub(crate) const CHANNELS: Channels = Channels::Mono;
+pub(crate) const FRAME_SIZE: usize = 960; // 20ms at 48kHz
+pub(crate) const BUF_SIZE: usize = 10; // 0.2s jitter max
+/// Frames the local drop rate is computed over, 5s at 20ms
+pub(crate) const FRAME_DROP_WINDOW: usize = 250;
+/// RMS below which a frame counts as quiet, about -40 dBFS
+pub(crate) const VAD_THRESHOLD_RMS: f32 = 0.01;
+/// Quiet frames before the bitrate drops to the floor, so pauses between words keep the target, 300ms at 20ms
+pub(crate) const VAD_HANGOVER_FRAMES: usize = 15;
+
+/// Encoder shared between the thread producing packets and anyone inspecting it
+pub type SharedEncoder = Arc<Mutex<Encoder>>;
+
+/// Complexities Opus accepts, CPU spent per frame against quality
+pub(crate) const COMPLEXITY_RANGE: std::ops::RangeInclusive<i32> = 0..=10;
+/// Bitrates Opus accepts in bits per second, narrower ones are clamped by the library
+pub(crate) const BITRATE_RANGE: std::ops::RangeInclusive<i32> = 6_000..=510_000;
+
+/// How the encoder is set up, local defaults unless the joined room has a codec policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderSettings {
+    pub bitrate: Bitrate,
+    pub channels: Channels,
+    pub fec: bool,
+    /// Transmitted channel count regardless of the input layout, set by `--force-mono`
+    pub force_channels: Option<Channels>,
+    /// Widest bandpass the encoder may adapt up to, set by `--max-bandwidth`
+    pub max_bandwidth: Option<Bandwidth>,
+    /// Coding mode the encoder is kept in, set by `--force-opus-mode`
+    pub forced_mode: Option<OpusMode>,
+    /// Significant bits in the encoder input, set by `--lsb-depth`
+    pub lsb_depth: Option<i32>,
+    /// Reset the encoder at every talk spurt start, so no stale prediction leaks past a mute
+    pub reset_on_unmute: bool,
+    /// Samples per channel in each encoded frame, set by `--expert-frame-duration-ms`
+    pub frame_size: usize,
+    /// Bits per second while the input is quiet, set by `--bitrate-floor`
+    pub bitrate_floor: Option<i32>,
+    /// From 0 to 10, the library default if not set. Set by `--complexity`
+    pub complexity: Option<i32>,
+    /// Discontinuous transmission, sending next to nothing through silence. Set by `--dtx`
+    pub dtx: bool,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            bitrate: Bitrate::Auto,
+            channels: CHANNELS,
+            fec: false,
+            force_channels: None,
+            max_bandwidth: None,
+            forced_mode: None,
+            lsb_depth: None,
+            reset_on_unmute: false,
+            frame_size: FRAME_SIZE,
+            bitrate_floor: None,
+            complexity: None,
+            dtx: false,
+        }
+    }
+}
+
+impl EncoderSettings {
+    /// Overrides every setting the policy specifies
+    pub fn with_policy(mut self, policy: Option<&ArsCodecPolicy>) -> Self {
+        let Some(policy) = policy else {
+            return self;
+        };
+        if let Some(bitrate) = policy.bitrate {
+            self.bitrate = Bitrate::Bits(bitrate);
+        }
+        match policy.channels {
+            None => {}
+            Some(1) => self.channels = Channels::Mono,
+            Some(2) => self.channels = Channels::Stereo,
+            Some(other) => {
+                tracing::warn!("Ignoring unsupported channel count {other} in codec policy")
+            }
+        }
+        if let Some(fec) = policy.fec {
+            self.fec = fec;
+        }
+        self
+    }
+
+    /// Applies the encoder flags given on the command line, these win over the room policy
+    /// except for the bitrate, which only fills in for a policy that leaves it open
+    pub fn with_config(mut self, config: &AppConfig) -> Result<Self> {
+        if config.stereo {
+            self.channels = Channels::Stereo;
+        }
+        if let Some(bitrate) = config.bitrate
+            && self.bitrate == Bitrate::Auto
+        {
+            self.bitrate = Bitrate::Bits(bitrate.0);
+        }
+        self.complexity = config.complexity.map(|complexity| complexity.0);
+        self.dtx = config.dtx;
+        if config.force_mono {
+            self.force_channels = Some(Channels::Mono);
+        }
+        self.max_bandwidth = config.max_bandwidth.map(|ceiling| ceiling.0);
+        self.forced_mode = config.force_opus_mode;
+        self.lsb_depth = config.lsb_depth.map(|depth| i32::from(depth.0));
+        self.reset_on_unmute = config.reset_encoder_on_unmute;
+        if let Some(duration) = config.expert_frame_duration {
+            self.frame_size = duration.frame_size(SAMPLE_RATE)?;
+        }
+        self.bitrate_floor = config.bitrate_floor.map(|floor| floor.0);
+        Ok(self)
+    }
+
+    /// The bindings have no ctl forcing a mode, so it is forced through the ones they do have.
+    /// The restricted low delay application is CELT only. Voice capped at wideband is coded
+    /// by SILK alone, as long as the bitrate stays below the ~64kbps Opus switches to CELT at.
+    /// Out of range settings are refused before they reach the encoder.
+    pub(crate) fn build_encoder(&self) -> Result<SharedEncoder> {
+        Ok(Arc::new(Mutex::new(self.new_encoder()?)))
+    }
+
+    /// The encoder of [`EncoderSettings::build_encoder`], not shared yet
+    pub(crate) fn new_encoder(&self) -> Result<Encoder> {
+        if let Bitrate::Bits(bits) = self.bitrate
+            && !BITRATE_RANGE.contains(&bits)
+        {
+            anyhow::bail!("bitrate {bits}bps is outside of {BITRATE_RANGE:?}");
+        }
+        if let Some(complexity) = self.complexity
+            && !COMPLEXITY_RANGE.contains(&complexity)
+        {
+            anyhow::bail!("complexity {complexity} is outside of {COMPLEXITY_RANGE:?}");
+        }
+        let application = match self.forced_mode {
+            Some(OpusMode::Celt) => Application::LowDelay,
+            _ => Application::Voip,
+        };
+        let mut encoder = Encoder::new(SAMPLE_RATE, self.channels, application)?;
+        encoder.set_bitrate(self.bitrate)?;
+        encoder.set_inband_fec(self.fec)?;
+        encoder.set_force_channels(self.force_channels)?;
+        encoder.set_dtx(self.dtx)?;
+        if let Some(complexity) = self.complexity {
+            encoder.set_complexity(complexity)?;
+        }
+        let max_bandwidth = match self.forced_mode {
+            Some(OpusMode::Silk) => {
+                encoder.set_signal(Signal::Voice)?;
+                Some(silk_bandwidth(self.max_bandwidth))
+            }
+            _ => self.max_bandwidth,
+        };
+        if let Some(max_bandwidth) = max_bandwidth {
+            encoder.set_max_bandwidth(max_bandwidth)?;
+        }
+        if let Some(depth) = self.lsb_depth {
+            encoder.set_lsb_depth(depth)?;
+        }
+        Ok(encoder)
+    }
+
+    /// Wall-clock duration of one frame
+    pub(crate) fn frame_duration(&self) -> Duration {
+        Duration::from_micros(self.frame_size as u64 * 1_000_000 / SAMPLE_RATE as u64)
+    }
+
+    /// Fails if the configured LSB depth claims more bits than the device captures.
+    /// Float samples carry 24 bits of mantissa, the most Opus accepts anyway.
+    pub(crate) fn for_capture_format(self, format: cpal::SampleFormat) -> Result<Self> {
+        let captured = if format.is_float() {
+            24
+        } else {
+            format.bits_per_sample() as i32
+        };
+        match self.lsb_depth {
+            Some(depth) if depth > captured => Err(anyhow::anyhow!(
+                "--lsb-depth {depth} exceeds the {captured} bits the input device captures as {format}"
+            )),
+            _ => Ok(self),
+        }
+    }
+
+    /// Adapts the settings to what `device` captures, see [`EncoderSettings::for_device`]
+    pub(crate) fn for_input_device(self, device: &cpal::Device) -> Result<(Channels, Self)> {
+        let default_config = device.default_input_config();
+        let device_channels = default_config
+            .as_ref()
+            .map(|config| config.channels())
+            .unwrap_or(1);
+        let settings = match &default_config {
+            Ok(config) => self.for_capture_format(config.sample_format())?,
+            Err(_) => self,
+        };
+        Ok(settings.for_device(device_channels))
+    }
+
+    /// Picks the capture layout for a device offering `device_channels` channels.
+    /// Stereo devices are captured as is for a stereo encoder, or when mono is forced and the encoder downmixes.
+    /// Otherwise mono is captured and upmixed as needed.
+    pub(crate) fn for_device(self, device_channels: u16) -> (Channels, Self) {
+        let stereo_wanted =
+            self.channels == Channels::Stereo || self.force_channels == Some(Channels::Mono);
+        if device_channels >= 2 && stereo_wanted {
+            let settings = Self {
+                channels: Channels::Stereo,
+                ..self
+            };
+            (Channels::Stereo, settings)
+        } else {
+            (CHANNELS, self)
+        }
+    }
+}
+
+/// Widest band SILK codes on its own, a narrower configured ceiling is kept
+fn silk_bandwidth(max_bandwidth: Option<Bandwidth>) -> Bandwidth {
+    match max_bandwidth {
+        Some(narrower @ (Bandwidth::Narrowband | Bandwidth::Mediumband)) => narrower,
+        _ => Bandwidth::Wideband,
+    }
+}
+
+/// Turns a captured mono frame into the encoder's channel layout.
+pub(crate) fn upmix(frame: &[f32], channels: Channels) -> Cow<'_, [f32]> {
+    match channels {
+        Channels::Mono => Cow::Borrowed(frame),
+        Channels::Stereo => Cow::Owned(frame.iter().flat_map(|s| [*s, *s]).collect()),
+    }
+}
+
+/// Tracks mute→unmute transitions of a source, the first frame after one starts a talk spurt.
+/// Talk spurt starts carry the RTP marker bit, per RFC 3551.
+pub(crate) struct TalkSpurt {
+    active: bool,
+    reset_encoder: bool,
+}
+
+impl TalkSpurt {
+    pub(crate) fn new(reset_encoder: bool) -> Self {
+        Self {
+            active: false,
+            reset_encoder,
+        }
+    }
+
+    /// Call while the source is muted
+    pub(crate) fn pause(&mut self) {
+        self.active = false;
+    }
+
+    /// Call before encoding each frame, returns whether the frame starts a talk spurt.
+    /// Resets the encoder first if that was asked for.
+    pub(crate) fn begin_frame(&mut self, encoder: &mut Encoder) -> bool {
+        if self.active {
+            return false;
+        }
+        self.active = true;
+        if self.reset_encoder
+            && let Err(e) = encoder.reset_state()
+        {
+            tracing::warn!("Failed to reset encoder on unmute: {e}");
+        }
+        true
+    }
+}
+
+/// Drops the encoder to a floor bitrate while the input is quiet and restores the bitrate it had
+/// once speech resumes, saving bandwidth in pauses without DTX's comfort noise.
+/// Quiet means [`VAD_HANGOVER_FRAMES`] frames in a row with an RMS below [`VAD_THRESHOLD_RMS`].
+pub(crate) struct BitrateFloor {
+    floor: Bitrate,
+    /// Bitrate to restore, taken from the encoder when dropping to the floor. None while at the target
+    target: Option<Bitrate>,
+    quiet_frames: usize,
+}
+
+impl BitrateFloor {
+    pub(crate) fn new(floor: i32) -> Self {
+        Self {
+            floor: Bitrate::Bits(floor),
+            target: None,
+            quiet_frames: 0,
+        }
+    }
+
+    /// Call before encoding each frame, switches the encoder's bitrate when the input's activity changes
+    pub(crate) fn begin_frame(&mut self, frame: &[f32], encoder: &mut Encoder) {
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32).sqrt();
+        if rms >= VAD_THRESHOLD_RMS {
+            self.quiet_frames = 0;
+            if let Some(target) = self.target.take() {
+                tracing::debug!("Speech resumed, restoring bitrate {target:?}");
+                if let Err(e) = encoder.set_bitrate(target) {
+                    tracing::warn!("Failed to restore the bitrate: {e}");
+                }
+            }
+            return;
+        }
+        self.quiet_frames += 1;
+        if self.quiet_frames < VAD_HANGOVER_FRAMES || self.target.is_some() {
+            return;
+        }
+        match encoder.get_bitrate() {
+            Ok(target) => {
+                tracing::debug!("Input went quiet, dropping to bitrate {:?}", self.floor);
+                if let Err(e) = encoder.set_bitrate(self.floor) {
+                    tracing::warn!("Failed to drop to the bitrate floor: {e}");
+                    return;
+                }
+                self.target = Some(target);
+            }
+            Err(e) => tracing::warn!("Failed to read the bitrate: {e}"),
+        }
+    }
+}
+
+/// What the encoder is actually doing, read back through the opus ctl getters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderStats {
+    pub bitrate: Bitrate,
+    pub bandwidth: Bandwidth,
+}
+
+impl EncoderStats {
+    pub fn read(encoder: &SharedEncoder) -> Result<Self> {
+        let mut encoder = encoder.lock().unwrap();
+        Ok(Self {
+            bitrate: encoder.get_bitrate()?,
+            bandwidth: encoder.get_bandwidth()?,
+        })
+    }
+}
+
+/// Encoded frames dropped locally because the send queue was full, over the last
+/// [`FRAME_DROP_WINDOW`] frames. Drops here point at a slow encode or send loop rather than the network.
+#[derive(Debug)]
+pub struct FrameDrops {
+    /// Oldest first, true for a dropped frame
+    window: VecDeque<bool>,
+    capacity: usize,
+    dropped: usize,
+}
+
+/// Written by the capture thread, read by the stats
+pub type SharedFrameDrops = Arc<Mutex<FrameDrops>>;
+
+impl FrameDrops {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            window: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn record(&mut self, dropped: bool) {
+        if self.window.len() == self.capacity && self.window.pop_front() == Some(true) {
+            self.dropped -= 1;
+        }
+        self.window.push_back(dropped);
+        self.dropped += usize::from(dropped);
+    }
+
+    /// Fraction of the window's frames that were dropped, 0 before any frame
+    pub fn rate(&self) -> f32 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+        self.dropped as f32 / self.window.len() as f32
+    }
+}
+
+/// Where the streamed audio comes from, picked with `--source`.
+pub enum AudioSource {
+    Mic(RTPOpusAudioSource),
+    File(FileAudioSource),
+}
+
+impl AudioSource {
+    /// Packets are sent as `ssrc`, the one declared on auth or reassigned by the server
+    pub fn open(
+        config: &AppConfig,
+        play_on_start: bool,
+        settings: EncoderSettings,
+        ssrc: u32,
+    ) -> Result<Self> {
+        Ok(match &config.source {
+            AudioSourceConfig::Mic => Self::Mic(RTPOpusAudioSource::new(
+                play_on_start,
+                settings,
+                ssrc,
+                config.input_device.clone(),
+            )?),
+            AudioSourceConfig::File(path) => Self::File(FileAudioSource::new(
+                path,
+                config.loop_source,
+                play_on_start,
+                settings,
+                ssrc,
+            )?),
+        })
+    }
+
+    pub async fn read(&mut self) -> Option<RtpPacket> {
+        match self {
+            Self::Mic(source) => source.read().await,
+            Self::File(source) => source.read().await,
+        }
+    }
+    pub async fn set_playing(&mut self, playing: bool) {
+        match self {
+            Self::Mic(source) => source.set_playing(playing).await,
+            Self::File(source) => source.set_playing(playing).await,
+        }
+    }
+    pub fn encoder(&self) -> SharedEncoder {
+        match self {
+            Self::Mic(source) => source.encoder(),
+            Self::File(source) => source.encoder(),
+        }
+    }
+    pub fn frame_drops(&self) -> SharedFrameDrops {
+        match self {
+            Self::Mic(source) => source.frame_drops(),
+            Self::File(source) => source.frame_drops(),
+        }
+    }
+}
+
+/// RTP position of the mic stream, kept across capture streams so switching devices doesn't restart it
+#[derive(Debug)]
+struct RtpClock {
+    sequence_no: RtpSequenceNumber,
+    timestamp: u32,
+}
+
+/// What every capture stream of a mic source feeds, whichever device it runs on
+#[derive(Clone)]
+struct CaptureSink {
+    sender: Sender<RtpPacket>,
+    playing: Arc<AtomicBool>,
+    encoder: SharedEncoder,
+    frame_drops: SharedFrameDrops,
+    clock: Arc<Mutex<RtpClock>>,
+    ssrc: u32,
+    /// Notified by a stream whose device went away
+    stream_failed: Arc<Notify>,
+}
+
+/// Outcome of device work run on the blocking pool, cpal lists and opens devices synchronously
+enum DeviceWork {
+    Listed(InputDevices),
+    Opened {
+        name: String,
+        /// The new capture stream and the channels the encoder now takes
+        capture: Result<(cpal::Stream, Channels)>,
+    },
+}
+
+/// Streams the mic, moving to another input device when its device is unplugged, see [`DeviceWatcher`].
 pub struct RTPOpusAudioSource {
     receiver: Receiver<RtpPacket>,
     _stream: cpal::Stream,
-    playing: Arc<AtomicBool>,
+    /// As configured, adapted to each device the capture runs on
+    settings: EncoderSettings,
+    /// Channels the encoder was built for, a device captured in another layout needs a new encoder
+    encoder_channels: Channels,
+    sink: CaptureSink,
+    watcher: DeviceWatcher,
+    device_poll: tokio::time::Interval,
+    /// Listing or opening devices in the background, one at a time
+    device_work: Option<JoinHandle<DeviceWork>>,
 }
 
 impl RTPOpusAudioSource {
-    pub fn new(play_on_start: bool) -> Result<Self> {
+    /// Captures from `preferred_device`, from the default input device if None.
+    /// Fails listing the devices present if `preferred_device` isn't one of them
+    pub fn new(
+        play_on_start: bool,
+        settings: EncoderSettings,
+        ssrc: u32,
+        preferred_device: Option<String>,
+    ) -> Result<Self> {
         let host = cpal::default_host();
-
-        let device = host
-            .default_input_device()
-            .expect("No input device available");
-        tracing::info!("Selected default audio device {:?}", device.description());
-
-        let config = cpal::StreamConfig {
-            channels: 1,
-            sample_rate: SAMPLE_RATE,
-            buffer_size: cpal::BufferSize::Default,
+        let devices = InputDevices::list(&host);
+        let device = match preferred_device.as_deref() {
+            Some(name) => find_input_device(&host, name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No input device named {name:?}, {}",
+                    available_devices(&devices.names)
+                )
+            })?,
+            None => host.default_input_device().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No default input device, pick one with --input-device: {}",
+                    available_devices(&devices.names)
+                )
+            })?,
         };
-        let playing = Arc::new(AtomicBool::new(play_on_start));
-        let encoder = Arc::new(Mutex::new(Encoder::new(
-            SAMPLE_RATE,
-            CHANNELS,
-            Application::Voip,
-        )?));
+        tracing::info!("Selected audio device {:?}", device.description());
 
+        let (capture_channels, device_settings) = settings.for_input_device(&device)?;
         let (sender, receiver) = tokio::sync::mpsc::channel::<RtpPacket>(BUF_SIZE);
-
-        let mut pcm_buffer = Vec::<f32>::new();
-        let mut sequence_no = 0;
-        let mut start_time = 1200;
-        let ssrc = rand::random_range(0..u32::MAX / 2);
-        let stream = device.build_input_stream(
-            &config,
-            {
-                let playing = Arc::clone(&playing);
-                let encoder = encoder.clone();
-
-                move |data: &[f32], _| {
-                    // it's ok reaaaallyyyy...
-                    // The data will be produced in the background, but so what?
-                    if !playing.load(std::sync::atomic::Ordering::Relaxed) {
-                        pcm_buffer.clear();
-                        return;
-                    }
-                    pcm_buffer.extend_from_slice(data);
-
-                    while pcm_buffer.len() >= FRAME_SIZE {
-                        let frame: Vec<f32> = pcm_buffer.drain(..FRAME_SIZE).collect();
-
-                        let mut output = vec![0u8; 4000];
-                        let mut encoder = encoder.lock().unwrap();
-
-                        if let Ok(len) = encoder.encode_float(&frame, &mut output) {
-                            output.truncate(len);
-                            let output = bytes::Bytes::from_iter(output.into_iter());
-                            let packet = create_rtp_packet(sequence_no, start_time, ssrc, output);
-                            sequence_no += 1;
-                            start_time += 160;
-                            // non-blocking send (drop if channel full)
-                            match sender.try_send(packet) {
-                                Err(tokio::sync::mpsc::error::TrySendError::Closed { .. }) => {
-                                    tracing::error!("e");
-                                    break;
-                                }
-                                _ => (),
-                            };
-                        }
-                    }
-                }
-            },
-            move |err| {
-                tracing::error!("Audio stream error: {:?}", err);
-            },
-            Some(Duration::from_secs(2)),
-        )?;
-        stream.play()?;
+        let sink = CaptureSink {
+            sender,
+            playing: Arc::new(AtomicBool::new(play_on_start)),
+            encoder: device_settings.build_encoder()?,
+            frame_drops: Arc::new(Mutex::new(FrameDrops::new(FRAME_DROP_WINDOW))),
+            clock: Arc::new(Mutex::new(RtpClock {
+                sequence_no: 0,
+                timestamp: 1200,
+            })),
+            ssrc,
+            stream_failed: Arc::new(Notify::new()),
+        };
+        let stream = open_capture(&device, capture_channels, device_settings, sink.clone())?;
+        let mut device_poll = tokio::time::interval(POLL_INTERVAL);
+        device_poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
         Ok(Self {
             receiver,
             _stream: stream,
-            playing,
+            settings,
+            encoder_channels: device_settings.channels,
+            sink,
+            watcher: DeviceWatcher::new(
+                preferred_device,
+                device_name(&device).unwrap_or_default(),
+                devices,
+            ),
+            device_poll,
+            device_work: None,
         })
     }
 
-    /// Async read of next Opus packet
+    /// Async read of next Opus packet, following input device changes while waiting
     pub async fn read(&mut self) -> Option<RtpPacket> {
-        self.receiver.recv().await
+        loop {
+            tokio::select! {
+                packet = self.receiver.recv() => return packet,
+                _ = self.sink.stream_failed.notified() => {
+                    self.watcher.lost();
+                    self.list_devices();
+                }
+                _ = self.device_poll.tick() => self.list_devices(),
+                done = device_work_done(&mut self.device_work) => self.follow_devices(done),
+            }
+        }
     }
     pub async fn set_playing(&mut self, playing: bool) {
-        self.playing
+        self.sink
+            .playing
             .store(playing, std::sync::atomic::Ordering::Relaxed);
     }
+    pub fn encoder(&self) -> SharedEncoder {
+        self.sink.encoder.clone()
+    }
+    pub fn frame_drops(&self) -> SharedFrameDrops {
+        self.sink.frame_drops.clone()
+    }
+
+    /// Lists the input devices off the async runtime, unless device work is still running
+    fn list_devices(&mut self) {
+        if self.device_work.is_none() {
+            self.device_work = Some(tokio::task::spawn_blocking(|| {
+                DeviceWork::Listed(InputDevices::list(&cpal::default_host()))
+            }));
+        }
+    }
+
+    /// Opens the capture on the device the watcher picks, if it picks one
+    fn follow_devices(&mut self, done: std::result::Result<DeviceWork, JoinError>) {
+        self.device_work = None;
+        match done {
+            Ok(DeviceWork::Listed(devices)) => {
+                let Some(name) = self.watcher.update(devices) else {
+                    return;
+                };
+                let (settings, encoder_channels) = (self.settings, self.encoder_channels);
+                let sink = self.sink.clone();
+                self.device_work = Some(tokio::task::spawn_blocking(move || {
+                    let capture = reopen(&name, settings, encoder_channels, sink);
+                    DeviceWork::Opened { name, capture }
+                }));
+            }
+            Ok(DeviceWork::Opened {
+                capture: Ok((stream, channels)),
+                ..
+            }) => {
+                self._stream = stream;
+                self.encoder_channels = channels;
+            }
+            Ok(DeviceWork::Opened {
+                name,
+                capture: Err(e),
+            }) => {
+                tracing::warn!("Failed to capture from {name:?}, retrying: {e}");
+                self.watcher.lost();
+            }
+            Err(e) => tracing::warn!("Failed to follow input devices: {e}"),
+        }
+    }
+}
+
+/// Waits for the device work in flight, pending while there is none
+async fn device_work_done(
+    work: &mut Option<JoinHandle<DeviceWork>>,
+) -> std::result::Result<DeviceWork, JoinError> {
+    match work {
+        Some(task) => task.await,
+        None => std::future::pending().await,
+    }
 }
 
-fn create_rtp_packet(
+/// Opens the capture on the device named `name`, blocking so it runs off the async runtime.
+/// Keeps the SSRC, sequence numbers and timestamps running, the first frame on the new device starts a talk spurt
+fn reopen(
+    name: &str,
+    settings: EncoderSettings,
+    encoder_channels: Channels,
+    sink: CaptureSink,
+) -> Result<(cpal::Stream, Channels)> {
+    let device = find_input_device(&cpal::default_host(), name)
+        .ok_or_else(|| anyhow::anyhow!("device went away"))?;
+    let (capture_channels, settings) = settings.for_input_device(&device)?;
+    if settings.channels != encoder_channels {
+        *sink.encoder.lock().unwrap() = settings.new_encoder()?;
+    }
+    let stream = open_capture(&device, capture_channels, settings, sink)?;
+    Ok((stream, settings.channels))
+}
+
+/// Starts capturing `device` in `capture_channels` into the sink
+fn open_capture(
+    device: &cpal::Device,
+    capture_channels: Channels,
+    settings: EncoderSettings,
+    sink: CaptureSink,
+) -> Result<cpal::Stream> {
+    let config = cpal::StreamConfig {
+        channels: capture_channels as u16,
+        sample_rate: SAMPLE_RATE,
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let frame_len = settings.frame_size * capture_channels as usize;
+    let mut pcm_buffer = Vec::<f32>::new();
+    let mut talk_spurt = TalkSpurt::new(settings.reset_on_unmute);
+    let mut bitrate_floor = settings.bitrate_floor.map(BitrateFloor::new);
+    let stream_failed = sink.stream_failed.clone();
+    let stream = device.build_input_stream(
+        &config,
+        move |data: &[f32], _| {
+            // it's ok reaaaallyyyy...
+            // The data will be produced in the background, but so what?
+            if !sink.playing.load(std::sync::atomic::Ordering::Relaxed) {
+                pcm_buffer.clear();
+                talk_spurt.pause();
+                return;
+            }
+            pcm_buffer.extend_from_slice(data);
+
+            while pcm_buffer.len() >= frame_len {
+                let frame: Vec<f32> = pcm_buffer.drain(..frame_len).collect();
+                let input = match capture_channels {
+                    Channels::Stereo => Cow::Borrowed(&frame[..]),
+                    Channels::Mono => upmix(&frame, settings.channels),
+                };
+
+                let mut output = vec![0u8; 4000];
+                let mut encoder = sink.encoder.lock().unwrap();
+                let marker = talk_spurt.begin_frame(&mut encoder);
+                if let Some(bitrate_floor) = bitrate_floor.as_mut() {
+                    bitrate_floor.begin_frame(&frame, &mut encoder);
+                }
+
+                if let Ok(len) = encoder.encode_float(&input, &mut output) {
+                    output.truncate(len);
+                    let output = bytes::Bytes::from_iter(output);
+                    let mut clock = sink.clock.lock().unwrap();
+                    let mut packet =
+                        create_rtp_packet(clock.sequence_no, clock.timestamp, sink.ssrc, output);
+                    packet.header.marker = marker;
+                    clock.sequence_no = clock.sequence_no.wrapping_add(1);
+                    clock.timestamp = clock.timestamp.wrapping_add(settings.frame_size as u32);
+                    // non-blocking send (drop if channel full)
+                    let sent = sink.sender.try_send(packet);
+                    sink.frame_drops.lock().unwrap().record(matches!(
+                        sent,
+                        Err(tokio::sync::mpsc::error::TrySendError::Full(_))
+                    ));
+                    if let Err(tokio::sync::mpsc::error::TrySendError::Closed { .. }) = sent {
+                        tracing::error!("e");
+                        break;
+                    }
+                }
+            }
+        },
+        move |err| {
+            tracing::error!("Audio stream error: {:?}", err);
+            if matches!(
+                err,
+                cpal::StreamError::DeviceNotAvailable | cpal::StreamError::StreamInvalidated
+            ) {
+                stream_failed.notify_one();
+            }
+        },
+        Some(Duration::from_secs(2)),
+    )?;
+    stream.play()?;
+    Ok(stream)
+}
+
+pub(crate) fn create_rtp_packet(
     sq_no: RtpSequenceNumber,
     timestamp: u32,
     ssrc: u32,
@@ -117,3 +741,300 @@ fn create_rtp_packet(
     let rtp_header = RtpHeader::new(111, sq_no, timestamp, ssrc);
     rvoip_rtp_core::RtpPacket::new(rtp_header, payload)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_config::{LsbDepth, OpusComplexity};
+
+    #[test]
+    fn forced_mono_is_applied_to_encoder() {
+        let settings = EncoderSettings {
+            force_channels: Some(Channels::Mono),
+            ..Default::default()
+        };
+        let (capture, settings) = settings.for_device(2);
+        assert_eq!(capture, Channels::Stereo);
+
+        let encoder = settings.build_encoder().unwrap();
+        let mut encoder = encoder.lock().unwrap();
+        assert_eq!(encoder.get_force_channels().unwrap(), Some(Channels::Mono));
+
+        // Stereo input comes out as a mono packet, per the stereo flag of the TOC byte
+        let frame = vec![0.1f32; FRAME_SIZE * 2];
+        let mut output = vec![0u8; 4000];
+        let len = encoder.encode_float(&frame, &mut output).unwrap();
+        assert!(len > 0);
+        assert_eq!(output[0] & 0x04, 0);
+    }
+
+    #[test]
+    fn max_bandwidth_caps_the_selected_band() {
+        let settings = EncoderSettings {
+            bitrate: Bitrate::Bits(64_000),
+            max_bandwidth: Some(Bandwidth::Wideband),
+            ..Default::default()
+        };
+        let encoder = settings.build_encoder().unwrap();
+        let mut encoder = encoder.lock().unwrap();
+        assert_eq!(encoder.get_max_bandwidth().unwrap(), Bandwidth::Wideband);
+
+        // A 15kHz tone at 64kbps would get fullband without the ceiling
+        let frame: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| (i as f32 / SAMPLE_RATE as f32 * 15_000.0 * std::f32::consts::TAU).sin() * 0.5)
+            .collect();
+        let mut output = vec![0u8; 4000];
+        for _ in 0..10 {
+            encoder.encode_float(&frame, &mut output).unwrap();
+        }
+        let selected = encoder.get_bandwidth().unwrap();
+        assert!(
+            matches!(
+                selected,
+                Bandwidth::Narrowband | Bandwidth::Mediumband | Bandwidth::Wideband
+            ),
+            "{selected:?}"
+        );
+    }
+
+    #[test]
+    fn talk_spurt_start_resets_encoder() {
+        let settings = EncoderSettings::default();
+        let frame: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| (i as f32 / SAMPLE_RATE as f32 * 440.0 * std::f32::consts::TAU).sin() * 0.5)
+            .collect();
+        let encode = |encoder: &mut Encoder| {
+            let mut output = vec![0u8; 4000];
+            let len = encoder.encode_float(&frame, &mut output).unwrap();
+            output.truncate(len);
+            output
+        };
+        let fresh = encode(&mut settings.build_encoder().unwrap().lock().unwrap());
+
+        for reset in [true, false] {
+            let encoder = settings.build_encoder().unwrap();
+            let mut encoder = encoder.lock().unwrap();
+            let mut talk_spurt = TalkSpurt::new(reset);
+            assert!(talk_spurt.begin_frame(&mut encoder));
+            for _ in 0..5 {
+                encode(&mut encoder);
+                assert!(!talk_spurt.begin_frame(&mut encoder));
+            }
+
+            talk_spurt.pause();
+            assert!(talk_spurt.begin_frame(&mut encoder));
+            // A reset encoder produces exactly what a new one would
+            assert_eq!(encode(&mut encoder) == fresh, reset);
+        }
+    }
+
+    #[test]
+    fn bitrate_follows_voice_activity() {
+        let settings = EncoderSettings {
+            bitrate: Bitrate::Bits(32_000),
+            ..Default::default()
+        };
+        let encoder = settings.build_encoder().unwrap();
+        let mut encoder = encoder.lock().unwrap();
+        let speech: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| (i as f32 / SAMPLE_RATE as f32 * 440.0 * std::f32::consts::TAU).sin() * 0.3)
+            .collect();
+        let quiet = vec![0.001f32; FRAME_SIZE];
+        let mut bitrate_floor = BitrateFloor::new(8_000);
+
+        bitrate_floor.begin_frame(&speech, &mut encoder);
+        assert_eq!(encoder.get_bitrate().unwrap(), Bitrate::Bits(32_000));
+        // A pause shorter than the hangover keeps the target
+        for _ in 1..VAD_HANGOVER_FRAMES {
+            bitrate_floor.begin_frame(&quiet, &mut encoder);
+        }
+        assert_eq!(encoder.get_bitrate().unwrap(), Bitrate::Bits(32_000));
+        bitrate_floor.begin_frame(&quiet, &mut encoder);
+        assert_eq!(encoder.get_bitrate().unwrap(), Bitrate::Bits(8_000));
+
+        bitrate_floor.begin_frame(&speech, &mut encoder);
+        assert_eq!(encoder.get_bitrate().unwrap(), Bitrate::Bits(32_000));
+        for _ in 0..VAD_HANGOVER_FRAMES {
+            bitrate_floor.begin_frame(&quiet, &mut encoder);
+        }
+        assert_eq!(encoder.get_bitrate().unwrap(), Bitrate::Bits(8_000));
+    }
+
+    #[test]
+    fn frame_drop_rate_rolls_over_the_window() {
+        let mut drops = FrameDrops::new(4);
+        assert_eq!(drops.rate(), 0.0);
+
+        // sent, dropped, dropped: 2 of 3
+        for dropped in [false, true, true] {
+            drops.record(dropped);
+        }
+        assert!((drops.rate() - 2.0 / 3.0).abs() < f32::EPSILON);
+
+        // Window full at sent, dropped, dropped, sent
+        drops.record(false);
+        assert_eq!(drops.rate(), 0.5);
+        // The oldest entries roll out: dropped, dropped, sent, sent then dropped, sent, sent, sent
+        drops.record(false);
+        assert_eq!(drops.rate(), 0.5);
+        drops.record(false);
+        assert_eq!(drops.rate(), 0.25);
+        drops.record(false);
+        assert_eq!(drops.rate(), 0.0);
+    }
+
+    #[test]
+    fn mono_devices_ignore_forced_mono() {
+        let settings = EncoderSettings {
+            force_channels: Some(Channels::Mono),
+            ..Default::default()
+        };
+        assert_eq!(settings.for_device(1), (Channels::Mono, settings));
+        assert_eq!(
+            EncoderSettings::default().for_device(2),
+            (Channels::Mono, EncoderSettings::default())
+        );
+    }
+
+    /// Codes 10 frames of a 300Hz tone, returns the configuration number of the last packet's TOC byte
+    fn coded_config(settings: EncoderSettings) -> u8 {
+        let encoder = settings.build_encoder().unwrap();
+        let mut encoder = encoder.lock().unwrap();
+        let frame: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| (i as f32 / SAMPLE_RATE as f32 * 300.0 * std::f32::consts::TAU).sin() * 0.5)
+            .collect();
+        let mut output = vec![0u8; 4000];
+        for _ in 0..10 {
+            encoder.encode_float(&frame, &mut output).unwrap();
+        }
+        output[0] >> 3
+    }
+
+    #[test]
+    fn forced_opus_mode_is_applied_to_the_encoder() {
+        let celt = EncoderSettings {
+            bitrate: Bitrate::Bits(24_000),
+            forced_mode: Some(OpusMode::Celt),
+            ..Default::default()
+        };
+        let encoder = celt.build_encoder().unwrap();
+        assert_eq!(
+            encoder.lock().unwrap().get_application().unwrap(),
+            Application::LowDelay
+        );
+        // Configurations 16 to 31 are CELT only, 0 to 11 SILK only
+        assert!(coded_config(celt) >= 16);
+
+        let silk = EncoderSettings {
+            bitrate: Bitrate::Bits(24_000),
+            forced_mode: Some(OpusMode::Silk),
+            max_bandwidth: Some(Bandwidth::Fullband),
+            ..Default::default()
+        };
+        let encoder = silk.build_encoder().unwrap();
+        let mut encoder = encoder.lock().unwrap();
+        assert_eq!(encoder.get_signal().unwrap(), Signal::Voice);
+        assert_eq!(encoder.get_max_bandwidth().unwrap(), Bandwidth::Wideband);
+        drop(encoder);
+        assert!(coded_config(silk) < 12);
+    }
+
+    #[test]
+    fn configured_lsb_depth_is_applied_to_the_encoder() {
+        let settings = EncoderSettings {
+            lsb_depth: Some(16),
+            ..Default::default()
+        };
+        let settings = settings
+            .for_capture_format(cpal::SampleFormat::I16)
+            .unwrap();
+        let encoder = settings.build_encoder().unwrap();
+        assert_eq!(encoder.lock().unwrap().get_lsb_depth().unwrap(), 16);
+    }
+
+    #[test]
+    fn out_of_range_lsb_depth_is_rejected() {
+        assert!("7".parse::<LsbDepth>().is_err());
+        assert!("25".parse::<LsbDepth>().is_err());
+        assert_eq!("24".parse::<LsbDepth>().unwrap(), LsbDepth(24));
+
+        let settings = EncoderSettings {
+            lsb_depth: Some(24),
+            ..Default::default()
+        };
+        assert!(
+            settings
+                .for_capture_format(cpal::SampleFormat::I16)
+                .is_err()
+        );
+        assert!(settings.for_capture_format(cpal::SampleFormat::F32).is_ok());
+    }
+
+    #[test]
+    fn configured_complexity_and_dtx_are_applied_to_the_encoder() {
+        let defaults = EncoderSettings::default().build_encoder().unwrap();
+        let default_complexity = defaults.lock().unwrap().get_complexity().unwrap();
+        assert!(!defaults.lock().unwrap().get_dtx().unwrap());
+
+        let settings = EncoderSettings {
+            bitrate: Bitrate::Bits(12_000),
+            complexity: Some(default_complexity - 5),
+            dtx: true,
+            ..Default::default()
+        };
+        let encoder = settings.build_encoder().unwrap();
+        let mut encoder = encoder.lock().unwrap();
+        assert_eq!(encoder.get_bitrate().unwrap(), Bitrate::Bits(12_000));
+        assert_eq!(encoder.get_complexity().unwrap(), default_complexity - 5);
+        assert!(encoder.get_dtx().unwrap());
+    }
+
+    #[test]
+    fn out_of_range_bitrate_and_complexity_are_errors() {
+        assert!("11".parse::<OpusComplexity>().is_err());
+        assert_eq!("0".parse::<OpusComplexity>().unwrap(), OpusComplexity(0));
+
+        for settings in [
+            EncoderSettings {
+                complexity: Some(11),
+                ..Default::default()
+            },
+            EncoderSettings {
+                bitrate: Bitrate::Bits(1_000_000),
+                ..Default::default()
+            },
+        ] {
+            assert!(settings.build_encoder().is_err());
+        }
+    }
+
+    #[test]
+    fn stereo_devices_are_captured_in_stereo_for_a_stereo_encoder() {
+        let settings = EncoderSettings {
+            channels: Channels::Stereo,
+            ..Default::default()
+        };
+
+        let (capture, stereo) = settings.for_device(2);
+        assert_eq!(capture, Channels::Stereo);
+        assert_eq!(stereo.channels, Channels::Stereo);
+        // A mono device is upmixed into the stereo encoder
+        let (capture, upmixed) = settings.for_device(1);
+        assert_eq!(capture, Channels::Mono);
+        assert_eq!(upmixed.channels, Channels::Stereo);
+
+        let encoder = stereo.build_encoder().unwrap();
+        let frame: Vec<f32> = (0..FRAME_SIZE)
+            .flat_map(|i| [0.1, -(i as f32) / 1e4])
+            .collect();
+        let mut output = vec![0u8; 4000];
+        let len = encoder
+            .lock()
+            .unwrap()
+            .encode_float(&frame, &mut output)
+            .unwrap();
+        assert!(len > 0);
+        // Stereo flag of the TOC byte
+        assert_ne!(output[0] & 0x04, 0);
+    }
+}
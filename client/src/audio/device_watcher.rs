@@ -0,0 +1,237 @@
+//! Follows input devices being plugged in and out while the mic source streams.
+//! cpal has no hot-plug notifications, so the input devices are listed every [`POLL_INTERVAL`]
+//! and on every stream error, and [`DeviceWatcher`] decides whether the capture moves to another device.
+//! The source rebuilds its capture stream on the device picked, see [`super::audio_source::RTPOpusAudioSource`].
+//! Listing and reopening run on tokio's blocking pool, cpal does both synchronously.
+//! Output devices are only looked up by name once, when playback starts.
+
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+/// How often the input devices are listed
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Input devices present at one point, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputDevices {
+    pub names: Vec<String>,
+    /// The host's default input device, if it has one
+    pub default: Option<String>,
+}
+
+impl InputDevices {
+    /// Lists the input devices of `host`, none if it can't enumerate them
+    pub fn list(host: &cpal::Host) -> Self {
+        let names = match host.input_devices() {
+            Ok(devices) => devices.filter_map(|device| device_name(&device)).collect(),
+            Err(e) => {
+                tracing::debug!("Failed to list input devices: {e}");
+                Vec::new()
+            }
+        };
+        Self {
+            names,
+            default: host
+                .default_input_device()
+                .and_then(|device| device_name(&device)),
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|known| known == name)
+    }
+}
+
+pub fn device_name(device: &cpal::Device) -> Option<String> {
+    device
+        .description()
+        .ok()
+        .map(|description| description.name().to_string())
+}
+
+/// Finds the input device named `name` on `host`
+pub fn find_input_device(host: &cpal::Host, name: &str) -> Option<cpal::Device> {
+    host.input_devices()
+        .ok()?
+        .find(|device| device_name(device).as_deref() == Some(name))
+}
+
+/// Finds the output device named `name` on `host`, the error lists the ones present
+pub fn find_output_device(host: &cpal::Host, name: &str) -> anyhow::Result<cpal::Device> {
+    let devices: Vec<cpal::Device> = host.output_devices()?.collect();
+    let names: Vec<String> = devices.iter().filter_map(device_name).collect();
+    devices
+        .into_iter()
+        .find(|device| device_name(device).as_deref() == Some(name))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No output device named {name:?}, {}",
+                available_devices(&names)
+            )
+        })
+}
+
+/// Names the devices to pick from for an error or warning
+pub fn available_devices(names: &[String]) -> String {
+    if names.is_empty() {
+        return "none are available".to_string();
+    }
+    let names: Vec<String> = names.iter().map(|name| format!("{name:?}")).collect();
+    format!("available are {}", names.join(", "))
+}
+
+/// Decides which input device the capture runs on as devices come and go.
+/// The preferred device wins whenever it's present, otherwise the default device and then any other is taken.
+/// The capture only moves when its device went away or the preferred one came back.
+#[derive(Debug)]
+pub struct DeviceWatcher {
+    preferred: Option<String>,
+    /// Device the capture runs on, None once it was lost
+    active: Option<String>,
+    /// Devices present at the last update
+    known: InputDevices,
+}
+
+impl DeviceWatcher {
+    pub fn new(preferred: Option<String>, active: String, devices: InputDevices) -> Self {
+        Self {
+            preferred,
+            active: Some(active),
+            known: devices,
+        }
+    }
+
+    /// The capture stream failed, the next update picks a device even if its device is still listed
+    pub fn lost(&mut self) {
+        self.active = None;
+    }
+
+    /// Takes the devices present now, returns the one the capture has to be rebuilt on if any
+    pub fn update(&mut self, devices: InputDevices) -> Option<String> {
+        let previous = std::mem::replace(&mut self.known, devices);
+        let active_gone = match &self.active {
+            Some(active) => !self.known.contains(active),
+            None => true,
+        };
+        let preferred_returned = self.preferred.as_ref().is_some_and(|preferred| {
+            self.known.contains(preferred)
+                && !previous.contains(preferred)
+                && self.active.as_ref() != Some(preferred)
+        });
+        if !active_gone && !preferred_returned {
+            return None;
+        }
+        let pick = self
+            .preferred
+            .iter()
+            .chain(&self.known.default)
+            .chain(&self.known.names)
+            .find(|name| self.known.contains(name))
+            .cloned();
+        match &pick {
+            Some(name) => tracing::info!(
+                "Input device {:?} {}, switching to {name:?}",
+                self.active,
+                if active_gone {
+                    "went away"
+                } else {
+                    "yields to the preferred one"
+                }
+            ),
+            None => tracing::warn!(
+                "Input device {:?} went away, none left to switch to",
+                self.active
+            ),
+        }
+        self.active = pick.clone();
+        pick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices(names: &[&str], default: Option<&str>) -> InputDevices {
+        InputDevices {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            default: default.map(String::from),
+        }
+    }
+
+    #[test]
+    fn available_devices_are_listed_by_name() {
+        assert_eq!(
+            available_devices(&["Built-in".to_string(), "USB mic".to_string()]),
+            "available are \"Built-in\", \"USB mic\""
+        );
+        assert_eq!(available_devices(&[]), "none are available");
+    }
+
+    #[test]
+    fn removed_device_moves_the_capture_to_the_default() {
+        let mut watcher = DeviceWatcher::new(
+            None,
+            "USB mic".to_string(),
+            devices(&["Built-in", "USB mic"], Some("Built-in")),
+        );
+        assert_eq!(
+            watcher.update(devices(&["Built-in", "USB mic"], Some("Built-in"))),
+            None
+        );
+
+        assert_eq!(
+            watcher.update(devices(&["Built-in"], Some("Built-in"))),
+            Some("Built-in".to_string())
+        );
+        // Plugging it back in doesn't move the capture again
+        assert_eq!(
+            watcher.update(devices(&["Built-in", "USB mic"], Some("Built-in"))),
+            None
+        );
+    }
+
+    #[test]
+    fn preferred_device_is_taken_back_once_it_returns() {
+        let headset = "Headset".to_string();
+        let mut watcher = DeviceWatcher::new(
+            Some(headset.clone()),
+            headset.clone(),
+            devices(&["Built-in", "Headset"], Some("Built-in")),
+        );
+
+        assert_eq!(
+            watcher.update(devices(&["Built-in"], Some("Built-in"))),
+            Some("Built-in".to_string())
+        );
+        assert_eq!(
+            watcher.update(devices(&["Built-in", "Headset"], Some("Built-in"))),
+            Some(headset)
+        );
+    }
+
+    #[test]
+    fn lost_stream_is_rebuilt_even_on_a_listed_device() {
+        let mut watcher =
+            DeviceWatcher::new(None, "Built-in".to_string(), devices(&["Built-in"], None));
+        watcher.lost();
+
+        assert_eq!(
+            watcher.update(devices(&["Built-in"], None)),
+            Some("Built-in".to_string())
+        );
+    }
+
+    #[test]
+    fn no_device_left_retries_on_the_next_update() {
+        let mut watcher =
+            DeviceWatcher::new(None, "USB mic".to_string(), devices(&["USB mic"], None));
+
+        assert_eq!(watcher.update(devices(&[], None)), None);
+        assert_eq!(
+            watcher.update(devices(&["Built-in"], Some("Built-in"))),
+            Some("Built-in".to_string())
+        );
+    }
+}
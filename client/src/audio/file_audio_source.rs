@@ -0,0 +1,351 @@
+//! Streams a WAV file instead of live mic input, for demos and testing.
+//! The file is downmixed to mono, resampled to the Opus rate and sent in real time.
+
+use std::{
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::Result;
+use rvoip_rtp_core::RtpPacket;
+use tokio::sync::mpsc::Receiver;
+
+use crate::audio::audio_source::{
+    BUF_SIZE, BitrateFloor, EncoderSettings, FRAME_DROP_WINDOW, FrameDrops, SAMPLE_RATE,
+    SharedEncoder, SharedFrameDrops, TalkSpurt, create_rtp_packet, upmix,
+};
+
+pub struct FileAudioSource {
+    receiver: Receiver<RtpPacket>,
+    playing: Arc<AtomicBool>,
+    encoder: SharedEncoder,
+    /// Stays at zero, the file is paced by the send queue instead of dropping frames
+    frame_drops: SharedFrameDrops,
+}
+
+impl FileAudioSource {
+    pub fn new(
+        path: impl AsRef<Path>,
+        looping: bool,
+        play_on_start: bool,
+        settings: EncoderSettings,
+        ssrc: u32,
+    ) -> Result<Self> {
+        let pcm = load_wav_mono(path.as_ref())?;
+        if pcm.is_empty() {
+            anyhow::bail!("{:?} contains no audio", path.as_ref());
+        }
+        tracing::info!(
+            "Streaming {:?} ({} frames, looping: {looping})",
+            path.as_ref(),
+            pcm.len().div_ceil(settings.frame_size)
+        );
+
+        let playing = Arc::new(AtomicBool::new(play_on_start));
+        let encoder = settings.build_encoder()?;
+        let frame_drops = Arc::new(Mutex::new(FrameDrops::new(FRAME_DROP_WINDOW)));
+        let (sender, receiver) = tokio::sync::mpsc::channel::<RtpPacket>(BUF_SIZE);
+
+        tokio::spawn({
+            let playing = playing.clone();
+            let encoder = encoder.clone();
+            let frame_drops = frame_drops.clone();
+            async move {
+                let frame_size = settings.frame_size;
+                let mut interval = tokio::time::interval(settings.frame_duration());
+                let mut position = 0;
+                let mut sequence_no = 0u16;
+                let mut timestamp = 0u32;
+                let mut frame = vec![0f32; frame_size];
+                let mut output = vec![0u8; 4000];
+                let mut talk_spurt = TalkSpurt::new(settings.reset_on_unmute);
+                let mut bitrate_floor = settings.bitrate_floor.map(BitrateFloor::new);
+                loop {
+                    interval.tick().await;
+                    if !playing.load(Ordering::Relaxed) {
+                        talk_spurt.pause();
+                        continue;
+                    }
+                    if position >= pcm.len() {
+                        if !looping {
+                            break;
+                        }
+                        position = 0;
+                    }
+                    // The last frame of the file is padded with silence
+                    let end = (position + frame_size).min(pcm.len());
+                    frame.fill(0.0);
+                    frame[..end - position].copy_from_slice(&pcm[position..end]);
+                    position = end;
+
+                    let (marker, encoded) = {
+                        let mut encoder = encoder.lock().unwrap();
+                        let marker = talk_spurt.begin_frame(&mut encoder);
+                        if let Some(bitrate_floor) = bitrate_floor.as_mut() {
+                            bitrate_floor.begin_frame(&frame, &mut encoder);
+                        }
+                        let encoded =
+                            encoder.encode_float(&upmix(&frame, settings.channels), &mut output);
+                        (marker, encoded)
+                    };
+                    let len = match encoded {
+                        Ok(len) => len,
+                        Err(e) => {
+                            tracing::error!("Failed to encode file audio: {e}");
+                            break;
+                        }
+                    };
+                    let mut packet = create_rtp_packet(
+                        sequence_no,
+                        timestamp,
+                        ssrc,
+                        bytes::Bytes::copy_from_slice(&output[..len]),
+                    );
+                    packet.header.marker = marker;
+                    sequence_no = sequence_no.wrapping_add(1);
+                    timestamp = timestamp.wrapping_add(frame_size as u32);
+                    if sender.send(packet).await.is_err() {
+                        break;
+                    }
+                    frame_drops.lock().unwrap().record(false);
+                }
+            }
+        });
+
+        Ok(Self {
+            receiver,
+            playing,
+            encoder,
+            frame_drops,
+        })
+    }
+
+    /// Async read of next Opus packet, None once a non-looping file has been sent completely
+    pub async fn read(&mut self) -> Option<RtpPacket> {
+        self.receiver.recv().await
+    }
+    pub async fn set_playing(&mut self, playing: bool) {
+        self.playing.store(playing, Ordering::Relaxed);
+    }
+    pub fn encoder(&self) -> SharedEncoder {
+        self.encoder.clone()
+    }
+    pub fn frame_drops(&self) -> SharedFrameDrops {
+        self.frame_drops.clone()
+    }
+}
+
+/// Reads a WAV file as mono f32 samples at [`SAMPLE_RATE`].
+fn load_wav_mono(path: &Path) -> Result<Vec<f32>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+    Ok(resample_linear(&mono, spec.sample_rate, SAMPLE_RATE))
+}
+
+/// Linear interpolation resampler, good enough for test and demo audio.
+pub fn resample_linear(input: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || input.is_empty() {
+        return input.to_vec();
+    }
+    let out_len = (input.len() as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let step = from_rate as f64 / to_rate as f64;
+    (0..out_len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let index = pos as usize;
+            let frac = (pos - index as f64) as f32;
+            let current = input[index.min(input.len() - 1)];
+            let next = input[(index + 1).min(input.len() - 1)];
+            current + (next - current) * frac
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::app_config::FrameDuration;
+    use crate::audio::audio_source::FRAME_SIZE;
+
+    const SSRC: u32 = 1234;
+
+    pub(crate) fn write_tone_wav(path: &Path, sample_rate: u32, channels: u16, samples: usize) {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..samples {
+            let t = i as f32 / sample_rate as f32;
+            let sample = ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16;
+            for _ in 0..channels {
+                writer.write_sample(sample).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    async fn count_packets(mut source: FileAudioSource) -> Vec<RtpPacket> {
+        let mut packets = Vec::new();
+        while let Some(packet) = source.read().await {
+            packets.push(packet);
+        }
+        packets
+    }
+
+    #[tokio::test]
+    async fn short_wav_produces_expected_packet_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        // 100ms and a half frame, the remainder is padded into a 6th frame
+        write_tone_wav(&path, SAMPLE_RATE, 1, 5 * FRAME_SIZE + FRAME_SIZE / 2);
+
+        let packets = count_packets(
+            FileAudioSource::new(&path, false, true, EncoderSettings::default(), SSRC).unwrap(),
+        )
+        .await;
+
+        assert_eq!(packets.len(), 6);
+        for (i, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.header.sequence_number, i as u16);
+            assert_eq!(packet.header.timestamp, (i * FRAME_SIZE) as u32);
+        }
+    }
+
+    #[tokio::test]
+    async fn wav_at_other_rate_is_resampled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone16k.wav");
+        // 100ms of 16kHz stereo is 5 frames at 48kHz
+        write_tone_wav(&path, 16_000, 2, 1600);
+
+        let packets = count_packets(
+            FileAudioSource::new(&path, false, true, EncoderSettings::default(), SSRC).unwrap(),
+        )
+        .await;
+
+        assert_eq!(packets.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn room_codec_policy_is_adopted() {
+        use crate::audio::audio_source::EncoderStats;
+        use lib_common_voxoxide::types::ArsAuthResponse;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        write_tone_wav(&path, SAMPLE_RATE, 1, 2 * FRAME_SIZE);
+        // As sent by the server when joining a room with a policy
+        let response: ArsAuthResponse =
+            serde_json::from_str(r#"{"codec_policy":{"bitrate":24000,"channels":2}}"#).unwrap();
+        let settings = EncoderSettings::default().with_policy(response.codec_policy.as_ref());
+
+        let source = FileAudioSource::new(&path, false, true, settings, SSRC).unwrap();
+        let encoder = source.encoder();
+        let packets = count_packets(source).await;
+
+        let stats = EncoderStats::read(&encoder).unwrap();
+        assert_eq!(stats.bitrate, opus::Bitrate::Bits(24_000));
+        assert!(!encoder.lock().unwrap().get_inband_fec().unwrap());
+        assert_eq!(packets.len(), 2);
+        for packet in packets {
+            // Stereo flag of the Opus TOC byte
+            assert_ne!(packet.payload[0] & 0x04, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn first_packet_after_unmute_is_marked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        write_tone_wav(&path, SAMPLE_RATE, 1, 20 * FRAME_SIZE);
+        let settings = EncoderSettings {
+            reset_on_unmute: true,
+            ..Default::default()
+        };
+        let mut source = FileAudioSource::new(&path, false, true, settings, SSRC).unwrap();
+
+        let markers = [
+            source.read().await.unwrap().header.marker,
+            source.read().await.unwrap().header.marker,
+        ];
+        assert_eq!(markers, [true, false]);
+
+        source.set_playing(false).await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        // Drain whatever was sent before the pause took effect
+        while source.receiver.try_recv().is_ok() {}
+        source.set_playing(true).await;
+
+        assert!(source.read().await.unwrap().header.marker);
+        assert!(!source.read().await.unwrap().header.marker);
+    }
+
+    #[tokio::test]
+    async fn expert_frame_duration_sets_frame_size() {
+        let duration: FrameDuration = "2.5".parse().unwrap();
+        let settings = EncoderSettings {
+            frame_size: duration.frame_size(SAMPLE_RATE).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(settings.frame_size, 120);
+        assert_eq!(settings.frame_duration(), Duration::from_micros(2500));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        write_tone_wav(&path, SAMPLE_RATE, 1, 4 * 120);
+        let packets =
+            count_packets(FileAudioSource::new(&path, false, true, settings, SSRC).unwrap()).await;
+
+        assert_eq!(packets.len(), 4);
+        for (i, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.header.timestamp, (i * 120) as u32);
+            assert_eq!(
+                opus::packet::get_nb_samples(&packet.payload, SAMPLE_RATE).unwrap(),
+                120
+            );
+        }
+    }
+
+    #[test]
+    fn frame_durations_are_validated() {
+        for invalid in ["3", "0", "120", "fast"] {
+            assert!(invalid.parse::<FrameDuration>().is_err(), "{invalid}");
+        }
+        let duration: FrameDuration = "2.5".parse().unwrap();
+        // 2.5ms at 44.1kHz would be 110.25 samples
+        assert!(duration.frame_size(44_100).is_err());
+        assert_eq!(duration.frame_size(8_000).unwrap(), 20);
+    }
+
+    #[test]
+    fn resample_linear_scales_length() {
+        let input: Vec<f32> = (0..160).map(|i| i as f32).collect();
+        let output = resample_linear(&input, 16_000, 48_000);
+        assert_eq!(output.len(), 480);
+        assert_eq!(output[3], 1.0);
+        assert!((output[4] - 4.0 / 3.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,372 @@
+//! Adaptive jitter buffer of the playback path, the client side counterpart of the relay's receive path.
+//! Packets wait until a frame is due and play out in sequence order, reordered ones included.
+//! Frames that never arrived play out as [`Playout::Lost`] for the decoder to conceal.
+//! The depth grows by a frame whenever a packet arrives too late or the buffer runs dry,
+//! and shrinks back by one after [`SHRINK_AFTER`] frames played without either.
+//! A frame missing when it's due may be held back for a grace window instead of being concealed right away,
+//! so a packet arriving just after its deadline still plays. Its slot is [`Playout::Pending`] meanwhile.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use rvoip_rtp_core::RtpPacket;
+
+/// How often a frame slot plays out, received streams are assumed to carry 20ms frames
+pub(crate) const PLAYOUT_INTERVAL: Duration = Duration::from_millis(20);
+/// Frames buffered before playout starts, 40ms at 20ms
+pub(crate) const MIN_DEPTH: usize = 2;
+/// Deepest the buffer adapts to, 200ms at 20ms
+pub(crate) const MAX_DEPTH: usize = 10;
+/// Frames played without a late packet or underrun before the depth shrinks, 5s at 20ms
+pub(crate) const SHRINK_AFTER: usize = 250;
+/// Playouts a stream may go without a packet before its buffer is dropped, 5s at 20ms
+const IDLE_STREAM_PLAYOUTS: usize = 250;
+
+/// What plays in the next frame slot of a stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum Playout {
+    Frame(RtpPacket),
+    /// The frame never arrived, the decoder conceals it
+    Lost,
+    /// Still filling up to the current depth, nothing plays
+    Buffering,
+    /// The frame is late but still within the grace window, pop again once the window has passed
+    Pending,
+}
+
+/// Buffer of a single RTP stream.
+#[derive(Debug)]
+pub struct JitterBuffer {
+    /// Keyed by sequence number extended past wrap-arounds
+    packets: BTreeMap<u64, RtpPacket>,
+    /// Extended sequence number of the newest packet seen
+    highest: Option<u64>,
+    /// Extended sequence number due next, None while buffering
+    next: Option<u64>,
+    depth: usize,
+    /// Frames played since the depth last changed or the buffer last ran dry
+    stable: usize,
+    lost_packets: u64,
+    late_packets: u64,
+    /// How long past its deadline a missing frame waits for its packet
+    late_grace: Duration,
+    /// When the frame that's due stops waiting, set while it's pending
+    grace_deadline: Option<Instant>,
+}
+
+impl Default for JitterBuffer {
+    fn default() -> Self {
+        Self::with_late_grace(Duration::ZERO)
+    }
+}
+
+impl JitterBuffer {
+    /// Waits up to `late_grace` past a frame's deadline for a packet that's late,
+    /// concealing missing frames right away if zero
+    pub fn with_late_grace(late_grace: Duration) -> Self {
+        Self {
+            packets: BTreeMap::new(),
+            highest: None,
+            next: None,
+            depth: MIN_DEPTH,
+            stable: 0,
+            lost_packets: 0,
+            late_packets: 0,
+            late_grace,
+            grace_deadline: None,
+        }
+    }
+
+    /// Buffers a received packet. Packets whose frame already played out are dropped.
+    pub fn push(&mut self, packet: RtpPacket) {
+        let sequence = self.extend(packet.header.sequence_number);
+        if self.next.is_some_and(|next| sequence < next) {
+            tracing::trace!("Dropping packet {sequence} that arrived after its playout");
+            self.late_packets += 1;
+            self.grow();
+            return;
+        }
+        self.highest = self.highest.max(Some(sequence));
+        self.packets.entry(sequence).or_insert(packet);
+        // Past the deepest the buffer may get the oldest frames are skipped, they'd only add latency
+        while self.packets.len() > MAX_DEPTH {
+            let (skipped, _) = self.packets.pop_first().unwrap();
+            self.next = self.next.map(|_| skipped + 1);
+        }
+    }
+
+    /// Takes whatever plays in the next frame slot, call once per frame duration at `now`.
+    /// A [`Playout::Pending`] slot is taken by popping again, see [`JitterBuffer::grace_deadline`]
+    pub fn pop(&mut self, now: Instant) -> Playout {
+        let next = match self.next {
+            Some(next) => next,
+            None if self.packets.len() >= self.depth => *self.packets.first_key_value().unwrap().0,
+            None => return Playout::Buffering,
+        };
+        if !self.packets.contains_key(&next) {
+            let deadline = *self.grace_deadline.get_or_insert(now + self.late_grace);
+            if now < deadline {
+                return Playout::Pending;
+            }
+        }
+        self.grace_deadline = None;
+        if self.packets.is_empty() {
+            tracing::trace!("Jitter buffer ran dry, buffering {} frames", self.depth + 1);
+            self.next = None;
+            self.grow();
+            return Playout::Buffering;
+        }
+        self.next = Some(next + 1);
+        self.stable += 1;
+        if self.stable >= SHRINK_AFTER && self.depth > MIN_DEPTH {
+            self.depth -= 1;
+            self.stable = 0;
+        }
+        match self.packets.remove(&next) {
+            Some(packet) => Playout::Frame(packet),
+            None => {
+                self.lost_packets += 1;
+                Playout::Lost
+            }
+        }
+    }
+
+    /// When the pending frame stops waiting for its packet, None unless the last pop was [`Playout::Pending`]
+    pub fn grace_deadline(&self) -> Option<Instant> {
+        self.grace_deadline
+    }
+
+    /// Frames the buffer currently holds back before playout
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Frames that never arrived and were concealed
+    pub fn lost_packets(&self) -> u64 {
+        self.lost_packets
+    }
+
+    /// Packets that arrived after their frame played and were dropped
+    pub fn late_packets(&self) -> u64 {
+        self.late_packets
+    }
+
+    fn grow(&mut self) {
+        self.depth = (self.depth + 1).min(MAX_DEPTH);
+        self.stable = 0;
+    }
+
+    /// Places a 16 bit sequence number next to the newest one seen, so ordering survives wrap-arounds
+    fn extend(&self, sequence: u16) -> u64 {
+        match self.highest {
+            // Start high enough that packets older than the first one don't underflow
+            None => (1 << 32) + u64::from(sequence),
+            Some(highest) => {
+                let delta = sequence.wrapping_sub(highest as u16) as i16;
+                highest.saturating_add_signed(i64::from(delta))
+            }
+        }
+    }
+}
+
+/// One [`JitterBuffer`] per SSRC, since streams forwarded from different members play out independently.
+/// Buffers of streams that stopped sending are dropped.
+#[derive(Debug, Default)]
+pub struct JitterBuffers {
+    /// Every stream's buffer and the playouts since its last packet
+    streams: HashMap<u32, (JitterBuffer, usize)>,
+    /// Given to the buffer of every new stream, see [`JitterBuffer::with_late_grace`]
+    late_grace: Duration,
+}
+
+/// Filled by the receive loop, read by the stats
+pub type SharedJitterBuffers = std::sync::Arc<std::sync::Mutex<JitterBuffers>>;
+
+impl JitterBuffers {
+    pub fn with_late_grace(late_grace: Duration) -> Self {
+        Self {
+            streams: HashMap::new(),
+            late_grace,
+        }
+    }
+
+    pub fn push(&mut self, packet: RtpPacket) {
+        let (buffer, idle) = self
+            .streams
+            .entry(packet.header.ssrc)
+            .or_insert_with(|| (JitterBuffer::with_late_grace(self.late_grace), 0));
+        *idle = 0;
+        buffer.push(packet);
+    }
+
+    /// Takes the next frame slot of every stream, call once per frame duration
+    pub fn pop(&mut self, now: Instant) -> Vec<(u32, Playout)> {
+        self.streams.retain(|ssrc, (buffer, idle)| {
+            *idle += 1;
+            if *idle <= IDLE_STREAM_PLAYOUTS {
+                return true;
+            }
+            tracing::debug!(
+                "Stream {ssrc} went idle, {} frames lost and {} packets late",
+                buffer.lost_packets(),
+                buffer.late_packets()
+            );
+            false
+        });
+        self.streams
+            .iter_mut()
+            .map(|(ssrc, (buffer, _))| (*ssrc, buffer.pop(now)))
+            .collect()
+    }
+
+    /// Takes the slot of every stream whose last pop was [`Playout::Pending`], call at [`JitterBuffers::grace_deadline`]
+    pub fn pop_pending(&mut self, now: Instant) -> Vec<(u32, Playout)> {
+        self.streams
+            .iter_mut()
+            .filter(|(_, (buffer, _))| buffer.grace_deadline().is_some())
+            .map(|(ssrc, (buffer, _))| (*ssrc, buffer.pop(now)))
+            .collect()
+    }
+
+    /// Earliest time a pending stream stops waiting for its late packet
+    pub fn grace_deadline(&self) -> Option<Instant> {
+        self.streams
+            .values()
+            .filter_map(|(buffer, _)| buffer.grace_deadline())
+            .min()
+    }
+
+    /// Deepest buffer of all streams, [`MIN_DEPTH`] while nothing is received
+    pub fn depth(&self) -> usize {
+        self.streams
+            .values()
+            .map(|(buffer, _)| buffer.depth())
+            .max()
+            .unwrap_or(MIN_DEPTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_config::LateGrace;
+
+    fn packet(sequence: u16) -> RtpPacket {
+        RtpPacket::new_with_payload(
+            111,
+            sequence,
+            u32::from(sequence) * 960,
+            7,
+            vec![sequence as u8].into(),
+        )
+    }
+
+    fn sequence_of(playout: Playout) -> Option<u16> {
+        match playout {
+            Playout::Frame(packet) => Some(packet.header.sequence_number),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn out_of_order_packets_play_out_in_order() {
+        let mut buffer = JitterBuffer::default();
+        // Sequence numbers wrap around mid-stream
+        for sequence in [65534, 0, 65535, 2, 1, 3] {
+            buffer.push(packet(sequence));
+        }
+
+        let played: Vec<Option<u16>> = (0..6)
+            .map(|_| sequence_of(buffer.pop(Instant::now())))
+            .collect();
+
+        assert_eq!(played, [65534, 65535, 0, 1, 2, 3].map(Some).to_vec());
+        assert_eq!(buffer.lost_packets(), 0);
+    }
+
+    #[test]
+    fn missing_frames_are_concealed_and_late_packets_deepen_the_buffer() {
+        let mut buffer = JitterBuffer::default();
+        for sequence in [10, 12, 13] {
+            buffer.push(packet(sequence));
+        }
+
+        assert_eq!(sequence_of(buffer.pop(Instant::now())), Some(10));
+        assert_eq!(buffer.pop(Instant::now()), Playout::Lost);
+        buffer.push(packet(11));
+        assert_eq!(sequence_of(buffer.pop(Instant::now())), Some(12));
+
+        assert_eq!(buffer.lost_packets(), 1);
+        assert_eq!(buffer.late_packets(), 1);
+        assert_eq!(buffer.depth(), MIN_DEPTH + 1);
+    }
+
+    #[test]
+    fn depth_grows_on_underrun_and_shrinks_once_stable() {
+        let mut buffer = JitterBuffer::default();
+        buffer.push(packet(0));
+        buffer.push(packet(1));
+        buffer.pop(Instant::now());
+        buffer.pop(Instant::now());
+        assert_eq!(buffer.pop(Instant::now()), Playout::Buffering);
+        assert_eq!(buffer.depth(), MIN_DEPTH + 1);
+
+        // Refilling takes a few frames before playout resumes
+        for sequence in 2..SHRINK_AFTER as u16 + 10 {
+            buffer.push(packet(sequence));
+            buffer.pop(Instant::now());
+        }
+        assert_eq!(buffer.depth(), MIN_DEPTH);
+    }
+
+    #[test]
+    fn late_packet_within_the_grace_window_plays_instead_of_being_concealed() {
+        let grace = Duration::from_millis(5);
+        let mut buffer = JitterBuffer::with_late_grace(grace);
+        for sequence in [10, 12, 13] {
+            buffer.push(packet(sequence));
+        }
+        let start = Instant::now();
+        assert_eq!(sequence_of(buffer.pop(start)), Some(10));
+
+        let due = start + PLAYOUT_INTERVAL;
+        assert_eq!(buffer.pop(due), Playout::Pending);
+        assert_eq!(buffer.grace_deadline(), Some(due + grace));
+        buffer.push(packet(11));
+        assert_eq!(sequence_of(buffer.pop(due + grace)), Some(11));
+
+        assert_eq!(sequence_of(buffer.pop(due + PLAYOUT_INTERVAL)), Some(12));
+        assert_eq!(buffer.lost_packets(), 0);
+        assert_eq!(buffer.late_packets(), 0);
+    }
+
+    #[test]
+    fn packet_after_the_grace_window_is_discarded_for_concealment() {
+        let grace = Duration::from_millis(5);
+        let mut buffer = JitterBuffer::with_late_grace(grace);
+        for sequence in [10, 12, 13] {
+            buffer.push(packet(sequence));
+        }
+        let start = Instant::now();
+        buffer.pop(start);
+
+        let due = start + PLAYOUT_INTERVAL;
+        assert_eq!(buffer.pop(due), Playout::Pending);
+        assert_eq!(buffer.pop(due + grace), Playout::Lost);
+        assert_eq!(buffer.grace_deadline(), None);
+        buffer.push(packet(11));
+
+        assert_eq!(sequence_of(buffer.pop(due + PLAYOUT_INTERVAL)), Some(12));
+        assert_eq!(buffer.lost_packets(), 1);
+        assert_eq!(buffer.late_packets(), 1);
+    }
+
+    #[test]
+    fn grace_window_must_end_before_the_next_frame_is_due() {
+        assert_eq!(
+            "19".parse::<LateGrace>().unwrap(),
+            LateGrace(Duration::from_millis(19))
+        );
+        assert!("20".parse::<LateGrace>().is_err());
+    }
+}
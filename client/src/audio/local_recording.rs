@@ -0,0 +1,263 @@
+use std::{
+    collections::{HashMap, VecDeque, hash_map::Entry},
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::Result;
+use rvoip_rtp_core::RtpPacket;
+
+use crate::audio::audio_source::{CHANNELS, SAMPLE_RATE};
+
+/// Longest frame Opus produces, 120ms at 48kHz
+const MAX_FRAME_SIZE: usize = 5760;
+/// Samples kept open for late or reordered packets before they're written, 1s at 48kHz
+const MIX_WINDOW: u64 = SAMPLE_RATE as u64;
+
+/// Decodes received audio into a local WAV, set by `--record-local`.
+/// Every SSRC gets its own decoder and is mixed in at its RTP timestamps,
+/// anchored to when its first packet arrived, so concurrent speakers overlap instead of queuing up
+/// and lost packets leave silence behind. Audio more than [`MIX_WINDOW`] behind the latest is dropped.
+/// Finalized on drop, so leaving a room in any way leaves a playable file behind.
+pub struct LocalRecording {
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+    streams: HashMap<u32, Stream>,
+    /// Arrival of the first packet, the start of the file
+    started: Option<Instant>,
+    /// Sums of the samples not written yet, starting at `written`
+    mix: VecDeque<i32>,
+    /// Samples written to the file so far
+    written: u64,
+    pcm: Vec<i16>,
+}
+
+/// Where one SSRC's timestamps fall in the file
+struct Stream {
+    decoder: opus::Decoder,
+    /// File position of the stream's first timestamp
+    anchor: u64,
+    /// The stream's first timestamp, extended past wraparounds like `last`
+    first: i64,
+    /// The latest timestamp seen, extended
+    last: i64,
+}
+
+impl Stream {
+    /// File position of `timestamp`, None if it's from before the stream started
+    fn position(&mut self, timestamp: u32) -> Option<u64> {
+        let extended = self.last + timestamp.wrapping_sub(self.last as u32) as i32 as i64;
+        self.last = self.last.max(extended);
+        self.anchor.checked_add_signed(extended - self.first)
+    }
+}
+
+impl LocalRecording {
+    pub fn create(path: &Path) -> Result<Self> {
+        let spec = hound::WavSpec {
+            channels: CHANNELS as u16,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        Ok(Self {
+            writer: Some(hound::WavWriter::create(path, spec)?),
+            streams: HashMap::new(),
+            started: None,
+            mix: VecDeque::new(),
+            written: 0,
+            pcm: vec![0; MAX_FRAME_SIZE],
+        })
+    }
+
+    /// Decodes one datagram received at `arrival` and mixes it into the file
+    pub fn write_datagram(&mut self, datagram: &[u8], arrival: Instant) -> Result<()> {
+        let packet = RtpPacket::parse(datagram)?;
+        let timestamp = packet.header.timestamp;
+        let started = *self.started.get_or_insert(arrival);
+        let stream = match self.streams.entry(packet.header.ssrc) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let elapsed = arrival.saturating_duration_since(started);
+                let anchor = (elapsed.as_nanos() * SAMPLE_RATE as u128 / 1_000_000_000) as u64;
+                entry.insert(Stream {
+                    decoder: opus::Decoder::new(SAMPLE_RATE, CHANNELS)?,
+                    anchor: anchor.max(self.written),
+                    first: timestamp as i64,
+                    last: timestamp as i64,
+                })
+            }
+        };
+        let len = stream
+            .decoder
+            .decode(&packet.payload, &mut self.pcm, false)?;
+        let Some(position) = stream.position(timestamp) else {
+            return Ok(());
+        };
+        let end = position + len as u64;
+        self.write_until(end.saturating_sub(MIX_WINDOW))?;
+        if end <= self.written {
+            tracing::debug!(
+                "Dropping a packet of SSRC {} that came too late to record",
+                packet.header.ssrc
+            );
+            return Ok(());
+        }
+        // Whatever of it was already written is too late
+        let skip = self.written.saturating_sub(position) as usize;
+        let offset = position.saturating_sub(self.written) as usize;
+        let needed = (end - self.written) as usize;
+        if self.mix.len() < needed {
+            self.mix.resize(needed, 0);
+        }
+        for (i, sample) in self.pcm[..len].iter().enumerate().skip(skip) {
+            self.mix[offset + i - skip] += *sample as i32;
+        }
+        Ok(())
+    }
+
+    /// Writes the mix up to file position `until`, silence where nothing was received
+    fn write_until(&mut self, until: u64) -> Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        while self.written < until {
+            let sum = self.mix.pop_front().unwrap_or(0);
+            writer.write_sample(sum.clamp(i16::MIN as i32, i16::MAX as i32) as i16)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes the rest of the mix and the WAV header, dropping does the same but can only log failures
+    pub fn finalize(mut self) -> Result<()> {
+        self.write_until(self.written + self.mix.len() as u64)?;
+        match self.writer.take() {
+            Some(writer) => Ok(writer.finalize()?),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LocalRecording {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_until(self.written + self.mix.len() as u64) {
+            tracing::warn!("Failed to write the end of the local recording: {e}");
+        }
+        if let Some(Err(e)) = self.writer.take().map(hound::WavWriter::finalize) {
+            tracing::warn!("Failed to finalize local recording: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::audio_source::FRAME_SIZE;
+
+    fn tone_datagrams(ssrc: u32, count: usize) -> Vec<bytes::Bytes> {
+        let mut encoder =
+            opus::Encoder::new(SAMPLE_RATE, CHANNELS, opus::Application::Voip).unwrap();
+        let mut output = vec![0u8; 4000];
+        (0..count)
+            .map(|i| {
+                let frame: Vec<i16> = (0..FRAME_SIZE)
+                    .map(|n| {
+                        let t = (i * FRAME_SIZE + n) as f32 / SAMPLE_RATE as f32;
+                        ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16
+                    })
+                    .collect();
+                let len = encoder.encode(&frame, &mut output).unwrap();
+                RtpPacket::new_with_payload(
+                    111,
+                    i as u16,
+                    (i * FRAME_SIZE) as u32,
+                    ssrc,
+                    output[..len].to_vec().into(),
+                )
+                .serialize()
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn samples(path: &Path) -> Vec<i16> {
+        hound::WavReader::open(path)
+            .unwrap()
+            .samples::<i16>()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn concurrent_streams_are_mixed_not_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.wav");
+        let now = Instant::now();
+
+        let mut recording = LocalRecording::create(&path).unwrap();
+        for datagram in tone_datagrams(7, 5).iter().chain(&tone_datagrams(8, 2)) {
+            recording.write_datagram(datagram, now).unwrap();
+        }
+        recording.finalize().unwrap();
+
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+        assert_eq!(reader.spec().channels, 1);
+        // Both started together, the shorter stream ends inside the longer one
+        assert_eq!(reader.len() as usize, 5 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn streams_start_at_their_arrival_and_gaps_stay_silent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.wav");
+        let now = Instant::now();
+        let first = tone_datagrams(7, 4);
+        let late = tone_datagrams(8, 1);
+
+        let mut recording = LocalRecording::create(&path).unwrap();
+        // Packet 1 of the first stream is lost
+        for datagram in [&first[0], &first[2], &first[3]] {
+            recording.write_datagram(datagram, now).unwrap();
+        }
+        // 100ms in, 5 frames
+        recording
+            .write_datagram(&late[0], now + std::time::Duration::from_millis(100))
+            .unwrap();
+        recording.finalize().unwrap();
+
+        let samples = samples(&path);
+        assert_eq!(samples.len(), 6 * FRAME_SIZE);
+        let silent = |frame: usize| {
+            samples[frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE]
+                .iter()
+                .all(|sample| *sample == 0)
+        };
+        assert!(!silent(0));
+        assert!(silent(1));
+        assert!(!silent(2) && !silent(3));
+        assert!(silent(4));
+        assert!(!silent(5));
+    }
+
+    #[test]
+    fn dropping_finalizes_and_garbage_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.wav");
+
+        let mut recording = LocalRecording::create(&path).unwrap();
+        recording
+            .write_datagram(&tone_datagrams(7, 1)[0], Instant::now())
+            .unwrap();
+        assert!(
+            recording
+                .write_datagram(b"not rtp", Instant::now())
+                .is_err()
+        );
+        drop(recording);
+
+        assert_eq!(samples(&path).len(), FRAME_SIZE);
+    }
+}
@@ -1,9 +1,34 @@
+#[cfg(feature = "audio")]
+pub mod adaptive_fec;
 pub mod audio_manager;
+#[cfg(feature = "audio")]
+pub mod audio_sink;
+#[cfg(feature = "audio")]
 pub mod audio_source;
+#[cfg(feature = "audio")]
+pub mod device_watcher;
+#[cfg(feature = "audio")]
+pub mod file_audio_source;
+#[cfg(feature = "audio")]
+pub mod jitter_buffer;
+#[cfg(feature = "audio")]
+pub mod local_recording;
+#[cfg(feature = "audio")]
+pub mod oversized_frames;
+#[cfg(feature = "audio")]
+pub mod send_pacer;
 use anyhow::{Result, anyhow};
+use lib_common_voxoxide::types::{ARS_ALPN, CloseCode};
 use quinn::Connection;
 
-use crate::{app_config::AppConfig, client_config::create_client_config};
+use crate::{
+    app_config::AppConfig, audio::audio_manager::AudioManagerError,
+    client_config::create_client_config,
+};
+
+/// TLS `no_application_protocol` alert, sent when the server supports none of our ALPN protocols
+const NO_APPLICATION_PROTOCOL: u8 = 120;
+
 pub async fn create_audio_connection(options: AppConfig) -> Result<Connection> {
     let client_config = create_client_config(&options)?;
     let mut endpoint = quinn::Endpoint::client(options.bind)?;
@@ -15,7 +40,145 @@ pub async fn create_audio_connection(options: AppConfig) -> Result<Connection> {
     let conn = endpoint
         .connect(remote, &host)?
         .await
-        .map_err(|e| anyhow!("failed to connect: {}", e))?;
+        .map_err(|e| match e {
+            quinn::ConnectionError::ConnectionClosed(close)
+                if close.error_code
+                    == quinn::TransportErrorCode::crypto(NO_APPLICATION_PROTOCOL) =>
+            {
+                AudioManagerError::IncompatibleServer {
+                    protocol: String::from_utf8_lossy(ARS_ALPN).into_owned(),
+                }
+                .into()
+            }
+            e => anyhow!("failed to connect: {}", e),
+        })?;
+    // Negotiated in the handshake, failing here beats failing on the first frame
+    if conn.max_datagram_size().is_none() {
+        conn.close(
+            CloseCode::ProtocolError.code().into(),
+            b"datagrams are required",
+        );
+        return Err(AudioManagerError::DatagramsUnsupported.into());
+    }
     tracing::info!("Connected to {host} at {remote}");
     Ok(conn)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use clap::Parser;
+    use quinn::crypto::rustls::QuicServerConfig;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    use super::*;
+
+    /// Serves the dev certificate on localhost, speaking only `alpn`.
+    /// Accepted connections are handed out through the receiver, they stay open as long as it does
+    pub(super) fn start_server(
+        alpn: &[u8],
+    ) -> (
+        quinn::Endpoint,
+        tokio::sync::mpsc::UnboundedReceiver<quinn::Connection>,
+    ) {
+        start_server_with_transport(alpn, quinn::TransportConfig::default())
+    }
+
+    /// Like [`start_server`], with the server's transport parameters set by `transport`
+    pub(super) fn start_server_with_transport(
+        alpn: &[u8],
+        transport: quinn::TransportConfig,
+    ) -> (
+        quinn::Endpoint,
+        tokio::sync::mpsc::UnboundedReceiver<quinn::Connection>,
+    ) {
+        let certs = CertificateDer::pem_file_iter("../dev-certs/dev-server.pem")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_file("../dev-certs/dev-server.key").unwrap();
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .unwrap();
+        crypto.alpn_protocols = vec![alpn.to_vec()];
+        let mut config =
+            quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto).unwrap()));
+        config.transport_config(Arc::new(transport));
+        let endpoint = quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let accepting = endpoint.clone();
+        let (accepted, connections) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(incoming) = accepting.accept().await {
+                if let Ok(connection) = incoming.await {
+                    let _ = accepted.send(connection);
+                }
+            }
+        });
+        (endpoint, connections)
+    }
+
+    pub(super) fn config_for(server: &quinn::Endpoint) -> AppConfig {
+        let _ = rustls::crypto::CryptoProvider::install_default(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        );
+        let port = server.local_addr().unwrap().port();
+        AppConfig::parse_from([
+            "client",
+            "--url",
+            &format!("quic://127.0.0.1:{port}"),
+            "--host",
+            "localhost",
+            "--bind",
+            "127.0.0.1:0",
+        ])
+    }
+
+    #[tokio::test]
+    async fn mismatched_alpn_is_an_incompatible_server() {
+        // A relay from before the protocol had its own identifier, and one from a later version
+        for alpn in [&b"hq-29"[..], b"vox-oxide/2"] {
+            let (server, _connections) = start_server(alpn);
+
+            let error = create_audio_connection(config_for(&server))
+                .await
+                .unwrap_err();
+
+            assert_eq!(
+                error.downcast_ref::<AudioManagerError>(),
+                Some(&AudioManagerError::IncompatibleServer {
+                    protocol: String::from_utf8_lossy(ARS_ALPN).into_owned()
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn server_without_datagrams_fails_the_connect() {
+        let mut transport = quinn::TransportConfig::default();
+        transport.datagram_receive_buffer_size(None);
+        let (server, _connections) = start_server_with_transport(ARS_ALPN, transport);
+
+        let error = create_audio_connection(config_for(&server))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<AudioManagerError>(),
+            Some(&AudioManagerError::DatagramsUnsupported)
+        );
+    }
+
+    #[tokio::test]
+    async fn matching_alpn_connects() {
+        let (server, _connections) = start_server(ARS_ALPN);
+
+        create_audio_connection(config_for(&server)).await.unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! Frames too large for a datagram, eg. a burst at a high bitrate on a path with a small MTU.
+//! `send_datagram` refuses them with [`SendDatagramError::TooLarge`], which costs that frame but not the call:
+//! the frame is dropped and logged, and with [`OversizedFramePolicy::ReduceBitrate`] a recurrence lowers
+//! the encoder's bitrate once so later frames fit again.
+
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use opus::Bitrate;
+use quinn::{Connection, SendDatagramError};
+use rvoip_rtp_core::RtpPacket;
+
+use crate::audio::audio_source::SharedEncoder;
+
+/// Bitrate the encoder falls back to when it had no explicit one
+pub(crate) const FALLBACK_BITRATE: i32 = 24_000;
+
+/// What happens after a frame didn't fit in a datagram, set by `--oversized-frames`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedFramePolicy {
+    /// Every oversized frame is dropped and logged
+    Drop,
+    /// Frames are dropped too, the second one also halves the bitrate, once per call
+    ReduceBitrate,
+}
+
+impl FromStr for OversizedFramePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Self::Drop),
+            "reduce-bitrate" => Ok(Self::ReduceBitrate),
+            _ => Err(anyhow!("expected `drop` or `reduce-bitrate`, got `{s}`")),
+        }
+    }
+}
+
+/// Oversized frames of one call
+#[derive(Debug)]
+pub struct OversizedFrames {
+    policy: OversizedFramePolicy,
+    dropped: u64,
+    bitrate_reduced: bool,
+}
+
+impl OversizedFrames {
+    pub fn new(policy: OversizedFramePolicy) -> Self {
+        Self {
+            policy,
+            dropped: 0,
+            bitrate_reduced: false,
+        }
+    }
+
+    /// Sends the packet, an oversized one is handled per the policy instead of failing the call
+    pub fn send(
+        &mut self,
+        connection: &Connection,
+        packet: &RtpPacket,
+        encoder: &SharedEncoder,
+    ) -> Result<()> {
+        let datagram = packet.serialize()?;
+        let len = datagram.len();
+        match connection.send_datagram(datagram) {
+            Err(SendDatagramError::TooLarge) => {
+                self.dropped += 1;
+                tracing::warn!(
+                    "Dropped a {len} byte frame, a datagram fits {} bytes ({} dropped so far)",
+                    connection.max_datagram_size().unwrap_or_default(),
+                    self.dropped
+                );
+                self.on_dropped(encoder)
+            }
+            sent => Ok(sent?),
+        }
+    }
+
+    fn on_dropped(&mut self, encoder: &SharedEncoder) -> Result<()> {
+        if self.policy != OversizedFramePolicy::ReduceBitrate
+            || self.bitrate_reduced
+            || self.dropped < 2
+        {
+            return Ok(());
+        }
+        self.bitrate_reduced = true;
+        let mut encoder = encoder.lock().unwrap();
+        let reduced = match encoder.get_bitrate()? {
+            Bitrate::Bits(bits) => Bitrate::Bits(bits / 2),
+            Bitrate::Auto | Bitrate::Max => Bitrate::Bits(FALLBACK_BITRATE),
+        };
+        tracing::warn!(
+            "Frames keep exceeding the datagram size, reducing the bitrate to {reduced:?}"
+        );
+        encoder.set_bitrate(reduced)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opus::{Application, Encoder};
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::audio::audio_source::{CHANNELS, SAMPLE_RATE};
+    use crate::audio::create_audio_connection;
+    use crate::audio::tests::{config_for, start_server};
+    use lib_common_voxoxide::types::ARS_ALPN;
+
+    fn packet(sequence: u16, payload_len: usize) -> RtpPacket {
+        RtpPacket::new_with_payload(111, sequence, 0, 7, vec![0; payload_len].into())
+    }
+
+    /// The server end is returned too, dropping it would close the connection
+    async fn connect() -> (Connection, UnboundedReceiver<Connection>) {
+        let (server, accepted) = start_server(ARS_ALPN);
+        let connection = create_audio_connection(config_for(&server)).await.unwrap();
+        (connection, accepted)
+    }
+
+    fn encoder(bitrate: Bitrate) -> SharedEncoder {
+        let mut encoder = Encoder::new(SAMPLE_RATE, CHANNELS, Application::Voip).unwrap();
+        encoder.set_bitrate(bitrate).unwrap();
+        Arc::new(Mutex::new(encoder))
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_dropped_without_ending_the_stream() {
+        let (connection, _server) = connect().await;
+        let encoder = encoder(Bitrate::Bits(64_000));
+        let mut frames = OversizedFrames::new(OversizedFramePolicy::Drop);
+
+        for sequence in 0..3 {
+            frames
+                .send(&connection, &packet(sequence, 4000), &encoder)
+                .unwrap();
+        }
+        frames.send(&connection, &packet(3, 100), &encoder).unwrap();
+
+        assert_eq!(frames.dropped, 3);
+        assert_eq!(connection.close_reason(), None);
+        assert_eq!(
+            encoder.lock().unwrap().get_bitrate().unwrap(),
+            Bitrate::Bits(64_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn recurring_oversized_frames_reduce_the_bitrate_once() {
+        let (connection, _server) = connect().await;
+        let encoder = encoder(Bitrate::Bits(64_000));
+        let mut frames = OversizedFrames::new(OversizedFramePolicy::ReduceBitrate);
+        let bitrate = || encoder.lock().unwrap().get_bitrate().unwrap();
+
+        frames
+            .send(&connection, &packet(0, 4000), &encoder)
+            .unwrap();
+        assert_eq!(bitrate(), Bitrate::Bits(64_000));
+        frames
+            .send(&connection, &packet(1, 4000), &encoder)
+            .unwrap();
+        assert_eq!(bitrate(), Bitrate::Bits(32_000));
+        frames
+            .send(&connection, &packet(2, 4000), &encoder)
+            .unwrap();
+        assert_eq!(bitrate(), Bitrate::Bits(32_000));
+
+        frames.send(&connection, &packet(3, 100), &encoder).unwrap();
+        assert_eq!(connection.close_reason(), None);
+    }
+}
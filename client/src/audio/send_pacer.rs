@@ -0,0 +1,122 @@
+//! Steady send timing, set by `--pace-sends`.
+//! Input devices hand frames over whenever their callback runs, which under AGC or resampling
+//! in the driver means bursts and gaps rather than one frame per frame duration. The pacer queues
+//! the frames and releases one per frame duration off a timer, so the relay's jitter estimate sees
+//! the network rather than the capture. Should the capture outpace the timer, the oldest frames
+//! beyond [`MAX_QUEUED_FRAMES`] are dropped instead of building up latency.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use rvoip_rtp_core::RtpPacket;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Frames waiting for their send slot at most, 100ms at 20ms
+pub(crate) const MAX_QUEUED_FRAMES: usize = 5;
+
+/// Send queue of one call
+#[derive(Debug)]
+pub struct SendPacer {
+    queue: VecDeque<RtpPacket>,
+    interval: Interval,
+    dropped: u64,
+}
+
+impl SendPacer {
+    pub fn new(frame_duration: Duration) -> Self {
+        let mut interval = tokio::time::interval(frame_duration);
+        // A stall pushes the following slots back instead of catching up in a burst
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            queue: VecDeque::with_capacity(MAX_QUEUED_FRAMES),
+            interval,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, packet: RtpPacket) {
+        if self.queue.len() >= MAX_QUEUED_FRAMES {
+            self.queue.pop_front();
+            self.dropped += 1;
+            tracing::debug!(
+                "Capture outpaces the send timer, dropped a queued frame ({} so far)",
+                self.dropped
+            );
+        }
+        self.queue.push_back(packet);
+    }
+
+    /// Waits for the next send slot with a frame queued, cancel safe
+    pub async fn next(&mut self) -> RtpPacket {
+        loop {
+            self.interval.tick().await;
+            if let Some(packet) = self.queue.pop_front() {
+                return packet;
+            }
+        }
+    }
+}
+
+/// The next paced frame, never resolves without a pacer
+pub async fn next_paced(pacer: Option<&mut SendPacer>) -> RtpPacket {
+    match pacer {
+        Some(pacer) => pacer.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::audio::audio_source::create_rtp_packet;
+
+    const FRAME: Duration = Duration::from_millis(20);
+
+    fn packet(sequence: u16) -> RtpPacket {
+        create_rtp_packet(sequence, u32::from(sequence) * 960, 7, vec![0].into())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_are_sent_one_frame_duration_apart() {
+        let mut pacer = SendPacer::new(FRAME);
+        // The device hands over four frames at once
+        for sequence in 0..4 {
+            pacer.push(packet(sequence));
+        }
+
+        let mut sent = Vec::new();
+        for _ in 0..4 {
+            let packet = pacer.next().await;
+            sent.push((packet.header.sequence_number, Instant::now()));
+        }
+
+        assert_eq!(
+            sent.iter()
+                .map(|(sequence, _)| *sequence)
+                .collect::<Vec<_>>(),
+            [0, 1, 2, 3]
+        );
+        for pair in sent.windows(2) {
+            assert_eq!(pair[1].1 - pair[0].1, FRAME);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_drops_the_oldest_frames() {
+        let mut pacer = SendPacer::new(FRAME);
+        for sequence in 0..MAX_QUEUED_FRAMES as u16 + 2 {
+            pacer.push(packet(sequence));
+        }
+
+        assert_eq!(pacer.next().await.header.sequence_number, 2);
+        assert_eq!(pacer.dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_paced_without_a_pacer() {
+        let next = tokio::time::timeout(FRAME * 2, next_paced(None)).await;
+        assert!(next.is_err());
+    }
+}
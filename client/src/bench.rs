@@ -0,0 +1,166 @@
+//! `client bench-encode`: measures what encoding costs at the configured settings.
+
+#[cfg(feature = "bench")]
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+use std::{fmt, time::Duration};
+
+use crate::app_config::AppConfig;
+#[cfg(not(feature = "audio"))]
+use crate::audio::audio_manager::AudioManagerError;
+#[cfg(feature = "audio")]
+use crate::audio::audio_source::{EncoderSettings, SAMPLE_RATE};
+
+/// Counts allocations per thread, so a benchmark only sees its own.
+/// Only allocations through the Rust allocator are counted, not libopus' own.
+/// Installed for the whole process, so only builds with the `bench` feature pay for it
+#[cfg(feature = "bench")]
+struct CountingAllocator;
+
+#[cfg(feature = "bench")]
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+#[cfg(feature = "bench")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[cfg(feature = "bench")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Allocations made on this thread so far, None unless built with the `bench` feature
+#[cfg(feature = "audio")]
+fn allocations() -> Option<u64> {
+    #[cfg(feature = "bench")]
+    return Some(ALLOCATIONS.with(Cell::get));
+    #[cfg(not(feature = "bench"))]
+    None
+}
+
+#[derive(Debug)]
+pub struct BenchReport {
+    pub frames: usize,
+    /// Time spent inside the encoder, generating the input isn't counted
+    pub encode_time: Duration,
+    pub encoded_bytes: usize,
+    /// Allocations made while encoding, None unless built with the `bench` feature
+    pub allocations: Option<u64>,
+}
+
+impl BenchReport {
+    pub fn frames_per_sec(&self) -> f64 {
+        self.frames as f64 / self.encode_time.as_secs_f64()
+    }
+
+    pub fn average_encode_time(&self) -> Duration {
+        self.encode_time / self.frames.max(1) as u32
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Encoded {} frames ({} bytes) in {:?}",
+            self.frames, self.encoded_bytes, self.encode_time
+        )?;
+        writeln!(f, "{:.0} frames/sec", self.frames_per_sec())?;
+        writeln!(f, "Average encode time: {:?}", self.average_encode_time())?;
+        match self.allocations {
+            Some(allocations) => write!(f, "Allocations while encoding: {allocations}"),
+            None => write!(
+                f,
+                "Allocations while encoding: not counted, build with `--features bench`"
+            ),
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+pub fn bench_encode(_config: &AppConfig, _seconds: u32) -> anyhow::Result<BenchReport> {
+    Err(AudioManagerError::AudioDisabled.into())
+}
+
+/// Encodes `seconds` of a synthetic tone with the encoder a room without codec policy would get.
+#[cfg(feature = "audio")]
+pub fn bench_encode(config: &AppConfig, seconds: u32) -> anyhow::Result<BenchReport> {
+    let settings = EncoderSettings::default().with_config(config)?;
+    let encoder = settings.build_encoder()?;
+    let mut encoder = encoder.lock().unwrap();
+    let channels = settings.channels as usize;
+    let frame_len = settings.frame_size * channels;
+    let frames = (seconds as u64 * SAMPLE_RATE as u64 / settings.frame_size as u64) as usize;
+    // A tone with some harmonics, so the encoder has more to do than for silence
+    let pcm: Vec<f32> = (0..frames * settings.frame_size)
+        .flat_map(|n| {
+            let t = n as f32 / SAMPLE_RATE as f32;
+            let sample = (t * 220.0 * std::f32::consts::TAU).sin() * 0.2
+                + (t * 660.0 * std::f32::consts::TAU).sin() * 0.1
+                + (t * 1870.0 * std::f32::consts::TAU).sin() * 0.05;
+            std::iter::repeat_n(sample, channels)
+        })
+        .collect();
+    let mut output = vec![0u8; 4000];
+
+    let mut encode_time = Duration::ZERO;
+    let mut encoded_bytes = 0;
+    let allocations_before = allocations();
+    for frame in pcm.chunks_exact(frame_len) {
+        let started = std::time::Instant::now();
+        encoded_bytes += encoder.encode_float(frame, &mut output)?;
+        encode_time += started.elapsed();
+    }
+    Ok(BenchReport {
+        frames,
+        encode_time,
+        encoded_bytes,
+        allocations: allocations()
+            .zip(allocations_before)
+            .map(|(after, before)| after - before),
+    })
+}
+
+#[cfg(all(test, feature = "audio"))]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn short_benchmark_reports_throughput() {
+        let config = AppConfig::parse_from(["client", "bench-encode", "--seconds", "1"]);
+
+        let report = bench_encode(&config, 1).unwrap();
+
+        assert_eq!(report.frames, 50);
+        assert!(report.encoded_bytes > 0);
+        assert!(report.frames_per_sec() > 0.0);
+        assert!(report.average_encode_time() > Duration::ZERO);
+        assert!(report.to_string().contains("frames/sec"));
+        assert_eq!(report.allocations.is_some(), cfg!(feature = "bench"));
+    }
+
+    #[test]
+    fn frame_duration_is_taken_from_the_config() {
+        let config = AppConfig::parse_from(["client", "--expert-frame-duration-ms", "10"]);
+
+        assert_eq!(bench_encode(&config, 1).unwrap().frames, 100);
+    }
+}
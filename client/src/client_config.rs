@@ -1,7 +1,10 @@
 use crate::app_config::AppConfig;
+use anyhow::Context;
+use lib_common_voxoxide::types::{ARS_ALPN, PemSource};
 use quinn::crypto::rustls::QuicClientConfig;
 use rustls::pki_types::CertificateDer;
 use rustls::pki_types::pem::PemObject;
+use std::path::Path;
 use std::sync::Arc;
 
 #[cfg(not(debug_assertions))]
@@ -12,25 +15,7 @@ const CERT: &[u8] = include_bytes!(env!("EMBEDDED_CERT_PATH"));
 pub fn create_client_config(config: &AppConfig) -> Result<quinn::ClientConfig, anyhow::Error> {
     let mut roots = rustls::RootCertStore::empty();
 
-    let certs = {
-        #[cfg(debug_assertions)]
-        {
-            tracing::info!("Using file certificate.");
-            match &config.cert_path {
-                Some(cert) => {
-                    CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?
-                }
-                None => panic!("Certificate path not provided and not embedded into binary"),
-            }
-        }
-        #[cfg(not(debug_assertions))]
-        {
-            tracing::info!("Using embedded certificate.");
-            CertificateDer::pem_reader_iter(&CERT[..]).collect::<Result<Vec<_>, _>>()?
-        }
-    };
-
-    for cert in certs {
+    for cert in root_certs(config.cert_path.as_deref())? {
         roots.add(cert)?;
     }
 
@@ -38,9 +23,82 @@ pub fn create_client_config(config: &AppConfig) -> Result<quinn::ClientConfig, a
         .with_root_certificates(roots)
         .with_no_client_auth();
 
-    client_crypto.alpn_protocols = [b"hq-29"].iter().map(|&x| x.into()).collect();
+    client_crypto.alpn_protocols = vec![ARS_ALPN.to_vec()];
 
     Ok(quinn::ClientConfig::new(Arc::new(
         QuicClientConfig::try_from(client_crypto)?,
     )))
 }
+
+/// The certificate at `cert_path` if given, so one release binary works against any deployment.
+/// It may also name an environment variable or stdin, see [`PemSource`].
+/// Release builds fall back to the embedded certificate, debug builds embed none.
+fn root_certs(cert_path: Option<&Path>) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    match cert_path {
+        Some(cert) => {
+            let source = PemSource::new(cert);
+            tracing::info!("Using certificate from {source}.");
+            let pem = source.read().with_context(|| format!("reading {source}"))?;
+            Ok(CertificateDer::pem_slice_iter(&pem).collect::<Result<Vec<_>, _>>()?)
+        }
+        #[cfg(not(debug_assertions))]
+        None => {
+            tracing::info!("Using embedded certificate.");
+            Ok(CertificateDer::pem_reader_iter(&CERT[..]).collect::<Result<Vec<_>, _>>()?)
+        }
+        #[cfg(debug_assertions)]
+        None => anyhow::bail!("Certificate path not provided and not embedded into binary"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn pem_file(path: &str) -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter(path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn explicit_pem_path_takes_the_file_certificate() {
+        let config = AppConfig::parse_from(["client", "--pem", "../dev-certs/dev-server.pem"]);
+
+        let certs = root_certs(config.cert_path.as_deref()).unwrap();
+
+        assert_eq!(certs, pem_file("../dev-certs/dev-server.pem"));
+        // Release builds embed the dev CA, which the file replaces
+        assert_ne!(certs, pem_file("../dev-certs/dev-ca.pem"));
+    }
+
+    #[test]
+    fn pem_is_taken_from_an_env_var_or_inline() {
+        let pem = std::fs::read_to_string("../dev-certs/dev-server.pem").unwrap();
+        unsafe { std::env::set_var("VOXOXIDE_CLIENT_TEST_PEM", &pem) };
+
+        let from_env = AppConfig::parse_from(["client", "--pem", "env:VOXOXIDE_CLIENT_TEST_PEM"]);
+        assert_eq!(
+            root_certs(from_env.cert_path.as_deref()).unwrap(),
+            pem_file("../dev-certs/dev-server.pem")
+        );
+        assert_eq!(
+            root_certs(Some(Path::new(&pem))).unwrap(),
+            pem_file("../dev-certs/dev-server.pem")
+        );
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn release_build_falls_back_to_the_embedded_certificate() {
+        let config = AppConfig::parse_from(["client"]);
+
+        assert_eq!(config.cert_path, None);
+        assert_eq!(
+            root_certs(None).unwrap(),
+            pem_file("../dev-certs/dev-ca.pem")
+        );
+    }
+}
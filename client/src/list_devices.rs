@@ -0,0 +1,168 @@
+//! `client --list-devices`: prints the audio devices to pick with `--input-device` and `--output-device`.
+
+use std::fmt;
+
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait};
+
+#[cfg(not(feature = "audio"))]
+use crate::audio::audio_manager::AudioManagerError;
+#[cfg(feature = "audio")]
+use crate::audio::device_watcher::device_name;
+
+/// One range of stream configurations a device supports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// The host's default device of its direction
+    pub default: bool,
+    pub configs: Vec<ConfigRange>,
+}
+
+/// Every input and output device of the default host
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeviceListing {
+    pub inputs: Vec<DeviceInfo>,
+    pub outputs: Vec<DeviceInfo>,
+}
+
+impl fmt::Display for ConfigRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channels = match self.channels {
+            1 => "mono".to_string(),
+            2 => "stereo".to_string(),
+            n => format!("{n} channels"),
+        };
+        if self.min_sample_rate == self.max_sample_rate {
+            write!(f, "{channels}, {} Hz", self.min_sample_rate)?;
+        } else {
+            write!(
+                f,
+                "{channels}, {}-{} Hz",
+                self.min_sample_rate, self.max_sample_rate
+            )?;
+        }
+        write!(f, ", {}", self.sample_format)
+    }
+}
+
+impl fmt::Display for DeviceListing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (title, devices) in [("Input", &self.inputs), ("Output", &self.outputs)] {
+            writeln!(f, "{title} devices:")?;
+            if devices.is_empty() {
+                writeln!(f, "  none")?;
+            }
+            for device in devices {
+                let default = if device.default { " (default)" } else { "" };
+                writeln!(f, "  {:?}{default}", device.name)?;
+                for config in &device.configs {
+                    writeln!(f, "    {config}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+pub fn list_devices() -> anyhow::Result<DeviceListing> {
+    Err(AudioManagerError::AudioDisabled.into())
+}
+
+/// Queries the default host, devices failing to report their configurations are listed without any
+#[cfg(feature = "audio")]
+pub fn list_devices() -> anyhow::Result<DeviceListing> {
+    let host = cpal::default_host();
+    let default_input = host.default_input_device().and_then(|d| device_name(&d));
+    let default_output = host.default_output_device().and_then(|d| device_name(&d));
+    let inputs = host
+        .input_devices()?
+        .filter_map(|device| {
+            let configs = device
+                .supported_input_configs()
+                .map(|configs| configs.map(config_range).collect())
+                .inspect_err(|e| tracing::debug!("Failed to query an input device: {e}"));
+            device_info(&device, configs.unwrap_or_default(), &default_input)
+        })
+        .collect();
+    let outputs = host
+        .output_devices()?
+        .filter_map(|device| {
+            let configs = device
+                .supported_output_configs()
+                .map(|configs| configs.map(config_range).collect())
+                .inspect_err(|e| tracing::debug!("Failed to query an output device: {e}"));
+            device_info(&device, configs.unwrap_or_default(), &default_output)
+        })
+        .collect();
+    Ok(DeviceListing { inputs, outputs })
+}
+
+#[cfg(feature = "audio")]
+fn config_range(config: cpal::SupportedStreamConfigRange) -> ConfigRange {
+    ConfigRange {
+        channels: config.channels(),
+        min_sample_rate: config.min_sample_rate(),
+        max_sample_rate: config.max_sample_rate(),
+        sample_format: config.sample_format().to_string(),
+    }
+}
+
+/// None for a device without a name, it couldn't be picked anyway
+#[cfg(feature = "audio")]
+fn device_info(
+    device: &cpal::Device,
+    configs: Vec<ConfigRange>,
+    default: &Option<String>,
+) -> Option<DeviceInfo> {
+    let name = device_name(device)?;
+    Some(DeviceInfo {
+        default: default.as_ref() == Some(&name),
+        name,
+        configs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_shows_names_defaults_and_ranges() {
+        let listing = DeviceListing {
+            inputs: vec![DeviceInfo {
+                name: "USB mic".to_string(),
+                default: true,
+                configs: vec![
+                    ConfigRange {
+                        channels: 1,
+                        min_sample_rate: 8_000,
+                        max_sample_rate: 48_000,
+                        sample_format: "i16".to_string(),
+                    },
+                    ConfigRange {
+                        channels: 6,
+                        min_sample_rate: 48_000,
+                        max_sample_rate: 48_000,
+                        sample_format: "f32".to_string(),
+                    },
+                ],
+            }],
+            outputs: Vec::new(),
+        };
+
+        assert_eq!(
+            listing.to_string(),
+            "Input devices:\n  \"USB mic\" (default)\n    mono, 8000-48000 Hz, i16\n    6 channels, 48000 Hz, f32\nOutput devices:\n  none\n"
+        );
+    }
+}
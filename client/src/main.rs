@@ -14,6 +14,9 @@ use crate::{app::App, audio::audio_manager};
 
 mod app;
 mod audio;
+mod bench;
+mod list_devices;
+mod selftest;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -21,7 +24,6 @@ async fn main() -> Result<()> {
     let opt = app_config::AppConfig::parse();
     let log_file = std::fs::OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(&opt.log_file)?;
 
@@ -37,6 +39,24 @@ async fn main() -> Result<()> {
 
     tracing::info!("App starting up...");
 
+    if opt.list_devices {
+        print!("{}", list_devices::list_devices()?);
+        return Ok(());
+    }
+
+    match opt.command {
+        Some(app_config::Command::Selftest { connect }) => {
+            let report = selftest::run(&opt, &selftest::CpalProbe, connect).await;
+            println!("{report}");
+            std::process::exit(if report.passed() { 0 } else { 1 });
+        }
+        Some(app_config::Command::BenchEncode { seconds }) => {
+            println!("{}", bench::bench_encode(&opt, seconds)?);
+            return Ok(());
+        }
+        None => {}
+    }
+
     color_eyre::install().map_err(|e| anyhow!(e))?;
     let audio_manager = audio_manager::AudioManager::new(opt.clone());
     let mut app = App::new(audio_manager, opt);
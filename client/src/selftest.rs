@@ -0,0 +1,232 @@
+//! `client selftest`: checks the local audio setup and optionally the server, step by step.
+
+use std::fmt;
+#[cfg(feature = "audio")]
+use std::time::Duration;
+
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait};
+use lib_common_voxoxide::types::{ArsAuthRequest, CloseCode};
+
+#[cfg(not(feature = "audio"))]
+use crate::audio::audio_manager::AudioManagerError;
+#[cfg(feature = "audio")]
+use crate::audio::audio_source::{CHANNELS, EncoderSettings, FRAME_SIZE, SAMPLE_RATE};
+use crate::{
+    app_config::AppConfig,
+    audio::{audio_manager::AudioManager, create_audio_connection},
+};
+
+/// Access to the audio hardware, swapped out in tests.
+pub trait AudioProbe {
+    /// Opens the default input device and returns its name
+    fn open_input_device(&self) -> anyhow::Result<String>;
+}
+
+/// Probes the real default input device through cpal.
+pub struct CpalProbe;
+
+#[cfg(not(feature = "audio"))]
+impl AudioProbe for CpalProbe {
+    fn open_input_device(&self) -> anyhow::Result<String> {
+        Err(AudioManagerError::AudioDisabled.into())
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioProbe for CpalProbe {
+    fn open_input_device(&self) -> anyhow::Result<String> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .ok_or_else(|| anyhow::anyhow!("no input device available"))?;
+        let config = cpal::StreamConfig {
+            channels: CHANNELS as u16,
+            sample_rate: SAMPLE_RATE,
+            buffer_size: cpal::BufferSize::Default,
+        };
+        // Dropped right away, opening it is all we want to know
+        let _stream = device.build_input_stream(
+            &config,
+            |_: &[f32], _| {},
+            |err| tracing::error!("Audio stream error: {:?}", err),
+            Some(Duration::from_secs(2)),
+        )?;
+        Ok(format!("{:?}", device.description()))
+    }
+}
+
+#[derive(Debug)]
+pub struct StepResult {
+    pub name: &'static str,
+    /// Details on success, the error on failure
+    pub outcome: Result<String, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SelfTestReport {
+    pub steps: Vec<StepResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.outcome.is_ok())
+    }
+
+    fn record(&mut self, name: &'static str, outcome: anyhow::Result<String>) {
+        self.steps.push(StepResult {
+            name,
+            outcome: outcome.map_err(|e| e.to_string()),
+        });
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            match &step.outcome {
+                Ok(details) => writeln!(f, "[PASS] {}: {details}", step.name)?,
+                Err(error) => writeln!(f, "[FAIL] {}: {error}", step.name)?,
+            }
+        }
+        write!(
+            f,
+            "{}",
+            if self.passed() {
+                "All checks passed"
+            } else {
+                "Some checks failed"
+            }
+        )
+    }
+}
+
+/// Runs every step, later steps still run when an earlier independent one fails.
+pub async fn run(config: &AppConfig, probe: &dyn AudioProbe, connect: bool) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    report.record("input device opens", probe.open_input_device());
+    check_encoder(&mut report);
+
+    if connect {
+        report.record(
+            "server connects and authenticates",
+            connect_and_authenticate(config).await,
+        );
+    }
+    report
+}
+
+#[cfg(not(feature = "audio"))]
+fn check_encoder(report: &mut SelfTestReport) {
+    report.record(
+        "opus encoder initializes",
+        Err(AudioManagerError::AudioDisabled.into()),
+    );
+}
+
+#[cfg(feature = "audio")]
+fn check_encoder(report: &mut SelfTestReport) {
+    let encoder = match EncoderSettings::default().build_encoder() {
+        Ok(encoder) => {
+            report.record(
+                "opus encoder initializes",
+                Ok(format!("{SAMPLE_RATE}Hz {CHANNELS:?}")),
+            );
+            Some(encoder)
+        }
+        Err(e) => {
+            report.record("opus encoder initializes", Err(e));
+            None
+        }
+    };
+    if let Some(encoder) = encoder {
+        let tone: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| (i as f32 / SAMPLE_RATE as f32 * 440.0 * std::f32::consts::TAU).sin() * 0.25)
+            .collect();
+        let mut output = vec![0u8; 4000];
+        let encoded = encoder
+            .lock()
+            .unwrap()
+            .encode_float(&tone, &mut output)
+            .map(|len| format!("{len} bytes"))
+            .map_err(Into::into);
+        report.record("tone encodes", encoded);
+    }
+}
+
+async fn connect_and_authenticate(config: &AppConfig) -> anyhow::Result<String> {
+    let mut connection = create_audio_connection(config.clone()).await?;
+    AudioManager::authenticate_audio_connection(&mut connection, ArsAuthRequest::for_room(0))
+        .await?;
+    connection.close(CloseCode::Normal.code().into(), b"selftest done");
+    Ok(format!("{}", connection.remote_address()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    struct MockProbe(bool);
+
+    impl AudioProbe for MockProbe {
+        fn open_input_device(&self) -> anyhow::Result<String> {
+            if self.0 {
+                Ok("mock input".to_string())
+            } else {
+                anyhow::bail!("mock device missing")
+            }
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    #[tokio::test]
+    async fn all_steps_pass_with_working_audio() {
+        let config = AppConfig::parse_from(["client"]);
+
+        let report = run(&config, &MockProbe(true), false).await;
+
+        assert!(report.passed(), "{report}");
+        let names: Vec<_> = report.steps.iter().map(|step| step.name).collect();
+        assert_eq!(
+            names,
+            [
+                "input device opens",
+                "opus encoder initializes",
+                "tone encodes"
+            ]
+        );
+        assert!(report.to_string().ends_with("All checks passed"));
+    }
+
+    #[cfg(feature = "audio")]
+    #[tokio::test]
+    async fn missing_device_fails_the_report() {
+        let config = AppConfig::parse_from(["client"]);
+
+        let report = run(&config, &MockProbe(false), false).await;
+
+        assert!(!report.passed());
+        assert!(
+            report
+                .to_string()
+                .contains("[FAIL] input device opens: mock device missing")
+        );
+        // Encoder checks don't depend on the device, so they still run
+        assert_eq!(report.steps.len(), 3);
+    }
+
+    #[cfg(not(feature = "audio"))]
+    #[tokio::test]
+    async fn encoder_check_fails_without_audio() {
+        let config = AppConfig::parse_from(["client"]);
+
+        let report = run(&config, &MockProbe(true), false).await;
+
+        assert!(!report.passed());
+        assert!(report.to_string().contains(&format!(
+            "[FAIL] opus encoder initializes: {}",
+            AudioManagerError::AudioDisabled
+        )));
+    }
+}
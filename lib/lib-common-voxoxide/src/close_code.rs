@@ -0,0 +1,45 @@
+use derive_more::Display;
+
+/// Application close codes used by both ends of a QUIC connection.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum CloseCode {
+    Normal = 0,
+    ServerShutdown = 1,
+    AuthFailed = 2,
+    ProtocolError = 3,
+    /// The peer sent more data than the server allows
+    BandwidthExceeded = 4,
+    /// A newer connection of the same user took over
+    Replaced = 5,
+    /// The client left the room on purpose
+    ClientLeft = 6,
+    /// The session ran for the longest time the server allows
+    SessionTimeLimit = 7,
+    /// A moderator removed the member from the room, the reason is the moderator's
+    Kicked = 8,
+    /// Nothing, not even a keepalive, arrived from the client for longer than the server waits
+    Inactive = 9,
+}
+
+impl CloseCode {
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_code(code: u64) -> Option<Self> {
+        Some(match code {
+            0 => Self::Normal,
+            1 => Self::ServerShutdown,
+            2 => Self::AuthFailed,
+            3 => Self::ProtocolError,
+            4 => Self::BandwidthExceeded,
+            5 => Self::Replaced,
+            6 => Self::ClientLeft,
+            7 => Self::SessionTimeLimit,
+            8 => Self::Kicked,
+            9 => Self::Inactive,
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,13 @@
+/// Longest display name accepted, in characters
+pub const MAX_DISPLAY_NAME_CHARS: usize = 32;
+
+/// Returns the name as members of a room get to see it: trimmed, with every run of whitespace
+/// collapsed into a single space. None if it is empty, too long or contains control characters.
+pub fn sanitize_display_name(name: &str) -> Option<String> {
+    if name.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return None;
+    }
+    let sanitized = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let chars = sanitized.chars().count();
+    (chars > 0 && chars <= MAX_DISPLAY_NAME_CHARS).then_some(sanitized)
+}
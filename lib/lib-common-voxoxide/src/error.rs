@@ -0,0 +1,62 @@
+use derive_more::{Display, Error, From};
+
+use crate::close_code::CloseCode;
+use crate::types::ArsAuthError;
+
+/// Errors of either end, one variant per category so they can be matched on
+/// instead of inspecting an `anyhow::Error`'s message.
+#[derive(Debug, Clone, Display, Error, From)]
+pub enum VoxoxideError {
+    /// The server refused the authentication request
+    #[display("auth failed: {_0}")]
+    Auth(ArsAuthError),
+    /// The peer sent something the protocol doesn't allow, eg. a malformed message
+    #[display("protocol error: {_0}")]
+    #[from(ignore)]
+    Protocol(#[error(not(source))] String),
+    /// Encoding or decoding audio failed
+    #[display("codec error: {_0}")]
+    #[from(ignore)]
+    Codec(#[error(not(source))] String),
+    /// The connection failed or was lost without a close code
+    #[display("transport error: {_0}")]
+    #[from(ignore)]
+    Transport(#[error(not(source))] String),
+    /// The peer closed the connection with an application close code, with the reason it gave
+    #[display("connection closed ({code}): {reason}")]
+    #[from(ignore)]
+    Closed { code: CloseCode, reason: String },
+    /// A setting is missing or invalid
+    #[display("invalid config: {_0}")]
+    #[from(ignore)]
+    Config(#[error(not(source))] String),
+}
+
+impl From<CloseCode> for VoxoxideError {
+    fn from(code: CloseCode) -> Self {
+        Self::Closed {
+            code,
+            reason: String::new(),
+        }
+    }
+}
+
+impl VoxoxideError {
+    /// Maps a QUIC application close to [`Self::Closed`],
+    /// a code this build doesn't know to [`Self::Protocol`]
+    pub fn from_close(code: u64, reason: &[u8]) -> Self {
+        let reason = String::from_utf8_lossy(reason).into_owned();
+        match CloseCode::from_code(code) {
+            Some(code) => Self::Closed { code, reason },
+            None => Self::Protocol(format!("closed with unknown code {code}: {reason}")),
+        }
+    }
+
+    /// The close code if the peer closed the connection
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            Self::Closed { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
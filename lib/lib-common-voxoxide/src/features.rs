@@ -0,0 +1,58 @@
+use std::ops::{BitAnd, BitOr};
+
+use serde::{Deserialize, Serialize};
+
+/// Optional protocol features as a bitmask, exchanged in the auth handshake.
+/// Each side announces what it supports and only the intersection is used on the connection,
+/// bits unknown to the other side are dropped by the intersection too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Features(u32);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+    /// In-band forward error correction in the Opus stream
+    pub const FEC: Self = Self(1);
+    /// Discontinuous transmission, nothing is sent during silence
+    pub const DTX: Self = Self(1 << 1);
+    /// Several frames per datagram
+    pub const BATCHING: Self = Self(1 << 2);
+    /// Receiving a single server-mixed stream instead of every member's own
+    pub const MIXING: Self = Self(1 << 3);
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl BitAnd for Features {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        self.intersection(rhs)
+    }
+}
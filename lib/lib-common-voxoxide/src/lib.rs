@@ -1,18 +1,48 @@
 #![allow(unused)]
 
+mod close_code;
+mod display_name;
+mod error;
+mod features;
+mod pem_source;
+mod protocol;
 mod raw;
 mod serde;
 
 #[cfg(feature = "serde")]
 pub mod types {
+    pub use crate::close_code::CloseCode;
+    pub use crate::display_name::{MAX_DISPLAY_NAME_CHARS, sanitize_display_name};
+    pub use crate::error::VoxoxideError;
+    pub use crate::features::Features;
+    pub use crate::pem_source::PemSource;
+    pub use crate::protocol::{
+        ARS_ALPN, FRAME_HEADER_LEN, KEEPALIVE_DATAGRAM, frame_message, framed_len, split_frame,
+    };
     pub use crate::serde::ars_auth::ArsAuthRequestSerde as ArsAuthRequest;
+    pub use crate::serde::ars_auth::ArsAuthResponseSerde as ArsAuthResponse;
+    pub use crate::serde::ars_auth::AudioFormatSerde as ArsAudioFormat;
     pub use crate::serde::ars_auth::AuthErrorSerde as ArsAuthError;
+    pub use crate::serde::ars_auth::CodecPolicySerde as ArsCodecPolicy;
+    pub use crate::serde::control::ControlMessageSerde as ArsControlMessage;
 }
 
 #[cfg(not(feature = "serde"))]
 pub mod types {
+    pub use crate::close_code::CloseCode;
+    pub use crate::display_name::{MAX_DISPLAY_NAME_CHARS, sanitize_display_name};
+    pub use crate::error::VoxoxideError;
+    pub use crate::features::Features;
+    pub use crate::pem_source::PemSource;
+    pub use crate::protocol::{
+        ARS_ALPN, FRAME_HEADER_LEN, KEEPALIVE_DATAGRAM, frame_message, framed_len, split_frame,
+    };
     pub use crate::raw::ars_auth::ArsAuthRequestRaw as ArsAuthRequest;
+    pub use crate::raw::ars_auth::ArsAuthResponseRaw as ArsAuthResponse;
+    pub use crate::raw::ars_auth::AudioFormatRaw as ArsAudioFormat;
     pub use crate::raw::ars_auth::AuthErrorRaw as ArsAuthError;
+    pub use crate::raw::ars_auth::CodecPolicyRaw as ArsCodecPolicy;
+    pub use crate::raw::control::ControlMessageRaw as ArsControlMessage;
 }
 
 #[cfg(test)]
@@ -22,4 +52,264 @@ mod tests {
         let error = crate::serde::ars_auth::AuthErrorSerde::InvalidAuthRequestReceived;
         assert_eq!(error.to_string(), "InvalidAuthRequestReceived");
     }
+
+    #[test]
+    fn test_to_string_raw() {
+        use crate::raw::ars_auth::AuthErrorRaw;
+        let error = AuthErrorRaw::InvalidAuthRequestReceived;
+        assert_eq!(error.to_string(), "InvalidAuthRequestReceived");
+        assert_eq!(AuthErrorRaw::RoomLocked.to_string(), "RoomLocked");
+    }
+
+    #[test]
+    fn test_auth_response_without_policy() {
+        use crate::serde::ars_auth::ArsAuthResponseSerde;
+        let response: ArsAuthResponseSerde = serde_json::from_str("{}").unwrap();
+        assert_eq!(response.codec_policy, None);
+        assert_eq!((response.session_id, response.session_key), (0, 0));
+    }
+
+    #[test]
+    fn test_auth_response_round_trip() {
+        use crate::serde::ars_auth::ArsAuthResponseSerde;
+        let response = ArsAuthResponseSerde {
+            member_id: 3,
+            session_id: 12,
+            session_key: 0xdead_beef,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains(r#""session_key":3735928559"#), "{json}");
+        assert_eq!(
+            serde_json::from_str::<ArsAuthResponseSerde>(&json).unwrap(),
+            response
+        );
+    }
+
+    #[test]
+    fn test_auth_request_round_trip() {
+        use crate::serde::ars_auth::{ArsAuthRequestSerde, AuthErrorSerde};
+        let mut request = ArsAuthRequestSerde::for_room(7);
+        request.user_id = Some(42);
+        request.token = Some("c0ffee".to_string());
+        let json = serde_json::to_string(&request).unwrap();
+
+        let parsed: ArsAuthRequestSerde = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.room_id, 7);
+        assert_eq!(parsed.user_id, Some(42));
+        assert_eq!(parsed.token.as_deref(), Some("c0ffee"));
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        // Requests without a token still parse
+        let anonymous: ArsAuthRequestSerde =
+            serde_json::from_str(r#"{"placeholder_id":10,"room_id":3}"#).unwrap();
+        assert_eq!(anonymous.token, None);
+
+        let error = serde_json::to_string(&AuthErrorSerde::Unauthorized).unwrap();
+        assert_eq!(error, r#""Unauthorized""#);
+        assert!(matches!(
+            serde_json::from_str::<AuthErrorSerde>(&error).unwrap(),
+            AuthErrorSerde::Unauthorized
+        ));
+    }
+
+    #[test]
+    fn test_control_message_tagging() {
+        use crate::serde::control::ControlMessageSerde;
+        let message = ControlMessageSerde::SetMemberMuted {
+            member_id: 3,
+            muted: true,
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"SetMemberMuted","member_id":3,"muted":true}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ControlMessageSerde>(&json).unwrap(),
+            message
+        );
+        // Rosters of servers predating mute flags and names parse as nobody muted or named
+        let roster = r#"{"type":"Roster","member_ids":[1,2]}"#;
+        assert_eq!(
+            serde_json::from_str::<ControlMessageSerde>(roster).unwrap(),
+            ControlMessageSerde::Roster {
+                member_ids: vec![1, 2],
+                muted: Vec::new(),
+                display_names: Vec::new(),
+            }
+        );
+        let named = ControlMessageSerde::Roster {
+            member_ids: vec![1, 2],
+            muted: vec![2],
+            display_names: vec![Some("Ada".to_string()), None],
+        };
+        let json = serde_json::to_string(&named).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"Roster","member_ids":[1,2],"muted":[2],"display_names":["Ada",null]}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ControlMessageSerde>(&json).unwrap(),
+            named
+        );
+    }
+
+    #[test]
+    fn test_features_negotiation() {
+        use crate::features::Features;
+        let client = Features::FEC | Features::DTX | Features::from_bits(1 << 31);
+        let server = Features::FEC | Features::MIXING;
+
+        let mutual = client & server;
+        assert_eq!(mutual, Features::FEC);
+        assert!(!mutual.contains(Features::DTX));
+        assert_eq!(serde_json::to_string(&mutual).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_display_name_sanitizing() {
+        use crate::display_name::{MAX_DISPLAY_NAME_CHARS, sanitize_display_name};
+        assert_eq!(
+            sanitize_display_name("  Ada \t Lovelace\n").as_deref(),
+            Some("Ada Lovelace")
+        );
+        assert_eq!(sanitize_display_name("Zoë 🎧").as_deref(), Some("Zoë 🎧"));
+        assert_eq!(sanitize_display_name("   "), None);
+        assert_eq!(sanitize_display_name("bell\u{7}"), None);
+        assert_eq!(
+            sanitize_display_name(&"é".repeat(MAX_DISPLAY_NAME_CHARS)).map(|n| n.chars().count()),
+            Some(MAX_DISPLAY_NAME_CHARS)
+        );
+        assert_eq!(
+            sanitize_display_name(&"é".repeat(MAX_DISPLAY_NAME_CHARS + 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pem_sources() {
+        use std::path::Path;
+
+        use crate::pem_source::PemSource;
+
+        const PEM: &str = "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n";
+        // Each test binary has its own environment, the name only has to be unique here
+        unsafe { std::env::set_var("VOXOXIDE_TEST_PEM", PEM) };
+
+        let env = PemSource::new(Path::new("env:VOXOXIDE_TEST_PEM"));
+        assert_eq!(env, PemSource::Env("VOXOXIDE_TEST_PEM".to_string()));
+        assert_eq!(env.read().unwrap(), PEM.as_bytes());
+
+        let literal = PemSource::new(Path::new(PEM));
+        assert_eq!(literal.read().unwrap(), PEM.as_bytes());
+        assert_eq!(literal.to_string(), "inline PEM");
+
+        assert_eq!(PemSource::new(Path::new("-")), PemSource::Stdin);
+        let file = PemSource::new(Path::new("certs/server.pem"));
+        assert_eq!(file.file(), Some(Path::new("certs/server.pem")));
+        assert!(
+            PemSource::new(Path::new("env:VOXOXIDE_UNSET_PEM"))
+                .read()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_close_code_round_trip() {
+        use crate::close_code::CloseCode;
+        for code in [
+            CloseCode::Normal,
+            CloseCode::ServerShutdown,
+            CloseCode::AuthFailed,
+            CloseCode::ProtocolError,
+            CloseCode::BandwidthExceeded,
+            CloseCode::Replaced,
+            CloseCode::ClientLeft,
+            CloseCode::SessionTimeLimit,
+            CloseCode::Kicked,
+            CloseCode::Inactive,
+        ] {
+            assert_eq!(CloseCode::from_code(code.code() as u64), Some(code));
+        }
+        assert_eq!(CloseCode::from_code(999), None);
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        use crate::protocol::{frame_message, framed_len, split_frame};
+
+        let pretty = b"{\n  \"room_id\": 4\n}";
+        let mut stream = frame_message(pretty);
+        stream.extend(frame_message(b"{}"));
+        assert_eq!(framed_len(&stream), Some(pretty.len()));
+
+        let (first, rest) = split_frame(&stream).unwrap();
+        assert_eq!(first, pretty);
+        let (second, rest) = split_frame(rest).unwrap();
+        assert_eq!(second, b"{}");
+        assert!(rest.is_empty());
+
+        // Incomplete headers and messages wait for more bytes
+        assert_eq!(framed_len(&stream[..2]), None);
+        assert_eq!(split_frame(&stream[..8]), None);
+    }
+
+    #[test]
+    fn test_error_display() {
+        use crate::close_code::CloseCode;
+        use crate::error::VoxoxideError;
+        use crate::types::ArsAuthError;
+
+        let auth = VoxoxideError::from(ArsAuthError::DuplicateUser);
+        assert!(matches!(
+            auth,
+            VoxoxideError::Auth(ArsAuthError::DuplicateUser)
+        ));
+        assert_eq!(
+            auth.to_string(),
+            format!("auth failed: {}", ArsAuthError::DuplicateUser)
+        );
+
+        let closed = VoxoxideError::from(CloseCode::Replaced);
+        assert_eq!(closed.close_code(), Some(CloseCode::Replaced));
+        assert_eq!(closed.to_string(), "connection closed (Replaced): ");
+
+        let kicked = VoxoxideError::from_close(CloseCode::Kicked.code().into(), b"too loud");
+        assert!(matches!(
+            &kicked,
+            VoxoxideError::Closed { code: CloseCode::Kicked, reason } if reason == "too loud"
+        ));
+        assert_eq!(kicked.to_string(), "connection closed (Kicked): too loud");
+        let unknown = VoxoxideError::from_close(999, b"?");
+        assert!(matches!(unknown, VoxoxideError::Protocol(_)));
+        assert_eq!(unknown.close_code(), None);
+
+        assert_eq!(
+            VoxoxideError::Config("unknown cipher suite".into()).to_string(),
+            "invalid config: unknown cipher suite"
+        );
+        assert_eq!(
+            VoxoxideError::Codec("corrupted stream".into()).to_string(),
+            "codec error: corrupted stream"
+        );
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error;
+
+        use crate::error::VoxoxideError;
+        use crate::types::ArsAuthError;
+
+        let auth = VoxoxideError::Auth(ArsAuthError::FormatMismatch);
+        assert_eq!(
+            auth.source().unwrap().to_string(),
+            ArsAuthError::FormatMismatch.to_string()
+        );
+        assert!(
+            VoxoxideError::Transport("timed out".into())
+                .source()
+                .is_none()
+        );
+    }
 }
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Prefix selecting an environment variable holding the PEM
+pub const ENV_PREFIX: &str = "env:";
+/// Selects stdin
+pub const STDIN: &str = "-";
+const PEM_BEGIN: &str = "-----BEGIN ";
+
+/// Where a certificate or key comes from, given in place of its path.
+/// Containers can hand secrets over without a file: `env:NAME` reads the environment variable `NAME`,
+/// `-` reads stdin and a value that is PEM itself is taken as is. Anything else is a file path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PemSource {
+    File(PathBuf),
+    Env(String),
+    Stdin,
+    Literal(String),
+}
+
+/// Stdin can only be read once, a key and certificate both given as `-` share what it held
+static STDIN_CONTENT: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+impl PemSource {
+    pub fn new(path: &Path) -> Self {
+        match path.to_str() {
+            Some(STDIN) => Self::Stdin,
+            Some(value) if value.starts_with(ENV_PREFIX) => {
+                Self::Env(value[ENV_PREFIX.len()..].to_string())
+            }
+            Some(value) if value.trim_start().starts_with(PEM_BEGIN) => {
+                Self::Literal(value.to_string())
+            }
+            _ => Self::File(path.to_path_buf()),
+        }
+    }
+
+    /// The path if it's a file, which may hold DER as well
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::File(path) => std::fs::read(path),
+            Self::Env(name) => std::env::var(name)
+                .map(String::into_bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("${name}: {e}"))),
+            Self::Stdin => {
+                let mut content = STDIN_CONTENT.lock().unwrap();
+                if content.is_none() {
+                    let mut read = Vec::new();
+                    io::stdin().read_to_end(&mut read)?;
+                    *content = Some(read);
+                }
+                Ok(content.clone().unwrap_or_default())
+            }
+            Self::Literal(pem) => Ok(pem.clone().into_bytes()),
+        }
+    }
+}
+
+/// Never prints literal PEM, it may be a private key
+impl fmt::Display for PemSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{path:?}"),
+            Self::Env(name) => write!(f, "${name}"),
+            Self::Stdin => write!(f, "stdin"),
+            Self::Literal(_) => write!(f, "inline PEM"),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+/// ALPN identifier of the ARS protocol, client and server refuse to talk without a match.
+/// Bump it on breaking protocol changes so mismatched builds fail the handshake clearly.
+/// Builds before the length-prefixed auth stream offered `hq-29`.
+pub const ARS_ALPN: &[u8] = b"vox-oxide/1";
+
+/// Datagram a client sends on a timer while it has no audio to send, eg. while muted,
+/// so the connection never looks idle. Shorter than an RTP header, the relay never mistakes it for audio
+pub const KEEPALIVE_DATAGRAM: &[u8] = &[0x00];
+
+/// Bytes of the big-endian length in front of every message the client sends on the auth stream
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// Prefixes `message` with its length, as the auth request and the control messages following it are sent
+pub fn frame_message(message: &[u8]) -> Vec<u8> {
+    let len = u32::try_from(message.len()).expect("message longer than u32::MAX bytes");
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + message.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// Length of the message at the front of `buffer`, None until its header fully arrived
+pub fn framed_len(buffer: &[u8]) -> Option<usize> {
+    let header: [u8; FRAME_HEADER_LEN] = buffer.get(..FRAME_HEADER_LEN)?.try_into().ok()?;
+    Some(u32::from_be_bytes(header) as usize)
+}
+
+/// Splits the message at the front of `buffer` from what follows it, None until it fully arrived
+pub fn split_frame(buffer: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = FRAME_HEADER_LEN + framed_len(buffer)?;
+    (buffer.len() >= end).then(|| (&buffer[FRAME_HEADER_LEN..end], &buffer[end..]))
+}
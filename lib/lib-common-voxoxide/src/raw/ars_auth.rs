@@ -1,19 +1,64 @@
-use core::fmt;
+use derive_more::{Display, Error};
 
-use derive_more::Error;
+use crate::features::Features;
 
-#[derive(Debug, Clone, Error)]
+#[derive(Debug, Clone, Error, Display)]
 pub enum AuthErrorRaw {
     NoAuthRequestReceived,
     InvalidAuthRequestReceived,
-}
-impl fmt::Display for AuthErrorRaw {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "invalid first item to double")
-    }
+    DuplicateUser,
+    FormatMismatch,
+    InvalidDisplayName,
+    RecordingConsentRequired,
+    SsrcCollision,
+    RoomLocked,
+    Unauthorized,
+    AuthTimedOut,
 }
 
 #[derive(Debug, Clone)]
 pub struct ArsAuthRequestRaw {
     placeholder_id: u32,
+    pub room_id: u32,
+    pub moderator_token: Option<String>,
+    pub user_id: Option<u64>,
+    pub token: Option<String>,
+    pub format: Option<AudioFormatRaw>,
+    pub features: Option<Features>,
+    pub display_name: Option<String>,
+    pub recording_consent: bool,
+    pub ssrc: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArsAuthResponseRaw {
+    pub member_id: u64,
+    pub session_id: u32,
+    pub session_key: u32,
+    pub moderator: bool,
+    pub codec_policy: Option<CodecPolicyRaw>,
+    pub features: Features,
+    pub reassigned_ssrc: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodecPolicyRaw {
+    pub bitrate: Option<i32>,
+    pub channels: Option<u8>,
+    pub fec: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormatRaw {
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+impl Default for AudioFormatRaw {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 1,
+        }
+    }
 }
@@ -0,0 +1,28 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlMessageRaw {
+    SetMemberMuted {
+        member_id: u64,
+        muted: bool,
+    },
+    SetAllMuted {
+        muted: bool,
+    },
+    Kick {
+        member_id: u64,
+        reason: String,
+    },
+    SetRoomLocked {
+        locked: bool,
+    },
+    SetSelfMuted {
+        muted: bool,
+    },
+    SessionEnding {
+        remaining_secs: u64,
+    },
+    Roster {
+        member_ids: Vec<u64>,
+        muted: Vec<u64>,
+        display_names: Vec<Option<String>>,
+    },
+}
@@ -1,20 +1,141 @@
 use core::fmt;
 use derive_more::{Display, Error};
 use serde::{Deserialize, Serialize};
+
+use crate::features::Features;
 #[derive(Debug, Clone, Serialize, Deserialize, Error, Display)]
 #[serde(rename_all = "PascalCase")]
 pub enum AuthErrorSerde {
     NoAuthRequestReceived,
     InvalidAuthRequestReceived,
+    /// Another connection is already authenticated as the same user
+    DuplicateUser,
+    /// The declared audio format differs from the one the room is fixed to
+    FormatMismatch,
+    /// The display name is empty, too long or contains control characters
+    InvalidDisplayName,
+    /// The room only admits members that consent to being recorded
+    RecordingConsentRequired,
+    /// The declared SSRC is already streamed by another member of the room
+    SsrcCollision,
+    /// A moderator locked the room, it takes no new members until it's unlocked
+    RoomLocked,
+    /// The server requires auth tokens and the request's is missing or doesn't match its user and room
+    Unauthorized,
+    /// The handshake did not complete within the server's auth timeout
+    AuthTimedOut,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArsAuthRequestSerde {
     placeholder_id: u32,
+    /// Room the client wants to join
+    #[serde(default)]
+    pub room_id: u32,
+    /// Grants moderator rights when it matches the room's configured token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moderator_token: Option<String>,
+    /// Identifies the user across connections, anonymous if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u64>,
+    /// Opaque proof that the user is `user_id` and may join `room_id`, for servers requiring it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Format of the audio the client sends, [`AudioFormatSerde::default`] if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<AudioFormatSerde>,
+    /// Optional features the client supports. Clients predating the exchange don't set it,
+    /// the server then assumes they support everything it does
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Features>,
+    /// Shown to other members instead of the member id, see [`crate::types::sanitize_display_name`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Whether the user agrees to being recorded, rooms may require it before recording anyone
+    #[serde(default)]
+    pub recording_consent: bool,
+    /// SSRC the client will stream with, so the server can catch a clash with another member on join
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssrc: Option<u32>,
 }
 
 impl ArsAuthRequestSerde {
     pub fn new() -> Self {
-        Self { placeholder_id: 10 }
+        Self {
+            placeholder_id: 10,
+            room_id: 0,
+            moderator_token: None,
+            user_id: None,
+            token: None,
+            format: None,
+            features: None,
+            display_name: None,
+            recording_consent: false,
+            ssrc: None,
+        }
+    }
+    pub fn for_room(room_id: u32) -> Self {
+        Self {
+            room_id,
+            ..Self::new()
+        }
+    }
+}
+
+/// Sent back on the control stream once the auth request is accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArsAuthResponseSerde {
+    /// Id other members of the room refer to this connection by
+    #[serde(default)]
+    pub member_id: u64,
+    /// Id the server assigned this session, unique until the server restarts
+    #[serde(default)]
+    pub session_id: u32,
+    /// Random key of this session, known only to the server and the client
+    #[serde(default)]
+    pub session_key: u32,
+    /// Whether the server accepted the moderator token
+    #[serde(default)]
+    pub moderator: bool,
+    /// Codec settings of the joined room, the client keeps its own defaults if not set
+    #[serde(default)]
+    pub codec_policy: Option<CodecPolicySerde>,
+    /// Features both sides support, the only ones used on this connection
+    #[serde(default)]
+    pub features: Features,
+    /// SSRC the client has to stream with instead of the declared one, which another member already uses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reassigned_ssrc: Option<u32>,
+}
+
+/// Encoder settings a room requires from its members, unset fields are left to the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodecPolicySerde {
+    /// Bits per second
+    #[serde(default)]
+    pub bitrate: Option<i32>,
+    /// 1 for mono, 2 for stereo
+    #[serde(default)]
+    pub channels: Option<u8>,
+    /// In-band forward error correction
+    #[serde(default)]
+    pub fec: Option<bool>,
+}
+
+/// Sample rate and channel count of a stream. Every member of a room has to send the same format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioFormatSerde {
+    pub sample_rate: u32,
+    /// 1 for mono, 2 for stereo
+    pub channels: u8,
+}
+
+/// 48kHz mono, assumed for clients that declare no format
+impl Default for AudioFormatSerde {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 1,
+        }
     }
 }
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+/// Messages sent on a unidirectional stream after auth, one message per stream.
+/// Members may also send them on the auth stream after the auth request, each framed by [`crate::types::frame_message`].
+/// Members send the moderation messages and their own mute state, the server sends the rest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "PascalCase")]
+pub enum ControlMessageSerde {
+    /// Moderator only: stop or resume forwarding a member's audio
+    SetMemberMuted { member_id: u64, muted: bool },
+    /// Moderator only: applies SetMemberMuted to every member except moderators
+    SetAllMuted { muted: bool },
+    /// Moderator only: closes the member's connection with [`crate::types::CloseCode::Kicked`] and `reason`
+    Kick { member_id: u64, reason: String },
+    /// Moderator only: a locked room turns away joining members with
+    /// [`crate::types::ArsAuthError::RoomLocked`], its moderators and current members are unaffected
+    SetRoomLocked { locked: bool },
+    /// Any member: whether it muted itself, shown to the others in the [`Self::Roster`].
+    /// Only informs the room, the server forwards whatever audio the member still sends
+    SetSelfMuted { muted: bool },
+    /// Server only: the session reaches the server's time limit in `remaining_secs` and is closed then
+    SessionEnding { remaining_secs: u64 },
+    /// Server only: the ids of the members now in the room, sent after a member joined, left,
+    /// was kicked or muted. `muted` holds those muted by themselves or a moderator,
+    /// `display_names` the name each of `member_ids` gave on auth, in the same order
+    Roster {
+        member_ids: Vec<u64>,
+        #[serde(default)]
+        muted: Vec<u64>,
+        #[serde(default)]
+        display_names: Vec<Option<String>>,
+    },
+}
@@ -1 +1,2 @@
 pub mod ars_auth;
+pub mod control;